@@ -1,16 +1,12 @@
-#![feature(test)]
-
 extern crate cuckoomap;
 #[cfg(feature = "farmhash")]
 extern crate farmhash;
-#[cfg(feature = "fnv")]
 extern crate fnv;
-extern crate rand;
-extern crate test;
 
 use self::cuckoomap::*;
-use std::error::Error;
+use criterion::{criterion_group, criterion_main, Criterion};
 use std::fs::File;
+use std::hint::black_box;
 use std::io::prelude::*;
 use std::path::Path;
 
@@ -19,61 +15,202 @@ fn get_words() -> String {
     let display = path.display();
 
     // Open the path in read-only mode, returns `io::Result<File>`
-    let mut file = match File::open(&path) {
-        // The `description` method of `io::Error` returns a string that
-        // describes the error
-        Err(why) => panic!("couldn't open {}: {}", display, Error::description(&why)),
+    let mut file = match File::open(path) {
+        Err(why) => panic!("couldn't open {}: {}", display, why),
         Ok(file) => file,
     };
 
     let mut contents = String::new();
     if let Err(why) = file.read_to_string(&mut contents) {
-        panic!("couldn't read {}: {}", display, Error::description(&why));
+        panic!("couldn't read {}: {}", display, why);
     }
     contents
 }
 
-fn perform_insertions<H: std::hash::Hasher + Default>(b: &mut test::Bencher) {
+fn perform_insertions<H: std::hash::Hasher + Default>(c: &mut Criterion, name: &str) {
     let contents = get_words();
-    let split: Vec<&str> = contents.split("\n").take(1000).collect();
+    let split: Vec<&str> = contents.split('\n').take(1000).collect();
     let mut cf = CuckooMap::<H>::with_capacity(split.len() * 2);
 
-    b.iter(|| {
-        for s in &split {
-            test::black_box(cf.test_and_add(s, Value(0)).unwrap());
-        }
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            for s in &split {
+                black_box(cf.test_and_add(s, [0]).unwrap());
+            }
+        })
     });
 }
 
-#[bench]
-fn bench_new(b: &mut test::Bencher) {
-    b.iter(|| {
-        test::black_box(CuckooMap::new());
-    });
+fn bench_new(c: &mut Criterion) {
+    c.bench_function("new", |b| b.iter(|| black_box(CuckooMap::new())));
 }
 
-#[bench]
-fn bench_clear(b: &mut test::Bencher) {
-    let mut cf = test::black_box(CuckooMap::new());
+fn bench_clear(c: &mut Criterion) {
+    let mut cf = black_box(CuckooMap::new());
 
-    b.iter(|| {
-        test::black_box(cf.clear());
+    // an empty map returns right away
+    c.bench_function("clear", |b| {
+        b.iter(|| {
+            cf.insert_or_update(&1, [1]).unwrap();
+            cf.clear()
+        })
     });
 }
 
 #[cfg(feature = "farmhash")]
-#[bench]
-fn bench_insertion_farmhash(b: &mut test::Bencher) {
-    perform_insertions::<farmhash::FarmHasher>(b);
+fn bench_insertion_farmhash(c: &mut Criterion) {
+    perform_insertions::<farmhash::FarmHasher>(c, "insertion_farmhash");
+}
+
+#[cfg(not(feature = "farmhash"))]
+fn bench_insertion_farmhash(_: &mut Criterion) {}
+
+fn bench_insertion_fnv(c: &mut Criterion) {
+    perform_insertions::<fnv::FnvHasher>(c, "insertion_fnv");
 }
 
-#[cfg(feature = "fnv")]
-#[bench]
-fn bench_insertion_fnv(b: &mut test::Bencher) {
-    perform_insertions::<fnv::FnvHasher>(b);
+fn bench_insertion_default(c: &mut Criterion) {
+    perform_insertions::<std::collections::hash_map::DefaultHasher>(c, "insertion_default");
+}
+
+fn bench_batch_insertion(c: &mut Criterion) {
+    // a table far larger than the caches, so every insert misses
+    let mut cf = CuckooMap::<fnv::FnvHasher>::with_capacity(1 << 24);
+    let keys: Vec<u64> = (0..100_000).collect();
+
+    c.bench_function("insertion_one_by_one", |b| {
+        b.iter(|| {
+            for key in &keys {
+                black_box(cf.insert_or_update(key, [0]).is_ok());
+            }
+        })
+    });
+    c.bench_function("insertion_batch", |b| {
+        b.iter(|| black_box(cf.insert_batch(keys.iter().map(|key| (key, [0]))).is_ok()))
+    });
+    c.bench_function("lookup_one_by_one", |b| {
+        b.iter(|| {
+            for key in &keys {
+                black_box(cf.get(key));
+            }
+        })
+    });
+    c.bench_function("lookup_many", |b| b.iter(|| black_box(cf.get_many(&keys))));
+}
+
+fn perform_saturated_insertions(c: &mut Criterion, name: &str, policy: KickPolicy) {
+    let mut cf = CuckooMap::<fnv::FnvHasher>::with_capacity(1 << 12);
+    cf.set_kick_policy(policy);
+
+    // fill until the first insertion fails
+    let mut i = 0u64;
+    while cf.insert_or_update(&i, [0]).is_ok() {
+        i += 1;
+    }
+
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            i += 1;
+            black_box(cf.insert_or_update(&i, [0]).is_ok())
+        })
+    });
 }
 
-#[bench]
-fn bench_insertion_default(b: &mut test::Bencher) {
-    perform_insertions::<std::collections::hash_map::DefaultHasher>(b);
+fn bench_saturated_insertion(c: &mut Criterion) {
+    perform_saturated_insertions(c, "saturated_insertion_fixed", KickPolicy::Fixed);
+    perform_saturated_insertions(
+        c,
+        "saturated_insertion_adaptive",
+        KickPolicy::Adaptive {
+            threshold: 0.4,
+            min_kicks: 16,
+        },
+    );
 }
+
+fn bench_ways(c: &mut Criterion) {
+    let capacity = 1 << 15;
+    // 90% of the capacity, which two-way hashing still reaches comfortably
+    let keys: Vec<u64> = (0..capacity as u64 * 9 / 10).collect();
+
+    for ways in 2..=4 {
+        let mut cf = CuckooMap::<fnv::FnvHasher>::with_capacity(capacity);
+        cf.set_ways(ways);
+
+        c.bench_function(&format!("insertion_{}_ways", ways), |b| {
+            b.iter(|| {
+                cf.clear();
+                for key in &keys {
+                    black_box(cf.insert_or_update(key, [0]).is_ok());
+                }
+            })
+        });
+        // half of the lookups miss and probe every candidate bucket
+        c.bench_function(&format!("lookup_{}_ways", ways), |b| {
+            b.iter(|| {
+                for key in 0..2 * keys.len() as u64 {
+                    black_box(cf.get(&key));
+                }
+            })
+        });
+    }
+}
+
+fn bench_path_search(c: &mut Criterion) {
+    let capacity = 1 << 15;
+    // 95% of the capacity, where random walks get long
+    let keys: Vec<u64> = (0..capacity as u64 * 95 / 100).collect();
+
+    let searches = [
+        ("random_walk", PathSearch::RandomWalk),
+        ("breadth_first", PathSearch::BreadthFirst { max_depth: 5 }),
+    ];
+    for &(name, search) in &searches {
+        let mut cf = CuckooMap::<fnv::FnvHasher>::with_capacity(capacity);
+        cf.set_path_search(search);
+
+        c.bench_function(&format!("insertion_{}", name), |b| {
+            b.iter(|| {
+                cf.clear();
+                for key in &keys {
+                    black_box(cf.insert_or_update(key, [0]).is_ok());
+                }
+            })
+        });
+    }
+}
+
+fn bench_hot_cache(c: &mut Criterion) {
+    // a table far larger than the caches, read through a few hot keys
+    let mut cf = CuckooMap::<fnv::FnvHasher>::with_capacity(1 << 22);
+    for key in 0..(1u64 << 21) {
+        cf.insert_or_update(&key, [0]).unwrap();
+    }
+    let hot_keys: Vec<u64> = (0..2000).map(|i| i * 1009).collect();
+
+    for &capacity in &[0, 4096] {
+        cf.set_hot_cache(capacity);
+        c.bench_function(&format!("lookup_skewed_hot_cache_{}", capacity), |b| {
+            b.iter(|| {
+                for key in &hot_keys {
+                    black_box(cf.get(key));
+                }
+            })
+        });
+    }
+}
+
+criterion_group!(
+    benches,
+    bench_new,
+    bench_clear,
+    bench_insertion_farmhash,
+    bench_insertion_fnv,
+    bench_insertion_default,
+    bench_saturated_insertion,
+    bench_batch_insertion,
+    bench_ways,
+    bench_path_search,
+    bench_hot_cache
+);
+criterion_main!(benches);
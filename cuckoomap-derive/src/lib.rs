@@ -0,0 +1,103 @@
+//! `#[derive(CuckooKey)]`, re-exported by `cuckoomap` with the `derive` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Error, Index, LitInt, Member};
+
+/// Implements `Hash` and `cuckoomap::CuckooKey` for a struct, hashing the key
+/// version followed by the fields in their explicit order.
+///
+/// Every field needs either `#[cuckoo_key(order = N)]` or
+/// `#[cuckoo_key(skip)]`, so reordering the declarations doesn't change the
+/// hash. The version is set with `#[cuckoo_key(version = N)]` on the struct
+/// and defaults to 0.
+#[proc_macro_derive(CuckooKey, attributes(cuckoo_key))]
+pub fn derive_cuckoo_key(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+fn expand(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let mut version = 0u32;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("cuckoo_key")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("version") {
+                version = meta.value()?.parse::<LitInt>()?.base10_parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("expected `version = N`"))
+            }
+        })?;
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => return Err(Error::new(input.ident.span(), "CuckooKey can only be derived for structs")),
+    };
+
+    let mut ordered: Vec<(u32, Member)> = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let member = match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(i)),
+        };
+
+        let mut order = None;
+        let mut skip = false;
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("cuckoo_key")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("order") {
+                    order = Some(meta.value()?.parse::<LitInt>()?.base10_parse::<u32>()?);
+                    Ok(())
+                } else if meta.path.is_ident("skip") {
+                    skip = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `order = N` or `skip`"))
+                }
+            })?;
+        }
+
+        match (order, skip) {
+            (Some(_), true) => {
+                return Err(Error::new(field.span(), "a field can't have both `order` and `skip`"));
+            }
+            (Some(order), false) => {
+                if ordered.iter().any(|(o, _)| *o == order) {
+                    return Err(Error::new(field.span(), format!("duplicate `order = {}`", order)));
+                }
+                ordered.push((order, member));
+            }
+            (None, true) => {}
+            (None, false) => {
+                return Err(Error::new(
+                    field.span(),
+                    "every field needs `#[cuckoo_key(order = N)]` or `#[cuckoo_key(skip)]`",
+                ));
+            }
+        }
+    }
+    ordered.sort_by_key(|(order, _)| *order);
+    let members = ordered.iter().map(|(_, member)| member);
+
+    for param in input.generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::core::hash::Hash));
+    }
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::core::hash::Hash for #name #ty_generics #where_clause {
+            fn hash<__H: ::core::hash::Hasher>(&self, state: &mut __H) {
+                ::core::hash::Hash::hash(&#version, state);
+                #( ::core::hash::Hash::hash(&self.#members, state); )*
+            }
+        }
+
+        impl #impl_generics ::cuckoomap::CuckooKey for #name #ty_generics #where_clause {
+            const KEY_VERSION: u32 = #version;
+        }
+    })
+}
@@ -0,0 +1,72 @@
+//! Measures the false positive rate of `get` at increasing load factors and
+//! prints it as CSV next to the estimate of `false_positive_probability`,
+//! once for every number of candidate buckets per key.
+//!
+//! The load factor grows in 5% steps until an insert fails. Keys are `u64`
+//! counters unless a file is given, in which case its lines are used: the
+//! first ones are inserted and the rest are probed as absent keys.
+//!
+//! ```text
+//! cargo run --release --example fp_rate [keys.txt] > fp_rate.csv
+//! ```
+
+use cuckoomap::{CuckooMap, MAX_WAYS};
+
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::process;
+
+const BUCKETS: usize = 1 << 16;
+const PROBES: usize = 100_000;
+
+fn main() {
+    let keys: Vec<String> = match env::args().nth(1) {
+        Some(path) => match fs::read_to_string(&path) {
+            Ok(contents) => contents.lines().map(str::to_owned).collect(),
+            Err(e) => {
+                eprintln!("failed to read {}: {}", path, e);
+                process::exit(1);
+            }
+        },
+        None => (0..(BUCKETS + PROBES) as u64).map(|i| i.to_string()).collect(),
+    };
+    if keys.len() <= PROBES {
+        eprintln!("need more than {} keys", PROBES);
+        process::exit(1);
+    }
+    let (inserted, probes) = keys.split_at(keys.len() - PROBES);
+
+    println!("ways,load_factor,empirical_fp_rate,estimated_fp_rate");
+    for ways in 2..=MAX_WAYS {
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(BUCKETS);
+        map.set_ways(ways);
+        let mut inserted = inserted.iter();
+
+        for step in 1..=20 {
+            let target = BUCKETS * step / 20;
+            while map.len() < target {
+                let key = match inserted.next() {
+                    Some(key) => key,
+                    None => break,
+                };
+                if map.insert_or_update(key, [0]).is_err() {
+                    eprintln!("{} ways: insert failed at load factor {:.4}", ways, map.len() as f64 / BUCKETS as f64);
+                    break;
+                }
+            }
+            if map.len() < target {
+                break;
+            }
+
+            let hits = probes.iter().filter(|key| map.get(*key).is_some()).count();
+            println!(
+                "{},{:.2},{:.6},{:.6}",
+                ways,
+                map.len() as f64 / BUCKETS as f64,
+                hits as f64 / probes.len() as f64,
+                map.false_positive_probability()
+            );
+        }
+    }
+}
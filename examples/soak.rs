@@ -0,0 +1,248 @@
+//! Runs a mixed workload of lookups, inserts and deletes against a map for a
+//! given time, checking every result against a `BTreeMap` oracle.
+//!
+//! Every few thousand operations a random chaos event interrupts the
+//! workload: the map is cleared, rebuilt at another capacity, merged with a
+//! second map, exported and imported or finalized. After each event the map
+//! is checked with `debug_validate` and every key of the oracle is looked up.
+//! The first mismatch aborts the run with the seed that reproduces it.
+//!
+//! ```text
+//! cargo run --release --example soak [seconds] [seed]
+//! ```
+
+use cuckoomap::{CuckooError, CuckooMap, CuckooMapView, MergeRule};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::env;
+use std::process;
+use std::time::{Duration, Instant};
+
+/// Keys are drawn from `0..KEYS`, so the map runs at high load.
+const KEYS: u64 = 1 << 16;
+/// Operations between chaos events.
+const ROUND: usize = 10_000;
+
+// with 4 byte fingerprints the keys are unlikely to collide, so results are exact
+type Map = CuckooMap<DefaultHasher, 4, 2>;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let seconds = args.get(1).map_or(Ok(3600), |s| s.parse());
+    let seed = args.get(2).map_or(Ok(rand::random()), |s| s.parse());
+    let (seconds, seed) = match (seconds, seed) {
+        (Ok(seconds), Ok(seed)) => (seconds, seed),
+        _ => {
+            eprintln!("usage: soak [seconds] [seed]");
+            process::exit(2);
+        }
+    };
+    println!("seed {}, running for {}s", seed, seconds);
+
+    let mut soak = Soak::new(seed);
+    let deadline = Instant::now() + Duration::from_secs(seconds);
+    let mut last_report = Instant::now();
+    while Instant::now() < deadline {
+        for _ in 0..ROUND {
+            soak.step();
+        }
+        soak.chaos();
+        soak.verify();
+        if last_report.elapsed() >= Duration::from_secs(10) {
+            soak.report();
+            last_report = Instant::now();
+        }
+    }
+    soak.report();
+}
+
+struct Soak {
+    seed: u64,
+    rng: StdRng,
+    map: Map,
+    oracle: BTreeMap<u64, [u8; 2]>,
+    ops: u64,
+    events: BTreeMap<&'static str, u64>,
+    lost: u64,
+}
+
+impl Soak {
+    fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            map: build(KEYS as usize, false),
+            oracle: BTreeMap::new(),
+            ops: 0,
+            events: BTreeMap::new(),
+            lost: 0,
+        }
+    }
+
+    /// One lookup, insert or delete of a random key.
+    fn step(&mut self) {
+        self.ops += 1;
+        let key = self.rng.gen_range(0, KEYS);
+        match self.rng.gen_range(0, 100) {
+            0..=49 => {
+                let found = self.map.get(&key);
+                if let Some(&value) = self.oracle.get(&key) {
+                    self.check(found == Some(value), "get returned a wrong value");
+                }
+            }
+            50..=84 => {
+                let value = self.rng.gen();
+                match self.map.insert_or_update(&key, value) {
+                    Ok(()) => {
+                        self.oracle.insert(key, value);
+                    }
+                    // a full map fails inserts without changing
+                    Err(CuckooError::NotEnoughSpace { .. }) => {}
+                    Err(e) => self.fail(&format!("insert failed: {}", e)),
+                }
+            }
+            _ => {
+                let deleted = self.map.delete(&key);
+                let expected = self.oracle.remove(&key).is_some();
+                self.check(deleted || !expected, "delete missed a present key");
+            }
+        }
+    }
+
+    fn chaos(&mut self) {
+        // clearing rarely, so the map spends most of its time at high load
+        let event = match self.rng.gen_range(0, 17) {
+            0 => {
+                self.map.clear();
+                self.oracle.clear();
+                "clear"
+            }
+            1..=4 => {
+                self.resize();
+                "resize"
+            }
+            5..=8 => {
+                self.merge();
+                "merge"
+            }
+            9..=12 => {
+                self.export();
+                "export"
+            }
+            _ => {
+                self.map.finalize(self.oracle.keys());
+                "finalize"
+            }
+        };
+        *self.events.entry(event).or_insert(0) += 1;
+    }
+
+    /// Rebuilds the map at a random capacity, with auto-growth enabled at
+    /// random, keeping the entries that fit.
+    fn resize(&mut self) {
+        let capacity = self.rng.gen_range(KEYS / 2, 2 * KEYS) as usize;
+        self.map = build(capacity, self.rng.gen());
+        let mut kept = BTreeMap::new();
+        for (&key, &value) in &self.oracle {
+            if self.map.insert_or_update(&key, value).is_ok() {
+                kept.insert(key, value);
+            }
+        }
+        self.oracle = kept;
+    }
+
+    /// Merges a map of random entries, which fails for maps that grew.
+    fn merge(&mut self) {
+        let mut other = build(self.map.capacity(), false);
+        let mut incoming = BTreeMap::new();
+        for _ in 0..self.rng.gen_range(1, 256) {
+            let (key, value) = (self.rng.gen_range(0, KEYS), self.rng.gen());
+            if other.insert_or_update(&key, value).is_ok() {
+                incoming.insert(key, value);
+            }
+        }
+
+        match self.map.merge(&other) {
+            Ok(report) => {
+                self.oracle.extend(incoming);
+                if report.dropped > 0 {
+                    // entries lost to a full map can't be told apart
+                    let map = &self.map;
+                    let before = self.oracle.len();
+                    self.oracle.retain(|key, value| map.get(key) == Some(*value));
+                    self.check(before - self.oracle.len() <= report.dropped, "merge lost more than it dropped");
+                    self.lost += report.dropped as u64;
+                }
+            }
+            Err(CuckooError::CapacityMismatch) => self.check(self.map.growths() > 0, "merge refused an equal map"),
+            Err(e) => self.fail(&format!("merge failed: {}", e)),
+        }
+    }
+
+    /// Exports the map and checks both an imported copy and a view of the
+    /// bytes against the oracle.
+    fn export(&mut self) {
+        let bytes = self.map.export().to_bytes();
+        let imported = match Map::import(&bytes) {
+            Ok(imported) => imported,
+            Err(e) => return self.fail(&format!("import failed: {}", e)),
+        };
+        let view = match CuckooMapView::<DefaultHasher, 4, 2>::new(&bytes) {
+            Ok(view) => view,
+            Err(e) => return self.fail(&format!("view failed: {}", e)),
+        };
+        self.check(imported.debug_validate().is_ok(), "the imported map is invalid");
+        self.check(imported.len() == self.map.len() && view.len() == self.map.len(), "export lost entries");
+        for (key, &value) in &self.oracle {
+            self.check(imported.get(key) == Some(value), "the imported map returned a wrong value");
+            self.check(view.get(key) == Some(value), "the view returned a wrong value");
+        }
+    }
+
+    fn verify(&self) {
+        if let Err(violation) = self.map.debug_validate() {
+            self.fail(violation);
+        }
+        self.check(self.map.len() == self.oracle.len(), "the map holds a different number of entries");
+        for (key, &value) in &self.oracle {
+            self.check(self.map.get(key) == Some(value), "a stored key returned a wrong value");
+        }
+    }
+
+    fn report(&self) {
+        println!(
+            "{} ops, {} entries, capacity {}, {} growths, {} stashed, {} lost to merges, events {:?}",
+            self.ops,
+            self.map.len(),
+            self.map.capacity(),
+            self.map.growths(),
+            self.map.stash_len(),
+            self.lost,
+            self.events
+        );
+    }
+
+    fn check(&self, ok: bool, what: &str) {
+        if !ok {
+            self.fail(what);
+        }
+    }
+
+    fn fail(&self, what: &str) {
+        eprintln!("after {} ops: {}", self.ops, what);
+        eprintln!("reproduce with: cargo run --release --example soak <seconds> {}", self.seed);
+        process::exit(1);
+    }
+}
+
+fn build(capacity: usize, auto_grow: bool) -> Map {
+    let mut map = Map::with_capacity(capacity);
+    map.set_stash_capacity(8);
+    map.set_merge_rule(MergeRule::Overwrite);
+    map.set_auto_grow(auto_grow);
+    map
+}
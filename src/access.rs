@@ -0,0 +1,161 @@
+use crate::bucket::ENTRIES_PER_BUCKET;
+use crate::util::remix_hash;
+
+use std::mem;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+/// One access bit per slot, a byte per bucket, set for a sample of the
+/// lookups that hit the slot.
+///
+/// Bits are only written when a lookup is sampled and the bit isn't set
+/// yet, so reads of hot entries don't keep dirtying the cache line.
+pub(crate) struct AccessTracker {
+    bits: Box<[AtomicU8]>,
+    sample_every: usize,
+    lookups: AtomicUsize,
+}
+
+impl AccessTracker {
+    /// Creates a tracker for `buckets` buckets that records about one in
+    /// `sample_every` lookups.
+    pub fn new(buckets: usize, sample_every: usize) -> Self {
+        Self {
+            bits: (0..buckets).map(|_| AtomicU8::new(0)).collect(),
+            sample_every,
+            lookups: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn sample_every(&self) -> usize {
+        self.sample_every
+    }
+
+    /// Counts a lookup and returns whether it is to be recorded.
+    ///
+    /// The count is scrambled first, so workloads that read the same keys
+    /// in a fixed order don't get the same keys sampled every round.
+    pub fn sample(&self) -> bool {
+        let count = self.lookups.fetch_add(1, Ordering::Relaxed) as u64;
+        remix_hash(count, 0).is_multiple_of(self.sample_every as u64)
+    }
+
+    pub fn mark(&self, bucket: usize, slot: usize) {
+        let bit = 1 << slot;
+        if self.bits[bucket].load(Ordering::Relaxed) & bit == 0 {
+            self.bits[bucket].fetch_or(bit, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_marked(&self, bucket: usize, slot: usize) -> bool {
+        self.bits[bucket].load(Ordering::Relaxed) & (1 << slot) != 0
+    }
+
+    /// Clears the bit of a slot that got a new entry.
+    pub fn unmark(&mut self, bucket: usize, slot: usize) {
+        *self.bits[bucket].get_mut() &= !(1 << slot);
+    }
+
+    pub fn clear(&mut self) {
+        for bits in self.bits.iter_mut() {
+            *bits.get_mut() = 0;
+        }
+    }
+
+    /// Number of bytes the bits occupy.
+    pub fn memory_usage(&self) -> usize {
+        self.bits.len()
+    }
+}
+
+/// A sequence number per slot, counting up with every new value written,
+/// so the copies of a key in several buckets can be told apart by which
+/// was written last.
+///
+/// Moved entries keep their number: every write of a slot takes the number
+/// carried so far and carries on the number of the entry it displaced, so
+/// a chain of kicks passes each entry's number along with it.
+pub(crate) struct WriteOrder {
+    seqs: Box<[u64]>,
+    next: u64,
+    carried: u64,
+}
+
+impl WriteOrder {
+    /// Creates the numbers of `buckets` buckets, all written before
+    /// tracking started.
+    pub fn new(buckets: usize) -> Self {
+        Self {
+            seqs: vec![0; buckets * ENTRIES_PER_BUCKET].into_boxed_slice(),
+            next: 1,
+            carried: 0,
+        }
+    }
+
+    /// Hands a fresh number to the next slot written, for a new value.
+    pub fn begin_write(&mut self) {
+        self.carry(self.next);
+        self.next += 1;
+    }
+
+    /// Hands `seq` to the next slot written, for an entry moved by taking
+    /// it out of its slot first.
+    pub fn carry(&mut self, seq: u64) {
+        self.carried = seq;
+    }
+
+    /// Gives a written slot the carried number, carrying on its old one.
+    pub fn swap(&mut self, bucket: usize, slot: usize) {
+        mem::swap(&mut self.seqs[bucket * ENTRIES_PER_BUCKET + slot], &mut self.carried);
+    }
+
+    /// Gives a slot whose value was overwritten in place a fresh number.
+    pub fn stamp(&mut self, bucket: usize, slot: usize) {
+        self.seqs[bucket * ENTRIES_PER_BUCKET + slot] = self.next;
+        self.next += 1;
+    }
+
+    pub fn get(&self, bucket: usize, slot: usize) -> u64 {
+        self.seqs[bucket * ENTRIES_PER_BUCKET + slot]
+    }
+
+    /// Numbers for `buckets` buckets continuing this order, with every slot
+    /// written before tracking started.
+    pub fn resized(&self, buckets: usize) -> Self {
+        Self {
+            next: self.next,
+            ..Self::new(buckets)
+        }
+    }
+
+    pub fn set(&mut self, bucket: usize, slot: usize, seq: u64) {
+        self.seqs[bucket * ENTRIES_PER_BUCKET + slot] = seq;
+    }
+
+    /// Number of bytes the numbers occupy.
+    pub fn memory_usage(&self) -> usize {
+        self.seqs.len() * mem::size_of::<u64>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampled_marks() {
+        let mut tracker = AccessTracker::new(2, 4);
+        let sampled = (0..10_000).filter(|_| tracker.sample()).count();
+        assert!(sampled > 2_300 && sampled < 2_700, "{} sampled", sampled);
+
+        tracker.mark(1, 2);
+        assert!(tracker.is_marked(1, 2));
+        assert!(!tracker.is_marked(1, 1));
+        assert!(!tracker.is_marked(0, 2));
+
+        tracker.unmark(1, 2);
+        assert!(!tracker.is_marked(1, 2));
+        tracker.mark(0, 0);
+        tracker.clear();
+        assert!(!tracker.is_marked(0, 0));
+    }
+}
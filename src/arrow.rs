@@ -0,0 +1,164 @@
+use crate::{CuckooMap, SnapshotMetadata};
+
+use arrow_array::builder::{FixedSizeBinaryBuilder, UInt64Builder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::io::Write;
+use std::sync::Arc;
+
+impl<H, const FP: usize, const V: usize> CuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    /// Returns a record batch with one `(bucket_index, fingerprint, value)`
+    /// row per stored entry, for analyzing the map with Arrow-based tools.
+    ///
+    /// `bucket_index` is a `UInt64`, the other columns are fixed-size binary
+    /// columns of `FP` and `V` bytes. Stashed entries are listed with the
+    /// bucket they were kicked out of.
+    pub fn to_arrow(&self) -> RecordBatch {
+        let mut buckets = UInt64Builder::with_capacity(self.len());
+        let mut fingerprints = FixedSizeBinaryBuilder::with_capacity(self.len(), FP as i32);
+        let mut values = FixedSizeBinaryBuilder::with_capacity(self.len(), V as i32);
+        for (i, entry) in self.placed_entries() {
+            buckets.append_value(i as u64);
+            fingerprints
+                .append_value(entry.fingerprint.data)
+                .expect("fingerprints are FP bytes wide");
+            values.append_value(entry.value).expect("values are V bytes wide");
+        }
+
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(buckets.finish()),
+            Arc::new(fingerprints.finish()),
+            Arc::new(values.finish()),
+        ];
+        RecordBatch::try_new(self.arrow_schema(), columns).expect("columns match the schema")
+    }
+
+    /// Writes the batch of `to_arrow` to `out` in the Arrow IPC file format,
+    /// which pandas, Polars, DuckDB and Spark read directly.
+    pub fn write_arrow_ipc<W: Write>(&self, out: W) -> Result<(), ArrowError> {
+        let mut writer = FileWriter::try_new(out, &self.arrow_schema())?;
+        writer.write(&self.to_arrow())?;
+        writer.finish()
+    }
+
+    /// The schema of the batch, carrying the map's `SnapshotMetadata`,
+    /// which `SnapshotMetadata::from_arrow` reads back.
+    fn arrow_schema(&self) -> Arc<Schema> {
+        let fields = vec![
+            Field::new("bucket_index", DataType::UInt64, false),
+            Field::new("fingerprint", DataType::FixedSizeBinary(FP as i32), false),
+            Field::new("value", DataType::FixedSizeBinary(V as i32), false),
+        ];
+        let metadata = self.metadata();
+        let mut entries = HashMap::new();
+        entries.insert(ITEMS.to_owned(), self.len().to_string());
+        if let Some(created) = metadata.created_millis() {
+            entries.insert(CREATED.to_owned(), created.to_string());
+        }
+        if let Some(source) = metadata.source() {
+            entries.insert(SOURCE.to_owned(), source.to_owned());
+        }
+        for (key, value) in metadata.tags() {
+            entries.insert(format!("{}{}", TAG_PREFIX, key), value.to_owned());
+        }
+        Arc::new(Schema::new_with_metadata(fields, entries))
+    }
+}
+
+const CREATED: &str = "cuckoomap.created_ms";
+const ITEMS: &str = "cuckoomap.items";
+const SOURCE: &str = "cuckoomap.source";
+const TAG_PREFIX: &str = "cuckoomap.tag.";
+
+impl SnapshotMetadata {
+    /// Reads the metadata a map wrote into the schema of its Arrow export,
+    /// e.g. from `FileReader::schema`. Other schema metadata is ignored.
+    pub fn from_arrow(schema: &Schema) -> Self {
+        let mut metadata = Self::default();
+        for (key, value) in schema.metadata() {
+            match key.as_str() {
+                CREATED => {
+                    if let Ok(millis) = value.parse() {
+                        metadata.set_created_millis(millis);
+                    }
+                }
+                ITEMS => {
+                    if let Ok(items) = value.parse() {
+                        metadata.set_items(items);
+                    }
+                }
+                SOURCE => metadata.set_source(value),
+                _ => {
+                    if let Some(tag) = key.strip_prefix(TAG_PREFIX) {
+                        metadata.insert_tag(tag, value);
+                    }
+                }
+            }
+        }
+        metadata
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::UInt64Type;
+    use arrow_ipc::reader::FileReader;
+    use std::collections::hash_map::DefaultHasher;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_arrow_round_trip() {
+        let mut map = CuckooMap::<DefaultHasher, 2, 3>::with_capacity(1 << 10);
+        for i in 0..100u8 {
+            map.insert_or_update(&i, [i, 0, 1]).unwrap();
+        }
+
+        let batch = map.to_arrow();
+        assert_eq!(batch.num_rows(), map.len());
+        let buckets = batch.column(0).as_primitive::<UInt64Type>();
+        let fingerprints = batch.column(1).as_fixed_size_binary();
+        let values = batch.column(2).as_fixed_size_binary();
+        for row in 0..batch.num_rows() {
+            let bucket = &map.buckets[buckets.value(row) as usize];
+            let fingerprint = [fingerprints.value(row)[0], fingerprints.value(row)[1]];
+            let stored = bucket.occupied().find(|e| e.fingerprint.data == fingerprint).unwrap();
+            assert_eq!(values.value(row), stored.value);
+        }
+
+        let mut file = Vec::new();
+        map.write_arrow_ipc(&mut file).unwrap();
+        let mut reader = FileReader::try_new(Cursor::new(file), None).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap(), batch);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_arrow_metadata() {
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        map.insert_or_update("a", [1]).unwrap();
+        map.metadata_mut().set_source("job-7");
+        map.metadata_mut().insert_tag("region", "eu");
+
+        let mut file = Vec::new();
+        map.write_arrow_ipc(&mut file).unwrap();
+        let reader = FileReader::try_new(Cursor::new(file), None).unwrap();
+        let metadata = SnapshotMetadata::from_arrow(&reader.schema());
+        assert_eq!(metadata.items(), Some(1));
+        assert_eq!(metadata.source(), Some("job-7"));
+        assert_eq!(metadata.tags().collect::<Vec<_>>(), [("region", "eu")]);
+        assert_eq!(metadata.created(), {
+            let mut exported = map.metadata().clone();
+            exported.set_created_millis(exported.created_millis().unwrap());
+            exported.created()
+        });
+    }
+}
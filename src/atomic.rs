@@ -0,0 +1,510 @@
+use crate::bucket::{Fingerprint, ENTRIES_PER_BUCKET, FINGERPRINT_SIZE, VALUE_SIZE};
+use crate::packed::PackedLayout;
+use crate::slots::{self, PathNode, Relocations, SlotAccess, MAX_RELOCATIONS};
+use crate::util::{get_alt_index, get_fai_from_hash, get_hash64};
+use crate::{CuckooError, ProbabilisticMap};
+
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// Longest chain of moves an insert searches for to free a slot.
+const MAX_PATH_DEPTH: u32 = 5;
+
+/// Times a lookup reads the candidate buckets at most, which bounds `get`.
+const MAX_READS: usize = 8;
+
+/// A `CuckooMap` whose slots are atomic words, shared between threads
+/// without locks.
+///
+/// Every slot is one `AtomicU32` in the `PackedLayout` of its fingerprint
+/// and value, so lookups are plain loads and inserts, updates and deletes
+/// compare-and-swap single slots. Keys have the same candidate buckets as
+/// in a `CuckooMap` of the same capacity and hasher.
+///
+/// An insert whose candidate buckets are full searches breadth-first for a
+/// short chain of entries that can move to their alternate buckets and
+/// moves them starting from the end of the chain. Every move copies the
+/// entry into its new slot before clearing the old one, so an entry is
+/// never missing from the table, only briefly present twice.
+///
+/// Every bucket has a version that a move bumps before the entry leaves
+/// the bucket. A lookup that misses reads the candidate buckets again only
+/// if the version of one of them changed meanwhile, and at most 8 times in
+/// all, so `get` is wait-free. Inserts and deletes are lock-free: they
+/// retry when a compare-and-swap fails because another thread changed the
+/// slot, and keep looking for as long as the versions change.
+///
+/// Unlike `CuckooMap` the table neither grows nor has a stash, and two
+/// threads inserting the same key at the same time may both store it,
+/// unless they insert with `test_and_add`.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::AtomicCuckooMap;
+/// use std::collections::hash_map::DefaultHasher;
+/// use std::thread;
+///
+/// let map = AtomicCuckooMap::<DefaultHasher, 2>::with_capacity(1 << 12);
+/// thread::scope(|s| {
+///     for t in 0..4u32 {
+///         let map = &map;
+///         s.spawn(move || {
+///             for i in 0..100 {
+///                 map.insert_or_update(&(t, i), [t as u8]).unwrap();
+///             }
+///         });
+///     }
+/// });
+/// assert_eq!(map.len(), 400);
+/// assert_eq!(map.get(&(3u32, 7)), Some([3]));
+/// ```
+pub struct AtomicCuckooMap<H = DefaultHasher, const FP: usize = FINGERPRINT_SIZE, const V: usize = VALUE_SIZE> {
+    slots: Box<[AtomicU32]>,
+    len: AtomicUsize,
+    /// A version per bucket, bumped whenever an entry moves out of it,
+    /// which tells a lookup that missed whether an entry may have moved
+    /// past it.
+    versions: Box<[AtomicU32]>,
+    _hasher: PhantomData<fn() -> H>,
+}
+
+impl<H, const FP: usize, const V: usize> AtomicCuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    /// Constructs a map with room for `cap` entries, rounded up like
+    /// `CuckooMap::with_capacity`.
+    pub fn with_capacity(cap: usize) -> Self {
+        let buckets = cmp::max(1, (cap / ENTRIES_PER_BUCKET).next_power_of_two());
+        Self {
+            slots: (0..buckets * ENTRIES_PER_BUCKET).map(|_| AtomicU32::new(0)).collect(),
+            len: AtomicUsize::new(0),
+            versions: (0..buckets).map(|_| AtomicU32::new(0)).collect(),
+            _hasher: PhantomData,
+        }
+    }
+
+    /// Checks if `key` is in the map, see `CuckooMap::get`.
+    ///
+    /// Wait-free: the candidate buckets are read at most 8 times. A lookup
+    /// racing with that many moves out of the key's buckets reports the key
+    /// missing, which takes inserts moving entries through both buckets
+    /// without pause during the lookup.
+    pub fn get<T: ?Sized + Hash>(&self, key: &T) -> Option<[u8; V]> {
+        let (fp, buckets) = self.locate(key);
+        self.find_within(fp, buckets, MAX_READS)
+            .map(|(_, word)| PackedLayout::<FP, V>::unpack(word).1)
+    }
+
+    /// Inserts `key` or updates its value.
+    ///
+    /// Fails with `NotEnoughSpace`, carrying the new entry, if no chain of
+    /// at most 5 moves frees a slot in its candidate buckets. Slots taken
+    /// by other threads first make it look again rather than fail. Other
+    /// entries are never lost.
+    pub fn insert_or_update<T: ?Sized + Hash>(&self, key: &T, value: [u8; V]) -> Result<(), CuckooError<FP, V>> {
+        let (fp, buckets) = self.locate(key);
+        let new = PackedLayout::<FP, V>::pack(fp.data, value);
+        loop {
+            if let Some((slot, word)) = self.find(fp, buckets) {
+                if word == new || self.slots[slot].compare_exchange(word, new, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+                    return Ok(());
+                }
+                // updated or moved concurrently, look again
+                continue;
+            }
+
+            for &bucket in &buckets {
+                for slot in self.bucket_slots(bucket) {
+                    if self.slots[slot].compare_exchange(0, new, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+                        self.len.fetch_add(1, Ordering::Relaxed);
+                        return Ok(());
+                    }
+                }
+            }
+
+            if !self.make_room(buckets) {
+                return Err(CuckooError::NotEnoughSpace { fingerprint: fp, value });
+            }
+        }
+    }
+
+    /// Inserts `key` unless it's present, see `CuckooMap::test_and_add`.
+    /// Returns true if `key` was inserted.
+    ///
+    /// Unlike `insert_or_update`, threads adding the same key at the same
+    /// time store it once, and exactly one of them gets `Ok(true)`. A key
+    /// found missing is claimed with a single compare-and-swap of the first
+    /// free slot. If another claim of the key landed in a different slot,
+    /// or an entry was moved while the key was looked up, the claim is
+    /// undone and the whole lookup runs again. Fails with `NotEnoughSpace`
+    /// only once no eviction chain can free a slot in the key's buckets.
+    pub fn test_and_add<T: ?Sized + Hash>(&self, key: &T, value: [u8; V]) -> Result<bool, CuckooError<FP, V>> {
+        let (fp, buckets) = self.locate(key);
+        let new = PackedLayout::<FP, V>::pack(fp.data, value);
+        loop {
+            let versions = self.versions(buckets);
+            if self.find(fp, buckets).is_some() {
+                return Ok(false);
+            }
+            let free = buckets
+                .iter()
+                .flat_map(|&bucket| self.bucket_slots(bucket))
+                .find(|&slot| self.slots[slot].load(Ordering::Acquire) == 0);
+            let slot = match free {
+                Some(slot) => slot,
+                None if self.make_room(buckets) => continue,
+                None => return Err(CuckooError::NotEnoughSpace { fingerprint: fp, value }),
+            };
+            if self.slots[slot].compare_exchange(0, new, Ordering::SeqCst, Ordering::Acquire).is_err() {
+                // taken meanwhile, maybe by the same key
+                continue;
+            }
+            self.len.fetch_add(1, Ordering::Relaxed);
+
+            let duplicated = buckets
+                .iter()
+                .flat_map(|&bucket| self.bucket_slots(bucket))
+                .any(|s| s != slot && self.holds(s, fp));
+            if !duplicated && self.versions(buckets) == versions {
+                return Ok(true);
+            }
+            // a racing claim sees this one too and backs off as well, so at
+            // most one of them returns true
+            if self.slots[slot].compare_exchange(new, 0, Ordering::SeqCst, Ordering::Acquire).is_ok() {
+                self.len.fetch_sub(1, Ordering::Relaxed);
+            } else {
+                // moved meanwhile, remove a copy wherever it is
+                self.remove(fp, buckets);
+            }
+        }
+    }
+
+    /// Deletes `key` from the map. Returns true if `key` existed before.
+    pub fn delete<T: ?Sized + Hash>(&self, key: &T) -> bool {
+        let (fp, buckets) = self.locate(key);
+        self.remove(fp, buckets)
+    }
+
+    /// Number of items in the map.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// Check if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of entries the map has room for.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Number of bytes the map occupies in memory.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of_val(self) + (self.slots.len() + self.versions.len()) * mem::size_of::<AtomicU32>()
+    }
+
+    /// Empties the map.
+    pub fn clear(&mut self) {
+        for slot in self.slots.iter_mut() {
+            *slot.get_mut() = 0;
+        }
+        *self.len.get_mut() = 0;
+    }
+
+    fn buckets(&self) -> usize {
+        self.slots.len() / ENTRIES_PER_BUCKET
+    }
+
+    fn bucket_slots(&self, bucket: usize) -> std::ops::Range<usize> {
+        bucket * ENTRIES_PER_BUCKET..(bucket + 1) * ENTRIES_PER_BUCKET
+    }
+
+    /// Returns the fingerprint of `key` and its candidate buckets.
+    fn locate<T: ?Sized + Hash>(&self, key: &T) -> (Fingerprint<FP>, [usize; 2]) {
+        let fai = get_fai_from_hash::<H, FP>(get_hash64::<T, H>(key));
+        let len = self.buckets();
+        (fai.fp, [fai.i1 % len, fai.i2 % len])
+    }
+
+    /// The other candidate bucket of the entry `word` in `bucket`.
+    fn alternate(&self, word: u32, bucket: usize) -> usize {
+        let data = PackedLayout::<FP, V>::unpack(word).0;
+        get_alt_index::<H, FP>(Fingerprint { data }, bucket) % self.buckets()
+    }
+
+    /// The versions of `buckets`.
+    fn versions(&self, buckets: [usize; 2]) -> [u32; 2] {
+        buckets.map(|bucket| self.versions[bucket].load(Ordering::SeqCst))
+    }
+
+    /// Returns the slot holding `fp` and its word, looking again for as
+    /// long as an entry may have moved from a bucket not yet read to one
+    /// already read.
+    fn find(&self, fp: Fingerprint<FP>, buckets: [usize; 2]) -> Option<(usize, u32)> {
+        self.find_within(fp, buckets, usize::MAX)
+    }
+
+    /// Like `find`, reading the buckets at most `reads` times.
+    fn find_within(&self, fp: Fingerprint<FP>, buckets: [usize; 2], reads: usize) -> Option<(usize, u32)> {
+        for _ in 0..reads {
+            let versions = self.versions(buckets);
+            for &bucket in &buckets {
+                for slot in self.bucket_slots(bucket) {
+                    let word = self.slots[slot].load(Ordering::Acquire);
+                    if !PackedLayout::<FP, V>::is_empty(word) && PackedLayout::<FP, V>::unpack(word).0 == fp.data {
+                        return Some((slot, word));
+                    }
+                }
+            }
+            if self.versions(buckets) == versions {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Whether `slot` holds an entry with fingerprint `fp`.
+    fn holds(&self, slot: usize, fp: Fingerprint<FP>) -> bool {
+        let word = self.slots[slot].load(Ordering::SeqCst);
+        !PackedLayout::<FP, V>::is_empty(word) && PackedLayout::<FP, V>::unpack(word).0 == fp.data
+    }
+
+    /// Clears the slot holding `fp`. Returns true if there was one.
+    fn remove(&self, fp: Fingerprint<FP>, buckets: [usize; 2]) -> bool {
+        while let Some((slot, word)) = self.find(fp, buckets) {
+            if self.slots[slot].compare_exchange(word, 0, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+                self.len.fetch_sub(1, Ordering::Relaxed);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Frees a slot in one of `roots` by moving the entries along the
+    /// shortest chain ending in a free slot. Returns `false` if there is no
+    /// such chain, `true` if a slot was freed or another thread changed the
+    /// table meanwhile, which calls for looking again.
+    fn make_room(&self, roots: [usize; 2]) -> bool {
+        let mut nodes = Vec::new();
+        match slots::shortest_path(self, &roots, MAX_PATH_DEPTH, &mut nodes) {
+            Some((leaf, free)) => {
+                // failing only when other threads changed the chain
+                self.apply_path(&nodes, leaf, free);
+                true
+            }
+            // unless a slot was freed in the meantime
+            None => roots.iter().any(|&bucket| slots::free_slot(self, bucket).is_some()),
+        }
+    }
+
+    /// Moves the entry of node `leaf` into a free slot of `free`, then the
+    /// entry of every node up the chain into the slot its child freed.
+    fn apply_path(&self, nodes: &[PathNode], leaf: usize, free: usize) -> bool {
+        let mut target = free;
+        for (bucket, slot) in slots::chain(nodes, leaf) {
+            if !self.move_entry(bucket, bucket * ENTRIES_PER_BUCKET + slot, target) {
+                return false;
+            }
+            target = bucket;
+        }
+        true
+    }
+
+    /// Moves the entry in `slot` of `bucket` to a free slot of its
+    /// alternate bucket `target`, copying it before clearing the old slot.
+    fn move_entry(&self, bucket: usize, slot: usize, target: usize) -> bool {
+        let word = self.slots[slot].load(Ordering::Acquire);
+        if PackedLayout::<FP, V>::is_empty(word) || self.alternate(word, bucket) != target {
+            return false;
+        }
+        let copy = match self
+            .bucket_slots(target)
+            .find(|&s| self.slots[s].compare_exchange(0, word, Ordering::AcqRel, Ordering::Acquire).is_ok())
+        {
+            Some(copy) => copy,
+            None => return false,
+        };
+
+        // announce the move before the entry leaves its old slot
+        self.versions[bucket].fetch_add(1, Ordering::SeqCst);
+        if self.slots[slot].compare_exchange(word, 0, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            return true;
+        }
+        // the entry was updated or deleted meanwhile, drop the stale copy
+        let _ = self.slots[copy].compare_exchange(word, 0, Ordering::AcqRel, Ordering::Acquire);
+        false
+    }
+}
+
+impl<H, const FP: usize, const V: usize> SlotAccess<FP> for AtomicCuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    fn fingerprint(&self, bucket: usize, slot: usize) -> Fingerprint<FP> {
+        let word = self.slots[bucket * ENTRIES_PER_BUCKET + slot].load(Ordering::Acquire);
+        if PackedLayout::<FP, V>::is_empty(word) {
+            return Fingerprint::empty();
+        }
+        Fingerprint { data: PackedLayout::<FP, V>::unpack(word).0 }
+    }
+
+    fn relocations(&self, fp: Fingerprint<FP>, bucket: usize) -> Relocations {
+        let alt = get_alt_index::<H, FP>(fp, bucket) % self.buckets();
+        let mut buckets = [0; MAX_RELOCATIONS];
+        buckets[..2].copy_from_slice(&[bucket, alt]);
+        (buckets, if alt == bucket { 1 } else { 2 })
+    }
+}
+
+impl<H, const FP: usize, const V: usize> ProbabilisticMap<V, FP> for AtomicCuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    fn get<T: ?Sized + Hash>(&self, key: &T) -> Option<[u8; V]> {
+        AtomicCuckooMap::get(self, key)
+    }
+
+    fn insert_or_update<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; V]) -> Result<(), CuckooError<FP, V>> {
+        AtomicCuckooMap::insert_or_update(self, key, value)
+    }
+
+    fn test_and_add<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; V]) -> Result<bool, CuckooError<FP, V>> {
+        AtomicCuckooMap::test_and_add(self, key, value)
+    }
+
+    fn delete<T: ?Sized + Hash>(&mut self, key: &T) -> bool {
+        AtomicCuckooMap::delete(self, key)
+    }
+
+    fn len(&self) -> usize {
+        AtomicCuckooMap::len(self)
+    }
+
+    fn clear(&mut self) {
+        AtomicCuckooMap::clear(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CuckooMap;
+
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    #[test]
+    fn test_no_false_negatives_under_concurrent_inserts() {
+        let map = AtomicCuckooMap::<DefaultHasher, 3, 1>::with_capacity(1 << 14);
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            // a reader watching keys inserted before the writers start
+            for i in 0..1_000u32 {
+                map.insert_or_update(&i, [0]).unwrap();
+            }
+            s.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    assert!((0..1_000u32).all(|i| map.get(&i) == Some([0])));
+                }
+            });
+
+            let writers: Vec<_> = (1..5u32)
+                .map(|t| {
+                    let map = &map;
+                    s.spawn(move || {
+                        for i in 0..3_500u32 {
+                            let key = t * 10_000 + i;
+                            map.insert_or_update(&key, [t as u8]).unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for writer in writers {
+                writer.join().unwrap();
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+
+        // 90% full
+        assert_eq!(map.len(), 15_000);
+        for t in 1..5u32 {
+            assert!((0..3_500u32).all(|i| map.get(&(t * 10_000 + i)) == Some([t as u8])));
+        }
+        let stored = map.slots.iter().filter(|s| s.load(Ordering::Relaxed) != 0).count();
+        assert_eq!(stored, map.len());
+
+        assert!(map.delete(&7u32));
+        assert!(!map.delete(&7u32));
+        assert_eq!(map.get(&7u32), None);
+        map.insert_or_update(&8u32, [2]).unwrap();
+        assert_eq!(map.get(&8u32), Some([2]));
+        assert_eq!(map.len(), 14_999);
+    }
+
+    #[test]
+    fn test_candidates_match_cuckoo_map() {
+        let atomic = AtomicCuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        for i in 0..100u32 {
+            atomic.insert_or_update(&i, [1]).unwrap();
+            map.insert_or_update(&i, [1]).unwrap();
+        }
+        assert_eq!(atomic.capacity(), map.capacity());
+        for (i, entry) in map.placed_entries() {
+            let bucket = atomic.bucket_slots(i).map(|s| atomic.slots[s].load(Ordering::Relaxed));
+            assert!(bucket.map(|w| PackedLayout::<1, 1>::unpack(w).0).any(|fp| fp == entry.fingerprint.data));
+        }
+    }
+
+    #[test]
+    fn test_and_add_claims_keys_once() {
+        let map = AtomicCuckooMap::<DefaultHasher, 3, 1>::with_capacity(1 << 12);
+        let added: Vec<AtomicUsize> = (0..3_500).map(|_| AtomicUsize::new(0)).collect();
+        thread::scope(|s| {
+            for t in 0..4u8 {
+                let (map, added) = (&map, &added);
+                s.spawn(move || {
+                    for i in 0..3_500u32 {
+                        // threads walk the keys in different orders
+                        let key = if t % 2 == 0 { i } else { 3_499 - i };
+                        if map.test_and_add(&key, [t]).unwrap() {
+                            added[key as usize].fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+
+        assert!(added.iter().all(|n| n.load(Ordering::Relaxed) == 1));
+        assert_eq!(map.len(), 3_500);
+        let stored = map.slots.iter().filter(|s| s.load(Ordering::Relaxed) != 0).count();
+        assert_eq!(stored, 3_500);
+        assert!((0..3_500u32).all(|i| map.get(&i).is_some()));
+    }
+
+    #[test]
+    fn test_full_table() {
+        let map = AtomicCuckooMap::<DefaultHasher, 3, 1>::with_capacity(1 << 10);
+        let mut i = 0u32;
+        while map.insert_or_update(&i, [1]).is_ok() {
+            i += 1;
+        }
+        // no chain of moves was left, so the failure is real
+        assert!(map.len() as f64 / map.capacity() as f64 > 0.9, "{} stored", map.len());
+        assert_eq!(map.len(), i as usize);
+        assert!((0..i).all(|k| map.get(&k) == Some([1])));
+        assert!(matches!(map.insert_or_update(&i, [1]), Err(CuckooError::NotEnoughSpace { .. })));
+        assert_eq!(map.get(&i), None);
+        assert!(!map.test_and_add(&0u32, [2]).unwrap());
+        assert!(matches!(map.test_and_add(&i, [1]), Err(CuckooError::NotEnoughSpace { .. })));
+        assert_eq!(map.len(), i as usize);
+    }
+}
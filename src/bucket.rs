@@ -1,19 +1,41 @@
+#[cfg(all(feature = "mmap", unix))]
+use crate::mmap::MmapMut;
+
+use std::alloc::{self, Layout};
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::{mem, ptr};
+
+/// The default fingerprint size in bytes.
 pub const FINGERPRINT_SIZE: usize = 1;
-// we define fingerprint 0 as empty
-const EMPTY_FINGERPRINT: [u8; FINGERPRINT_SIZE] = [0; FINGERPRINT_SIZE];
+/// The largest supported fingerprint size, limited by the 32 hash bits
+/// fingerprints are taken from.
+pub const MAX_FINGERPRINT_SIZE: usize = 4;
+/// The default value size in bytes.
 pub const VALUE_SIZE: usize = 1;
+pub const ENTRIES_PER_BUCKET: usize = 4;
+/// The size in bytes of the cache lines bucket arrays are aligned to.
+pub const CACHE_LINE: usize = 64;
 
-// Fingerprint Size is 1 byte so lets remove the Vec
-#[derive(PartialEq, Copy, Clone, Hash)]
-pub struct Fingerprint {
-    pub data: [u8; FINGERPRINT_SIZE],
+/// The fingerprint of a key, `FP` bytes wide. All zeros is the empty
+/// fingerprint, marking an empty slot.
+#[derive(PartialEq, Eq, Copy, Clone, Hash, Debug)]
+#[repr(C)]
+pub struct Fingerprint<const FP: usize = FINGERPRINT_SIZE> {
+    pub data: [u8; FP],
 }
 
-impl Fingerprint {
+impl<const FP: usize> Fingerprint<FP> {
+    const SUPPORTED: () = assert!(
+        FP >= 1 && FP <= MAX_FINGERPRINT_SIZE,
+        "fingerprints must be 1 to 4 bytes wide"
+    );
+
     /// Attempts to create a new Fingerprint based on the given
     /// number. If the created Fingerprint would be equal to the
     /// empty Fingerprint, None is returned.
-    pub fn from_data(data: [u8; FINGERPRINT_SIZE]) -> Option<Self> {
+    pub fn from_data(data: [u8; FP]) -> Option<Self> {
+        let () = Self::SUPPORTED;
         let result = Self { data };
         if result.is_empty() {
             None
@@ -24,63 +46,437 @@ impl Fingerprint {
 
     /// Returns the empty Fingerprint.
     pub fn empty() -> Self {
-        Self {
-            data: EMPTY_FINGERPRINT,
-        }
+        let () = Self::SUPPORTED;
+        Self { data: [0; FP] }
     }
 
     /// Checks if this is the empty Fingerprint.
     pub fn is_empty(&self) -> bool {
-        self.data == EMPTY_FINGERPRINT
+        self.data == [0; FP]
     }
 }
 
 
+/// A single `(Fingerprint, value)` pair stored in one slot of a bucket.
 #[derive(Clone, Copy)]
-pub struct Bucket {
-    pub fingerprint: Fingerprint,
-    pub value: [u8; VALUE_SIZE]
+#[repr(C)]
+pub struct Entry<const FP: usize, const V: usize> {
+    pub fingerprint: Fingerprint<FP>,
+    pub value: [u8; V]
 }
 
-impl Bucket {
-    /// Creates a new bucket with a pre-allocated buffer.
+impl<const FP: usize, const V: usize> Entry<FP, V> {
+    const SUPPORTED: () = assert!(V >= 1, "values must be at least 1 byte wide");
+
+    /// Creates an empty entry.
     pub fn new() -> Self {
+        let () = Self::SUPPORTED;
         Self {
             fingerprint: Fingerprint::empty(),
-            value: [0; VALUE_SIZE] // just initalize with anything
+            value: [0; V] // just initalize with anything
+        }
+    }
+}
+
+/// The slots of a bucket, with the fingerprints of all slots stored
+/// before their values, so finding a fingerprint reads only the first
+/// `FP * ENTRIES_PER_BUCKET` bytes.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Bucket<const FP: usize, const V: usize> {
+    pub fingerprints: [Fingerprint<FP>; ENTRIES_PER_BUCKET],
+    pub values: [[u8; V]; ENTRIES_PER_BUCKET],
+}
+
+impl<const FP: usize, const V: usize> Bucket<FP, V> {
+    /// Creates a new bucket with a pre-allocated buffer. Bucket arrays
+    /// are zeroed instead, which is the same.
+    #[cfg(test)]
+    pub fn new() -> Self {
+        let () = Entry::<FP, V>::SUPPORTED;
+        Self {
+            fingerprints: [Fingerprint::empty(); ENTRIES_PER_BUCKET],
+            values: [[0; V]; ENTRIES_PER_BUCKET], // just initalize with anything
+        }
+    }
+
+    /// Returns the slot holding the given fingerprint, if any.
+    /// This operation is O(1).
+    pub fn find(&self, fingerprint: Fingerprint<FP>) -> Option<usize> {
+        match self.matches(fingerprint) {
+            0 => None,
+            slots => Some(slots.trailing_zeros() as usize),
+        }
+    }
+
+    /// Returns a mask with bit `slot` set for every slot holding the given
+    /// fingerprint, comparing all slots at once where SIMD is available.
+    #[inline]
+    pub fn matches(&self, fingerprint: Fingerprint<FP>) -> u8 {
+        let mut haystack = [0; 16];
+        let mut needle = [0; 16];
+        // SAFETY: fingerprints are `repr(C)` byte arrays, so the array of
+        // them has no padding and spans at most 16 initialized bytes.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                self.fingerprints.as_ptr() as *const u8,
+                haystack.as_mut_ptr(),
+                mem::size_of::<[Fingerprint<FP>; ENTRIES_PER_BUCKET]>(),
+            );
+        }
+        for slot in 0..ENTRIES_PER_BUCKET {
+            needle[slot * FP..(slot + 1) * FP].copy_from_slice(&fingerprint.data);
+        }
+        let bytes = simd::eq_mask(&haystack, &needle);
+
+        // a slot matches if all of its fingerprint bytes do
+        let all = (1 << FP) - 1;
+        let mut slots = 0;
+        for slot in 0..ENTRIES_PER_BUCKET {
+            if (bytes >> (slot * FP)) & all == all {
+                slots |= 1 << slot;
+            }
         }
+        slots
     }
 
-    /// Sets the fingerprint of the `Bucket` if not already augmented
-    /// OR the fingerprint is the same.
+    /// Returns the value stored for the given fingerprint, if any.
     /// This operation is O(1).
-    pub fn set(&mut self, fingerprint: Fingerprint, value: [u8; VALUE_SIZE]) -> bool {
-    
-        if self.fingerprint.is_empty() {
-            self.fingerprint = fingerprint;
-            self.value = value;
-            return true;
-        } else if self.fingerprint == fingerprint {
-            self.value = value;
-            return true;
+    pub fn get(&self, fingerprint: Fingerprint<FP>) -> Option<[u8; V]> {
+        self.find(fingerprint).map(|slot| self.values[slot])
+    }
+
+    /// Returns the entry in the given slot, which may be empty.
+    pub fn entry(&self, slot: usize) -> Entry<FP, V> {
+        Entry {
+            fingerprint: self.fingerprints[slot],
+            value: self.values[slot],
         }
+    }
+
+    /// Overwrites the given slot, returning the entry it held before.
+    pub fn replace(&mut self, slot: usize, entry: Entry<FP, V>) -> Entry<FP, V> {
+        let old = self.entry(slot);
+        self.fingerprints[slot] = entry.fingerprint;
+        self.values[slot] = entry.value;
+        old
+    }
+
+    /// Stores the entry in the first empty slot, returning the slot or `None`
+    /// if the bucket is full. This operation is O(1).
+    pub fn set(&mut self, fingerprint: Fingerprint<FP>, value: [u8; V]) -> Option<usize> {
+        let slot = self.find(Fingerprint::empty())?;
+        self.replace(slot, Entry { fingerprint, value });
+        Some(slot)
+    }
 
-        false
+    /// Overwrites the value of the entry holding the given fingerprint,
+    /// returning its slot. This operation is O(1).
+    pub fn update(&mut self, fingerprint: Fingerprint<FP>, value: [u8; V]) -> Option<usize> {
+        let slot = self.find(fingerprint)?;
+        self.values[slot] = value;
+        Some(slot)
     }
 
-    /// Deletes the given fingerprint from the bucket. This operation is O(1).
-    pub fn reset(&mut self, fingerprint: Fingerprint) -> bool {
+    /// Deletes the given fingerprint from the bucket, returning the slot it
+    /// was stored in. This operation is O(1).
+    pub fn reset(&mut self, fingerprint: Fingerprint<FP>) -> Option<usize> {
+        let slot = self.find(fingerprint)?;
+        self.fingerprints[slot] = Fingerprint::empty();
+        // no need to invalidate data
+        Some(slot)
+    }
+
+    /// Iterates over the entries of all slots, including empty ones.
+    pub fn entries(&self) -> impl Iterator<Item = Entry<FP, V>> + '_ {
+        (0..ENTRIES_PER_BUCKET).map(move |slot| self.entry(slot))
+    }
+
+    /// Iterates over the occupied slots.
+    pub fn occupied(&self) -> impl Iterator<Item = Entry<FP, V>> + '_ {
+        self.entries().filter(|e| !e.fingerprint.is_empty())
+    }
+}
 
-        if self.fingerprint == fingerprint {
-            self.fingerprint = Fingerprint::empty();
-            // no need to invalidate data
-            true
+impl<const FP: usize, const V: usize> Bucket<FP, V> {
+    /// The number of buckets in an aligned group: the most that fit into a
+    /// cache line, rounded down to a power of two, but at least 2.
+    ///
+    /// If the size of a bucket divides `CACHE_LINE`, every group is exactly
+    /// one cache line, otherwise it spans two at most.
+    pub const GROUP: usize = {
+        let fitting = CACHE_LINE / mem::size_of::<Self>();
+        if fitting < 2 {
+            2
         } else {
-            false
+            1 << (usize::BITS - 1 - fitting.leading_zeros())
         }
+    };
+}
+
+/// A fixed number of buckets starting at a cache line boundary, so buckets
+/// whose size divides `CACHE_LINE` never straddle two cache lines.
+pub struct BucketArray<const FP: usize, const V: usize> {
+    ptr: NonNull<Bucket<FP, V>>,
+    len: usize,
+    /// The start of the allocation, up to `CACHE_LINE - 1` bytes before
+    /// the first bucket.
+    allocation: NonNull<u8>,
+    /// The file mapping holding the buckets instead of an allocation.
+    #[cfg(all(feature = "mmap", unix))]
+    mapping: Option<MmapMut>,
+}
+
+// SAFETY: the array owns its buckets, which are plain bytes.
+unsafe impl<const FP: usize, const V: usize> Send for BucketArray<FP, V> {}
+unsafe impl<const FP: usize, const V: usize> Sync for BucketArray<FP, V> {}
+
+impl<const FP: usize, const V: usize> BucketArray<FP, V> {
+    /// Allocates `len` empty buckets.
+    ///
+    /// An empty bucket is all zeros, so the memory is requested zeroed,
+    /// which the allocator can get from the OS without writing it. It
+    /// only does so for small alignments, so the buckets are aligned
+    /// within an allocation a cache line larger instead.
+    pub fn new(len: usize) -> Self {
+        let () = Fingerprint::<FP>::SUPPORTED;
+        let () = Entry::<FP, V>::SUPPORTED;
+        let Some(layout) = Self::layout(len) else {
+            return Self::default();
+        };
+        // SAFETY: the layout has a non-zero size.
+        let allocation = unsafe { alloc::alloc_zeroed(layout) };
+        let Some(allocation) = NonNull::new(allocation) else {
+            alloc::handle_alloc_error(layout);
+        };
+        let offset = allocation.as_ptr().align_offset(CACHE_LINE);
+        assert!(offset < CACHE_LINE, "the allocation can't be aligned");
+        // SAFETY: the offset is within the extra cache line, and zeroed
+        // bytes are valid empty buckets.
+        let ptr = unsafe { NonNull::new_unchecked(allocation.as_ptr().add(offset) as *mut Bucket<FP, V>) };
+        Self {
+            ptr,
+            len,
+            allocation,
+            #[cfg(all(feature = "mmap", unix))]
+            mapping: None,
+        }
+    }
+
+    /// Takes the `len` buckets starting `offset` bytes into a file mapping.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buckets don't fit into the mapping or the offset isn't
+    /// a multiple of `CACHE_LINE`.
+    #[cfg(all(feature = "mmap", unix))]
+    pub fn from_mapping(mapping: MmapMut, offset: usize, len: usize) -> Self {
+        let () = Fingerprint::<FP>::SUPPORTED;
+        let () = Entry::<FP, V>::SUPPORTED;
+        let size = len.checked_mul(mem::size_of::<Bucket<FP, V>>()).expect("capacity overflow");
+        assert!(len > 0 && offset.is_multiple_of(CACHE_LINE), "the buckets must be aligned to a cache line");
+        assert!(offset.checked_add(size).is_some_and(|end| end <= mapping.len()), "the buckets exceed the mapping");
+        // SAFETY: the buckets are within the mapping, which is page aligned,
+        // and any bytes are valid buckets.
+        let ptr = unsafe { NonNull::new_unchecked(mapping.as_ptr().add(offset) as *mut Bucket<FP, V>) };
+        Self {
+            ptr,
+            len,
+            allocation: NonNull::dangling(),
+            mapping: Some(mapping),
+        }
+    }
+
+    /// The file mapping holding the buckets, if any.
+    #[cfg(all(feature = "mmap", unix))]
+    pub fn mapping_mut(&mut self) -> Option<&mut MmapMut> {
+        self.mapping.as_mut()
+    }
+
+    /// Whether the buckets live in a file mapping rather than on the heap.
+    pub fn is_mapped(&self) -> bool {
+        #[cfg(all(feature = "mmap", unix))]
+        let mapped = self.mapping.is_some();
+        #[cfg(not(all(feature = "mmap", unix)))]
+        let mapped = false;
+        mapped
     }
 
+    /// Empties all buckets at once.
     pub fn clear(&mut self) {
-        self.fingerprint = Fingerprint::empty()
+        // SAFETY: the pointer covers `len` buckets, and zeroed bytes are
+        // valid empty buckets.
+        unsafe { ptr::write_bytes(self.ptr.as_ptr(), 0, self.len) };
+    }
+
+    /// The layout of the allocation holding `len` buckets, `None` if there
+    /// are none.
+    fn layout(len: usize) -> Option<Layout> {
+        if len == 0 {
+            return None;
+        }
+        let size = len
+            .checked_mul(mem::size_of::<Bucket<FP, V>>())
+            .and_then(|size| size.checked_add(CACHE_LINE))
+            .expect("capacity overflow");
+        Some(Layout::from_size_align(size, mem::align_of::<Bucket<FP, V>>()).expect("capacity overflow"))
+    }
+}
+
+impl<const FP: usize, const V: usize> Default for BucketArray<FP, V> {
+    fn default() -> Self {
+        Self {
+            ptr: NonNull::dangling(),
+            len: 0,
+            allocation: NonNull::dangling(),
+            #[cfg(all(feature = "mmap", unix))]
+            mapping: None,
+        }
+    }
+}
+
+impl<const FP: usize, const V: usize> Deref for BucketArray<FP, V> {
+    type Target = [Bucket<FP, V>];
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: the pointer is either dangling for an empty slice or
+        // points to `len` initialized buckets.
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<const FP: usize, const V: usize> DerefMut for BucketArray<FP, V> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: as for `deref`, and the array is borrowed mutably.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<const FP: usize, const V: usize> Drop for BucketArray<FP, V> {
+    fn drop(&mut self) {
+        // a mapping unmaps itself
+        if self.is_mapped() {
+            return;
+        }
+        if let Some(layout) = Self::layout(self.len) {
+            // SAFETY: the buckets were allocated with this layout, and
+            // don't need to be dropped.
+            unsafe { alloc::dealloc(self.allocation.as_ptr(), layout) };
+        }
+    }
+}
+
+/// Byte-wise comparison of two 16 byte blocks in one instruction.
+mod simd {
+    /// Returns a mask with bit `i` set if `a[i] == b[i]`.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    pub fn eq_mask(a: &[u8; 16], b: &[u8; 16]) -> u16 {
+        use std::arch::x86_64::{__m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8};
+        // SAFETY: SSE2 is part of the x86_64 baseline, and both loads read
+        // 16 bytes from 16 byte arrays without alignment requirements.
+        unsafe {
+            let a = _mm_loadu_si128(a.as_ptr() as *const __m128i);
+            let b = _mm_loadu_si128(b.as_ptr() as *const __m128i);
+            _mm_movemask_epi8(_mm_cmpeq_epi8(a, b)) as u16
+        }
+    }
+
+    /// Returns a mask with bit `i` set if `a[i] == b[i]`.
+    #[cfg(target_arch = "aarch64")]
+    pub fn eq_mask(a: &[u8; 16], b: &[u8; 16]) -> u16 {
+        use std::arch::aarch64::{vaddv_u8, vandq_u8, vceqq_u8, vget_high_u8, vget_low_u8, vld1q_u8};
+        const BITS: [u8; 16] = [1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4, 8, 16, 32, 64, 128];
+        // SAFETY: NEON is part of the aarch64 baseline, and all loads read
+        // 16 bytes from 16 byte arrays.
+        unsafe {
+            // NEON has no movemask, so every lane keeps its own bit and
+            // each half is summed up into a byte
+            let eq = vceqq_u8(vld1q_u8(a.as_ptr()), vld1q_u8(b.as_ptr()));
+            let bits = vandq_u8(eq, vld1q_u8(BITS.as_ptr()));
+            u16::from(vaddv_u8(vget_low_u8(bits))) | u16::from(vaddv_u8(vget_high_u8(bits))) << 8
+        }
+    }
+
+    /// Returns a mask with bit `i` set if `a[i] == b[i]`.
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn eq_mask(a: &[u8; 16], b: &[u8; 16]) -> u16 {
+        (0..16).filter(|&i| a[i] == b[i]).fold(0, |mask, i| mask | 1 << i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let mut bucket = Bucket::<2, 1>::new();
+        let fp = |data| Fingerprint::from_data(data).unwrap();
+        bucket.set(fp([1, 2]), [0]);
+        bucket.set(fp([2, 1]), [0]);
+        bucket.set(fp([1, 2]), [0]);
+        assert_eq!(bucket.matches(fp([1, 2])), 0b0101);
+        assert_eq!(bucket.matches(fp([1, 1])), 0);
+        assert_eq!(bucket.matches(Fingerprint::empty()), 0b1000);
+        assert_eq!(bucket.find(fp([2, 1])), Some(1));
+
+        let mut bucket = Bucket::<4, 4>::new();
+        bucket.set(Fingerprint::from_data([1, 2, 3, 4]).unwrap(), [1, 2, 3, 4]);
+        bucket.set(Fingerprint::from_data([4, 3, 2, 1]).unwrap(), [4, 3, 2, 1]);
+        assert_eq!(bucket.matches(Fingerprint::from_data([4, 3, 2, 1]).unwrap()), 0b10);
+        assert_eq!(bucket.matches(Fingerprint::from_data([1, 2, 3, 3]).unwrap()), 0);
+
+        let mut a = [0; 16];
+        a[3] = 7;
+        a[15] = 9;
+        let mut b = a;
+        b[15] = 8;
+        assert_eq!(simd::eq_mask(&a, &b), 0x7fff);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_fingerprints_before_values() {
+        let mut bucket = Bucket::<2, 3>::new();
+        let fp = Fingerprint::from_data([1, 2]).unwrap();
+        bucket.set(fp, [3, 4, 5]);
+        let old = bucket.replace(2, Entry { fingerprint: fp, value: [6, 7, 8] });
+        assert!(old.fingerprint.is_empty());
+
+        // SAFETY: buckets are `repr(C)` arrays of byte arrays
+        let bytes: [u8; 20] = unsafe { mem::transmute(bucket) };
+        assert_eq!(bytes[..8], [1, 2, 0, 0, 1, 2, 0, 0]);
+        assert_eq!(bytes[8..], [3, 4, 5, 0, 0, 0, 6, 7, 8, 0, 0, 0]);
+        assert_eq!(bucket.occupied().map(|e| e.value).collect::<Vec<_>>(), [[3, 4, 5], [6, 7, 8]]);
+    }
+
+    #[test]
+    fn test_bucket_array_alignment() {
+        for len in [1, 3, 1 << 10] {
+            let buckets = BucketArray::<1, 1>::new(len);
+            assert_eq!(buckets.len(), len);
+            assert_eq!(buckets.as_ptr() as usize % CACHE_LINE, 0);
+            assert!(buckets.iter().all(|b| b.occupied().next().is_none()));
+        }
+        assert!(BucketArray::<4, 8>::new(0).is_empty());
+
+        // SAFETY: buckets are `repr(C)` arrays of byte arrays
+        let empty: [u8; 48] = unsafe { mem::transmute(Bucket::<4, 8>::new()) };
+        assert_eq!(empty, [0; 48]);
+
+        assert_eq!(Bucket::<1, 1>::GROUP, 8);
+        assert_eq!(Bucket::<2, 2>::GROUP, 4);
+        assert_eq!(Bucket::<2, 1>::GROUP, 4);
+        assert_eq!(Bucket::<4, 8>::GROUP, 2);
+    }
+
+    #[test]
+    fn test_bucket_array_clear() {
+        let mut buckets = BucketArray::<2, 2>::new(64);
+        for (i, bucket) in buckets.iter_mut().enumerate() {
+            bucket.set(Fingerprint::from_data([i as u8 + 1, 1]).unwrap(), [1, 2]);
+        }
+        buckets.clear();
+        assert!(buckets.iter().all(|b| b.occupied().next().is_none()));
+    }
+}
@@ -0,0 +1,264 @@
+use crate::bucket::{FINGERPRINT_SIZE, VALUE_SIZE};
+use crate::{CuckooMap, IndexScheme, KickPolicy, PathSearch, DEFAULT_CAPACITY, MAX_REBUCKET};
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::marker::PhantomData;
+
+/// Configures a `CuckooMap` before constructing it, as returned by
+/// `CuckooMap::builder`.
+///
+/// Every option defaults to the value a map has after `with_capacity`.
+/// Invalid combinations panic in `build` with the message of the setter
+/// they correspond to.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::CuckooMap;
+///
+/// let mut map = CuckooMap::builder()
+///     .capacity(1 << 12)
+///     .max_kicks(100)
+///     .seed(42)
+///     .hasher::<fnv::FnvHasher>()
+///     .value_size::<4>()
+///     .build();
+///
+/// map.insert_or_update("hello", [1, 2, 3, 4]).unwrap();
+/// assert_eq!(map.get("hello"), Some([1, 2, 3, 4]));
+/// assert_eq!(map.max_kicks(), 100);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct CuckooMapBuilder<H = DefaultHasher, const FP: usize = FINGERPRINT_SIZE, const V: usize = VALUE_SIZE> {
+    capacity: usize,
+    exact_capacity: bool,
+    max_kicks: u32,
+    seed: Option<u64>,
+    hash_seed: Option<u64>,
+    random_hash_seed: bool,
+    ways: usize,
+    index_scheme: Option<IndexScheme>,
+    auto_grow: bool,
+    stash_capacity: Option<usize>,
+    memory_cap: Option<usize>,
+    hot_cache: usize,
+    access_sampling: usize,
+    recency_window: usize,
+    kick_policy: KickPolicy,
+    path_search: PathSearch,
+    _hasher: PhantomData<fn() -> H>,
+}
+
+impl Default for CuckooMapBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CuckooMapBuilder {
+    /// Starts with the defaults of `CuckooMap::new`.
+    pub fn new() -> Self {
+        Self {
+            capacity: DEFAULT_CAPACITY,
+            exact_capacity: false,
+            max_kicks: MAX_REBUCKET,
+            seed: None,
+            hash_seed: None,
+            random_hash_seed: false,
+            ways: 2,
+            index_scheme: None,
+            auto_grow: false,
+            stash_capacity: None,
+            memory_cap: None,
+            hot_cache: 0,
+            access_sampling: 0,
+            recency_window: 0,
+            kick_policy: KickPolicy::default(),
+            path_search: PathSearch::default(),
+            _hasher: PhantomData,
+        }
+    }
+}
+
+impl<H, const FP: usize, const V: usize> CuckooMapBuilder<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    /// Sets the number of entries the map holds, see `CuckooMap::with_capacity`.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self.exact_capacity = false;
+        self
+    }
+
+    /// Sets the number of entries the map holds without rounding it up to a
+    /// power of two, see `CuckooMap::with_exact_capacity`.
+    pub fn exact_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self.exact_capacity = true;
+        self
+    }
+
+    /// See `CuckooMap::set_max_kicks`.
+    pub fn max_kicks(mut self, max_kicks: u32) -> Self {
+        self.max_kicks = max_kicks;
+        self
+    }
+
+    /// Seeds the eviction RNG, see `CuckooMap::seed_eviction_rng`.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// See `CuckooMap::set_hash_seed`.
+    pub fn hash_seed(mut self, seed: u64) -> Self {
+        self.hash_seed = Some(seed);
+        self.random_hash_seed = false;
+        self
+    }
+
+    /// Seeds the hash of every key randomly, see
+    /// `CuckooMap::randomize_hash_seed`. The seed is available from
+    /// `CuckooMap::hash_seed` afterwards.
+    pub fn random_hash_seed(mut self) -> Self {
+        self.hash_seed = None;
+        self.random_hash_seed = true;
+        self
+    }
+
+    /// See `CuckooMap::set_ways`.
+    pub fn ways(mut self, ways: usize) -> Self {
+        self.ways = ways;
+        self
+    }
+
+    /// See `CuckooMap::set_index_scheme`.
+    pub fn index_scheme(mut self, index_scheme: IndexScheme) -> Self {
+        self.index_scheme = Some(index_scheme);
+        self
+    }
+
+    /// See `CuckooMap::set_auto_grow`.
+    pub fn auto_grow(mut self, auto_grow: bool) -> Self {
+        self.auto_grow = auto_grow;
+        self
+    }
+
+    /// See `CuckooMap::set_stash_capacity`.
+    pub fn stash_capacity(mut self, capacity: usize) -> Self {
+        self.stash_capacity = Some(capacity);
+        self
+    }
+
+    /// See `CuckooMap::set_memory_cap`.
+    pub fn memory_cap(mut self, bytes: usize) -> Self {
+        self.memory_cap = Some(bytes);
+        self
+    }
+
+    /// See `CuckooMap::set_hot_cache`.
+    pub fn hot_cache(mut self, capacity: usize) -> Self {
+        self.hot_cache = capacity;
+        self
+    }
+
+    /// See `CuckooMap::set_access_sampling`.
+    pub fn access_sampling(mut self, sample_every: usize) -> Self {
+        self.access_sampling = sample_every;
+        self
+    }
+
+    /// See `CuckooMap::set_recency_window`.
+    pub fn recency_window(mut self, window: usize) -> Self {
+        self.recency_window = window;
+        self
+    }
+
+    /// See `CuckooMap::set_kick_policy`.
+    pub fn kick_policy(mut self, policy: KickPolicy) -> Self {
+        self.kick_policy = policy;
+        self
+    }
+
+    /// See `CuckooMap::set_path_search`.
+    pub fn path_search(mut self, search: PathSearch) -> Self {
+        self.path_search = search;
+        self
+    }
+
+    /// Switches to the hasher `H2`.
+    pub fn hasher<H2: Hasher + Default>(self) -> CuckooMapBuilder<H2, FP, V> {
+        self.retype()
+    }
+
+    /// Switches to fingerprints of `FP2` bytes.
+    pub fn fingerprint_size<const FP2: usize>(self) -> CuckooMapBuilder<H, FP2, V> {
+        self.retype()
+    }
+
+    /// Switches to values of `V2` bytes.
+    pub fn value_size<const V2: usize>(self) -> CuckooMapBuilder<H, FP, V2> {
+        self.retype()
+    }
+
+    /// Constructs the configured map.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the options are invalid, e.g. auto-growth with more than 2 ways.
+    pub fn build(self) -> CuckooMap<H, FP, V> {
+        let mut map = if self.exact_capacity {
+            CuckooMap::with_exact_capacity(self.capacity)
+        } else {
+            CuckooMap::with_capacity(self.capacity)
+        };
+        map.set_max_kicks(self.max_kicks);
+        if let Some(seed) = self.seed {
+            map.seed_eviction_rng(seed);
+        }
+        if let Some(seed) = self.hash_seed {
+            map.set_hash_seed(seed);
+        } else if self.random_hash_seed {
+            map.randomize_hash_seed();
+        }
+        map.set_ways(self.ways);
+        if let Some(index_scheme) = self.index_scheme {
+            map.set_index_scheme(index_scheme);
+        }
+        map.set_auto_grow(self.auto_grow);
+        if let Some(capacity) = self.stash_capacity {
+            map.set_stash_capacity(capacity);
+        }
+        map.set_memory_cap(self.memory_cap);
+        map.set_hot_cache(self.hot_cache);
+        map.set_access_sampling(self.access_sampling);
+        map.set_recency_window(self.recency_window);
+        map.set_kick_policy(self.kick_policy);
+        map.set_path_search(self.path_search);
+        map
+    }
+
+    fn retype<H2, const FP2: usize, const V2: usize>(self) -> CuckooMapBuilder<H2, FP2, V2> {
+        CuckooMapBuilder {
+            capacity: self.capacity,
+            exact_capacity: self.exact_capacity,
+            max_kicks: self.max_kicks,
+            seed: self.seed,
+            hash_seed: self.hash_seed,
+            random_hash_seed: self.random_hash_seed,
+            ways: self.ways,
+            index_scheme: self.index_scheme,
+            auto_grow: self.auto_grow,
+            stash_capacity: self.stash_capacity,
+            memory_cap: self.memory_cap,
+            hot_cache: self.hot_cache,
+            access_sampling: self.access_sampling,
+            recency_window: self.recency_window,
+            kick_policy: self.kick_policy,
+            path_search: self.path_search,
+            _hasher: PhantomData,
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// The time source of TTLs and rate limits, see `CuckooMap::set_clock`.
+///
+/// Time is the `Duration` since an origin of the clock's choosing, which
+/// only has to stay fixed and never go backwards, e.g. the ticks of a
+/// hardware timer. Closures returning a `Duration` are clocks as well.
+pub trait Clock {
+    /// The time elapsed since the clock's origin.
+    fn now(&self) -> Duration;
+}
+
+impl<F: Fn() -> Duration> Clock for F {
+    fn now(&self) -> Duration {
+        self()
+    }
+}
+
+/// The monotonic clock of the OS, `std::time::Instant`, counting from the
+/// first time any `SystemClock` was read in this process.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        static ORIGIN: OnceLock<Instant> = OnceLock::new();
+        ORIGIN.get_or_init(Instant::now).elapsed()
+    }
+}
+
+/// A clock that only moves when told to, for deterministic tests.
+///
+/// Clones share their time, so a test keeps one to advance the clone it
+/// handed to a map.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::{CuckooMap, MockClock};
+/// use std::collections::hash_map::DefaultHasher;
+/// use std::time::Duration;
+///
+/// let clock = MockClock::new();
+/// let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+/// map.set_clock(clock.clone());
+/// map.set_ttl(Duration::from_secs(60));
+/// map.insert_or_update("session", [1]).unwrap();
+///
+/// clock.advance(Duration::from_secs(59));
+/// assert_eq!(map.get("session"), Some([1]));
+/// clock.advance(Duration::from_secs(1));
+/// assert_eq!(map.get("session"), None);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct MockClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    /// Starts at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the time forward by `by`.
+    pub fn advance(&self, by: Duration) {
+        self.nanos.fetch_add(by.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Sets the time.
+    pub fn set(&self, now: Duration) {
+        self.nanos.store(now.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+}
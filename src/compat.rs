@@ -0,0 +1,118 @@
+use crate::{CuckooError, CuckooMap};
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Drop-in replacement for `cuckoofilter::CuckooFilter`, backed by a `CuckooMap`.
+///
+/// Mirrors the upstream method signatures so existing users can switch crates
+/// first and start storing values later through `as_map_mut`.
+/// Unlike upstream, adding the same item twice stores it only once.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::CuckooFilter;
+///
+/// let mut cf = CuckooFilter::new();
+///
+/// cf.add("hello world").unwrap();
+/// assert!(cf.contains("hello world"));
+/// assert!(!cf.test_and_add("hello world").unwrap());
+/// assert!(cf.delete("hello world"));
+/// assert!(cf.is_empty());
+/// ```
+pub struct CuckooFilter<H> {
+    map: CuckooMap<H>,
+}
+
+impl Default for CuckooFilter<DefaultHasher> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CuckooFilter<DefaultHasher> {
+    /// Construct a CuckooFilter with default capacity and hasher.
+    pub fn new() -> Self {
+        Self {
+            map: CuckooMap::new(),
+        }
+    }
+}
+
+impl<H> CuckooFilter<H>
+where
+    H: Hasher + Default,
+{
+    /// Constructs a Cuckoo Filter with a given max capacity
+    pub fn with_capacity(cap: usize) -> Self {
+        Self {
+            map: CuckooMap::with_capacity(cap),
+        }
+    }
+
+    /// Checks if `data` is in the filter.
+    pub fn contains<T: ?Sized + Hash>(&self, data: &T) -> bool {
+        self.map.get(data).is_some()
+    }
+
+    /// Adds `data` to the filter, see `CuckooMap::insert_or_update`.
+    pub fn add<T: ?Sized + Hash>(&mut self, data: &T) -> Result<(), CuckooError> {
+        self.map.insert_or_update(data, [0])
+    }
+
+    /// Adds `data` to the filter if it does not exist in the filter yet.
+    /// Returns `Ok(true)` if `data` was not yet present in the filter and added
+    /// successfully.
+    pub fn test_and_add<T: ?Sized + Hash>(&mut self, data: &T) -> Result<bool, CuckooError> {
+        self.map.test_and_add(data, [0])
+    }
+
+    /// Number of items in the filter.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Number of bytes the filter occupies in memory
+    pub fn memory_usage(&self) -> usize {
+        self.map.memory_usage()
+    }
+
+    /// Check if filter is empty
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Deletes `data` from the filter. Returns true if `data` existed in the
+    /// filter before.
+    pub fn delete<T: ?Sized + Hash>(&mut self, data: &T) -> bool {
+        self.map.delete(data)
+    }
+
+    /// Empty all the buckets in a filter and reset the number of items.
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    /// The underlying map, for reading values.
+    pub fn as_map(&self) -> &CuckooMap<H> {
+        &self.map
+    }
+
+    /// The underlying map, for storing values.
+    pub fn as_map_mut(&mut self) -> &mut CuckooMap<H> {
+        &mut self.map
+    }
+
+    /// Consumes the filter, returning the underlying map.
+    pub fn into_map(self) -> CuckooMap<H> {
+        self.map
+    }
+}
+
+impl<H> From<CuckooMap<H>> for CuckooFilter<H> {
+    fn from(map: CuckooMap<H>) -> Self {
+        Self { map }
+    }
+}
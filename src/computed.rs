@@ -0,0 +1,184 @@
+use crate::bucket::FINGERPRINT_SIZE;
+use crate::{CuckooError, CuckooMap};
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// A `CuckooMap` of `u64` values computed from the hash of their key.
+///
+/// Routing tables often map keys to values that mostly follow from the key,
+/// e.g. a shard `hash % n` with a few keys moved elsewhere. Here `predict`
+/// computes the value from the key hash of the map, see
+/// `CuckooMap::key_hash`, and each entry only stores the signed difference
+/// to the actual value in `V` bytes. Values matching the prediction store a
+/// zero delta, so `V = 1` keeps entries at `FP + 1` bytes while allowing
+/// corrections of -128 to 127. Values too far from the prediction are
+/// rejected at insert.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::ComputedCuckooMap;
+/// use std::collections::hash_map::DefaultHasher;
+///
+/// let mut map = ComputedCuckooMap::<DefaultHasher>::with_capacity(1024, |hash| hash % 16);
+///
+/// map.insert_computed("hello").unwrap();
+/// let shard = map.get("hello").unwrap();
+/// assert!(shard < 16);
+///
+/// // move the key to the neighbouring shard
+/// map.insert_or_update("hello", (shard + 1) % 16).unwrap();
+/// assert_eq!(map.get("hello"), Some((shard + 1) % 16));
+///
+/// // too far from the prediction for a single byte
+/// assert!(map.insert_or_update("hello", 1 << 20).is_err());
+/// ```
+pub struct ComputedCuckooMap<H = DefaultHasher, const FP: usize = FINGERPRINT_SIZE, const V: usize = 1> {
+    map: CuckooMap<H, FP, V>,
+    predict: fn(u64) -> u64,
+}
+
+impl<H, const FP: usize, const V: usize> ComputedCuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    /// Computes values with `predict` on top of an already configured map.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `V` is larger than 8, the size of a `u64` value.
+    pub fn new(map: CuckooMap<H, FP, V>, predict: fn(u64) -> u64) -> Self {
+        assert!(V <= 8, "deltas of u64 values take at most 8 bytes");
+        Self { map, predict }
+    }
+
+    /// Constructs a ComputedCuckooMap with a given max capacity.
+    pub fn with_capacity(cap: usize, predict: fn(u64) -> u64) -> Self {
+        Self::new(CuckooMap::with_capacity(cap), predict)
+    }
+
+    /// Returns the value of `key`: the prediction corrected by the stored delta.
+    pub fn get<K: ?Sized + Hash>(&self, key: &K) -> Option<u64> {
+        let hash = self.map.key_hash(key);
+        let delta = self.map.get_hashed(hash)?;
+        Some((self.predict)(hash).wrapping_add(decode_delta(delta) as u64))
+    }
+
+    /// Stores `value` for `key` as its delta from the prediction.
+    ///
+    /// Fails with `CuckooError::InvalidValue` if the delta doesn't fit in
+    /// `V` bytes, leaving the map unchanged.
+    pub fn insert_or_update<K: ?Sized + Hash>(&mut self, key: &K, value: u64) -> Result<(), CuckooError<FP, V>> {
+        let hash = self.map.key_hash(key);
+        let delta = value.wrapping_sub((self.predict)(hash)) as i64;
+        let delta = encode_delta(delta).ok_or(CuckooError::InvalidValue("the value is too far from the prediction"))?;
+        self.map.insert_hashed(hash, delta)
+    }
+
+    /// Stores `key` with the predicted value, a zero delta.
+    pub fn insert_computed<K: ?Sized + Hash>(&mut self, key: &K) -> Result<(), CuckooError<FP, V>> {
+        let hash = self.map.key_hash(key);
+        self.map.insert_hashed(hash, [0; V])
+    }
+
+    /// The value `predict` computes for `key`, whether it's stored or not.
+    pub fn predicted<K: ?Sized + Hash>(&self, key: &K) -> u64 {
+        (self.predict)(self.map.key_hash(key))
+    }
+
+    /// See `CuckooMap::delete`.
+    pub fn delete<K: ?Sized + Hash>(&mut self, key: &K) -> bool {
+        self.map.delete(key)
+    }
+
+    /// Number of items in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Check if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Number of bytes the map occupies in memory.
+    pub fn memory_usage(&self) -> usize {
+        self.map.memory_usage()
+    }
+
+    /// Empty all the buckets and reset the number of items.
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    /// The underlying map of deltas, for statistics and settings getters.
+    pub fn as_map(&self) -> &CuckooMap<H, FP, V> {
+        &self.map
+    }
+
+    /// Consumes the computed map, returning the underlying map of deltas.
+    pub fn into_map(self) -> CuckooMap<H, FP, V> {
+        self.map
+    }
+}
+
+/// The low `V` bytes of `delta`, if they hold it without loss.
+fn encode_delta<const V: usize>(delta: i64) -> Option<[u8; V]> {
+    let bytes = delta.to_le_bytes();
+    let mut encoded = [0; V];
+    encoded.copy_from_slice(&bytes[..V]);
+    (decode_delta(encoded) == delta).then_some(encoded)
+}
+
+/// Sign extends the `V` little endian bytes of a delta.
+fn decode_delta<const V: usize>(encoded: [u8; V]) -> i64 {
+    let fill = if encoded[V - 1] & 0x80 != 0 { 0xff } else { 0 };
+    let mut bytes = [fill; 8];
+    bytes[..V].copy_from_slice(&encoded);
+    i64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_encoding() {
+        for delta in [0, 1, -1, 127, -128, 1000, i64::MIN, i64::MAX] {
+            assert_eq!(encode_delta::<8>(delta).map(decode_delta), Some(delta));
+        }
+        assert_eq!(encode_delta::<1>(-128), Some([0x80]));
+        assert_eq!(encode_delta::<1>(128), None);
+        assert_eq!(encode_delta::<1>(-129), None);
+        assert_eq!(encode_delta::<2>(-129).map(decode_delta), Some(-129));
+    }
+
+    #[test]
+    fn test_computed_values() {
+        let mut map = ComputedCuckooMap::<DefaultHasher, 4>::with_capacity(4096, |hash| hash % 64);
+        for i in 0..3_000u32 {
+            if i % 10 == 0 {
+                let moved = (map.predicted(&i) + 1) % 64;
+                map.insert_or_update(&i, moved).unwrap();
+            } else {
+                map.insert_computed(&i).unwrap();
+            }
+        }
+        assert_eq!(map.len(), 3_000);
+        assert_eq!(map.memory_usage(), map.as_map().memory_usage());
+        for i in 0..3_000u32 {
+            let expected = if i % 10 == 0 { (map.predicted(&i) + 1) % 64 } else { map.predicted(&i) };
+            assert_eq!(map.get(&i), Some(expected));
+        }
+
+        // wrapping below zero is a delta of -1
+        let key = (0..).find(|i: &u32| map.predicted(i) == 0).unwrap();
+        map.insert_or_update(&key, u64::MAX).unwrap();
+        assert_eq!(map.get(&key), Some(u64::MAX));
+
+        let err = map.insert_or_update(&1u32, map.predicted(&1u32) + 128).unwrap_err();
+        assert!(matches!(err, CuckooError::InvalidValue(_)));
+        assert_eq!(map.get(&1u32), Some(map.predicted(&1u32)));
+    }
+}
@@ -0,0 +1,370 @@
+use crate::bucket::{Fingerprint, ENTRIES_PER_BUCKET};
+use crate::slots::{Relocations, SlotAccess, MAX_RELOCATIONS};
+use crate::CuckooError;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::fmt;
+
+/// Relocations before an insert gives up, `kMaxCuckooCount` upstream.
+const MAX_CUCKOO_COUNT: usize = 500;
+
+/// The `TwoIndependentMultiplyShift` hash family of efficient/cuckoofilter,
+/// hashing a 64bit key to `(add + multiply * key) >> 64` in 128bit
+/// arithmetic.
+///
+/// Upstream draws both parameters from `std::random_device` in the
+/// constructor, so the C++ side has to be patched to use known ones before
+/// its tables can be shared.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MultiplyShift {
+    pub multiply: u128,
+    pub add: u128,
+}
+
+impl MultiplyShift {
+    /// Draws random parameters, like upstream does.
+    pub fn random() -> Self {
+        let mut rng = StdRng::from_entropy();
+        Self {
+            multiply: rng.gen(),
+            add: rng.gen(),
+        }
+    }
+
+    pub fn hash(&self, key: u64) -> u64 {
+        (self.add.wrapping_add(self.multiply.wrapping_mul(u128::from(key))) >> 64) as u64
+    }
+}
+
+/// A cuckoo filter compatible byte for byte with `CuckooFilter<uint64_t,
+/// 8 * FP, SingleTable>` of the reference C++ implementation at
+/// <https://github.com/efficient/cuckoofilter>, to migrate services that
+/// share filters with C++ one at a time.
+///
+/// Tags, bucket indexes and the table layout are derived like upstream:
+///
+/// - a key's 64bit hash, see `MultiplyShift`, gives the bucket index by its
+///   upper and the tag by its lower `8 * FP` bits, with a zero tag
+///   replaced by 1,
+/// - the alternate bucket is `(i ^ tag * 0x5bd1e995) % num_buckets` in
+///   32bit arithmetic,
+/// - the table is `num_buckets` buckets of 4 tags, each tag stored in `FP`
+///   little endian bytes with 0 marking an empty slot,
+/// - a tag that found no slot after 500 relocations becomes the single
+///   victim, which blocks further inserts until a delete makes room.
+///
+/// Upstream picks the slot to kick out with `rand()`, so both sides read
+/// each other's tables, but the tables only stay identical until the first
+/// kick. Semi-sorted `PackedTable` filters are not supported, and tags of
+/// other widths than 8, 16 or 32 bits can't be represented.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::{CppCuckooFilter, MultiplyShift};
+///
+/// let hash = MultiplyShift { multiply: 0x9e37_79b9_7f4a_7c15_f39c_c060_5ced_c835, add: 1 };
+/// let mut filter = CppCuckooFilter::<2>::new(1000, hash);
+/// filter.add(42).unwrap();
+///
+/// // e.g. written by the C++ side and loaded by the Rust side
+/// let table = filter.table().to_vec();
+/// let loaded = CppCuckooFilter::<2>::from_table(table, filter.len(), filter.victim(), hash).unwrap();
+/// assert!(loaded.contains(42));
+/// ```
+pub struct CppCuckooFilter<const FP: usize = 1> {
+    table: Vec<u8>,
+    num_buckets: usize,
+    num_items: usize,
+    victim: Option<(usize, u32)>,
+    hash: MultiplyShift,
+    rng: StdRng,
+}
+
+impl<const FP: usize> CppCuckooFilter<FP> {
+    const SUPPORTED: () = assert!(FP == 1 || FP == 2 || FP == 4, "tags must be 1, 2 or 4 bytes wide");
+
+    /// Constructs an empty filter sized for `max_num_keys` keys like the C++
+    /// constructor: the next power of two of `max_num_keys / 4` buckets,
+    /// doubled if that would be more than 96% full.
+    pub fn new(max_num_keys: usize, hash: MultiplyShift) -> Self {
+        let mut num_buckets = (max_num_keys / ENTRIES_PER_BUCKET).max(1).next_power_of_two();
+        if max_num_keys as f64 / num_buckets as f64 / ENTRIES_PER_BUCKET as f64 > 0.96 {
+            num_buckets <<= 1;
+        }
+        Self::with_table(vec![0; num_buckets * Self::bucket_size()], 0, None, hash)
+    }
+
+    /// Loads a table written by the C++ implementation: the bytes of its
+    /// `num_buckets` buckets without the padding buckets, its `Size()` and
+    /// its victim as `(index, tag)`, if it has one.
+    ///
+    /// Returns `None` if the table isn't a power of two of buckets long or
+    /// the victim doesn't fit in it.
+    pub fn from_table(table: Vec<u8>, num_items: usize, victim: Option<(usize, u32)>, hash: MultiplyShift) -> Option<Self> {
+        let num_buckets = table.len() / Self::bucket_size();
+        if !table.len().is_multiple_of(Self::bucket_size()) || !num_buckets.is_power_of_two() {
+            return None;
+        }
+        if let Some((index, tag)) = victim {
+            if index >= num_buckets || tag == 0 || tag > Self::tag_mask() {
+                return None;
+            }
+        }
+        Some(Self::with_table(table, num_items, victim, hash))
+    }
+
+    fn with_table(table: Vec<u8>, num_items: usize, victim: Option<(usize, u32)>, hash: MultiplyShift) -> Self {
+        let () = Self::SUPPORTED;
+        Self {
+            num_buckets: table.len() / Self::bucket_size(),
+            table,
+            num_items,
+            victim,
+            hash,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// Checks if `key` is in the filter, like `Contain`.
+    pub fn contains(&self, key: u64) -> bool {
+        let (i1, tag) = self.index_and_tag(key);
+        let i2 = self.alt_index(i1, tag);
+        self.is_victim(i1, i2, tag) || self.find(i1, tag).is_some() || self.find(i2, tag).is_some()
+    }
+
+    /// Adds `key` to the filter, like `Add`. Fails only while there is a
+    /// victim, in which case `key` was not added.
+    pub fn add(&mut self, key: u64) -> Result<(), CuckooError<FP>> {
+        let (i, tag) = self.index_and_tag(key);
+        if self.victim.is_some() {
+            return Err(CuckooError::NotEnoughSpace {
+                fingerprint: Self::fingerprint_of(tag),
+                value: [0],
+            });
+        }
+        self.add_tag(i, tag);
+        Ok(())
+    }
+
+    /// Deletes `key` from the filter, like `Delete`. Returns true if `key`
+    /// was in the filter before.
+    pub fn delete(&mut self, key: u64) -> bool {
+        let (i1, tag) = self.index_and_tag(key);
+        let i2 = self.alt_index(i1, tag);
+        let deleted = [i1, i2].iter().any(|&i| match self.find(i, tag) {
+            Some(slot) => {
+                self.write_tag(i, slot, 0);
+                true
+            }
+            None => false,
+        });
+        if deleted {
+            self.num_items -= 1;
+            // the freed slot may take the victim back
+            if let Some((i, tag)) = self.victim.take() {
+                self.add_tag(i, tag);
+            }
+            true
+        } else if self.is_victim(i1, i2, tag) {
+            self.victim = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Number of tags in the table, like `Size`. The victim isn't counted.
+    pub fn len(&self) -> usize {
+        self.num_items
+    }
+
+    /// Check if filter is empty
+    pub fn is_empty(&self) -> bool {
+        self.num_items == 0 && self.victim.is_none()
+    }
+
+    pub fn num_buckets(&self) -> usize {
+        self.num_buckets
+    }
+
+    /// The bucket index and tag that found no slot, if any.
+    pub fn victim(&self) -> Option<(usize, u32)> {
+        self.victim
+    }
+
+    pub fn hash(&self) -> MultiplyShift {
+        self.hash
+    }
+
+    /// The bytes of the table, laid out like the buckets of the C++
+    /// `SingleTable`.
+    pub fn table(&self) -> &[u8] {
+        &self.table
+    }
+
+    /// Consumes the filter, returning its table.
+    pub fn into_table(self) -> Vec<u8> {
+        self.table
+    }
+
+    /// Places a tag starting in bucket `i`, like `AddImpl`.
+    fn add_tag(&mut self, i: usize, tag: u32) {
+        let (mut i, mut tag) = (i, tag);
+        for count in 0..MAX_CUCKOO_COUNT {
+            if let Some(slot) = self.find(i, 0) {
+                self.write_tag(i, slot, tag);
+                self.num_items += 1;
+                return;
+            }
+            // the first alternate bucket is tried before anything is kicked
+            if count > 0 {
+                let slot = self.rng.gen_range(0, ENTRIES_PER_BUCKET);
+                let kicked = self.read_tag(i, slot);
+                self.write_tag(i, slot, tag);
+                tag = kicked;
+            }
+            i = self.alt_index(i, tag);
+        }
+        self.victim = Some((i, tag));
+    }
+
+    fn index_and_tag(&self, key: u64) -> (usize, u32) {
+        let hash = self.hash.hash(key);
+        let index = (hash >> 32) as usize & (self.num_buckets - 1);
+        let tag = hash as u32 & Self::tag_mask();
+        (index, tag.max(1))
+    }
+
+    fn alt_index(&self, i: usize, tag: u32) -> usize {
+        (i as u32 ^ tag.wrapping_mul(0x5bd1_e995)) as usize & (self.num_buckets - 1)
+    }
+
+    fn is_victim(&self, i1: usize, i2: usize, tag: u32) -> bool {
+        self.victim.is_some_and(|(i, t)| t == tag && (i == i1 || i == i2))
+    }
+
+    fn find(&self, i: usize, tag: u32) -> Option<usize> {
+        SlotAccess::find(self, i, Self::fingerprint_of(tag))
+    }
+
+    fn read_tag(&self, i: usize, slot: usize) -> u32 {
+        let offset = (i * ENTRIES_PER_BUCKET + slot) * FP;
+        let mut bytes = [0; 4];
+        bytes[..FP].copy_from_slice(&self.table[offset..offset + FP]);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn write_tag(&mut self, i: usize, slot: usize, tag: u32) {
+        let offset = (i * ENTRIES_PER_BUCKET + slot) * FP;
+        self.table[offset..offset + FP].copy_from_slice(&tag.to_le_bytes()[..FP]);
+    }
+
+    fn fingerprint_of(tag: u32) -> Fingerprint<FP> {
+        let mut data = [0; FP];
+        data.copy_from_slice(&tag.to_le_bytes()[..FP]);
+        Fingerprint { data }
+    }
+
+    fn tag_mask() -> u32 {
+        (u64::MAX >> (64 - 8 * FP)) as u32
+    }
+
+    fn bucket_size() -> usize {
+        ENTRIES_PER_BUCKET * FP
+    }
+}
+
+/// The slots are read like those of the other tables, while kicks stay
+/// upstream's random walk.
+impl<const FP: usize> SlotAccess<FP> for CppCuckooFilter<FP> {
+    fn fingerprint(&self, bucket: usize, slot: usize) -> Fingerprint<FP> {
+        Self::fingerprint_of(self.read_tag(bucket, slot))
+    }
+
+    fn relocations(&self, fp: Fingerprint<FP>, bucket: usize) -> Relocations {
+        let mut tag = [0; 4];
+        tag[..FP].copy_from_slice(&fp.data);
+        let alt = self.alt_index(bucket, u32::from_le_bytes(tag));
+        let mut buckets = [0; MAX_RELOCATIONS];
+        buckets[..2].copy_from_slice(&[bucket, alt]);
+        (buckets, if alt == bucket { 1 } else { 2 })
+    }
+}
+
+impl<const FP: usize> fmt::Debug for CppCuckooFilter<FP> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CppCuckooFilter")
+            .field("num_buckets", &self.num_buckets)
+            .field("num_items", &self.num_items)
+            .field("victim", &self.victim)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // hashes every key to itself, so the key spells out index and tag
+    const IDENTITY: MultiplyShift = MultiplyShift { multiply: 1 << 64, add: 0 };
+
+    #[test]
+    fn test_cpp_layout() {
+        let mut filter = CppCuckooFilter::<2>::new(1024, IDENTITY);
+        assert_eq!(filter.num_buckets(), 512);
+        assert_eq!(CppCuckooFilter::<1>::new(1000, IDENTITY).num_buckets(), 512);
+        assert_eq!(CppCuckooFilter::<1>::new(900, IDENTITY).num_buckets(), 256);
+        assert_eq!(CppCuckooFilter::<1>::new(1, IDENTITY).num_buckets(), 1);
+
+        filter.add(5 << 32 | 0xabcd).unwrap();
+        assert_eq!(&filter.table()[5 * 8..5 * 8 + 2], &[0xcd, 0xab]);
+        // a zero tag is stored as 1
+        filter.add(7 << 32).unwrap();
+        assert_eq!(&filter.table()[7 * 8..7 * 8 + 2], &[1, 0]);
+        assert!(filter.contains(7 << 32 | 1));
+
+        // the fifth key of a bucket goes to the alternate one
+        for _ in 0..5 {
+            filter.add(9 << 32 | 0x1234).unwrap();
+        }
+        let alt = (9 ^ 0x1234u32.wrapping_mul(0x5bd1_e995)) as usize % 512;
+        assert_eq!(&filter.table()[alt * 8..alt * 8 + 2], &[0x34, 0x12]);
+        let tag = Fingerprint { data: [0x34, 0x12] };
+        assert_eq!(SlotAccess::find(&filter, alt, tag), Some(0));
+        assert_eq!(filter.relocations(tag, 9).0[..2], [9, alt]);
+        assert_eq!(filter.len(), 7);
+
+        assert!(filter.delete(9 << 32 | 0x1234));
+        assert!(filter.delete(5 << 32 | 0xabcd));
+        assert!(!filter.contains(5 << 32 | 0xabcd));
+        assert_eq!(filter.len(), 5);
+
+        let table = filter.table().to_vec();
+        let loaded = CppCuckooFilter::<2>::from_table(table, filter.len(), None, IDENTITY).unwrap();
+        assert!(loaded.contains(7 << 32));
+        assert!(CppCuckooFilter::<2>::from_table(vec![0; 24], 0, None, IDENTITY).is_none());
+        assert!(CppCuckooFilter::<2>::from_table(vec![0; 16], 0, Some((2, 1)), IDENTITY).is_none());
+    }
+
+    #[test]
+    fn test_cpp_victim() {
+        let mut filter = CppCuckooFilter::<4>::new(8, MultiplyShift::random());
+        let mut key = 0;
+        while filter.victim().is_none() {
+            filter.add(key).unwrap();
+            key += 1;
+        }
+        // the victim isn't counted
+        assert_eq!(filter.len() as u64, key - 1);
+        assert!(filter.add(key).is_err());
+        assert!((0..key).all(|key| filter.contains(key)));
+
+        // the victim is placed again, or stays the victim
+        assert!(filter.delete(0));
+        assert_eq!((filter.len() + filter.victim().iter().count()) as u64, key - 1);
+        assert!((1..key).all(|key| filter.contains(key)));
+    }
+}
@@ -0,0 +1,154 @@
+use crate::bucket::ENTRIES_PER_BUCKET;
+use crate::util::FaI;
+
+/// Distribution statistics of a hasher over a sample of keys,
+/// as returned by `CuckooMap::hasher_quality_report`.
+#[derive(Clone, Debug)]
+pub struct HasherQuality {
+    /// Number of sampled keys.
+    pub samples: usize,
+    /// Pearson's chi-square statistic of the keys' primary bucket indexes
+    /// against a uniform distribution.
+    pub chi_square: f64,
+    /// Degrees of freedom of `chi_square`, i.e. the number of buckets minus one.
+    pub degrees_of_freedom: usize,
+    /// Shannon entropy of the first byte of the sampled fingerprints in bits.
+    pub fingerprint_entropy: f64,
+    /// Entropy of a perfectly uniform first fingerprint byte in bits.
+    pub max_fingerprint_entropy: f64,
+}
+
+impl HasherQuality {
+    pub(crate) fn measure<I, const FP: usize>(bucket_count: usize, fais: I) -> Self
+    where
+        I: Iterator<Item = FaI<FP>>,
+    {
+        let mut bucket_hits = vec![0usize; bucket_count];
+        let mut fp_hits = vec![0usize; 256];
+        let mut samples = 0;
+
+        for fai in fais {
+            bucket_hits[fai.i1 % bucket_count] += 1;
+            fp_hits[fai.fp.data[0] as usize] += 1;
+            samples += 1;
+        }
+
+        let expected = samples as f64 / bucket_count as f64;
+        let chi_square = if samples == 0 {
+            0.0
+        } else {
+            bucket_hits
+                .iter()
+                .map(|&hits| (hits as f64 - expected).powi(2) / expected)
+                .sum()
+        };
+
+        let fingerprint_entropy = fp_hits
+            .iter()
+            .filter(|&&hits| hits > 0)
+            .map(|&hits| {
+                let p = hits as f64 / samples as f64;
+                -p * p.log2()
+            })
+            .sum();
+
+        Self {
+            samples,
+            chi_square,
+            degrees_of_freedom: bucket_count - 1,
+            fingerprint_entropy,
+            // the empty fingerprint is never produced, which only excludes
+            // a first byte of zero for single byte fingerprints
+            max_fingerprint_entropy: if FP == 1 { 255f64.log2() } else { 8.0 },
+        }
+    }
+
+    /// How many standard deviations `chi_square` lies above its expectation
+    /// for uniformly distributed keys.
+    pub fn bucket_z_score(&self) -> f64 {
+        if self.degrees_of_freedom == 0 {
+            return 0.0;
+        }
+        let df = self.degrees_of_freedom as f64;
+        (self.chi_square - df) / (2.0 * df).sqrt()
+    }
+
+    /// Whether the hasher spreads the sample badly enough to hurt the load factor:
+    /// buckets are filled far less evenly than at random, or fingerprints
+    /// carry less than 90% of their possible entropy.
+    ///
+    /// The entropy estimate is only meaningful for samples of a few thousand keys.
+    pub fn is_poor(&self) -> bool {
+        self.bucket_z_score() > 3.0 || self.fingerprint_entropy < 0.9 * self.max_fingerprint_entropy
+    }
+}
+
+/// The size of a map for a whole dataset, as projected from a sample of its
+/// keys by `CuckooMap::project_capacity`.
+#[derive(Clone, Debug)]
+pub struct CapacityProjection {
+    /// Number of sampled keys.
+    pub samples: usize,
+    /// Mean density the tables filled with the sample reached before their
+    /// first failed insert.
+    pub mean_density: f64,
+    /// Standard deviation of those densities.
+    pub density_std_dev: f64,
+    /// Density the full map can be filled to at the requested probability
+    /// of a failed insert.
+    pub target_density: f64,
+    /// Number of buckets the full map needs.
+    pub buckets: usize,
+}
+
+impl CapacityProjection {
+    /// Lowest density ever projected, for samples spread too widely to
+    /// extrapolate from.
+    const MIN_DENSITY: f64 = 0.05;
+
+    pub(crate) fn extrapolate(samples: usize, densities: &[f64], total_expected: usize, failure_probability: f64) -> Self {
+        let n = densities.len() as f64;
+        let mean_density = densities.iter().sum::<f64>() / n;
+        let variance = densities.iter().map(|d| (d - mean_density).powi(2)).sum::<f64>() / (n - 1.0);
+        let density_std_dev = variance.sqrt();
+
+        let target_density = (mean_density - upper_quantile(failure_probability) * density_std_dev)
+            .clamp(Self::MIN_DENSITY.min(mean_density), mean_density);
+        let buckets = (total_expected as f64 / (target_density * ENTRIES_PER_BUCKET as f64)).ceil() as usize;
+        Self {
+            samples,
+            mean_density,
+            density_std_dev,
+            target_density,
+            buckets: buckets.max(1),
+        }
+    }
+
+    /// Number of entries of the projected buckets, to construct the map with.
+    /// `CuckooMap::with_capacity` rounds it up to a power of two of buckets.
+    pub fn capacity(&self) -> usize {
+        self.buckets * ENTRIES_PER_BUCKET
+    }
+}
+
+/// Returns how many standard deviations above the mean a normally
+/// distributed variable exceeds with probability `p`, for `p` up to 0.5.
+///
+/// Uses the rational approximation 26.2.23 of Abramowitz and Stegun, which
+/// is off by less than 4.5e-4.
+fn upper_quantile(p: f64) -> f64 {
+    let t = (-2.0 * p.ln()).sqrt();
+    t - (2.515_517 + 0.802_853 * t + 0.010_328 * t * t) / (1.0 + 1.432_788 * t + 0.189_269 * t * t + 0.001_308 * t * t * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upper_quantile() {
+        assert!(upper_quantile(0.5).abs() < 1e-3);
+        assert!((upper_quantile(0.05) - 1.645).abs() < 1e-3);
+        assert!((upper_quantile(0.001) - 3.090).abs() < 1e-3);
+    }
+}
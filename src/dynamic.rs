@@ -0,0 +1,184 @@
+use crate::bucket::VALUE_SIZE;
+use crate::{CuckooError, CuckooMap, ProbabilisticMap};
+
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error as StdError;
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+
+use fnv::FnvHasher;
+
+/// The hashers a `DynCuckooMap` can be created with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HasherKind {
+    /// `std::collections::hash_map::DefaultHasher`, parsed from `"default"`.
+    Default,
+    /// `fnv::FnvHasher`, parsed from `"fnv"`.
+    Fnv,
+    /// `farmhash::FarmHasher`, parsed from `"farmhash"`.
+    #[cfg(feature = "farmhash")]
+    Farm,
+}
+
+/// Returned when parsing a `HasherKind` from an unknown name.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseHasherKindError(String);
+
+impl fmt::Display for ParseHasherKindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown hasher {:?}", self.0)
+    }
+}
+
+impl StdError for ParseHasherKindError {}
+
+impl FromStr for HasherKind {
+    type Err = ParseHasherKindError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(HasherKind::Default),
+            "fnv" => Ok(HasherKind::Fnv),
+            #[cfg(feature = "farmhash")]
+            "farmhash" => Ok(HasherKind::Farm),
+            _ => Err(ParseHasherKindError(s.to_owned())),
+        }
+    }
+}
+
+/// A `CuckooMap` whose hasher is chosen at runtime, e.g. from a config file.
+///
+/// Each operation dispatches on the hasher with a single `match`, so only
+/// the hashers listed in `HasherKind` are compiled in, once each.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::{DynCuckooMap, HasherKind};
+///
+/// let kind: HasherKind = "fnv".parse().unwrap();
+/// let mut map = DynCuckooMap::with_capacity(kind, 1 << 10);
+///
+/// map.insert_or_update("hello world", [1]).unwrap();
+/// assert_eq!(map.get("hello world"), Some([1]));
+/// assert_eq!(map.kind(), HasherKind::Fnv);
+/// ```
+pub enum DynCuckooMap {
+    Default(CuckooMap<DefaultHasher>),
+    Fnv(CuckooMap<FnvHasher>),
+    #[cfg(feature = "farmhash")]
+    Farm(CuckooMap<farmhash::FarmHasher>),
+}
+
+macro_rules! dispatch {
+    ($self:expr, $map:ident => $body:expr) => {
+        match $self {
+            DynCuckooMap::Default($map) => $body,
+            DynCuckooMap::Fnv($map) => $body,
+            #[cfg(feature = "farmhash")]
+            DynCuckooMap::Farm($map) => $body,
+        }
+    };
+}
+
+impl DynCuckooMap {
+    /// Constructs a map with the given hasher and default capacity.
+    pub fn new(kind: HasherKind) -> Self {
+        Self::with_capacity(kind, crate::DEFAULT_CAPACITY)
+    }
+
+    /// Constructs a map with the given hasher and capacity.
+    pub fn with_capacity(kind: HasherKind, cap: usize) -> Self {
+        match kind {
+            HasherKind::Default => DynCuckooMap::Default(CuckooMap::with_capacity(cap)),
+            HasherKind::Fnv => DynCuckooMap::Fnv(CuckooMap::with_capacity(cap)),
+            #[cfg(feature = "farmhash")]
+            HasherKind::Farm => DynCuckooMap::Farm(CuckooMap::with_capacity(cap)),
+        }
+    }
+
+    /// The hasher the map was created with.
+    pub fn kind(&self) -> HasherKind {
+        match self {
+            DynCuckooMap::Default(_) => HasherKind::Default,
+            DynCuckooMap::Fnv(_) => HasherKind::Fnv,
+            #[cfg(feature = "farmhash")]
+            DynCuckooMap::Farm(_) => HasherKind::Farm,
+        }
+    }
+
+    /// See `CuckooMap::get`.
+    pub fn get<T: ?Sized + Hash>(&self, key: &T) -> Option<[u8; VALUE_SIZE]> {
+        dispatch!(self, map => map.get(key))
+    }
+
+    /// See `CuckooMap::insert_or_update`.
+    pub fn insert_or_update<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; VALUE_SIZE]) -> Result<(), CuckooError> {
+        dispatch!(self, map => map.insert_or_update(key, value))
+    }
+
+    /// See `CuckooMap::test_and_add`.
+    pub fn test_and_add<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; VALUE_SIZE]) -> Result<bool, CuckooError> {
+        dispatch!(self, map => map.test_and_add(key, value))
+    }
+
+    /// See `CuckooMap::delete`.
+    pub fn delete<T: ?Sized + Hash>(&mut self, key: &T) -> bool {
+        dispatch!(self, map => map.delete(key))
+    }
+
+    /// Number of items in the map.
+    pub fn len(&self) -> usize {
+        dispatch!(self, map => map.len())
+    }
+
+    /// Check if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        dispatch!(self, map => map.is_empty())
+    }
+
+    /// Number of bytes the map occupies in memory.
+    pub fn memory_usage(&self) -> usize {
+        dispatch!(self, map => map.memory_usage())
+    }
+
+    /// Empty all the buckets and reset the number of items.
+    pub fn clear(&mut self) {
+        dispatch!(self, map => map.clear())
+    }
+}
+
+impl ProbabilisticMap for DynCuckooMap {
+    fn get<T: ?Sized + Hash>(&self, key: &T) -> Option<[u8; VALUE_SIZE]> {
+        DynCuckooMap::get(self, key)
+    }
+
+    fn insert_or_update<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; VALUE_SIZE]) -> Result<(), CuckooError> {
+        DynCuckooMap::insert_or_update(self, key, value)
+    }
+
+    fn delete<T: ?Sized + Hash>(&mut self, key: &T) -> bool {
+        DynCuckooMap::delete(self, key)
+    }
+
+    fn len(&self) -> usize {
+        DynCuckooMap::len(self)
+    }
+
+    fn clear(&mut self) {
+        DynCuckooMap::clear(self)
+    }
+}
+
+impl From<CuckooMap<DefaultHasher>> for DynCuckooMap {
+    fn from(map: CuckooMap<DefaultHasher>) -> Self {
+        DynCuckooMap::Default(map)
+    }
+}
+
+impl From<CuckooMap<FnvHasher>> for DynCuckooMap {
+    fn from(map: CuckooMap<FnvHasher>) -> Self {
+        DynCuckooMap::Fnv(map)
+    }
+}
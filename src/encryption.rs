@@ -0,0 +1,166 @@
+//! XChaCha20 encryption of exports and persistent map files, so snapshots
+//! can be stored where others can read them.
+//!
+//! Encrypted bytes start with the magic number `CKME`, the format version,
+//! 1 byte, and a random nonce, 24 bytes, followed by the encrypted bytes of
+//! the export or file. The encryption isn't authenticated: a wrong key is
+//! detected, but changes to the encrypted bytes only by the checksum of
+//! exports, persistent map files have none.
+
+use crate::export::{ExportedCuckooMap, ImportError};
+use crate::CuckooMap;
+
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::XChaCha20;
+use rand::RngCore;
+
+use std::convert::TryInto;
+use std::hash::Hasher;
+
+const ENCRYPTED_MAGIC: &[u8; 4] = b"CKME";
+/// Version of the encrypted format.
+const ENCRYPTED_VERSION: u8 = 1;
+const NONCE_SIZE: usize = 24;
+/// Bytes before the encrypted ones.
+pub(crate) const ENCRYPTED_HEADER_SIZE: usize = ENCRYPTED_MAGIC.len() + 1 + NONCE_SIZE;
+
+/// A 256bit key to encrypt exports and persistent map files with, see
+/// `ExportedCuckooMap::to_encrypted_bytes`.
+pub type EncryptionKey = [u8; 32];
+
+/// Encrypts `bytes` in place with a random nonce, returning the header to
+/// store before them.
+pub(crate) fn encrypt(key: &EncryptionKey, bytes: &mut [u8]) -> [u8; ENCRYPTED_HEADER_SIZE] {
+    let mut header = [0; ENCRYPTED_HEADER_SIZE];
+    header[..ENCRYPTED_MAGIC.len()].copy_from_slice(ENCRYPTED_MAGIC);
+    header[ENCRYPTED_MAGIC.len()] = ENCRYPTED_VERSION;
+    // a nonce this long can be random without risking reuse
+    rand::thread_rng().fill_bytes(&mut header[ENCRYPTED_MAGIC.len() + 1..]);
+    apply_keystream(key, &header, bytes);
+    header
+}
+
+/// Decrypts `bytes` in place, encrypted by `encrypt` with the returned
+/// `header`. Whether the key was right is up to the caller to check.
+pub(crate) fn decrypt(key: &EncryptionKey, header: &[u8], bytes: &mut [u8]) -> Result<(), ImportError> {
+    if header.len() < ENCRYPTED_HEADER_SIZE {
+        return Err(ImportError::Truncated);
+    }
+    if header[..ENCRYPTED_MAGIC.len()] != ENCRYPTED_MAGIC[..] {
+        return Err(ImportError::NotAnExport);
+    }
+    match header[ENCRYPTED_MAGIC.len()] {
+        ENCRYPTED_VERSION => {}
+        version => return Err(ImportError::UnsupportedVersion(version)),
+    }
+    apply_keystream(key, header, bytes);
+    Ok(())
+}
+
+/// XORs `bytes` with the keystream of `key` and the nonce in `header`.
+fn apply_keystream(key: &EncryptionKey, header: &[u8], bytes: &mut [u8]) {
+    let nonce: &[u8; NONCE_SIZE] = header[ENCRYPTED_MAGIC.len() + 1..ENCRYPTED_HEADER_SIZE]
+        .try_into()
+        .expect("24 bytes");
+    XChaCha20::new(key.into(), nonce.into()).apply_keystream(bytes);
+}
+
+impl<const FP: usize, const V: usize> ExportedCuckooMap<FP, V> {
+    /// Encodes the export like `to_bytes` and encrypts it with XChaCha20
+    /// under `key`, see `from_encrypted_bytes`.
+    ///
+    /// Each call picks a random nonce, so encrypting the same export twice
+    /// gives different bytes. The encryption hides the entries, but isn't
+    /// authenticated: only the checksum of the export detects changes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cuckoomap::{CuckooMap, ImportError};
+    /// use std::collections::hash_map::DefaultHasher;
+    ///
+    /// let key = [7; 32];
+    /// let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+    /// map.insert_or_update("hello", [1]).unwrap();
+    ///
+    /// let bytes = map.export().to_encrypted_bytes(&key);
+    /// let map = CuckooMap::<DefaultHasher>::import_encrypted(&bytes, &key).unwrap();
+    /// assert_eq!(map.get("hello"), Some([1]));
+    /// assert_eq!(
+    ///     CuckooMap::<DefaultHasher>::import_encrypted(&bytes, &[8; 32]).err(),
+    ///     Some(ImportError::DecryptionFailed)
+    /// );
+    /// ```
+    pub fn to_encrypted_bytes(&self, key: &EncryptionKey) -> Vec<u8> {
+        let mut bytes = self.to_bytes();
+        let header = encrypt(key, &mut bytes);
+        let mut out = Vec::with_capacity(header.len() + bytes.len());
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&bytes);
+        out
+    }
+
+    /// Decrypts the bytes of `to_encrypted_bytes` and decodes them like
+    /// `from_bytes`, failing with `DecryptionFailed` for another key.
+    pub fn from_encrypted_bytes(bytes: &[u8], key: &EncryptionKey) -> Result<Self, ImportError> {
+        let (header, encrypted) = bytes.split_at(bytes.len().min(ENCRYPTED_HEADER_SIZE));
+        let mut decrypted = encrypted.to_vec();
+        decrypt(key, header, &mut decrypted)?;
+        match Self::from_bytes(&decrypted) {
+            Err(ImportError::NotAnExport) => Err(ImportError::DecryptionFailed),
+            result => result,
+        }
+    }
+}
+
+impl<H, const FP: usize, const V: usize> CuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    /// Decrypts and imports an export written by
+    /// `ExportedCuckooMap::to_encrypted_bytes`, see `import`.
+    pub fn import_encrypted(bytes: &[u8], key: &EncryptionKey) -> Result<Self, ImportError> {
+        let exported = ExportedCuckooMap::<FP, V>::from_encrypted_bytes(bytes, key)?;
+        Self::import_exported(exported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bucket::{FINGERPRINT_SIZE, VALUE_SIZE};
+    use std::collections::hash_map::DefaultHasher;
+
+    #[test]
+    fn test_encrypted_export() {
+        let key = [1; 32];
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        for i in 0..500u32 {
+            map.insert_or_update(&i, [i as u8]).unwrap();
+        }
+        let exported = map.export();
+        let plain = exported.to_bytes();
+        let bytes = exported.to_encrypted_bytes(&key);
+        assert_eq!(bytes.len(), ENCRYPTED_HEADER_SIZE + plain.len());
+        assert_ne!(bytes[ENCRYPTED_HEADER_SIZE..], plain[..]);
+        // a fresh nonce every time
+        assert_ne!(exported.to_encrypted_bytes(&key), bytes);
+
+        let imported = CuckooMap::<DefaultHasher>::import_encrypted(&bytes, &key).unwrap();
+        assert_eq!(imported.len(), map.len());
+        assert!((0..500u32).all(|i| imported.get(&i) == map.get(&i)));
+
+        type Exported = ExportedCuckooMap<FINGERPRINT_SIZE, VALUE_SIZE>;
+        let import = |bytes: &[u8], key| Exported::from_encrypted_bytes(bytes, key).err();
+        assert_eq!(import(&bytes, &[2; 32]), Some(ImportError::DecryptionFailed));
+        assert_eq!(import(&plain, &key), Some(ImportError::NotAnExport));
+        assert_eq!(import(&bytes[..10], &key), Some(ImportError::Truncated));
+        let mut newer = bytes.clone();
+        newer[ENCRYPTED_MAGIC.len()] = ENCRYPTED_VERSION + 1;
+        assert_eq!(import(&newer, &key), Some(ImportError::UnsupportedVersion(ENCRYPTED_VERSION + 1)));
+        // unauthenticated, but the checksum of the export catches changes
+        let mut changed = bytes.clone();
+        *changed.last_mut().unwrap() ^= 1;
+        assert_eq!(import(&changed, &key), Some(ImportError::ChecksumMismatch));
+    }
+}
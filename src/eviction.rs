@@ -0,0 +1,211 @@
+use crate::bucket::{Fingerprint, ENTRIES_PER_BUCKET, FINGERPRINT_SIZE, VALUE_SIZE};
+
+use rand::{Rng, RngCore};
+
+/// An entry in a full bucket that an insert could kick out.
+#[derive(Clone, Copy, Debug)]
+pub struct EvictionCandidate<const FP: usize = FINGERPRINT_SIZE, const V: usize = VALUE_SIZE> {
+    /// Index of the bucket.
+    pub bucket: usize,
+    /// Slot of the entry within the bucket.
+    pub slot: usize,
+    /// Fingerprint of the entry.
+    pub fingerprint: Fingerprint<FP>,
+    /// Value of the entry.
+    pub value: [u8; V],
+    /// Whether a sampled lookup hit the entry since it was stored, always
+    /// `false` without access tracking.
+    pub accessed: bool,
+}
+
+/// Chooses where the kick loop of an insert starts evicting when all
+/// candidate buckets of the new entry are full.
+///
+/// After the first eviction every kicked entry moves on to one of its other
+/// candidate buckets, so this is the only choice the kick loop makes besides
+/// picking random slots and, with more than two ways, random buckets.
+pub trait EvictionStrategy<const FP: usize = FINGERPRINT_SIZE, const V: usize = VALUE_SIZE> {
+    /// Returns the position in `candidates` of the entry that gets evicted.
+    /// The entries of the primary bucket come before those of the other
+    /// candidate buckets, in slot order.
+    fn choose(&mut self, candidates: &[EvictionCandidate<FP, V>], rng: &mut dyn RngCore) -> usize;
+}
+
+/// Picks one of the candidates at random. This is the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RandomEviction;
+
+impl<const FP: usize, const V: usize> EvictionStrategy<FP, V> for RandomEviction {
+    fn choose(&mut self, candidates: &[EvictionCandidate<FP, V>], rng: &mut dyn RngCore) -> usize {
+        rng.gen_range(0, candidates.len())
+    }
+}
+
+/// Cycles through the candidates on every eviction.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RoundRobinEviction {
+    next: usize,
+}
+
+impl<const FP: usize, const V: usize> EvictionStrategy<FP, V> for RoundRobinEviction {
+    fn choose(&mut self, candidates: &[EvictionCandidate<FP, V>], _: &mut dyn RngCore) -> usize {
+        let chosen = self.next % candidates.len();
+        self.next = chosen + 1;
+        chosen
+    }
+}
+
+/// Treats values as priorities and evicts the entry with the lowest value,
+/// picking at random on ties.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PriorityEviction;
+
+impl<const FP: usize, const V: usize> EvictionStrategy<FP, V> for PriorityEviction {
+    fn choose(&mut self, candidates: &[EvictionCandidate<FP, V>], rng: &mut dyn RngCore) -> usize {
+        let lowest = match candidates.iter().map(|c| c.value).min() {
+            Some(lowest) => lowest,
+            None => return 0,
+        };
+        let ties: Vec<usize> = (0..candidates.len())
+            .filter(|&i| candidates[i].value == lowest)
+            .collect();
+        ties[rng.gen_range(0, ties.len())]
+    }
+}
+
+/// Evicts one of the entries no sampled lookup hit since they were stored,
+/// picking at random among them, or among all candidates if every one was
+/// hit. An approximation of least recently used eviction that needs access
+/// tracking, see `CuckooMap::set_access_sampling`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LruEviction;
+
+impl<const FP: usize, const V: usize> EvictionStrategy<FP, V> for LruEviction {
+    fn choose(&mut self, candidates: &[EvictionCandidate<FP, V>], rng: &mut dyn RngCore) -> usize {
+        let idle: Vec<usize> = (0..candidates.len()).filter(|&i| !candidates[i].accessed).collect();
+        if idle.is_empty() {
+            rng.gen_range(0, candidates.len())
+        } else {
+            idle[rng.gen_range(0, idle.len())]
+        }
+    }
+}
+
+/// How many times less likely the kick loop picks a recently written slot
+/// than any other slot of a bucket.
+const RECENCY_BIAS: u32 = 8;
+
+/// The slots written by the last few operations, which the kick loop
+/// avoids so it doesn't kick out an entry it just placed.
+pub(crate) struct RecencyRing {
+    /// `(bucket, slot)` pairs, overwritten in turn, `usize::MAX` while unused.
+    writes: Box<[(usize, usize)]>,
+    next: usize,
+}
+
+impl RecencyRing {
+    pub fn new(window: usize) -> Self {
+        Self {
+            writes: vec![(usize::MAX, 0); window].into_boxed_slice(),
+            next: 0,
+        }
+    }
+
+    pub fn window(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn record(&mut self, bucket: usize, slot: usize) {
+        self.writes[self.next] = (bucket, slot);
+        self.next = (self.next + 1) % self.writes.len();
+    }
+
+    /// Returns a mask with bit `slot` set for every recently written slot of
+    /// `bucket`.
+    pub fn recent(&self, bucket: usize) -> u8 {
+        self.writes
+            .iter()
+            .filter(|&&(b, _)| b == bucket)
+            .fold(0, |mask, &(_, slot)| mask | 1 << slot)
+    }
+
+    /// Picks a random slot of `bucket`, weighing recently written ones down
+    /// by `RECENCY_BIAS`.
+    pub fn pick_slot(&self, bucket: usize, rng: &mut dyn RngCore) -> usize {
+        let recent = self.recent(bucket);
+        let weight = |slot: usize| if recent & 1 << slot == 0 { RECENCY_BIAS } else { 1 };
+        let mut pick = rng.gen_range(0, (0..ENTRIES_PER_BUCKET).map(weight).sum::<u32>());
+        for slot in 0..ENTRIES_PER_BUCKET {
+            if pick < weight(slot) {
+                return slot;
+            }
+            pick -= weight(slot);
+        }
+        unreachable!("the pick is below the sum of the weights")
+    }
+
+    pub fn clear(&mut self) {
+        for write in self.writes.iter_mut() {
+            *write = (usize::MAX, 0);
+        }
+    }
+
+    /// Number of bytes the ring occupies.
+    pub fn memory_usage(&self) -> usize {
+        self.writes.len() * std::mem::size_of::<(usize, usize)>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::mock::StepRng;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_strategies() {
+        let candidate = |value| EvictionCandidate::<1, 1> {
+            bucket: 0,
+            slot: 0,
+            fingerprint: Fingerprint::empty(),
+            value: [value],
+            accessed: value > 1,
+        };
+        let mut rng = StepRng::new(0, 1);
+
+        let mut round_robin = RoundRobinEviction::default();
+        let choices: Vec<usize> = (0..4)
+            .map(|_| round_robin.choose(&[candidate(0); 3], &mut rng))
+            .collect();
+        assert_eq!(choices, vec![0, 1, 2, 0]);
+
+        assert_eq!(PriorityEviction.choose(&[candidate(1), candidate(2)], &mut rng), 0);
+        assert_eq!(PriorityEviction.choose(&[candidate(3), candidate(2), candidate(1)], &mut rng), 2);
+
+        assert_eq!(LruEviction.choose(&[candidate(3), candidate(1), candidate(2)], &mut rng), 1);
+        assert!(LruEviction.choose(&[candidate(2), candidate(3)], &mut rng) < 2);
+    }
+
+    #[test]
+    fn test_recency_ring() {
+        let mut ring = RecencyRing::new(3);
+        ring.record(5, 0);
+        ring.record(5, 2);
+        ring.record(7, 1);
+        assert_eq!(ring.recent(5), 0b101);
+        // the oldest write drops out
+        ring.record(7, 3);
+        assert_eq!(ring.recent(5), 0b100);
+        assert_eq!(ring.recent(7), 0b1010);
+
+        // slots 1 and 3 are picked with weight 1, slots 0 and 2 with 8
+        let mut rng = StdRng::seed_from_u64(1);
+        let recent = (0..18_000).filter(|_| ring.pick_slot(7, &mut rng) % 2 == 1).count();
+        assert!(recent > 1_600 && recent < 2_400, "{} recent picks", recent);
+
+        ring.clear();
+        assert_eq!(ring.recent(7), 0);
+    }
+}
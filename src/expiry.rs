@@ -0,0 +1,138 @@
+use crate::bucket::Fingerprint;
+use crate::CuckooMap;
+
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+/// Deadlines of the entries of a map in TTL mode.
+///
+/// Entries are identified like in the hot cache, by their fingerprint and
+/// smallest candidate bucket, which stay the same wherever the entry is
+/// relocated to.
+pub(crate) struct Expiry<const FP: usize> {
+    pub ttl: Duration,
+    /// How long expired entries are still reported as stale.
+    pub grace: Duration,
+    /// Deadlines as read from the map's clock.
+    deadlines: HashMap<(usize, Fingerprint<FP>), Duration>,
+    /// The bucket the next sweep starts at.
+    pub cursor: usize,
+}
+
+impl<const FP: usize> Expiry<FP> {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            grace: Duration::ZERO,
+            deadlines: HashMap::new(),
+            cursor: 0,
+        }
+    }
+
+    /// Starts the lifetime of an entry that was just written.
+    pub fn touch(&mut self, tag: usize, fp: Fingerprint<FP>, now: Duration) {
+        self.deadlines.insert((tag, fp), now + self.ttl);
+    }
+
+    pub fn forget(&mut self, tag: usize, fp: Fingerprint<FP>) {
+        self.deadlines.remove(&(tag, fp));
+    }
+
+    /// Whether the entry outlived its deadline. Entries written before the
+    /// TTL was set have none and never expire.
+    pub fn is_expired(&self, tag: usize, fp: Fingerprint<FP>, now: Duration) -> bool {
+        self.deadlines.get(&(tag, fp)).is_some_and(|&deadline| deadline <= now)
+    }
+
+    /// Whether the entry outlived its deadline and the grace period after
+    /// it, which makes it due for sweeping.
+    pub fn is_gone(&self, tag: usize, fp: Fingerprint<FP>, now: Duration) -> bool {
+        self.deadlines.get(&(tag, fp)).is_some_and(|&deadline| deadline + self.grace <= now)
+    }
+
+    pub fn clear(&mut self) {
+        self.deadlines.clear();
+        self.cursor = 0;
+    }
+
+    /// Number of bytes the deadlines occupy.
+    pub fn memory_usage(&self) -> usize {
+        self.deadlines.capacity() * std::mem::size_of::<((usize, Fingerprint<FP>), Duration)>()
+    }
+}
+
+/// A background thread sweeping a shared map in TTL mode at a fixed
+/// interval, see `CuckooMap::sweep`.
+///
+/// The thread stops when the sweeper is stopped or dropped. It holds the
+/// map's lock only for one sweep of `budget_buckets` buckets at a time.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::{CuckooMap, Sweeper};
+/// use std::collections::hash_map::DefaultHasher;
+/// use std::sync::{Arc, Mutex};
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+/// map.set_ttl(Duration::from_millis(10));
+/// map.insert_or_update("session", [1]).unwrap();
+///
+/// let map = Arc::new(Mutex::new(map));
+/// let sweeper = Sweeper::spawn(Arc::clone(&map), Duration::from_millis(5), 64);
+/// thread::sleep(Duration::from_millis(100));
+/// sweeper.stop();
+/// assert!(map.lock().unwrap().is_empty());
+/// ```
+pub struct Sweeper {
+    stop: Option<Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Sweeper {
+    /// Sweeps `budget_buckets` buckets of `map` every `interval`.
+    pub fn spawn<H, const FP: usize, const V: usize>(
+        map: Arc<Mutex<CuckooMap<H, FP, V>>>,
+        interval: Duration,
+        budget_buckets: usize,
+    ) -> Self
+    where
+        H: Hasher + Default + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                map.lock().unwrap_or_else(PoisonError::into_inner).sweep(budget_buckets);
+            }
+        });
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Stops the thread and waits for it to finish its current sweep.
+    pub fn stop(mut self) {
+        self.shut_down();
+    }
+
+    fn shut_down(&mut self) {
+        // dropping the sender wakes the thread up
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        self.shut_down();
+    }
+}
@@ -0,0 +1,705 @@
+use crate::bucket::{Entry, Fingerprint, ENTRIES_PER_BUCKET, FINGERPRINT_SIZE, VALUE_SIZE};
+use crate::util::{crc32, get_hash64};
+use crate::{
+    mask_for, CuckooMap, IndexScheme, Layout, SnapshotMetadata, Watermarks, MAX_GROWTHS, MAX_STASH, MAX_TAGS, MAX_WAYS,
+};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use std::cmp;
+use std::convert::TryInto;
+use std::error::Error as StdError;
+use std::fmt;
+use std::hash::Hasher;
+use std::io;
+
+/// Hashed to tell apart maps built with different hashers, which place
+/// the same keys in different buckets.
+pub(crate) const HASHER_PROBE: &str = "cuckoomap";
+
+const MAGIC: &[u8; 4] = b"CKMP";
+/// Version of the format `to_bytes` writes. Readers reject newer versions,
+/// but skip fields appended to the configuration block.
+///
+/// Version 2 added the value schema to the configuration block, read as
+/// untagged from version 1. Version 3 added the watermarks after the
+/// metadata, read as zero from older versions.
+const VERSION: u8 = 3;
+
+/// Why `ExportedCuckooMap::from_bytes` or `CuckooMap::import` rejected
+/// their input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ImportError {
+    /// The input doesn't start with the magic number of an export.
+    NotAnExport,
+    /// Written in a newer version of the format.
+    UnsupportedVersion(u8),
+    /// The checksum doesn't match, the input got corrupted.
+    ChecksumMismatch,
+    /// Exported from a map with another fingerprint or value size, given
+    /// in bytes.
+    LayoutMismatch { fingerprint_size: usize, value_size: usize },
+    /// Exported from a map with a different hasher.
+    HasherMismatch,
+    /// The input ends early.
+    Truncated,
+    /// The checksum matches but the contents don't add up, e.g. the number
+    /// of entries differs from the entries in the buckets.
+    Invalid,
+    /// The input was encrypted, but doesn't decrypt to a map with the key.
+    DecryptionFailed,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::NotAnExport => f.write_str("NotAnExport"),
+            ImportError::UnsupportedVersion(version) => write!(f, "UnsupportedVersion({})", version),
+            ImportError::ChecksumMismatch => f.write_str("ChecksumMismatch"),
+            ImportError::LayoutMismatch {
+                fingerprint_size,
+                value_size,
+            } => write!(f, "LayoutMismatch({}, {})", fingerprint_size, value_size),
+            ImportError::HasherMismatch => f.write_str("HasherMismatch"),
+            ImportError::Truncated => f.write_str("Truncated"),
+            ImportError::Invalid => f.write_str("Invalid"),
+            ImportError::DecryptionFailed => f.write_str("DecryptionFailed"),
+        }
+    }
+}
+
+impl StdError for ImportError {}
+
+/// The entries of a `CuckooMap` as raw bytes, returned by `CuckooMap::export`
+/// and turned back into a map by `CuckooMap::from`.
+///
+/// Besides the buckets and the stash an export holds what decides where
+/// keys are placed: the hasher, ways, index scheme, growths and hash seed,
+/// along with the `SnapshotMetadata` and `Watermarks` of the map. Other settings start out
+/// with their defaults after importing, as after `with_capacity`.
+///
+/// `to_bytes` encodes it for storing it in Redis or a file, e.g., and
+/// `from_bytes` decodes it, detecting corruption with a checksum.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::{CuckooMap, ExportedCuckooMap};
+/// use std::collections::hash_map::DefaultHasher;
+///
+/// let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+/// map.insert_or_update("hello", [1]).unwrap();
+///
+/// let bytes = map.export().to_bytes();
+/// let exported = ExportedCuckooMap::from_bytes(&bytes).unwrap();
+/// assert_eq!(exported.len(), 1);
+/// let restored = CuckooMap::<DefaultHasher>::from(exported);
+/// assert_eq!(restored.get("hello"), Some([1]));
+/// ```
+#[derive(Clone)]
+pub struct ExportedCuckooMap<const FP: usize = FINGERPRINT_SIZE, const V: usize = VALUE_SIZE> {
+    length: usize,
+    /// The hash of `HASHER_PROBE`.
+    hasher: u64,
+    ways: usize,
+    index_scheme: IndexScheme,
+    growths: u32,
+    hash_seed: Option<u64>,
+    value_schema: Option<u8>,
+    /// The fingerprint and value of every slot, bucket by bucket.
+    values: Vec<u8>,
+    /// Stashed entries along with the bucket they were kicked out of.
+    stash: Vec<(usize, Entry<FP, V>)>,
+    metadata: SnapshotMetadata,
+    watermarks: Watermarks,
+}
+
+impl<const FP: usize, const V: usize> ExportedCuckooMap<FP, V> {
+    /// Number of entries of the exported map.
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Whether the exported map was empty.
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Number of buckets of the exported map.
+    pub fn buckets(&self) -> usize {
+        self.values.len() / (ENTRIES_PER_BUCKET * (FP + V))
+    }
+
+    /// The metadata of the exported map, with the number of entries noted.
+    pub fn metadata(&self) -> &SnapshotMetadata {
+        &self.metadata
+    }
+
+    /// The value schema the exported map was tagged with, see
+    /// `CuckooMap::set_value_schema`.
+    pub fn value_schema(&self) -> Option<u8> {
+        self.value_schema
+    }
+
+    /// The watermarks of the exported map, zero if it was exported before
+    /// they were.
+    pub fn watermarks(&self) -> Watermarks {
+        self.watermarks
+    }
+
+    /// Encodes the export, all numbers in little endian:
+    ///
+    /// - the magic number `CKMP` and the format version, 1 byte
+    /// - the configuration block, prefixed with its length as 2 bytes:
+    ///   the fingerprint and value size, the hash of the hasher, the ways,
+    ///   index scheme, growths, hash seed, number of buckets and value schema
+    /// - the number of entries, 8 bytes, and the bucket bytes
+    /// - the stash, the metadata and the watermarks
+    /// - the CRC-32 of everything before, 4 bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.values.len() + 128);
+        self.write(&mut out).expect("writing to a Vec doesn't fail");
+        let checksum = crc32(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+        out
+    }
+
+    fn write(&self, out: &mut Vec<u8>) -> io::Result<()> {
+        out.extend_from_slice(MAGIC);
+        out.write_u8(VERSION)?;
+
+        let mut config = Vec::new();
+        config.write_u8(FP as u8)?;
+        config.write_u32::<LittleEndian>(V as u32)?;
+        config.write_u64::<LittleEndian>(self.hasher)?;
+        config.write_u8(self.ways as u8)?;
+        config.write_u8(scheme_code(self.index_scheme))?;
+        config.write_u8(self.growths as u8)?;
+        write_option(&mut config, self.hash_seed)?;
+        config.write_u64::<LittleEndian>(self.buckets() as u64)?;
+        write_schema(&mut config, self.value_schema)?;
+        out.write_u16::<LittleEndian>(config.len() as u16)?;
+        out.extend_from_slice(&config);
+
+        out.write_u64::<LittleEndian>(self.length as u64)?;
+        out.extend_from_slice(&self.values);
+
+        out.write_u8(self.stash.len() as u8)?;
+        for (j, entry) in &self.stash {
+            out.write_u64::<LittleEndian>(*j as u64)?;
+            out.extend_from_slice(&entry.fingerprint.data);
+            out.extend_from_slice(&entry.value);
+        }
+
+        write_metadata(out, &self.metadata)?;
+        write_watermarks(out, &self.watermarks)
+    }
+
+    /// Decodes the bytes of `to_bytes`, checking the magic number, the
+    /// version, the checksum, the fingerprint and value size and that the
+    /// contents are consistent, in this order.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ImportError> {
+        let raw = RawExport::parse::<FP, V>(bytes)?;
+        let stash = raw.stash.chunks_exact(8 + FP + V).map(|bytes| {
+            let (j, entry) = bytes.split_at(8);
+            (u64::from_le_bytes(j.try_into().expect("8 bytes")) as usize, read_entry(entry))
+        });
+        Ok(Self {
+            length: raw.length,
+            hasher: raw.hasher,
+            ways: raw.layout.ways,
+            index_scheme: raw.layout.index_scheme,
+            growths: raw.layout.growths,
+            hash_seed: raw.hash_seed,
+            value_schema: raw.value_schema,
+            values: raw.values.to_vec(),
+            stash: stash.collect(),
+            metadata: raw.metadata,
+            watermarks: raw.watermarks,
+        })
+    }
+}
+
+/// The parts of an export, borrowing the buckets and the stash from its
+/// bytes.
+pub(crate) struct RawExport<'a> {
+    pub length: usize,
+    /// The hash of `HASHER_PROBE`.
+    pub hasher: u64,
+    pub layout: Layout,
+    pub hash_seed: Option<u64>,
+    pub value_schema: Option<u8>,
+    /// The fingerprint and value of every slot, bucket by bucket.
+    pub values: &'a [u8],
+    /// Stashed entries, each the bucket it was kicked out of as 8 bytes
+    /// followed by the entry.
+    pub stash: &'a [u8],
+    pub metadata: SnapshotMetadata,
+    pub watermarks: Watermarks,
+}
+
+impl<'a> RawExport<'a> {
+    /// Splits up the bytes of `to_bytes`, see `ExportedCuckooMap::from_bytes`.
+    pub fn parse<const FP: usize, const V: usize>(bytes: &'a [u8]) -> Result<Self, ImportError> {
+        if bytes.len() < MAGIC.len() + 1 || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(ImportError::NotAnExport);
+        }
+        let version = bytes[MAGIC.len()];
+        if version > VERSION {
+            return Err(ImportError::UnsupportedVersion(version));
+        }
+        if bytes.len() < MAGIC.len() + 1 + 4 {
+            return Err(ImportError::Truncated);
+        }
+        let (contents, checksum) = bytes.split_at(bytes.len() - 4);
+        if crc32(contents) != u32::from_le_bytes(checksum.try_into().expect("checksums are 4 bytes")) {
+            return Err(ImportError::ChecksumMismatch);
+        }
+
+        let mut input = Reader(&contents[MAGIC.len() + 1..]);
+        let raw = Self::read::<FP, V>(&mut input, version)?;
+        if !input.0.is_empty() {
+            return Err(ImportError::Invalid);
+        }
+        Ok(raw)
+    }
+
+    fn read<const FP: usize, const V: usize>(input: &mut Reader<'a>, version: u8) -> Result<Self, ImportError> {
+        let config_len = usize::from(input.u16()?);
+        let mut config = Reader(input.bytes(config_len)?);
+        let (fingerprint_size, value_size) = (usize::from(config.u8()?), config.u32()? as usize);
+        if fingerprint_size != FP || value_size != V {
+            return Err(ImportError::LayoutMismatch {
+                fingerprint_size,
+                value_size,
+            });
+        }
+        let hasher = config.u64()?;
+        let ways = usize::from(config.u8()?);
+        let index_scheme = scheme_from_code(config.u8()?).ok_or(ImportError::Invalid)?;
+        let growths = u32::from(config.u8()?);
+        let hash_seed = config.option()?;
+        let bucket_count = config.u64()? as usize;
+        if !valid_layout(bucket_count, index_scheme, ways, growths) {
+            return Err(ImportError::Invalid);
+        }
+        let value_schema = if version >= 2 { config.schema()? } else { None };
+
+        let length = input.u64()? as usize;
+        let size = bucket_count.checked_mul(ENTRIES_PER_BUCKET * (FP + V)).ok_or(ImportError::Invalid)?;
+        let values = input.bytes(size)?;
+
+        let stashed = usize::from(input.u8()?);
+        if stashed > MAX_STASH {
+            return Err(ImportError::Invalid);
+        }
+        let stash = input.bytes(stashed * (8 + FP + V))?;
+        for bytes in stash.chunks_exact(8 + FP + V) {
+            if u64::from_le_bytes(bytes[..8].try_into().expect("8 bytes")) >= bucket_count as u64 {
+                return Err(ImportError::Invalid);
+            }
+        }
+
+        let metadata = input.metadata()?;
+        let watermarks = if version >= 3 { input.watermarks()? } else { Watermarks::default() };
+
+        let placed = values.chunks_exact(FP + V).filter(|entry| entry[..FP] != [0; FP][..]).count();
+        if length != placed + stashed {
+            return Err(ImportError::Invalid);
+        }
+        Ok(Self {
+            length,
+            hasher,
+            layout: Layout {
+                len: bucket_count,
+                mask: mask_for(bucket_count),
+                ways,
+                index_scheme,
+                growths,
+            },
+            hash_seed,
+            value_schema,
+            values,
+            stash,
+            metadata,
+            watermarks,
+        })
+    }
+}
+
+/// Whether entries can be placed in `bucket_count` buckets with the given
+/// index scheme and ways, and found again after `growths` doublings.
+pub(crate) fn valid_layout(bucket_count: usize, index_scheme: IndexScheme, ways: usize, growths: u32) -> bool {
+    bucket_count > 0
+        && (index_scheme == IndexScheme::Cyclic || bucket_count.is_power_of_two())
+        && (2..=MAX_WAYS).contains(&ways)
+        && (ways == 2 || index_scheme == IndexScheme::Xor)
+        && (growths == 0 || (ways == 2 && index_scheme == IndexScheme::Xor))
+        && growths <= MAX_GROWTHS
+}
+
+pub(crate) fn scheme_code(index_scheme: IndexScheme) -> u8 {
+    match index_scheme {
+        IndexScheme::Xor => 0,
+        IndexScheme::Cyclic => 1,
+        IndexScheme::Local => 2,
+    }
+}
+
+pub(crate) fn scheme_from_code(code: u8) -> Option<IndexScheme> {
+    match code {
+        0 => Some(IndexScheme::Xor),
+        1 => Some(IndexScheme::Cyclic),
+        2 => Some(IndexScheme::Local),
+        _ => None,
+    }
+}
+
+pub(crate) fn write_option(out: &mut Vec<u8>, value: Option<u64>) -> io::Result<()> {
+    out.write_u8(value.is_some() as u8)?;
+    out.write_u64::<LittleEndian>(value.unwrap_or(0))
+}
+
+/// Writes a value schema id after a flag telling whether there is one.
+pub(crate) fn write_schema(out: &mut Vec<u8>, id: Option<u8>) -> io::Result<()> {
+    out.write_u8(id.is_some() as u8)?;
+    out.write_u8(id.unwrap_or(0))
+}
+
+/// Writes the creation time, the number of items, the source and the tags
+/// of `metadata`.
+pub(crate) fn write_metadata(out: &mut Vec<u8>, metadata: &SnapshotMetadata) -> io::Result<()> {
+    write_option(out, metadata.created_millis())?;
+    write_option(out, metadata.items())?;
+    match metadata.source() {
+        Some(source) => {
+            out.write_u8(1)?;
+            write_str(out, source)?;
+        }
+        None => out.write_u8(0)?,
+    }
+    out.write_u8(metadata.tags().count() as u8)?;
+    for (key, value) in metadata.tags() {
+        write_str(out, key)?;
+        write_str(out, value)?;
+    }
+    Ok(())
+}
+
+/// Writes the highest load factor as the bits of an `f64`, then the
+/// evictions and failures of `watermarks`.
+pub(crate) fn write_watermarks(out: &mut Vec<u8>, watermarks: &Watermarks) -> io::Result<()> {
+    out.write_u64::<LittleEndian>(watermarks.max_load_factor.to_bits())?;
+    out.write_u64::<LittleEndian>(watermarks.evictions)?;
+    out.write_u64::<LittleEndian>(watermarks.failures)
+}
+
+/// Writes a metadata string, at most `MAX_TAG_LEN` bytes, after its length.
+fn write_str(out: &mut Vec<u8>, s: &str) -> io::Result<()> {
+    out.write_u8(s.len() as u8)?;
+    out.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+/// Reads an entry from its `FP + V` bytes.
+fn read_entry<const FP: usize, const V: usize>(bytes: &[u8]) -> Entry<FP, V> {
+    let (fingerprint, value) = bytes.split_at(FP);
+    Entry {
+        fingerprint: Fingerprint {
+            data: fingerprint.try_into().expect("entries are FP + V bytes"),
+        },
+        value: value.try_into().expect("entries are FP + V bytes"),
+    }
+}
+
+/// Reads the numbers and strings `to_bytes` writes off the front of a
+/// slice, failing with `Truncated` where it ends early.
+pub(crate) struct Reader<'a>(pub &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], ImportError> {
+        if self.0.len() < len {
+            return Err(ImportError::Truncated);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, ImportError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, ImportError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().expect("2 bytes")))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, ImportError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().expect("4 bytes")))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, ImportError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().expect("8 bytes")))
+    }
+
+    pub fn option(&mut self) -> Result<Option<u64>, ImportError> {
+        let present = self.u8()? == 1;
+        let value = self.u64()?;
+        Ok(if present { Some(value) } else { None })
+    }
+
+    /// Reads a value schema id written by `write_schema`.
+    pub fn schema(&mut self) -> Result<Option<u8>, ImportError> {
+        let present = self.u8()? == 1;
+        let id = self.u8()?;
+        Ok(if present { Some(id) } else { None })
+    }
+
+    pub fn string(&mut self) -> Result<String, ImportError> {
+        let len = usize::from(self.u8()?);
+        let bytes = self.bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| ImportError::Invalid)
+    }
+
+    /// Reads metadata written by `write_metadata`.
+    pub fn metadata(&mut self) -> Result<SnapshotMetadata, ImportError> {
+        let mut metadata = SnapshotMetadata::default();
+        if let Some(millis) = self.option()? {
+            metadata.set_created_millis(millis);
+        }
+        if let Some(items) = self.option()? {
+            metadata.set_items(items);
+        }
+        if self.u8()? == 1 {
+            metadata.set_source(&self.string()?);
+        }
+        let tags = usize::from(self.u8()?);
+        if tags > MAX_TAGS {
+            return Err(ImportError::Invalid);
+        }
+        for _ in 0..tags {
+            let key = self.string()?;
+            metadata.insert_tag(&key, &self.string()?);
+        }
+        Ok(metadata)
+    }
+
+    /// Reads watermarks written by `write_watermarks`.
+    pub fn watermarks(&mut self) -> Result<Watermarks, ImportError> {
+        let max_load_factor = f64::from_bits(self.u64()?);
+        if max_load_factor.is_nan() || max_load_factor < 0.0 {
+            return Err(ImportError::Invalid);
+        }
+        Ok(Watermarks {
+            max_load_factor,
+            evictions: self.u64()?,
+            failures: self.u64()?,
+        })
+    }
+}
+
+impl<H, const FP: usize, const V: usize> CuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    /// Exports the entries of the map, see `ExportedCuckooMap`.
+    pub fn export(&self) -> ExportedCuckooMap<FP, V> {
+        let mut values = Vec::with_capacity(self.buckets.len() * ENTRIES_PER_BUCKET * (FP + V));
+        for bucket in self.buckets.iter() {
+            for entry in bucket.entries() {
+                values.extend_from_slice(&entry.fingerprint.data);
+                values.extend_from_slice(&entry.value);
+            }
+        }
+        let mut metadata = self.metadata.clone();
+        metadata.set_items(self.len as u64);
+        ExportedCuckooMap {
+            length: self.len,
+            hasher: get_hash64::<_, H>(HASHER_PROBE),
+            ways: self.ways,
+            index_scheme: self.index_scheme,
+            growths: self.growths,
+            hash_seed: self.hash_seed,
+            value_schema: self.value_schema,
+            values,
+            stash: self.stash.clone(),
+            metadata,
+            watermarks: self.watermarks,
+        }
+    }
+
+    /// Decodes an export written by `ExportedCuckooMap::to_bytes` into a
+    /// map, like `from_bytes` followed by `from`, but failing with
+    /// `HasherMismatch` instead of panicking for another hasher.
+    pub fn import(bytes: &[u8]) -> Result<Self, ImportError> {
+        Self::import_exported(ExportedCuckooMap::from_bytes(bytes)?)
+    }
+
+    /// Restores `exported`, failing with `HasherMismatch` for another hasher.
+    pub(crate) fn import_exported(exported: ExportedCuckooMap<FP, V>) -> Result<Self, ImportError> {
+        if exported.hasher != get_hash64::<_, H>(HASHER_PROBE) {
+            return Err(ImportError::HasherMismatch);
+        }
+        Ok(Self::from(exported))
+    }
+}
+
+/// Restores an exported map.
+///
+/// # Panics
+///
+/// Panics if the map was exported with a different hasher.
+impl<H, const FP: usize, const V: usize> From<ExportedCuckooMap<FP, V>> for CuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    fn from(exported: ExportedCuckooMap<FP, V>) -> Self {
+        assert_eq!(
+            exported.hasher,
+            get_hash64::<_, H>(HASHER_PROBE),
+            "the map was exported with a different hasher"
+        );
+        let mut map = Self::with_buckets(exported.buckets(), exported.index_scheme);
+        for (k, bytes) in exported.values.chunks_exact(FP + V).enumerate() {
+            map.buckets[k / ENTRIES_PER_BUCKET].replace(k % ENTRIES_PER_BUCKET, read_entry(bytes));
+        }
+        map.len = exported.length;
+        map.stash_capacity = cmp::max(map.stash_capacity, exported.stash.len());
+        map.stash.reserve_exact(map.stash_capacity);
+        map.stash.extend(exported.stash);
+        map.ways = exported.ways;
+        map.growths = exported.growths;
+        map.hash_seed = exported.hash_seed;
+        map.value_schema = exported.value_schema;
+        map.metadata = exported.metadata;
+        map.watermarks = exported.watermarks;
+        map.note_load();
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CuckooMapView, ValueSchema};
+    use std::collections::hash_map::DefaultHasher;
+
+    #[test]
+    fn test_export_round_trip() {
+        let mut map = CuckooMap::<DefaultHasher, 4, 3>::with_capacity(1 << 10);
+        map.set_auto_grow(true);
+        map.set_hash_seed(7);
+        map.metadata_mut().set_source("test");
+        map.metadata_mut().insert_tag("run", "1");
+        for i in 0..3_000u32 {
+            map.insert_or_update(&i, [i as u8, 1, 2]).unwrap();
+        }
+        assert!(map.growths() > 0);
+
+        let bytes = map.export().to_bytes();
+        let restored = CuckooMap::<DefaultHasher, 4, 3>::from(ExportedCuckooMap::from_bytes(&bytes).unwrap());
+        assert_eq!(restored.len(), 3_000);
+        assert_eq!(restored.capacity(), map.capacity());
+        assert_eq!(restored.growths(), map.growths());
+        assert!((0..3_000u32).all(|i| restored.get(&i) == Some([i as u8, 1, 2])));
+        assert_eq!(restored.metadata().source(), Some("test"));
+        assert_eq!(restored.metadata().tag("run"), Some("1"));
+        assert_eq!(restored.metadata().items(), Some(3_000));
+        assert_eq!(restored.watermarks(), map.watermarks());
+        assert!(map.watermarks().evictions > 0);
+        assert!(map.watermarks().max_load_factor >= map.len() as f64 / map.capacity() as f64);
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 10);
+        map.insert_or_update("hello", [1]).unwrap();
+        let bytes = map.export().to_bytes();
+        assert_eq!(CuckooMap::<DefaultHasher, 2>::import(&bytes).unwrap().get("hello"), Some([1]));
+
+        let import = |bytes: &[u8]| CuckooMap::<DefaultHasher, 2>::import(bytes).err();
+        assert_eq!(import(b"PK\x03\x04"), Some(ImportError::NotAnExport));
+        let mut newer = bytes.clone();
+        newer[4] = VERSION + 1;
+        assert_eq!(import(&newer), Some(ImportError::UnsupportedVersion(VERSION + 1)));
+        let mut flipped = bytes.clone();
+        flipped[100] ^= 1;
+        assert_eq!(import(&flipped), Some(ImportError::ChecksumMismatch));
+        assert_eq!(import(&bytes[..bytes.len() - 1]), Some(ImportError::ChecksumMismatch));
+        assert_eq!(
+            ExportedCuckooMap::<2, 2>::from_bytes(&bytes).err(),
+            Some(ImportError::LayoutMismatch {
+                fingerprint_size: 2,
+                value_size: 1
+            })
+        );
+        assert_eq!(
+            CuckooMap::<fnv::FnvHasher, 2>::import(&bytes).err(),
+            Some(ImportError::HasherMismatch)
+        );
+
+        // a checksum matching contents that were cut short or don't add up
+        let resealed = |contents: &[u8]| [contents, &crc32(contents).to_le_bytes()].concat();
+        assert_eq!(import(&resealed(&bytes[..40])), Some(ImportError::Truncated));
+        let mut miscounted = bytes[..bytes.len() - 4].to_vec();
+        let length = 7 + miscounted[5] as usize;
+        miscounted[length] += 1;
+        assert_eq!(import(&resealed(&miscounted)), Some(ImportError::Invalid));
+    }
+
+    #[test]
+    #[should_panic(expected = "different hasher")]
+    fn test_export_other_hasher() {
+        let map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        let _ = CuckooMap::<fnv::FnvHasher>::from(map.export());
+    }
+
+    #[test]
+    fn test_export_value_schema() {
+        let schema = ValueSchema::<u8, 1>::new().register(2, |bytes| bytes[0]);
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        map.set_value_schema(2);
+        map.insert_or_update("hello", [7]).unwrap();
+        let bytes = map.export().to_bytes();
+
+        let restored = CuckooMap::<DefaultHasher>::import(&bytes).unwrap();
+        assert_eq!(restored.value_schema(), Some(2));
+        assert_eq!(restored.get_decoded("hello", &schema), Ok(Some(7)));
+        let view = CuckooMapView::<DefaultHasher>::new(&bytes).unwrap();
+        assert_eq!(view.value_schema(), Some(2));
+        assert_eq!(view.get_decoded("hello", &schema), Ok(Some(7)));
+
+        let untagged = CuckooMap::<DefaultHasher>::with_capacity(1 << 10).export().to_bytes();
+        assert_eq!(CuckooMap::<DefaultHasher>::import(&untagged).unwrap().value_schema(), None);
+    }
+
+    #[test]
+    fn test_export_watermarks() {
+        let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(64);
+        let mut failed = 0;
+        for i in 0..100u32 {
+            failed += map.insert_or_update(&i, [1]).is_err() as u64;
+        }
+        for i in 0..50u32 {
+            map.delete(&i);
+        }
+        let watermarks = map.watermarks();
+        assert_eq!(watermarks.failures, failed);
+        assert!(failed > 0 && watermarks.evictions > 0);
+        assert!(watermarks.max_load_factor > 0.9);
+        map.clear();
+        assert_eq!(map.watermarks(), watermarks);
+
+        let bytes = map.export().to_bytes();
+        assert_eq!(ExportedCuckooMap::<2, 1>::from_bytes(&bytes).unwrap().watermarks(), watermarks);
+        assert_eq!(CuckooMap::<DefaultHasher, 2>::import(&bytes).unwrap().watermarks(), watermarks);
+        assert_eq!(CuckooMapView::<DefaultHasher, 2>::new(&bytes).unwrap().watermarks(), watermarks);
+
+        // version 2 ends with the metadata
+        let mut old = bytes[..bytes.len() - 4 - 24].to_vec();
+        old[4] = 2;
+        old.extend_from_slice(&crc32(&old).to_le_bytes());
+        assert_eq!(CuckooMap::<DefaultHasher, 2>::import(&old).unwrap().watermarks(), Watermarks::default());
+    }
+}
@@ -0,0 +1,130 @@
+use crate::bucket::Fingerprint;
+
+/// Lines per set of the hot cache.
+const WAYS: usize = 4;
+
+#[derive(Clone, Copy)]
+struct Line<const FP: usize, const V: usize> {
+    /// The smallest candidate bucket of the cached key, which together with
+    /// the fingerprint identifies the entry a lookup resolves to.
+    bucket: usize,
+    fingerprint: Fingerprint<FP>,
+    value: [u8; V],
+    referenced: bool,
+}
+
+#[derive(Clone, Copy)]
+struct Set<const FP: usize, const V: usize> {
+    lines: [Line<FP, V>; WAYS],
+    hand: usize,
+}
+
+/// A small set-associative cache of recently read `(fingerprint, value)`
+/// pairs, replacing lines within a set with the CLOCK algorithm.
+///
+/// Lines are tagged with the fingerprint and the smallest candidate bucket
+/// of a key, which are all a lookup in the table depends on, so the map
+/// only has to invalidate that tag when it writes the entry.
+pub(crate) struct HotCache<const FP: usize, const V: usize> {
+    sets: Box<[Set<FP, V>]>,
+}
+
+impl<const FP: usize, const V: usize> HotCache<FP, V> {
+    /// Creates a cache holding about `capacity` entries.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let line = Line {
+            bucket: 0,
+            fingerprint: Fingerprint::empty(),
+            value: [0; V],
+            referenced: false,
+        };
+        let n_sets = (capacity / WAYS).max(1).next_power_of_two();
+        Self {
+            sets: vec![Set { lines: [line; WAYS], hand: 0 }; n_sets].into_boxed_slice(),
+        }
+    }
+
+    /// Number of entries the cache holds at most.
+    pub fn capacity(&self) -> usize {
+        self.sets.len() * WAYS
+    }
+
+    fn set_mut(&mut self, bucket: usize, fingerprint: Fingerprint<FP>) -> &mut Set<FP, V> {
+        let mut index = bucket;
+        for &byte in &fingerprint.data {
+            index = index.rotate_left(8) ^ byte as usize;
+        }
+        let n_sets = self.sets.len();
+        &mut self.sets[index % n_sets]
+    }
+
+    /// Returns the cached value, marking the line as recently used.
+    pub fn get(&mut self, bucket: usize, fingerprint: Fingerprint<FP>) -> Option<[u8; V]> {
+        let set = self.set_mut(bucket, fingerprint);
+        let line = set.lines.iter_mut().find(|l| l.fingerprint == fingerprint && l.bucket == bucket)?;
+        line.referenced = true;
+        Some(line.value)
+    }
+
+    /// Caches a value, replacing the first line the clock hand finds that
+    /// wasn't used since the hand last passed it.
+    pub fn insert(&mut self, bucket: usize, fingerprint: Fingerprint<FP>, value: [u8; V]) {
+        let set = self.set_mut(bucket, fingerprint);
+        let new = Line { bucket, fingerprint, value, referenced: false };
+        if let Some(line) = set.lines.iter_mut().find(|l| l.fingerprint == fingerprint && l.bucket == bucket) {
+            *line = new;
+            return;
+        }
+
+        loop {
+            let line = &mut set.lines[set.hand];
+            set.hand = (set.hand + 1) % WAYS;
+            if line.fingerprint.is_empty() || !line.referenced {
+                *line = new;
+                return;
+            }
+            line.referenced = false;
+        }
+    }
+
+    /// Drops the cached value, if any.
+    pub fn remove(&mut self, bucket: usize, fingerprint: Fingerprint<FP>) {
+        let set = self.set_mut(bucket, fingerprint);
+        if let Some(line) = set.lines.iter_mut().find(|l| l.fingerprint == fingerprint && l.bucket == bucket) {
+            line.fingerprint = Fingerprint::empty();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for set in self.sets.iter_mut() {
+            for line in set.lines.iter_mut() {
+                line.fingerprint = Fingerprint::empty();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_keeps_referenced_lines() {
+        let mut cache = HotCache::<1, 1>::with_capacity(WAYS);
+        let fp = |b| Fingerprint::from_data([b]).unwrap();
+        for b in 1..=WAYS as u8 {
+            cache.insert(0, fp(b), [b]);
+        }
+        assert_eq!(cache.get(0, fp(1)), Some([1]));
+
+        // the hand skips the referenced line and replaces the next one
+        cache.insert(0, fp(9), [9]);
+        assert_eq!(cache.get(0, fp(1)), Some([1]));
+        assert_eq!(cache.get(0, fp(2)), None);
+        assert_eq!(cache.get(0, fp(9)), Some([9]));
+
+        cache.remove(0, fp(9));
+        assert_eq!(cache.get(0, fp(9)), None);
+        assert_eq!(cache.get(1, fp(1)), None);
+    }
+}
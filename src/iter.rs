@@ -0,0 +1,25 @@
+use crate::bucket::{Entry, Fingerprint, FINGERPRINT_SIZE, VALUE_SIZE};
+
+use std::vec;
+
+/// An owning iterator over the `(fingerprint, value)` entries of a `CuckooMap`,
+/// as returned by `CuckooMap::into_iter`.
+#[derive(Clone)]
+pub struct IntoIter<const FP: usize = FINGERPRINT_SIZE, const V: usize = VALUE_SIZE> {
+    pub(crate) entries: vec::IntoIter<Entry<FP, V>>,
+}
+
+impl<const FP: usize, const V: usize> Iterator for IntoIter<FP, V> {
+    type Item = (Fingerprint<FP>, [u8; V]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries
+            .by_ref()
+            .find(|entry| !entry.fingerprint.is_empty())
+            .map(|entry| (entry.fingerprint, entry.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.entries.len()))
+    }
+}
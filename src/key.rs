@@ -0,0 +1,36 @@
+use std::hash::Hash;
+
+/// A key whose hash stays stable when its type is refactored.
+///
+/// `#[derive(Hash)]` hashes fields in declaration order, so reordering the
+/// fields of a composite key silently moves every key to other buckets.
+/// `#[derive(CuckooKey)]`, available with the `derive` feature, instead
+/// hashes the fields in an order given per field, prefixed by a version that
+/// can be bumped when the hashing scheme has to change on purpose.
+///
+/// # Examples
+///
+/// ```
+/// # use cuckoomap_derive::CuckooKey;
+/// use cuckoomap::CuckooMap;
+///
+/// #[derive(CuckooKey)]
+/// #[cuckoo_key(version = 1)]
+/// struct Session {
+///     #[cuckoo_key(order = 1)]
+///     user: u64,
+///     #[cuckoo_key(order = 0)]
+///     tenant: u32,
+///     #[cuckoo_key(skip)]
+///     label: String,
+/// }
+///
+/// let mut map = CuckooMap::new();
+/// let session = Session { user: 7, tenant: 1, label: "web".into() };
+/// map.insert_or_update(&session, [1]).unwrap();
+/// assert_eq!(map.get(&Session { label: "api".into(), ..session }), Some([1]));
+/// ```
+pub trait CuckooKey: Hash {
+    /// Version of the key's hashing scheme, hashed before the fields.
+    const KEY_VERSION: u32;
+}
@@ -16,63 +16,500 @@
 //! extern crate cuckoomap;
 //! ```
 
+mod access;
+mod atomic;
+#[cfg(feature = "arrow")]
+mod arrow;
 mod bucket;
+mod builder;
+mod clock;
+mod compat;
+mod computed;
+mod cpp;
+mod diagnostics;
+mod dynamic;
+#[cfg(feature = "encryption")]
+mod encryption;
+mod eviction;
+mod expiry;
+mod export;
+mod hot;
+mod iter;
+mod key;
+mod metadata;
+#[cfg(all(feature = "mmap", unix))]
+mod mmap;
+mod mock;
+pub mod packed;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod policy;
+mod rate_limit;
+#[cfg(feature = "replay")]
+pub mod replay;
+mod scalable;
+#[cfg(feature = "serde_support")]
+mod serialize;
+mod sharded;
+pub mod slots;
+mod typed;
 mod util;
+mod value;
+mod view;
+
+pub use crate::atomic::AtomicCuckooMap;
+pub use crate::bucket::Fingerprint;
+pub use crate::builder::CuckooMapBuilder;
+pub use crate::clock::{Clock, MockClock, SystemClock};
+pub use crate::compat::CuckooFilter;
+pub use crate::computed::ComputedCuckooMap;
+pub use crate::cpp::{CppCuckooFilter, MultiplyShift};
+pub use crate::diagnostics::{CapacityProjection, HasherQuality};
+pub use crate::dynamic::{DynCuckooMap, HasherKind, ParseHasherKindError};
+#[cfg(feature = "encryption")]
+pub use crate::encryption::EncryptionKey;
+pub use crate::expiry::Sweeper;
+pub use crate::export::{ExportedCuckooMap, ImportError};
+pub use crate::eviction::{
+    EvictionCandidate, EvictionStrategy, LruEviction, PriorityEviction, RandomEviction, RoundRobinEviction,
+};
+pub use crate::iter::IntoIter;
+pub use crate::key::CuckooKey;
+pub use crate::metadata::{SnapshotMetadata, MAX_TAGS, MAX_TAG_LEN};
+#[cfg(feature = "derive")]
+pub use cuckoomap_derive::CuckooKey;
+pub use crate::mock::MockCuckooMap;
+#[cfg(all(feature = "mmap", unix))]
+pub use crate::mmap::{MappedCuckooMap, PersistentCuckooMap};
+pub use crate::policy::{DuplicatePolicy, IndexScheme, KickPolicy, MergeRule, PathSearch, UpdatePolicy};
+pub use crate::rate_limit::RateLimiter;
+pub use crate::scalable::ScalableCuckooMap;
+pub use crate::sharded::ShardedCuckooMap;
+pub use crate::typed::TypedCuckooMap;
+pub use crate::value::{SchemaError, ValueCodec, ValueLayout, ValueSchema};
+pub use crate::view::{CuckooMapView, SnapshotReader};
 
-use crate::bucket::{Bucket, Fingerprint};
-use crate::util::{get_alt_index, get_fai, FaI};
+use crate::access::{AccessTracker, WriteOrder};
+use crate::bucket::{Bucket, BucketArray, Entry};
+use crate::eviction::RecencyRing;
+use crate::expiry::Expiry;
+use crate::export::valid_layout;
+use crate::hot::HotCache;
+use crate::slots::{PathNode, SlotAccess};
+use crate::util::{
+    get_alt_index, get_cyclic_alt_index, get_dary_indexes, get_local_alt_index, get_fai_from_hash, get_migrated_index, get_seeded_hash64, get_shard, prefetch, remix_hash, FaI,
+};
 
 use std::cmp;
 use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
 use std::error::Error as StdError;
 use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::iter::repeat;
 use std::marker::PhantomData;
 use std::mem;
+use std::ops::ControlFlow;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
 
-use bucket::VALUE_SIZE;
-#[cfg(feature = "serde_support")]
-use serde_derive::{Deserialize, Serialize};
+use bucket::{ENTRIES_PER_BUCKET, FINGERPRINT_SIZE, VALUE_SIZE};
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+
+/// Like `debug_assert!`, but only checked with the `strict-debug` feature.
+/// Used to turn silent anomalies into panics while debugging misuse.
+macro_rules! strict_assert {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "strict-debug")]
+        debug_assert!($($arg)*);
+    };
+}
+
+/// Number of keys `insert_batch` hashes and prefetches ahead of placing them.
+const BATCH_WINDOW: usize = 16;
+
+/// Keys `get_many` hashes ahead of the one it probes.
+const PREFETCH_DISTANCE: usize = 16;
+
+/// Tables `project_capacity` fills with the sample.
+const PROJECTION_TRIALS: u64 = 8;
 
-/// If insertion fails, we will retry this many times.
+/// Fewest keys `project_capacity` projects from.
+const MIN_PROJECTION_SAMPLE: usize = 64;
+
+/// Callback set with `CuckooMap::set_on_store`.
+type StoreHook<const V: usize> = Box<dyn FnMut(u64, [u8; V]) + Send + Sync>;
+
+/// Callback set with `CuckooMap::set_value_validator`.
+type ValueValidator<const V: usize> = Box<dyn Fn(&[u8; V]) -> Result<(), &'static str> + Send + Sync>;
+
+/// Callback set with `CuckooMap::set_on_resize_progress`.
+type ResizeHook = Box<dyn FnMut(ResizeProgress) + Send + Sync>;
+
+/// Number of buckets a growth migrates between two progress reports.
+const RESIZE_PROGRESS_STEP: usize = 4096;
+
+/// Default number of relocations an insert may make, see
+/// `CuckooMap::set_max_kicks`.
 pub const MAX_REBUCKET: u32 = 500;
 
+/// The largest number of candidate buckets per key, see `CuckooMap::set_ways`.
+pub const MAX_WAYS: usize = 4;
+
+/// How often an auto-growing map doubles its buckets at most, see
+/// `CuckooMap::set_auto_grow`.
+pub const MAX_GROWTHS: u32 = 4;
+
+/// How many entries the stash of a map holds at most, see
+/// `CuckooMap::set_stash_capacity`.
+pub const MAX_STASH: usize = 8;
+
+/// How many entries `finalize` inspects at most in search of a chain
+/// that lets an entry move home, bounding the search in crowded maps.
+const MAX_CHAIN_NODES: usize = 256;
+
+/// An entry `finalize` may move into its primary bucket, from the bucket
+/// it is stored in.
+struct Homecomer<const FP: usize> {
+    fp: Fingerprint<FP>,
+    at: usize,
+    primary: usize,
+    home: bool,
+}
+
+/// Stash capacity of a newly constructed map.
+const DEFAULT_STASH_CAPACITY: usize = 4;
+
+/// The largest number of buckets a lookup probes: two per generation of an
+/// auto-growing map.
+const MAX_CANDIDATES: usize = 2 * (MAX_GROWTHS as usize + 1);
+
+/// Buckets a key may be stored in, the first `.1` of `.0` are valid.
+type Candidates = ([usize; MAX_CANDIDATES], usize);
+
+/// What decides the candidate buckets of a key besides the hasher: the
+/// number of buckets, ways, index scheme and growths. Shared by maps and
+/// views of exported maps.
+#[derive(Clone, Copy)]
+pub(crate) struct Layout {
+    pub len: usize,
+    /// See `CuckooMap::mask`.
+    pub mask: usize,
+    pub ways: usize,
+    pub index_scheme: IndexScheme,
+    pub growths: u32,
+}
+
+impl Layout {
+    /// Reduces an index to one of the buckets.
+    #[inline]
+    fn reduce(&self, i: usize) -> usize {
+        if self.mask != 0 {
+            i & self.mask
+        } else {
+            i % self.len
+        }
+    }
+
+    /// See `CuckooMap::candidates`.
+    pub fn candidates<H: Hasher + Default, const FP: usize, const V: usize>(&self, fai: &FaI<FP>) -> Candidates {
+        if self.ways != 2 || self.index_scheme != IndexScheme::Xor {
+            return self.relocations::<H, FP, V>(fai.fp, fai.i1);
+        }
+
+        let len = self.len;
+
+        let alt_hash = fai.i1 ^ fai.i2;
+        let mut buckets = [0; MAX_CANDIDATES];
+        let mut n = 0;
+        for growths in 0..=self.growths {
+            // the primary bucket in a table of l buckets, moved along by
+            // every doubling since
+            let mut l = len >> growths;
+            let mut i = fai.i1 & (l - 1);
+            while l < len {
+                i = get_migrated_index(alt_hash, i, l);
+                l *= 2;
+            }
+            for &i in &[i, self.reduce(i ^ alt_hash)] {
+                if !buckets[..n].contains(&i) {
+                    buckets[n] = i;
+                    n += 1;
+                }
+            }
+        }
+        (buckets, n)
+    }
+
+    /// See `CuckooMap::relocations`.
+    pub fn relocations<H: Hasher + Default, const FP: usize, const V: usize>(
+        &self,
+        fp: Fingerprint<FP>,
+        i: usize,
+    ) -> Candidates {
+        let len = self.len;
+        let i = self.reduce(i);
+        let mut buckets = [0; MAX_CANDIDATES];
+        if self.ways == 2 {
+            let alt = match self.index_scheme {
+                IndexScheme::Xor => self.reduce(get_alt_index::<H, FP>(fp, i)),
+                IndexScheme::Cyclic => get_cyclic_alt_index::<H, FP>(fp, i, len),
+                IndexScheme::Local => get_local_alt_index::<H, FP>(fp, i, cmp::min(Bucket::<FP, V>::GROUP, len)),
+            };
+            buckets[..2].copy_from_slice(&[i, alt]);
+            return (buckets, if i == alt { 1 } else { 2 });
+        }
+
+        let (indexes, n) = get_dary_indexes::<H, FP>(fp, i, len, self.ways);
+        buckets[..n].copy_from_slice(&indexes[..n]);
+        if let Some(own) = buckets[..n].iter().position(|&j| j == i) {
+            buckets[..=own].rotate_right(1);
+        }
+        (buckets, n)
+    }
+}
+
+/// The `mask` of a table with `len` buckets.
+pub(crate) fn mask_for(len: usize) -> usize {
+    if len.is_power_of_two() {
+        len - 1
+    } else {
+        0
+    }
+}
+
 /// The default number of buckets.
 pub const DEFAULT_CAPACITY: usize = (1 << 20) - 1;
 
-#[derive(Debug)]
-pub enum CuckooError {
-    NotEnoughSpace,
+/// Details about a successful lookup, as returned by `CuckooMap::get_detailed`.
+///
+/// Only fingerprints are stored, so every hit is probabilistic: a key that was
+/// never inserted matches with roughly `false_positive_probability`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lookup<const V: usize = VALUE_SIZE> {
+    /// The stored value, after resolving duplicates per policy.
+    pub value: [u8; V],
+    /// Index of the bucket the value was read from.
+    pub bucket: usize,
+    /// Whether that bucket is the key's primary bucket.
+    pub primary: bool,
+    /// Whether the entry outlived its TTL and is only returned within the
+    /// grace period, see `CuckooMap::set_stale_grace`.
+    pub expired: bool,
+    /// Whether the fingerprint matched in more than one candidate bucket.
+    pub ambiguous: bool,
+    /// Estimated probability that an absent key matches at the current load.
+    pub false_positive_probability: f64,
+}
+
+/// What `CuckooMap::insert_best_effort` did with an entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InsertOutcome {
+    /// The entry was stored or updated without losing any other entry.
+    Stored,
+    /// The entry was stored, but another entry was kicked out of the map.
+    Evicted,
+    /// The entry was not stored.
+    Dropped,
+}
+
+/// How many `insert_best_effort` calls ended with each `InsertOutcome`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InsertStats {
+    pub stored: usize,
+    pub evicted: usize,
+    pub dropped: usize,
+}
+
+/// Accounts for every entry `CuckooMap::merge` processed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MergeReport {
+    /// Entries that were not present yet and got inserted.
+    pub added: usize,
+    /// Entries present in both maps, whose values were combined per merge rule.
+    pub conflicts: usize,
+    /// Inserts that ran out of space, each of which lost one entry.
+    pub dropped: usize,
+}
+
+/// The highest pressure a map was under, as returned by
+/// `CuckooMap::watermarks`.
+///
+/// Unlike the current load, these only ever rise. They are kept by `clear`,
+/// exports and persistent map files, so capacity planning sees the history
+/// of a map across restarts.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Watermarks {
+    /// The largest ratio of entries, stashed ones included, to slots.
+    pub max_load_factor: f64,
+    /// Entries inserts moved out of their bucket to make room.
+    pub evictions: u64,
+    /// Inserts that failed for lack of space.
+    pub failures: u64,
+}
+
+/// Where lookups found their keys, as returned by `CuckooMap::probe_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProbeStats {
+    /// Hits in the key's primary bucket.
+    pub primary_hits: usize,
+    /// Hits in the key's alternate bucket only.
+    pub alternate_hits: usize,
+    /// Entries `get_and_promote` and `finalize` moved back into their
+    /// primary bucket.
+    pub promotions: usize,
+}
+
+/// The counters behind `ProbeStats`, shared by lookups through `&self`.
+#[derive(Default)]
+struct ProbeCounters {
+    primary_hits: AtomicUsize,
+    alternate_hits: AtomicUsize,
+}
+
+/// How far a growth of the bucket array got, as passed to the hook set with
+/// `CuckooMap::set_on_resize_progress`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResizeProgress {
+    /// Buckets of the old array whose entries were moved so far.
+    pub migrated: usize,
+    /// Buckets of the old array.
+    pub total: usize,
+}
+
+/// Why an operation on a map failed.
+///
+/// Errors are small and `Copy`, so returning them never allocates, and new
+/// variants may be added without a breaking release.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CuckooError<const FP: usize = FINGERPRINT_SIZE, const V: usize = VALUE_SIZE> {
+    /// The entry left without a slot: the one kicked out last if the stash
+    /// is disabled, the one given otherwise. Callers can retry it, log it or
+    /// store it elsewhere.
+    NotEnoughSpace {
+        fingerprint: Fingerprint<FP>,
+        value: [u8; V],
+    },
+    CapacityMismatch,
+    Throttled,
+    WriteVerificationFailed,
+    /// The value validator rejected the value, for the given reason.
+    InvalidValue(&'static str),
+    /// Growing the buckets would have exceeded the memory cap. Carries the
+    /// entry left without a slot like `NotEnoughSpace`.
+    MemoryCapExceeded {
+        fingerprint: Fingerprint<FP>,
+        value: [u8; V],
+    },
 }
 
-impl fmt::Display for CuckooError {
+impl<const FP: usize, const V: usize> fmt::Display for CuckooError<FP, V> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str("NotEnoughSpace")
+        match self {
+            CuckooError::NotEnoughSpace { .. } => f.write_str("NotEnoughSpace"),
+            CuckooError::CapacityMismatch => f.write_str("CapacityMismatch"),
+            CuckooError::Throttled => f.write_str("Throttled"),
+            CuckooError::WriteVerificationFailed => f.write_str("WriteVerificationFailed"),
+            CuckooError::InvalidValue(reason) => write!(f, "InvalidValue: {}", reason),
+            CuckooError::MemoryCapExceeded { .. } => f.write_str("MemoryCapExceeded"),
+        }
     }
 }
 
-impl StdError for CuckooError {
+impl<const FP: usize, const V: usize> StdError for CuckooError<FP, V> {
     fn description(&self) -> &str {
-        "Not enough space to store this item, rebucketing failed."
+        match self {
+            CuckooError::NotEnoughSpace { .. } => "Not enough space to store this item, rebucketing failed.",
+            CuckooError::CapacityMismatch => "Both maps must have the same number of buckets.",
+            CuckooError::Throttled => "The insert rate limit was exceeded.",
+            CuckooError::WriteVerificationFailed => "A written bucket did not read back as written.",
+            CuckooError::InvalidValue(_) => "The value validator rejected the value.",
+            CuckooError::MemoryCapExceeded { .. } => "Growing the buckets would exceed the memory cap.",
+        }
+    }
+}
+
+/// The operations shared by `CuckooMap` and its exact test double `MockCuckooMap`.
+///
+/// Application code written against this trait can be unit-tested
+/// deterministically and use the real cuckoo map in production.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::{CuckooMap, MockCuckooMap, ProbabilisticMap};
+///
+/// fn remember<M: ProbabilisticMap>(map: &mut M, word: &str) -> bool {
+///     map.test_and_add(word, [1]).unwrap()
+/// }
+///
+/// let mut mock = MockCuckooMap::new();
+/// assert!(remember(&mut mock, "foo"));
+/// assert!(!remember(&mut mock, "foo"));
+///
+/// let mut real = CuckooMap::new();
+/// assert!(remember(&mut real, "foo"));
+/// assert_eq!(ProbabilisticMap::len(&real), 1);
+/// ```
+pub trait ProbabilisticMap<const V: usize = VALUE_SIZE, const FP: usize = FINGERPRINT_SIZE> {
+    /// Returns the value stored for `key`, see `CuckooMap::get`.
+    fn get<T: ?Sized + Hash>(&self, key: &T) -> Option<[u8; V]>;
+
+    /// Stores `value` for `key`, see `CuckooMap::insert_or_update`.
+    fn insert_or_update<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; V]) -> Result<(), CuckooError<FP, V>>;
+
+    /// Stores `value` for `key` unless the key is present,
+    /// see `CuckooMap::test_and_add`.
+    fn test_and_add<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; V]) -> Result<bool, CuckooError<FP, V>> {
+        if self.get(key).is_some() {
+            Ok(false)
+        } else {
+            self.insert_or_update(key, value).map(|_| true)
+        }
+    }
+
+    /// Removes `key`, see `CuckooMap::delete`.
+    fn delete<T: ?Sized + Hash>(&mut self, key: &T) -> bool;
+
+    /// Number of items in the map.
+    fn len(&self) -> usize;
+
+    /// Check if the map is empty.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
     }
+
+    /// Removes all items.
+    fn clear(&mut self);
 }
 
-/// A cuckoo filter class exposes a Bloomier filter interface,
-/// providing methods of add, delete, contains.
+/// type for value of the key-value pair
+/// gets saved inside an Entry together with the Key's Fingerprint
+#[derive(Clone, Copy)]
+pub struct Value(pub u8);
+
+/// A cuckoo map class exposes a Bloomier filter interface,
+/// providing methods of insert_or_update, delete, get.
+///
+/// Fingerprints are `FP` bytes wide, 1 by default and up to 4. Every extra
+/// byte lowers the false positive rate by a factor of 256 at the cost of
+/// one more byte per entry.
 ///
 /// # Examples
 ///
 /// ```
 /// extern crate cuckoomap;
-/// use cuckoomap::Value;
 ///
 /// let words = vec!["foo", "bar", "xylophone", "milagro"];
 /// let mut cf = cuckoomap::CuckooMap::new();
 ///
 /// let mut insertions = 0;
 /// for s in &words {
-///     if cf.test_and_add(s, Value::new()).unwrap() {
+///     if cf.test_and_add(s, [0]).unwrap() {
 ///         insertions += 1;
 ///     }
 /// }
@@ -80,25 +517,21 @@ impl StdError for CuckooError {
 /// assert_eq!(insertions, words.len());
 /// assert_eq!(cf.len(), words.len());
 ///
-/// // Re-add the first element.
-/// cf.add(words[0], Value::new());
+/// // Re-adding the first element only updates its value.
+/// cf.insert_or_update(words[0], [1]).unwrap();
 ///
-/// assert_eq!(cf.len(), words.len() + 1);
+/// assert_eq!(cf.len(), words.len());
+/// assert_eq!(cf.get(words[0]), Some([1]));
 ///
 /// for s in &words {
 ///     cf.delete(s);
 /// }
 ///
-/// assert_eq!(cf.len(), 1);
-/// assert!(!cf.is_empty());
-///
-/// cf.delete(words[0]);
-///
 /// assert_eq!(cf.len(), 0);
 /// assert!(cf.is_empty());
 ///
 /// for s in &words {
-///     if cf.test_and_add(s, Value::new()).unwrap() {
+///     if cf.test_and_add(s, [0]).unwrap() {
 ///         insertions += 1;
 ///     }
 /// }
@@ -106,21 +539,134 @@ impl StdError for CuckooError {
 /// cf.clear();
 ///
 /// assert!(cf.is_empty());
-///
 /// ```
-/// 
-
-/// type for value of the key-value pair
-/// gets saved inside an Entry together with the Key's Fingerprint
-#[derive(Clone, Copy)]
-pub struct Value(pub u8);
-
-pub struct CuckooMap<H> {
-    buckets: Box<[Bucket]>,
+pub struct CuckooMap<H, const FP: usize = FINGERPRINT_SIZE, const V: usize = VALUE_SIZE> {
+    buckets: BucketArray<FP, V>,
+    /// `buckets.len() - 1` if that is a power of two, so indexes reduce to
+    /// a bucket with `i & mask` instead of a division, 0 otherwise.
+    mask: usize,
     len: usize,
+    duplicate_policy: DuplicatePolicy<V>,
+    update_policy: UpdatePolicy<V>,
+    merge_rule: MergeRule,
+    max_kicks: u32,
+    kick_policy: KickPolicy,
+    path_search: PathSearch,
+    /// Search tree of `relocate_along_shortest_path`, kept to reuse its allocation.
+    path_nodes: Vec<PathNode>,
+    ways: usize,
+    index_scheme: IndexScheme,
+    hash_seed: Option<u64>,
+    value_schema: Option<u8>,
+    metadata: SnapshotMetadata,
+    watermarks: Watermarks,
+    auto_grow: bool,
+    growths: u32,
+    rate_limiter: Option<RateLimiter>,
+    clock: Box<dyn Clock + Send + Sync>,
+    rng: Box<dyn RngCore + Send + Sync>,
+    eviction: Box<dyn EvictionStrategy<FP, V> + Send + Sync>,
+    verify_writes: bool,
+    verification_failures: usize,
+    best_effort_stats: InsertStats,
+    on_store: Option<StoreHook<V>>,
+    value_validator: Option<ValueValidator<V>>,
+    on_resize_progress: Option<ResizeHook>,
+    probe_counters: Option<ProbeCounters>,
+    promotions: usize,
+    hot_cache: Option<Mutex<HotCache<FP, V>>>,
+    access_tracker: Option<AccessTracker>,
+    expiry: Option<Expiry<FP>>,
+    recency: Option<RecencyRing>,
+    /// Which copy of a key was written last, for `DuplicatePolicy::PreferNewest`.
+    write_order: Option<WriteOrder>,
+    /// Entries an insert couldn't find a slot for, along with the bucket
+    /// they were kicked out of.
+    stash: Vec<(usize, Entry<FP, V>)>,
+    stash_capacity: usize,
+    memory_cap: Option<usize>,
     _hasher: std::marker::PhantomData<H>,
 }
 
+impl<H, const FP: usize, const V: usize> ProbabilisticMap<V, FP> for CuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    fn get<T: ?Sized + Hash>(&self, key: &T) -> Option<[u8; V]> {
+        CuckooMap::get(self, key)
+    }
+
+    fn insert_or_update<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; V]) -> Result<(), CuckooError<FP, V>> {
+        CuckooMap::insert_or_update(self, key, value)
+    }
+
+    fn test_and_add<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; V]) -> Result<bool, CuckooError<FP, V>> {
+        CuckooMap::test_and_add(self, key, value)
+    }
+
+    fn delete<T: ?Sized + Hash>(&mut self, key: &T) -> bool {
+        CuckooMap::delete(self, key)
+    }
+
+    fn len(&self) -> usize {
+        CuckooMap::len(self)
+    }
+
+    fn clear(&mut self) {
+        CuckooMap::clear(self)
+    }
+}
+
+impl<H, const FP: usize, const V: usize> SlotAccess<FP> for CuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    fn fingerprint(&self, bucket: usize, slot: usize) -> Fingerprint<FP> {
+        self.buckets[bucket].fingerprints[slot]
+    }
+
+    fn relocations(&self, fp: Fingerprint<FP>, bucket: usize) -> slots::Relocations {
+        CuckooMap::relocations(self, fp, bucket)
+    }
+
+    fn find(&self, bucket: usize, fp: Fingerprint<FP>) -> Option<usize> {
+        self.buckets[bucket].find(fp)
+    }
+}
+
+impl<H, const FP: usize, const V: usize> IntoIterator for CuckooMap<H, FP, V> {
+    type Item = (Fingerprint<FP>, [u8; V]);
+    type IntoIter = IntoIter<FP, V>;
+
+    /// Consumes the map, yielding its `(fingerprint, value)` entries.
+    fn into_iter(self) -> IntoIter<FP, V> {
+        let entries: Vec<Entry<FP, V>> = self
+            .buckets
+            .iter()
+            .flat_map(Bucket::occupied)
+            .chain(self.stash.iter().map(|&(_, entry)| entry))
+            .collect();
+        IntoIter {
+            entries: entries.into_iter(),
+        }
+    }
+}
+
+/// The `prefix_bits` most significant bits of a fingerprint, which are the
+/// most significant bits of the key's hash.
+fn partition_prefix<const FP: usize>(fp: Fingerprint<FP>, prefix_bits: u32) -> u64 {
+    let bits = 8 * FP as u32;
+    assert!(prefix_bits <= bits, "a {} byte fingerprint has only {} bits", FP, bits);
+    let mut data = [0; 8];
+    data[8 - FP..].copy_from_slice(&fp.data);
+    let value = u64::from_be_bytes(data);
+    if prefix_bits == 0 {
+        0
+    } else {
+        value >> (bits - prefix_bits)
+    }
+}
+
 impl Default for CuckooMap<DefaultHasher> {
     fn default() -> Self {
         Self::new()
@@ -132,176 +678,3306 @@ impl CuckooMap<DefaultHasher> {
     pub fn new() -> Self {
         Self::with_capacity(DEFAULT_CAPACITY)
     }
+
+    /// Returns a builder to configure a map before constructing it.
+    pub fn builder() -> CuckooMapBuilder {
+        CuckooMapBuilder::new()
+    }
 }
 
-impl<H> CuckooMap<H>
+impl<H, const FP: usize, const V: usize> CuckooMap<H, FP, V>
 where
     H: Hasher + Default,
 {
     /// Constructs a Cuckoo Map with a given max capacity
     pub fn with_capacity(cap: usize) -> Self {
-        let capacity = cmp::max(1, cap.next_power_of_two());
+        let capacity = cmp::max(1, (cap / ENTRIES_PER_BUCKET).next_power_of_two());
+        Self::with_buckets(capacity, IndexScheme::Xor)
+    }
+
+    /// Constructs a map holding `cap` entries, rounded up to a whole bucket
+    /// only, instead of to the next power of two like `with_capacity`.
+    ///
+    /// The map uses `IndexScheme::Cyclic` to find the alternate buckets of
+    /// keys, so it fits a memory budget exactly, but it can neither grow nor
+    /// use more than 2 ways.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cuckoomap::{CuckooMap, IndexScheme};
+    /// use std::collections::hash_map::DefaultHasher;
+    ///
+    /// let mut map = CuckooMap::<DefaultHasher>::with_exact_capacity(3_000);
+    /// assert_eq!(map.capacity(), 3_000);
+    /// assert_eq!(map.index_scheme(), IndexScheme::Cyclic);
+    ///
+    /// map.insert_or_update("hello", [1]).unwrap();
+    /// assert_eq!(map.get("hello"), Some([1]));
+    /// ```
+    pub fn with_exact_capacity(cap: usize) -> Self {
+        let capacity = cmp::max(1, cap.div_ceil(ENTRIES_PER_BUCKET));
+        Self::with_buckets(capacity, IndexScheme::Cyclic)
+    }
 
+    fn with_buckets(capacity: usize, index_scheme: IndexScheme) -> Self {
+        Self::with_bucket_array(BucketArray::new(capacity), index_scheme)
+    }
+
+    fn with_bucket_array(buckets: BucketArray<FP, V>, index_scheme: IndexScheme) -> Self {
+        let capacity = buckets.len();
         Self {
-            buckets: repeat(Bucket::new())
-                .take(capacity)
-                .collect::<Vec<_>>()
-                .into_boxed_slice(),
+            buckets,
+            mask: mask_for(capacity),
             len: 0,
+            duplicate_policy: DuplicatePolicy::default(),
+            update_policy: UpdatePolicy::default(),
+            merge_rule: MergeRule::default(),
+            max_kicks: MAX_REBUCKET,
+            kick_policy: KickPolicy::default(),
+            path_search: PathSearch::default(),
+            path_nodes: Vec::new(),
+            ways: 2,
+            index_scheme,
+            hash_seed: None,
+            value_schema: None,
+            metadata: SnapshotMetadata::new(),
+            watermarks: Watermarks::default(),
+            auto_grow: false,
+            growths: 0,
+            rate_limiter: None,
+            clock: Box::new(SystemClock),
+            rng: Box::new(StdRng::from_entropy()),
+            eviction: Box::new(RandomEviction),
+            verify_writes: false,
+            verification_failures: 0,
+            best_effort_stats: InsertStats::default(),
+            on_store: None,
+            value_validator: None,
+            on_resize_progress: None,
+            probe_counters: None,
+            promotions: 0,
+            hot_cache: None,
+            access_tracker: None,
+            expiry: None,
+            recency: None,
+            write_order: None,
+            stash: Vec::with_capacity(DEFAULT_STASH_CAPACITY),
+            stash_capacity: DEFAULT_STASH_CAPACITY,
+            memory_cap: None,
             _hasher: PhantomData,
         }
     }
 
-    /// Checks if `key` is in the filter.
-    /// returns `Some([u8; VALUE_SIZE])` if key probably is in the map
-    /// returns `None` if key is definitely not in the map
-    pub fn get<T: ?Sized + Hash>(&self, key: &T) -> Option<[u8; VALUE_SIZE]> {
-        let FaI { fp, i1, i2 } = get_fai::<T, H>(key);
-        let len = self.buckets.len();
-        
-        if self.buckets[i1 % len].fingerprint == fp {
-            return Some(self.buckets[i1 % len].value)
-        }
+    /// Returns the shard in `0..n_shards` that owns `key`.
+    ///
+    /// The shard is derived from the unseeded hash of `key` with `H`, so every
+    /// node of a cluster using the same hasher routes a key to the same shard.
+    /// Routing ignores the hash seed, see `set_hash_seed`: maps of different
+    /// seeds agree on the shard, though they place the key differently.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n_shards` is zero.
+    pub fn shard_of<T: ?Sized + Hash>(key: &T, n_shards: usize) -> usize {
+        get_shard::<T, H>(key, n_shards)
+    }
 
-        if self.buckets[i2 % len].fingerprint == fp {
-            return Some(self.buckets[i2 % len].value)
-        }
-        
-        // not found
-        None
+    /// Returns which of the `1 << prefix_bits` partitions of
+    /// `iter_partition` holds `key`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_bits` exceeds the fingerprint's bits.
+    pub fn partition_of<T: ?Sized + Hash>(&self, key: &T, prefix_bits: u32) -> u64 {
+        partition_prefix(get_fai_from_hash::<H, FP>(self.key_hash(key)).fp, prefix_bits)
     }
 
-    /// Adds `key` along with a `value` to the filter. Returns `Ok` if the insertion was successful,
-    /// but could fail with a `NotEnoughSpace` error, especially when the filter
-    /// is nearing its capacity.
-    /// Note that while you can put any hashable type in the same filter, beware
-    /// for side effects like that the same number can have diferent hashes
-    /// depending on the type.
-    /// So for the filter, 4711i64 isn't the same as 4711u64.
+    /// Measures how evenly the hasher spreads `sample_keys` over this map's
+    /// buckets and fingerprints.
     ///
-    /// **Note:** When this returns `NotEnoughSpace`, the element given was
-    /// actually added to the filter, but some random *other* element was
-    /// removed. This might improve in the future.
-    pub fn insert_or_update<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; VALUE_SIZE]) -> Result<(), CuckooError> {
-        let fai = get_fai::<T, H>(key);
-        
-        let mut current_bucket = Bucket {
-            fingerprint: fai.fp,
-            value
+    /// Check `HasherQuality::is_poor` before committing to a hasher for a key
+    /// distribution, e.g. FNV with sequential integer keys.
+    pub fn hasher_quality_report<I>(&self, sample_keys: I) -> HasherQuality
+    where
+        I: IntoIterator,
+        I::Item: Hash,
+    {
+        let fais = sample_keys.into_iter().map(|key| get_fai_from_hash::<H, FP>(self.key_hash(&key)));
+        HasherQuality::measure(self.buckets.len(), fais)
+    }
+
+    /// Projects the capacity a map configured like this one needs to hold
+    /// `total_expected` keys, with `failure_probability` of an insert failing
+    /// before all of them are stored, from a sample of the keys.
+    ///
+    /// The sample is inserted into several tables too small to hold all of
+    /// it, each placing the keys by a differently remixed hash, and the
+    /// density every table reached before its first failed insert is
+    /// measured. Assuming these densities are normally distributed, the
+    /// full map is sized for the density only `failure_probability` of them
+    /// fall short of. Small tables spread wider than large ones, so the
+    /// projection errs on the large side.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sample holds fewer than 64 keys or `failure_probability`
+    /// is not in `(0, 0.5]`.
+    pub fn project_capacity<I>(&self, sample: I, total_expected: usize, failure_probability: f64) -> CapacityProjection
+    where
+        I: IntoIterator,
+        I::Item: Hash,
+    {
+        let hashes: Vec<u64> = sample.into_iter().map(|key| self.key_hash(&key)).collect();
+        assert!(hashes.len() >= MIN_PROJECTION_SAMPLE, "the sample must hold at least 64 keys");
+        assert!(
+            failure_probability > 0.0 && failure_probability <= 0.5,
+            "failure_probability must be in (0, 0.5]"
+        );
+
+        let fitting = hashes.len() / ENTRIES_PER_BUCKET;
+        let buckets = match self.index_scheme {
+            IndexScheme::Xor | IndexScheme::Local => 1 << (usize::BITS - 1 - fitting.leading_zeros()),
+            IndexScheme::Cyclic => fitting,
         };
+        let densities: Vec<f64> = (0..PROJECTION_TRIALS)
+            .map(|trial| {
+                let mut map = Self::with_buckets(buckets, self.index_scheme);
+                map.ways = self.ways;
+                map.max_kicks = self.max_kicks;
+                map.kick_policy = self.kick_policy;
+                map.path_search = self.path_search;
+                map.stash_capacity = self.stash_capacity;
+                for &hash in &hashes {
+                    if map.insert_hashed(remix_hash(hash, trial), [0; V]).is_err() {
+                        break;
+                    }
+                }
+                map.len() as f64 / map.capacity() as f64
+            })
+            .collect();
+        CapacityProjection::extrapolate(hashes.len(), &densities, total_expected, failure_probability)
+    }
 
-        if self.put(fai.i1, &current_bucket) || self.put(fai.i2, &current_bucket) {
-            return Ok(());
-        }
+    /// Sets how keys found in more than one candidate bucket are resolved.
+    ///
+    /// `PreferNewest` only orders copies written after it was set.
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy<V>) {
+        self.write_order = match (policy, self.write_order.take()) {
+            (DuplicatePolicy::PreferNewest, Some(order)) => Some(order),
+            (DuplicatePolicy::PreferNewest, None) => Some(WriteOrder::new(self.buckets.len())),
+            _ => None,
+        };
+        self.duplicate_policy = policy;
+        self.clear_hot_cache();
+    }
 
-        let len = self.buckets.len();
-        let mut i = fai.random_index(&mut rand::thread_rng());
+    /// Returns the policy used for keys found in more than one candidate bucket.
+    pub fn duplicate_policy(&self) -> DuplicatePolicy<V> {
+        self.duplicate_policy
+    }
 
-        for _ in 0..MAX_REBUCKET {
-            let kicked_bucket;
-        
-            // save bucket that will get kicket out
-            kicked_bucket = self.buckets[i % len];
+    /// Sets what inserting an already present key does to its value.
+    pub fn set_update_policy(&mut self, policy: UpdatePolicy<V>) {
+        self.update_policy = policy;
+    }
 
-            // save current_bucket into current position
-            self.buckets[i % len] = current_bucket;
+    /// Returns what inserting an already present key does to its value.
+    pub fn update_policy(&self) -> UpdatePolicy<V> {
+        self.update_policy
+    }
 
-            // generate next position for kicked_bucket
-            i = get_alt_index::<H>(kicked_bucket.fingerprint, i);
-            
-            if self.put(i, &kicked_bucket) {
-                return Ok(());
-            }
-            current_bucket = kicked_bucket;
-        }
+    /// Sets how `merge` combines values of keys present in both maps.
+    pub fn set_merge_rule(&mut self, rule: MergeRule) {
+        self.merge_rule = rule;
+    }
 
-        // TODO: consider resizing the buffer
+    /// Returns the rule `merge` uses for keys present in both maps.
+    pub fn merge_rule(&self) -> MergeRule {
+        self.merge_rule
+    }
 
-        // fp is dropped here, which means that the last item that was
-        // rebucketed gets removed from the filter.
-        // TODO: One could introduce a single-item cache for this element,
-        // check this cache in all methods additionally to the actual filter,
-        // and return NotEnoughSpace if that cache is already in use.
-        // This would complicate the code, but stop random elements from
-        // getting removed and result in nicer behaviour for the user.
-        Err(CuckooError::NotEnoughSpace)
+    /// Sets how many relocations an insert may make before giving up,
+    /// `MAX_REBUCKET` by default. Larger maps may need more to reach a high
+    /// load factor, latency-sensitive callers may want fewer.
+    pub fn set_max_kicks(&mut self, max_kicks: u32) {
+        self.max_kicks = max_kicks;
     }
 
-    /// Adds `key` to the filter if it does not exist in the filter yet.
-    /// Returns `Ok(true)` if `key` was not yet present in the filter and added
-    /// successfully.
-    pub fn test_and_add<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; VALUE_SIZE]) -> Result<bool, CuckooError> {
-        if self.get(key).is_some() {
-            Ok(false)
-        } else {
-            self.insert_or_update(key, value).map(|_| true)
-        }
+    /// Returns how many relocations an insert may make before giving up.
+    pub fn max_kicks(&self) -> u32 {
+        self.max_kicks
     }
 
-    /// Number of items in the filter.
-    pub fn len(&self) -> usize {
-        self.len
+    /// Sets how the relocation budget of inserts shrinks as the map fills up.
+    pub fn set_kick_policy(&mut self, policy: KickPolicy) {
+        self.kick_policy = policy;
     }
 
-    /// Number of bytes the filter occupies in memory
-    pub fn memory_usage(&self) -> usize {
-        mem::size_of_val(self) + self.buckets.len() * mem::size_of::<Bucket>()
+    /// Returns how the relocation budget of inserts shrinks as the map fills up.
+    pub fn kick_policy(&self) -> KickPolicy {
+        self.kick_policy
     }
 
-    /// Check if filter is empty
-    pub fn is_empty(&self) -> bool {
-        self.len == 0
+    /// Sets how inserts whose candidate buckets are full make room.
+    ///
+    /// The breadth-first search only writes the entries it moves, which
+    /// saves the wasted writes of long random walks near full load. It
+    /// ignores the eviction strategy, and its depth is further limited by
+    /// the kick budget. An auto-growing map that finds no path still falls
+    /// back to a random walk to decide whether to grow.
+    pub fn set_path_search(&mut self, search: PathSearch) {
+        self.path_search = search;
     }
 
-    /// Deletes `key` from the filter. Returns true if `key` existed in the
-    /// filter before.
-    pub fn delete<T: ?Sized + Hash>(&mut self, key: &T) -> bool {
-        let FaI { fp, i1, i2 } = get_fai::<T, H>(key);
-        self.remove(fp, i1) || self.remove(fp, i2)
+    /// Returns how inserts whose candidate buckets are full make room.
+    pub fn path_search(&self) -> PathSearch {
+        self.path_search
     }
 
-    /// Empty all the buckets in a filter and reset the number of items.
-    pub fn clear(&mut self) {
-        if self.is_empty() {
-            return;
-        }
+    /// Sets the number of candidate buckets per key, 2 by default and up to
+    /// `MAX_WAYS` (d-ary cuckoo hashing).
+    ///
+    /// With more candidates the table fills up further before inserts fail,
+    /// at the cost of probing more buckets per lookup and a higher false
+    /// positive rate: both 3 and 4 ways about double the rate of 2 ways, as
+    /// the candidates of a key are drawn from a group of four buckets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ways` is not 2, 3 or 4, or if the map is not empty, since
+    /// stored entries were placed for the previous number of ways. Maps
+    /// constructed with `with_exact_capacity` only support 2 ways.
+    pub fn set_ways(&mut self, ways: usize) {
+        assert!((2..=MAX_WAYS).contains(&ways), "ways must be 2, 3 or 4");
+        assert!(self.is_empty(), "ways can only be changed while the map is empty");
+        assert!(ways == 2 || !self.auto_grow, "auto-growing maps use 2 ways");
+        assert!(ways == 2 || self.index_scheme == IndexScheme::Xor, "only maps using IndexScheme::Xor use more than 2 ways");
+        self.ways = ways;
+        self.growths = 0;
+    }
 
-        for bucket in self.buckets.iter_mut() {
-            bucket.clear();
-        }
-        self.len = 0;
+    /// Returns the number of candidate buckets per key.
+    pub fn ways(&self) -> usize {
+        self.ways
     }
 
-    /// Removes the item with the given fingerprint from the bucket indexed by i.
-    fn remove(&mut self, fp: Fingerprint, i: usize) -> bool {
-        let len = self.buckets.len();
-        if self.buckets[i % len].reset(fp) {
-            self.len -= 1;
-            true
-        } else {
-            false
-        }
+    /// Switches between `IndexScheme::Xor` and `IndexScheme::Local`, which
+    /// keeps both candidate buckets of a key within one cache line, so a
+    /// lookup that misses its primary bucket reads no other line.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cuckoomap::{CuckooMap, IndexScheme};
+    /// use std::collections::hash_map::DefaultHasher;
+    ///
+    /// let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 12);
+    /// map.set_index_scheme(IndexScheme::Local);
+    /// map.insert_or_update("hello", [1]).unwrap();
+    /// assert_eq!(map.get("hello"), Some([1]));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the map is not empty, since stored entries were placed for
+    /// the previous scheme, if either scheme is `Cyclic`, which is set by
+    /// `with_exact_capacity` only, or if switching to `Local` in a map with
+    /// more than 2 ways or auto-growth.
+    pub fn set_index_scheme(&mut self, index_scheme: IndexScheme) {
+        assert!(self.is_empty(), "the index scheme can only be changed while the map is empty");
+        assert!(
+            self.index_scheme != IndexScheme::Cyclic && index_scheme != IndexScheme::Cyclic,
+            "only maps constructed with with_exact_capacity use IndexScheme::Cyclic"
+        );
+        assert!(index_scheme == IndexScheme::Xor || self.ways == 2, "only maps using IndexScheme::Xor use more than 2 ways");
+        assert!(index_scheme == IndexScheme::Xor || !self.auto_grow, "only maps using IndexScheme::Xor can grow");
+        self.index_scheme = index_scheme;
+        self.growths = 0;
     }
 
-    /// overwrites a bucket if fingerprint matches (prob. because of same key)
-    fn put(&mut self, i: usize, bucket: &Bucket) -> bool {
-        let len = self.buckets.len();
+    /// Returns how the alternate bucket of a key is derived, `Cyclic` for
+    /// maps constructed with `with_exact_capacity`.
+    pub fn index_scheme(&self) -> IndexScheme {
+        self.index_scheme
+    }
 
-        if self.buckets[i % len].set(bucket.fingerprint, bucket.value) {
-            self.len += 1;
-            true
-        } else {
-            false
-        }
+    /// Seeds the hash of every key with `seed`.
+    ///
+    /// Without a seed, all maps using the same hasher place a key in the same
+    /// buckets, so anyone who knows the hasher can craft keys that all share
+    /// two buckets and make inserts fail. With a secret seed, such keys can't
+    /// be found offline. Lookups of a seeded map only find keys inserted with
+    /// the same seed, so persisted or merged maps need to keep it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the map is not empty, since stored entries were placed with
+    /// the previous seed.
+    pub fn set_hash_seed(&mut self, seed: u64) {
+        assert!(self.is_empty(), "the hash seed can only be changed while the map is empty");
+        self.hash_seed = Some(seed);
     }
 
-    /// calculates the the ratio of filled / empty buckets
-    pub fn density(&self) -> f64 {
+    /// Seeds the hash of every key with a random seed drawn from the
+    /// operating system and returns it, see `set_hash_seed`.
+    pub fn randomize_hash_seed(&mut self) -> u64 {
+        let seed = StdRng::from_entropy().next_u64();
+        self.set_hash_seed(seed);
+        seed
+    }
 
-        let n_filled_buckets = self.buckets.iter()
-            .filter(|b| !b.fingerprint.is_empty())
-            .count();
+    /// Removes the hash seed, so keys are placed by their plain hash again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the map is not empty.
+    pub fn clear_hash_seed(&mut self) {
+        assert!(self.is_empty(), "the hash seed can only be changed while the map is empty");
+        self.hash_seed = None;
+    }
+
+    /// Returns the seed keys are hashed with, if any.
+    pub fn hash_seed(&self) -> Option<u64> {
+        self.hash_seed
+    }
+
+    /// Tags the map's values as written in the format with schema id `id`,
+    /// see `ValueSchema`. The tag is kept with the map when it is saved, so
+    /// the values can be decoded correctly after their format changed.
+    pub fn set_value_schema(&mut self, id: u8) {
+        self.value_schema = Some(id);
+    }
+
+    /// Returns the schema id the map's values are tagged with, if any.
+    pub fn value_schema(&self) -> Option<u8> {
+        self.value_schema
+    }
+
+    /// Returns where the map came from, see `SnapshotMetadata`.
+    pub fn metadata(&self) -> &SnapshotMetadata {
+        &self.metadata
+    }
+
+    /// Returns the metadata to set the source or tags of the map.
+    pub fn metadata_mut(&mut self) -> &mut SnapshotMetadata {
+        &mut self.metadata
+    }
+
+    /// Returns the highest load, and the evictions and failed inserts so
+    /// far, see `Watermarks`.
+    pub fn watermarks(&self) -> Watermarks {
+        self.watermarks
+    }
+
+    /// Enables doubling the buckets, up to `MAX_GROWTHS` times, when an
+    /// insert runs out of relocations, instead of stashing an entry or
+    /// returning `NotEnoughSpace`.
+    ///
+    /// Only fingerprints are stored, so the index bit a doubling adds is
+    /// unknown for the entries already stored. They are migrated into either
+    /// half of the new buckets based on their fingerprint instead, and every
+    /// lookup also probes the two buckets each such generation of entries
+    /// may be in. Each growth therefore adds up to two probes per lookup and
+    /// raises the false positive rate accordingly.
+    ///
+    /// # Panics
+    ///
+    /// Panics if enabled for a map with more than 2 ways, one constructed
+    /// with `with_exact_capacity`, one with a TTL or one whose buckets live
+    /// in a file.
+    pub fn set_auto_grow(&mut self, auto_grow: bool) {
+        assert!(!auto_grow || self.ways == 2, "auto-growing maps use 2 ways");
+        assert!(!auto_grow || self.index_scheme == IndexScheme::Xor, "only maps using IndexScheme::Xor can grow");
+        assert!(!auto_grow || self.expiry.is_none(), "maps with a TTL can't grow");
+        assert!(!auto_grow || !self.buckets.is_mapped(), "maps backed by a file can't grow");
+        self.auto_grow = auto_grow;
+    }
+
+    /// Returns whether the map grows when an insert runs out of relocations.
+    pub fn auto_grow(&self) -> bool {
+        self.auto_grow
+    }
+
+    /// Number of times the map has doubled its buckets since it was
+    /// created or last cleared.
+    pub fn growths(&self) -> u32 {
+        self.growths
+    }
+
+    /// Sets how many entries the stash holds, 4 by default.
+    ///
+    /// When an insert runs out of relocations, the entry kicked out last is
+    /// kept in the stash instead of being lost. Lookups and deletes check the
+    /// stash too, and later inserts move its entries back into the buckets
+    /// as soon as one of their candidates has a free slot. While the stash is
+    /// full, inserts that would have to kick out entries fail with
+    /// `NotEnoughSpace` without changing the map. With a capacity of 0 such
+    /// inserts lose a random entry instead, as they did before the stash.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` exceeds `MAX_STASH` or the stash holds more
+    /// entries than `capacity`.
+    pub fn set_stash_capacity(&mut self, capacity: usize) {
+        assert!(capacity <= MAX_STASH, "the stash holds at most {} entries", MAX_STASH);
+        assert!(self.stash.len() <= capacity, "the stash holds more than {} entries", capacity);
+        // allocated in full, so entries spill into it without allocating
+        self.stash.reserve_exact(capacity - self.stash.len());
+        self.stash_capacity = capacity;
+    }
+
+    /// Returns how many entries the stash holds at most.
+    pub fn stash_capacity(&self) -> usize {
+        self.stash_capacity
+    }
+
+    /// Number of entries currently kept in the stash.
+    pub fn stash_len(&self) -> usize {
+        self.stash.len()
+    }
+
+    /// Caps the bytes the map occupies, as counted by `memory_usage`, for
+    /// processes that rather fail an insert than get killed for running
+    /// out of memory. `None` removes the cap.
+    ///
+    /// An insert that would have to grow the buckets past the cap, counting
+    /// the old and the new buckets that both exist while entries move,
+    /// fails with `MemoryCapExceeded` instead, otherwise behaving as if
+    /// auto-growth was disabled. The stash is allocated in full by
+    /// `set_stash_capacity`, so spilling entries into it never allocates.
+    ///
+    /// Only growth is checked: configuration calls such as `set_hot_cache`
+    /// still allocate, and a map already above the cap is not shrunk.
+    pub fn set_memory_cap(&mut self, bytes: Option<usize>) {
+        self.memory_cap = bytes;
+    }
+
+    /// Returns the memory cap in bytes, if any.
+    pub fn memory_cap(&self) -> Option<usize> {
+        self.memory_cap
+    }
+
+    /// Limits how many inserts per second are accepted; inserts beyond the
+    /// limit fail with `Throttled`. `None` removes the limit.
+    ///
+    /// Bulk operations such as `merge` are not throttled, and inserts the
+    /// value validator rejects take no token.
+    pub fn set_rate_limiter(&mut self, limiter: Option<RateLimiter>) {
+        self.rate_limiter = limiter;
+    }
+
+    /// Replaces the clock TTLs and the rate limiter read, `SystemClock` by
+    /// default, e.g. with a `MockClock` in tests.
+    ///
+    /// Deadlines and refills so far were read from the previous clock, so
+    /// entries written before lose their deadline and never expire, like
+    /// entries written before `set_ttl`.
+    pub fn set_clock<C>(&mut self, clock: C)
+    where
+        C: Clock + Send + Sync + 'static,
+    {
+        self.clock = Box::new(clock);
+        if let Some(expiry) = &mut self.expiry {
+            expiry.clear();
+        }
+        if let Some(limiter) = &mut self.rate_limiter {
+            limiter.restart();
+        }
+    }
+
+    /// Sets how inserts choose which entry to kick out first.
+    pub fn set_eviction_strategy<E>(&mut self, strategy: E)
+    where
+        E: EvictionStrategy<FP, V> + Send + Sync + 'static,
+    {
+        self.eviction = Box::new(strategy);
+    }
+
+    /// Remembers the slots of the last `window` writes and makes the kick
+    /// loop 8 times less likely to pick them than any other slot, or stops
+    /// if `window` is 0.
+    ///
+    /// Near full load, a kick chain often comes back to a bucket it passed
+    /// through, and kicking out the entry it just placed there undoes its
+    /// own work; without a stash that entry may end up dropped. A window
+    /// of a few times the typical chain length is enough.
+    pub fn set_recency_window(&mut self, window: usize) {
+        self.recency = if window == 0 { None } else { Some(RecencyRing::new(window)) };
+    }
+
+    /// Number of writes the kick loop avoids undoing, 0 if disabled.
+    pub fn recency_window(&self) -> usize {
+        self.recency.as_ref().map_or(0, RecencyRing::window)
+    }
+
+    /// Calls `hook` with the 64bit hash of the key and the stored value after
+    /// every successful insert, e.g. to mirror entries to a durable store.
+    ///
+    /// The value passed is the one actually stored, after applying the
+    /// update policy.
+    ///
+    /// `merge` bypasses the hook: it only sees fingerprints, not the hashes
+    /// of the keys, so a store mirrored by the hook misses merged entries
+    /// and has to be merged separately.
+    pub fn set_on_store<F>(&mut self, hook: F)
+    where
+        F: FnMut(u64, [u8; V]) + Send + Sync + 'static,
+    {
+        self.on_store = Some(Box::new(hook));
+    }
+
+    /// Removes the hook set with `set_on_store`.
+    pub fn clear_on_store(&mut self) {
+        self.on_store = None;
+    }
+
+    /// Checks every value with `validator` before it is stored, rejecting
+    /// the insert with `InvalidValue` and the returned reason if it fails,
+    /// e.g. to keep out a sentinel value the application reserves.
+    ///
+    /// The value checked is the one that would be stored, after applying
+    /// the update policy. `merge` doesn't validate the entries it adds.
+    pub fn set_value_validator<F>(&mut self, validator: F)
+    where
+        F: Fn(&[u8; V]) -> Result<(), &'static str> + Send + Sync + 'static,
+    {
+        self.value_validator = Some(Box::new(validator));
+    }
+
+    /// Removes the validator set with `set_value_validator`.
+    pub fn clear_value_validator(&mut self) {
+        self.value_validator = None;
+    }
+
+    /// Calls `hook` while the bucket array grows, every few thousand
+    /// migrated buckets and once when the growth is done, e.g. to drive a
+    /// progress bar. The insert that triggered the growth blocks meanwhile.
+    ///
+    /// Only maps with `set_auto_grow` enabled ever grow.
+    pub fn set_on_resize_progress<F>(&mut self, hook: F)
+    where
+        F: FnMut(ResizeProgress) + Send + Sync + 'static,
+    {
+        self.on_resize_progress = Some(Box::new(hook));
+    }
+
+    /// Removes the hook set with `set_on_resize_progress`.
+    pub fn clear_on_resize_progress(&mut self) {
+        self.on_resize_progress = None;
+    }
+
+    /// Enables re-reading every written bucket to detect memory corruption.
+    ///
+    /// Inserts whose writes don't read back correctly fail with
+    /// `WriteVerificationFailed`, all mismatches are counted in
+    /// `verification_failures`.
+    pub fn set_verify_writes(&mut self, verify: bool) {
+        self.verify_writes = verify;
+    }
+
+    /// Number of writes that did not read back as written since construction.
+    pub fn verification_failures(&self) -> usize {
+        self.verification_failures
+    }
+
+    /// Reseeds the random number generator choosing which entries get kicked
+    /// out, making the placement of subsequent inserts reproducible.
+    pub fn seed_eviction_rng(&mut self, seed: u64) {
+        self.set_eviction_rng(StdRng::seed_from_u64(seed));
+    }
+
+    /// Replaces the random number generator behind every random choice of
+    /// an insert: the entry the eviction strategy picks, the bucket a kicked
+    /// entry moves on to and the slot it takes there.
+    ///
+    /// Tests can pass a scripted generator such as
+    /// `rand::rngs::mock::StepRng` to drive the kick loop down a specific path.
+    pub fn set_eviction_rng<R>(&mut self, rng: R)
+    where
+        R: RngCore + Send + Sync + 'static,
+    {
+        self.rng = Box::new(rng);
+    }
+
+    /// Checks if `key` is in the filter.
+    /// returns `Some([u8; V])` if key probably is in the map
+    /// returns `None` if key is definitely not in the map
+    pub fn get<T: ?Sized + Hash>(&self, key: &T) -> Option<[u8; V]> {
+        self.probe(&get_fai_from_hash::<H, FP>(self.key_hash(key)))
+    }
+
+    /// Looks up all `keys` like `get`, returning their values in order.
+    ///
+    /// Lookups are pipelined: the candidate buckets of a key are prefetched
+    /// as soon as it is hashed, but only probed `PREFETCH_DISTANCE` keys
+    /// later, so the cache misses of large maps overlap with hashing and
+    /// probing other keys instead of being paid one after another.
+    pub fn get_many<K, I>(&self, keys: I) -> Vec<Option<[u8; V]>>
+    where
+        K: Hash,
+        I: IntoIterator<Item = K>,
+    {
+        let keys = keys.into_iter();
+        let mut values = Vec::with_capacity(keys.size_hint().0);
+        // keys whose buckets are on their way into the cache, in a ring
+        // whose next slot holds the oldest one
+        let mut in_flight = [None; PREFETCH_DISTANCE];
+        let mut n = 0;
+
+        for key in keys {
+            let fai = get_fai_from_hash::<H, FP>(self.key_hash(&key));
+            self.prefetch_candidates(&fai);
+            if let Some(oldest) = in_flight[n % PREFETCH_DISTANCE].replace(fai) {
+                values.push(self.probe(&oldest));
+            }
+            n += 1;
+        }
+        let rest = (n..n + PREFETCH_DISTANCE).filter_map(|i| in_flight[i % PREFETCH_DISTANCE]);
+        values.extend(rest.map(|fai| self.probe(&fai)));
+        values
+    }
+
+    /// Like `get`, but decodes the stored bytes as a `C`.
+    pub fn get_typed<T: ?Sized + Hash, C: ValueCodec<V>>(&self, key: &T) -> Option<C> {
+        self.get(key).map(C::decode)
+    }
+
+    /// Like `get`, but decodes the stored bytes with the decoder `schema`
+    /// has for the map's value schema.
+    ///
+    /// Fails without a lookup if the map is untagged or its schema is
+    /// unknown, so bytes of another format are never silently decoded.
+    pub fn get_decoded<T: ?Sized + Hash, C>(&self, key: &T, schema: &ValueSchema<C, V>) -> Result<Option<C>, SchemaError> {
+        let id = self.value_schema.ok_or(SchemaError::Untagged)?;
+        if !schema.knows(id) {
+            return Err(SchemaError::Unknown(id));
+        }
+        self.get(key).map(|value| schema.decode(id, value)).transpose()
+    }
+
+    /// Like `get`, but moves an entry found only in its alternate bucket back
+    /// to its primary bucket if a slot there has freed up, so later lookups
+    /// of frequently read keys find them in the first bucket probed.
+    pub fn get_and_promote<T: ?Sized + Hash>(&mut self, key: &T) -> Option<[u8; V]> {
+        let fai = get_fai_from_hash::<H, FP>(self.key_hash(key));
+        let value = self.probe(&fai)?;
+
+        let primary = self.candidates(&fai).0[0];
+        let (hits, n) = self.hits(&fai);
+        if n > 0 && hits[0] != primary && self.buckets[primary].find(Fingerprint::empty()).is_some() {
+            let entry = Entry {
+                fingerprint: fai.fp,
+                value: self.buckets[hits[0]].get(fai.fp)?,
+            };
+            let seq = self.write_seq(hits[0], fai.fp);
+            self.remove(fai.fp, hits[0]);
+            self.carry_write_seq(seq);
+            self.put(primary, &entry);
+            self.promotions += 1;
+        }
+        Some(value)
+    }
+
+    /// Moves entries stored in an alternate bucket back into their primary
+    /// bucket, e.g. once a bulk load is done, so that read-heavy serving
+    /// finds more of them in the first bucket probed. Returns the number of
+    /// entries moved.
+    ///
+    /// Only fingerprints are stored, so which candidate bucket is primary
+    /// follows from the key alone: `keys` are the keys to consider, usually
+    /// all keys loaded. Where the primary bucket of an entry is full, other
+    /// entries away from home are moved out of it along the shortest chain
+    /// ending in a free slot, so every entry moved ends up in its primary
+    /// bucket. Entries of other keys stay where they are.
+    pub fn finalize<K, I>(&mut self, keys: I) -> usize
+    where
+        K: Hash,
+        I: IntoIterator<Item = K>,
+    {
+        let mut away = Vec::new();
+        for key in keys {
+            let fai = get_fai_from_hash::<H, FP>(self.key_hash(&key));
+            let primary = self.candidates(&fai).0[0];
+            let (hits, n) = self.hits(&fai);
+            // entries sharing their fingerprint with another one in the
+            // same bucket can't be told apart
+            if n == 1 && hits[0] != primary && self.buckets[hits[0]].matches(fai.fp).count_ones() == 1 {
+                away.push(Homecomer { fp: fai.fp, at: hits[0], primary, home: false });
+            }
+        }
+        let mut residents: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (k, entry) in away.iter().enumerate() {
+            residents.entry(entry.at).or_default().push(k);
+        }
+
+        let mut moved = 0;
+        loop {
+            let before = moved;
+            for k in 0..away.len() {
+                if away[k].home {
+                    continue;
+                }
+                let chain = match self.chain_home(k, &away, &residents) {
+                    Some(chain) => chain,
+                    None => continue,
+                };
+                // the entry leaving first frees a slot for the last one,
+                // in case the chain ends in the bucket it leaves
+                let value = self.buckets[away[k].at].get(away[k].fp).expect("entries away are stored");
+                let seq = self.write_seq(away[k].at, away[k].fp);
+                self.remove(away[k].fp, away[k].at);
+                for &m in chain.iter().rev() {
+                    let entry = &away[m];
+                    let value = self.buckets[entry.at].get(entry.fp).expect("entries away are stored");
+                    let moved_seq = self.write_seq(entry.at, entry.fp);
+                    self.remove(entry.fp, entry.at);
+                    self.carry_write_seq(moved_seq);
+                    self.put(entry.primary, &Entry { fingerprint: entry.fp, value });
+                }
+                self.carry_write_seq(seq);
+                self.put(away[k].primary, &Entry { fingerprint: away[k].fp, value });
+                for &m in chain.iter().chain(&[k]) {
+                    away[m].home = true;
+                    if let Some(members) = residents.get_mut(&away[m].at) {
+                        members.retain(|&r| r != m);
+                    }
+                }
+                moved += chain.len() + 1;
+            }
+            if moved == before {
+                break;
+            }
+        }
+        moved
+    }
+
+    /// Counts of lookups that hit in the primary and the alternate bucket,
+    /// and of entries promoted back to their primary bucket.
+    ///
+    /// Lookups are only counted while enabled with `set_probe_stats`.
+    pub fn probe_stats(&self) -> ProbeStats {
+        let counters = self.probe_counters.as_ref();
+        ProbeStats {
+            primary_hits: counters.map_or(0, |c| c.primary_hits.load(Ordering::Relaxed)),
+            alternate_hits: counters.map_or(0, |c| c.alternate_hits.load(Ordering::Relaxed)),
+            promotions: self.promotions,
+        }
+    }
+
+    /// Starts counting in which bucket lookups hit, or stops and resets the
+    /// counts.
+    ///
+    /// Counting is off by default, since every lookup of threads sharing the
+    /// map would write to the same counters.
+    pub fn set_probe_stats(&mut self, enabled: bool) {
+        self.probe_counters = enabled.then(ProbeCounters::default);
+    }
+
+    /// Whether lookups are counted in `probe_stats`.
+    pub fn probe_stats_enabled(&self) -> bool {
+        self.probe_counters.is_some()
+    }
+
+    /// Puts a cache of about `capacity` recently read entries in front of
+    /// the table, or removes it if `capacity` is 0.
+    ///
+    /// Lookups of cached keys skip probing the buckets, which pays off for
+    /// skewed workloads reading the same keys over and over. The cache is
+    /// kept consistent by every write and is bypassed once an auto-growing
+    /// map has grown. Cache hits are not counted in `probe_stats`.
+    pub fn set_hot_cache(&mut self, capacity: usize) {
+        self.hot_cache = if capacity == 0 {
+            None
+        } else {
+            Some(Mutex::new(HotCache::with_capacity(capacity)))
+        };
+    }
+
+    /// Number of entries the hot cache holds at most, 0 without one.
+    pub fn hot_cache_capacity(&self) -> usize {
+        match &self.hot_cache {
+            Some(cache) => cache.lock().map_or(0, |cache| cache.capacity()),
+            None => 0,
+        }
+    }
+
+    /// Tracks which entries are read by setting an access bit for about one
+    /// in `sample_every` lookups that hit, or stops tracking if it is 0.
+    ///
+    /// The bits feed `LruEviction` and `idle_ratio`. Sampling keeps lookups
+    /// from writing to memory every time at the cost of taking longer to
+    /// notice rarely read entries. An entry moved by an insert or a growth
+    /// counts as idle again until a sampled lookup hits it.
+    pub fn set_access_sampling(&mut self, sample_every: usize) {
+        self.access_tracker = if sample_every == 0 {
+            None
+        } else {
+            Some(AccessTracker::new(self.buckets.len(), sample_every))
+        };
+    }
+
+    /// Returns how many lookups share one access bit update, 0 without
+    /// access tracking.
+    pub fn access_sampling(&self) -> usize {
+        self.access_tracker.as_ref().map_or(0, AccessTracker::sample_every)
+    }
+
+    /// Fraction of the entries in the buckets no sampled lookup hit since
+    /// they were stored or the access bits were last reset, or `None`
+    /// without access tracking or entries.
+    ///
+    /// In a cache, this is roughly the share of entries that are dead weight.
+    pub fn idle_ratio(&self) -> Option<f64> {
+        let tracker = self.access_tracker.as_ref()?;
+        let (mut stored, mut idle) = (0, 0);
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            for (slot, entry) in bucket.entries().enumerate() {
+                if !entry.fingerprint.is_empty() {
+                    stored += 1;
+                    if !tracker.is_marked(i, slot) {
+                        idle += 1;
+                    }
+                }
+            }
+        }
+        if stored == 0 {
+            None
+        } else {
+            Some(idle as f64 / stored as f64)
+        }
+    }
+
+    /// Marks all entries as idle again, e.g. at the start of every period
+    /// `idle_ratio` is to be measured over.
+    pub fn reset_access_bits(&mut self) {
+        if let Some(tracker) = &mut self.access_tracker {
+            tracker.clear();
+        }
+    }
+
+    /// Expires entries `ttl` after they were last inserted or updated.
+    ///
+    /// Expired entries are no longer found, but keep their slot and count
+    /// in `len` and `density` until they are overwritten, deleted or swept
+    /// by `sweep`, e.g. from a `Sweeper` thread. Entries stored before the
+    /// TTL was set never expire.
+    ///
+    /// The deadlines are kept next to the buckets, taking about 40 bytes
+    /// per entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the map auto-grows, since migrated entries couldn't be
+    /// matched with their deadlines anymore.
+    pub fn set_ttl(&mut self, ttl: Duration) {
+        assert!(!self.auto_grow, "maps with a TTL can't grow");
+        match &mut self.expiry {
+            Some(expiry) => expiry.ttl = ttl,
+            None => self.expiry = Some(Expiry::new(ttl)),
+        }
+    }
+
+    /// Stops expiring entries and drops their deadlines.
+    pub fn clear_ttl(&mut self) {
+        self.expiry = None;
+    }
+
+    /// Returns the time to live of entries, if they expire.
+    pub fn ttl(&self) -> Option<Duration> {
+        self.expiry.as_ref().map(|expiry| expiry.ttl)
+    }
+
+    /// Keeps expired entries for `grace` past their TTL, for callers that
+    /// serve stale values while they refresh them.
+    ///
+    /// Within the grace period `get` no longer finds an expired entry, but
+    /// `get_detailed` still returns it with `Lookup::expired` set, and
+    /// `sweep` leaves it in place. Zero, the default, turns it off.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the map has no TTL, see `set_ttl`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cuckoomap::{CuckooMap, MockClock};
+    /// use std::collections::hash_map::DefaultHasher;
+    /// use std::time::Duration;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 10);
+    /// map.set_clock(clock.clone());
+    /// map.set_ttl(Duration::from_secs(60));
+    /// map.set_stale_grace(Duration::from_secs(10));
+    /// map.insert_or_update("page", [1]).unwrap();
+    ///
+    /// clock.advance(Duration::from_secs(65));
+    /// assert_eq!(map.get("page"), None);
+    /// let stale = map.get_detailed("page").unwrap();
+    /// assert!(stale.expired);
+    /// assert_eq!(stale.value, [1]);
+    /// ```
+    pub fn set_stale_grace(&mut self, grace: Duration) {
+        match &mut self.expiry {
+            Some(expiry) => expiry.grace = grace,
+            None => panic!("stale entries need a TTL"),
+        }
+    }
+
+    /// Returns how long expired entries are still returned as stale, zero
+    /// without a TTL.
+    pub fn stale_grace(&self) -> Duration {
+        self.expiry.as_ref().map_or(Duration::ZERO, |expiry| expiry.grace)
+    }
+
+    /// Deletes the expired entries of the next `budget_buckets` buckets,
+    /// continuing where the last sweep stopped, and returns how many it
+    /// deleted. The stash is swept whenever a sweep passes the last bucket.
+    /// Entries within their grace period, see `set_stale_grace`, are kept.
+    ///
+    /// Sweeping a few buckets at a time bounds the pause of every call, so
+    /// a server can sweep between requests instead of letting expired
+    /// entries linger and skew `density` and the false positive rate.
+    pub fn sweep(&mut self, budget_buckets: usize) -> usize {
+        let mut expiry = match self.expiry.take() {
+            Some(expiry) => expiry,
+            None => return 0,
+        };
+        let now = self.clock.now();
+        let len = self.buckets.len();
+        let mut swept = 0;
+        for _ in 0..cmp::min(budget_buckets, len) {
+            let i = expiry.cursor;
+            for slot in 0..ENTRIES_PER_BUCKET {
+                let fp = self.buckets[i].fingerprints[slot];
+                if fp.is_empty() {
+                    continue;
+                }
+                let tag = self.hot_tag(&FaI::stored_at::<H>(fp, i));
+                if expiry.is_gone(tag, fp, now) {
+                    expiry.forget(tag, fp);
+                    self.remove(fp, i);
+                    swept += 1;
+                }
+            }
+            expiry.cursor = self.reduce(i + 1);
+            if expiry.cursor == 0 {
+                let mut stash = mem::take(&mut self.stash);
+                let stashed = stash.len();
+                stash.retain(|&(j, entry)| {
+                    let tag = self.hot_tag(&FaI::stored_at::<H>(entry.fingerprint, j));
+                    let expired = expiry.is_gone(tag, entry.fingerprint, now);
+                    if expired {
+                        expiry.forget(tag, entry.fingerprint);
+                    }
+                    !expired
+                });
+                self.len -= stashed - stash.len();
+                swept += stashed - stash.len();
+                self.stash = stash;
+            }
+        }
+        self.expiry = Some(expiry);
+        if swept > 0 {
+            self.clear_hot_cache();
+        }
+        swept
+    }
+
+    /// Whether the entry described by `fai` outlived its TTL.
+    fn expired(&self, fai: &FaI<FP>) -> bool {
+        match &self.expiry {
+            Some(expiry) => expiry.is_expired(self.hot_tag(fai), fai.fp, self.clock.now()),
+            None => false,
+        }
+    }
+
+    /// Whether the entry described by `fai` outlived its TTL and grace
+    /// period.
+    fn gone(&self, fai: &FaI<FP>) -> bool {
+        match &self.expiry {
+            Some(expiry) => expiry.is_gone(self.hot_tag(fai), fai.fp, self.clock.now()),
+            None => false,
+        }
+    }
+
+    /// Like `get`, but also reports where the value was found and how likely
+    /// the hit is to be a false positive, for callers that verify
+    /// high-stakes hits against a source of truth.
+    ///
+    /// Unlike `get`, this still returns an expired entry within its grace
+    /// period, marked as `expired`, see `set_stale_grace`.
+    pub fn get_detailed<T: ?Sized + Hash>(&self, key: &T) -> Option<Lookup<V>> {
+        let fai = get_fai_from_hash::<H, FP>(self.key_hash(key));
+        let expired = self.expired(&fai);
+        let value = match expired {
+            true if self.gone(&fai) => return None,
+            // stale values neither enter the hot cache nor count as accesses
+            true => self.lookup_hit(&fai)?.0,
+            false => self.probe(&fai)?,
+        };
+        let (hits, n) = self.hits(&fai);
+        let bucket = match self.stashed(&fai) {
+            Some(k) if n == 0 => self.stash[k].0,
+            _ => hits[0],
+        };
+
+        Some(Lookup {
+            value,
+            bucket,
+            primary: bucket == self.candidates(&fai).0[0],
+            expired,
+            ambiguous: n > 1,
+            false_positive_probability: self.false_positive_probability(),
+        })
+    }
+
+    /// Counts the candidate buckets of `key` holding its fingerprint, with
+    /// a stashed entry counting as one more.
+    ///
+    /// 0 means `key` is not in the map and 1 that it is stored once. More
+    /// than 1 means several entries answer for `key`, either copies of its
+    /// entry, e.g. left by a `merge`, or entries of other keys with the same
+    /// fingerprint. Expired entries still count.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cuckoomap::CuckooMap;
+    /// use std::collections::hash_map::DefaultHasher;
+    ///
+    /// let mut map = CuckooMap::<DefaultHasher, 3>::with_capacity(1 << 10);
+    /// assert_eq!(map.match_count("hello"), 0);
+    /// map.insert_or_update("hello", [1]).unwrap();
+    /// assert_eq!(map.match_count("hello"), 1);
+    /// ```
+    pub fn match_count<T: ?Sized + Hash>(&self, key: &T) -> u8 {
+        let fai = get_fai_from_hash::<H, FP>(self.key_hash(key));
+        let (_, n) = self.hits(&fai);
+        (n + self.stashed(&fai).is_some() as usize) as u8
+    }
+
+    /// Estimated probability that `get` returns a value for a key that was
+    /// never inserted, given the current number of entries.
+    pub fn false_positive_probability(&self) -> f64 {
+        self.false_positive_probability_at(self.len)
+    }
+
+    /// The estimate of `false_positive_probability` for `len` entries.
+    pub(crate) fn false_positive_probability_at(&self, len: usize) -> f64 {
+        let fingerprints = (1u64 << (8 * FP)) - 1;
+        let per_slot = len as f64 / self.capacity() as f64 / fingerprints as f64;
+        1.0 - (1.0 - per_slot).powi(self.matching_slots() as i32)
+    }
+
+    /// Number of slots whose entry a lookup matches if it has the same
+    /// fingerprint.
+    fn matching_slots(&self) -> usize {
+        // with three ways a key's bucket group still has four buckets, and
+        // every entry of the group with the same fingerprint matches
+        let buckets = if self.ways == 2 { 2 * (self.growths as usize + 1) } else { MAX_WAYS };
+        buckets * ENTRIES_PER_BUCKET
+    }
+
+    /// Adds `key` along with a `value` to the filter. Returns `Ok` if the insertion was successful,
+    /// but could fail with a `NotEnoughSpace` error, especially when the filter
+    /// is nearing its capacity.
+    /// Note that while you can put any hashable type in the same filter, beware
+    /// for side effects like that the same number can have diferent hashes
+    /// depending on the type.
+    /// So for the filter, 4711i64 isn't the same as 4711u64.
+    ///
+    /// **Note:** When this returns `NotEnoughSpace`, the stash is full and
+    /// the element given was not added. Only with the stash disabled, see
+    /// `set_stash_capacity`, the element was actually added to the filter,
+    /// but some random *other* element was removed. Either way the error
+    /// carries the fingerprint and value of the entry that is not stored.
+    pub fn insert_or_update<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; V]) -> Result<(), CuckooError<FP, V>> {
+        self.insert_with_budget(key, value, self.max_kicks)
+    }
+
+    /// Like `insert_or_update`, but encodes `value` into the stored bytes.
+    pub fn insert_typed<T: ?Sized + Hash, C: ValueCodec<V>>(&mut self, key: &T, value: C) -> Result<(), CuckooError<FP, V>> {
+        self.insert_or_update(key, value.encode())
+    }
+
+    /// Like `insert_or_update`, but gives up after `max_kicks` relocations
+    /// instead of `max_kicks()`, which bounds the latency of a single call.
+    /// The kick policy may lower the budget further.
+    pub fn insert_with_budget<T: ?Sized + Hash>(
+        &mut self,
+        key: &T,
+        value: [u8; V],
+        max_kicks: u32,
+    ) -> Result<(), CuckooError<FP, V>> {
+        let hash = self.key_hash(key);
+        self.store(hash, get_fai_from_hash::<H, FP>(hash), value, max_kicks)
+    }
+
+    /// Inserts all `(key, value)` pairs like `insert_or_update`, stopping at the first error.
+    ///
+    /// Keys are hashed a window at a time and their candidate buckets are
+    /// prefetched before any of them is placed, so the cache misses of a
+    /// window overlap instead of being paid one after another.
+    pub fn insert_batch<K, I>(&mut self, items: I) -> Result<(), CuckooError<FP, V>>
+    where
+        K: Hash,
+        I: IntoIterator<Item = (K, [u8; V])>,
+    {
+        let mut items = items.into_iter();
+        let mut window = Vec::with_capacity(BATCH_WINDOW);
+
+        loop {
+            window.clear();
+            window.extend(
+                items
+                    .by_ref()
+                    .take(BATCH_WINDOW)
+                    .map(|(key, value)| {
+                        let hash = self.key_hash(&key);
+                        (hash, get_fai_from_hash::<H, FP>(hash), value)
+                    }),
+            );
+            if window.is_empty() {
+                return Ok(());
+            }
+
+            for (_, fai, _) in &window {
+                self.prefetch_candidates(fai);
+            }
+            for &(hash, fai, value) in &window {
+                self.store(hash, fai, value, self.max_kicks)?;
+            }
+        }
+    }
+
+    /// Like `insert_or_update`, but never fails: the returned outcome tells
+    /// whether the entry was stored, stored at the cost of another entry, or
+    /// dropped, and is counted in `best_effort_stats`.
+    ///
+    /// Meant for pipelines that rather lose an entry than handle an error.
+    pub fn insert_best_effort<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; V]) -> InsertOutcome {
+        let hash = self.key_hash(key);
+        let fai = get_fai_from_hash::<H, FP>(hash);
+        let outcome = match self.store(hash, fai, value, self.max_kicks) {
+            Ok(()) => InsertOutcome::Stored,
+            // without a stash the kick loop running out of budget loses the
+            // entry kicked out last; that is only the new entry if it never
+            // got placed
+            Err(CuckooError::NotEnoughSpace { .. } | CuckooError::MemoryCapExceeded { .. }) if self.lookup(&fai).is_some() => {
+                InsertOutcome::Evicted
+            }
+            Err(_) => InsertOutcome::Dropped,
+        };
+
+        let stats = &mut self.best_effort_stats;
+        match outcome {
+            InsertOutcome::Stored => stats.stored += 1,
+            InsertOutcome::Evicted => stats.evicted += 1,
+            InsertOutcome::Dropped => stats.dropped += 1,
+        }
+        outcome
+    }
+
+    /// Outcomes of all `insert_best_effort` calls since construction.
+    pub fn best_effort_stats(&self) -> InsertStats {
+        self.best_effort_stats
+    }
+
+    /// Returns the 64bit hash of `key` the map places it by, which includes
+    /// the hash seed, for the `_hashed` methods.
+    pub fn key_hash<T: ?Sized + Hash>(&self, key: &T) -> u64 {
+        get_seeded_hash64::<T, H>(key, self.hash_seed)
+    }
+
+    /// Like `get`, but takes the 64bit hash of the key instead of the key.
+    ///
+    /// This lets callers that hash keys themselves, e.g. through a foreign
+    /// function interface, share a map with Rust callers deterministically.
+    /// The hash is used as is, so a seeded map only finds keys hashed with
+    /// its seed, see `key_hash`.
+    pub fn get_hashed(&self, hash: u64) -> Option<[u8; V]> {
+        self.probe(&get_fai_from_hash::<H, FP>(hash))
+    }
+
+    /// Like `insert_or_update`, but takes the 64bit hash of the key instead of the key.
+    pub fn insert_hashed(&mut self, hash: u64, value: [u8; V]) -> Result<(), CuckooError<FP, V>> {
+        self.store(hash, get_fai_from_hash::<H, FP>(hash), value, self.max_kicks)
+    }
+
+    /// Like `delete`, but takes the 64bit hash of the key instead of the key.
+    pub fn delete_hashed(&mut self, hash: u64) -> bool {
+        self.delete_fai(&get_fai_from_hash::<H, FP>(hash))
+    }
+
+    /// Returns the value of `key`, or on a miss inserts and returns the value
+    /// `loader` produces, e.g. from a slower backing store. The key is hashed
+    /// and probed only once, and the map can't change between the miss and
+    /// the insert.
+    pub fn get_or_insert_with<T, F>(&mut self, key: &T, loader: F) -> Result<[u8; V], CuckooError<FP, V>>
+    where
+        T: ?Sized + Hash,
+        F: FnOnce() -> [u8; V],
+    {
+        let hash = self.key_hash(key);
+        let fai = get_fai_from_hash::<H, FP>(hash);
+        if let Some(value) = self.probe(&fai) {
+            return Ok(value);
+        }
+        let value = loader();
+        self.store(hash, fai, value, self.max_kicks)?;
+        Ok(value)
+    }
+
+    /// Adds `key` to the filter if it does not exist in the filter yet.
+    /// Returns `Ok(true)` if `key` was not yet present in the filter and added
+    /// successfully.
+    pub fn test_and_add<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; V]) -> Result<bool, CuckooError<FP, V>> {
+        if self.get(key).is_some() {
+            Ok(false)
+        } else {
+            self.insert_or_update(key, value).map(|_| true)
+        }
+    }
+
+    /// Number of items in the filter.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Number of entries the filter has room for.
+    pub fn capacity(&self) -> usize {
+        self.buckets.len() * ENTRIES_PER_BUCKET
+    }
+
+    /// Number of bytes the filter occupies in memory
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of_val(self)
+            + self.buckets.len() * mem::size_of::<Bucket<FP, V>>()
+            + self.stash.capacity() * mem::size_of::<(usize, Entry<FP, V>)>()
+            + self.path_nodes.capacity() * mem::size_of::<PathNode>()
+            + self.access_tracker.as_ref().map_or(0, AccessTracker::memory_usage)
+            + self.expiry.as_ref().map_or(0, Expiry::memory_usage)
+            + self.recency.as_ref().map_or(0, RecencyRing::memory_usage)
+            + self.write_order.as_ref().map_or(0, WriteOrder::memory_usage)
+    }
+
+    /// Check if filter is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Deletes `key` from the filter. Returns true if `key` existed in the
+    /// filter before.
+    pub fn delete<T: ?Sized + Hash>(&mut self, key: &T) -> bool {
+        self.delete_fai(&get_fai_from_hash::<H, FP>(self.key_hash(key)))
+    }
+
+    /// Inserts all entries of `other` into this map, combining the values of
+    /// keys present in both maps according to the merge rule.
+    ///
+    /// Running out of space doesn't stop the merge, the returned report
+    /// counts the entries lost that way along with the added and combined
+    /// ones. Both maps must have the same number of buckets, ways, index
+    /// scheme, hash seed and value schema and must not have grown, otherwise
+    /// `CapacityMismatch` is returned and nothing is merged.
+    ///
+    /// Merged entries don't reach the hook set with `set_on_store`.
+    pub fn merge(&mut self, other: &Self) -> Result<MergeReport, CuckooError<FP, V>> {
+        if self.buckets.len() != other.buckets.len()
+            || self.ways != other.ways
+            || self.index_scheme != other.index_scheme
+            || self.hash_seed != other.hash_seed
+            || self.value_schema != other.value_schema
+            || self.growths != 0
+            || other.growths != 0
+        {
+            return Err(CuckooError::CapacityMismatch);
+        }
+
+        let mut report = MergeReport::default();
+        for (i, entry) in other.placed_entries() {
+            let fai = FaI::stored_at::<H>(entry.fingerprint, i);
+            let value = match self.lookup(&fai) {
+                Some(existing) => {
+                    report.conflicts += 1;
+                    self.merge_rule.apply(existing, entry.value)
+                }
+                None => {
+                    report.added += 1;
+                    entry.value
+                }
+            };
+            if self.insert_fai(fai, value, self.max_kicks).is_err() {
+                report.dropped += 1;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Iterates over the `(bucket, fingerprint, value)` entries whose
+    /// fingerprint starts with the `prefix_bits` bits of `partition`.
+    ///
+    /// The fingerprint is taken from the most significant bits of the key's
+    /// hash, so this partitions the entries by hash prefix without knowing
+    /// the keys. The `1 << prefix_bits`
+    /// partitions are disjoint and cover all entries, which lets as many
+    /// workers rebuild or verify a share of a huge map each.
+    /// `partition_of` tells the partition of a key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_bits` exceeds the fingerprint's bits or `partition`
+    /// is not below `1 << prefix_bits`.
+    pub fn iter_partition(
+        &self,
+        prefix_bits: u32,
+        partition: u64,
+    ) -> impl Iterator<Item = (usize, Fingerprint<FP>, [u8; V])> + '_ {
+        // checks prefix_bits before it is used as a shift
+        partition_prefix(Fingerprint::<FP>::empty(), prefix_bits);
+        assert!(partition < 1 << prefix_bits, "partition {} is out of range for {} bits", partition, prefix_bits);
+        self.placed_entries()
+            .filter(move |(_, entry)| partition_prefix(entry.fingerprint, prefix_bits) == partition)
+            .map(|(i, entry)| (i, entry.fingerprint, entry.value))
+    }
+
+    /// Copies all stored `(fingerprint, value)` entries into a vector.
+    pub fn to_owned_entries(&self) -> Vec<(Fingerprint<FP>, [u8; V])> {
+        self.entries().map(|entry| (entry.fingerprint, entry.value)).collect()
+    }
+
+    /// Calls `f` with the fingerprint and value of every stored entry, in
+    /// bucket order with stashed entries last, until it returns `Break`.
+    ///
+    /// Returns the `Break` of `f`, or `Continue` once all entries were
+    /// visited. This is a plain loop over the slots, cheaper than a chain
+    /// of iterator adapters for finding the first entry of interest.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cuckoomap::CuckooMap;
+    /// use std::collections::hash_map::DefaultHasher;
+    /// use std::ops::ControlFlow;
+    ///
+    /// let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+    /// map.insert_or_update("a", [1]).unwrap();
+    /// map.insert_or_update("b", [200]).unwrap();
+    ///
+    /// let large = map.visit(|_, value| match value[0] {
+    ///     v if v > 100 => ControlFlow::Break(v),
+    ///     _ => ControlFlow::Continue(()),
+    /// });
+    /// assert_eq!(large, ControlFlow::Break(200));
+    /// ```
+    pub fn visit<B, F>(&self, mut f: F) -> ControlFlow<B>
+    where
+        F: FnMut(Fingerprint<FP>, [u8; V]) -> ControlFlow<B>,
+    {
+        for bucket in self.buckets.iter() {
+            for (&fp, &value) in bucket.fingerprints.iter().zip(&bucket.values) {
+                if fp.is_empty() {
+                    continue;
+                }
+                if let ControlFlow::Break(b) = f(fp, value) {
+                    return ControlFlow::Break(b);
+                }
+            }
+        }
+        for (_, entry) in &self.stash {
+            if let ControlFlow::Break(b) = f(entry.fingerprint, entry.value) {
+                return ControlFlow::Break(b);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Counts the stored entries per value of their first value byte,
+    /// in a single scan over the buckets.
+    pub fn count_by_value(&self) -> [usize; 256] {
+        let mut counts = [0; 256];
+        for entry in self.entries() {
+            counts[entry.value[0] as usize] += 1;
+        }
+        counts
+    }
+
+    /// Estimates the number of distinct keys inserted per category, with
+    /// `category` deriving the category of a key from its stored value, e.g.
+    /// a tenant id, in a single scan over the buckets.
+    ///
+    /// A key whose fingerprint matches an entry in its candidate buckets
+    /// updates that entry instead of adding one, so the entries undercount
+    /// the keys more the fuller the map is. The entries of every category
+    /// are scaled up by the ratio of the estimated number of distinct keys
+    /// to the number of entries of the whole map.
+    pub fn estimated_cardinality_by<K, F>(&self, mut category: F) -> HashMap<K, f64>
+    where
+        K: Hash + Eq,
+        F: FnMut(&[u8; V]) -> K,
+    {
+        let mut counts = HashMap::new();
+        for entry in self.entries() {
+            *counts.entry(category(&entry.value)).or_insert(0.0) += 1.0;
+        }
+        if self.len == 0 {
+            return counts;
+        }
+
+        // while n distinct keys go in, the entries grow as
+        // dm/dn = 1 - slots * m / (capacity * fingerprints), so
+        // n = -k * ln(1 - m / k) with k = capacity * fingerprints / slots
+        let fingerprints = ((1u64 << (8 * FP)) - 1) as f64;
+        let k = self.capacity() as f64 * fingerprints / self.matching_slots() as f64;
+        let m = self.len as f64;
+        let correction = -k * (1.0 - m / k).ln() / m;
+        for count in counts.values_mut() {
+            *count *= correction;
+        }
+        counts
+    }
+
+    /// Consumes the map and frees its bucket array on a background thread,
+    /// so dropping a huge map does not stall the calling thread.
+    ///
+    /// The returned handle can be joined to wait for the deallocation.
+    pub fn into_background_drop(mut self) -> thread::JoinHandle<()> {
+        let buckets = mem::take(&mut self.buckets);
+        thread::spawn(move || drop(buckets))
+    }
+
+    /// Empty all the buckets in a filter and reset the number of items.
+    pub fn clear(&mut self) {
+        // without entries there are no earlier generations to probe
+        self.growths = 0;
+        if self.is_empty() {
+            return;
+        }
+
+        self.buckets.clear();
+        self.stash.clear();
+        self.len = 0;
+        self.clear_hot_cache();
+        self.reset_access_bits();
+        if let Some(expiry) = &mut self.expiry {
+            expiry.clear();
+        }
+        if let Some(recency) = &mut self.recency {
+            recency.clear();
+        }
+    }
+
+    /// Takes a token from the rate limiter, if there is one.
+    fn throttle(&mut self) -> Result<(), CuckooError<FP, V>> {
+        match &mut self.rate_limiter {
+            Some(limiter) => {
+                if limiter.try_acquire_at(self.clock.now()) {
+                    Ok(())
+                } else {
+                    Err(CuckooError::Throttled)
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Inserts on behalf of a caller: applies the update policy, the value
+    /// validator and the rate limit before handing over to `insert_fai`,
+    /// then runs the store hook.
+    fn store(&mut self, hash: u64, fai: FaI<FP>, value: [u8; V], max_kicks: u32) -> Result<(), CuckooError<FP, V>> {
+        let value = match self.lookup(&fai) {
+            Some(existing) => self.update_policy.apply(existing, value),
+            None => value,
+        };
+        if let Some(validator) = &self.value_validator {
+            validator(&value).map_err(CuckooError::InvalidValue)?;
+        }
+        // after validating, so rejected values don't use up tokens
+        self.throttle()?;
+        let failures = self.verification_failures;
+        self.insert_fai(fai, value, max_kicks)?;
+        if self.verification_failures != failures {
+            return Err(CuckooError::WriteVerificationFailed);
+        }
+        if let Some(hook) = &mut self.on_store {
+            hook(hash, value);
+        }
+        Ok(())
+    }
+
+    /// Hints the CPU to load the candidate buckets of `fai`.
+    fn prefetch_candidates(&self, fai: &FaI<FP>) {
+        let (buckets, n) = self.candidates(fai);
+        for &i in &buckets[..n] {
+            prefetch(&self.buckets[i]);
+        }
+    }
+
+    /// Looks up the entry described by `fai` on behalf of a caller,
+    /// counting in which bucket it was found.
+    fn probe(&self, fai: &FaI<FP>) -> Option<[u8; V]> {
+        // checked before the cache, which may still hold expired entries
+        if self.expired(fai) {
+            return None;
+        }
+        let hot_cache = self.hot_cache.as_ref().filter(|_| self.growths == 0);
+        // a contended cache is skipped rather than waited for
+        if let Some(Ok(mut cache)) = hot_cache.map(Mutex::try_lock) {
+            if let Some(value) = cache.get(self.hot_tag(fai), fai.fp) {
+                self.record_access(fai);
+                return Some(value);
+            }
+        }
+
+        let (value, primary) = self.lookup_hit(fai)?;
+        if let Some(counters) = &self.probe_counters {
+            let hits = if primary { &counters.primary_hits } else { &counters.alternate_hits };
+            hits.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(Ok(mut cache)) = hot_cache.map(Mutex::try_lock) {
+            cache.insert(self.hot_tag(fai), fai.fp, value);
+        }
+        self.record_access(fai);
+        Some(value)
+    }
+
+    /// Sets the access bits of the entries described by `fai` if access
+    /// tracking samples this lookup.
+    fn record_access(&self, fai: &FaI<FP>) {
+        let tracker = match &self.access_tracker {
+            Some(tracker) if tracker.sample() => tracker,
+            _ => return,
+        };
+        let (hits, n) = self.hits(fai);
+        for &i in &hits[..n] {
+            if let Some(slot) = self.buckets[i].find(fai.fp) {
+                tracker.mark(i, slot);
+            }
+        }
+    }
+
+    /// Clears the access bit of a slot that got a new entry and remembers
+    /// it as recently written.
+    fn note_write(&mut self, i: usize, slot: usize) {
+        let i = self.reduce(i);
+        if let Some(tracker) = &mut self.access_tracker {
+            tracker.unmark(i, slot);
+        }
+        if let Some(recency) = &mut self.recency {
+            recency.record(i, slot);
+        }
+        if let Some(order) = &mut self.write_order {
+            order.swap(i, slot);
+        }
+    }
+
+    /// The write sequence number of the copy of `fp` in the bucket indexed
+    /// by i, 0 without write order tracking.
+    fn write_seq(&self, i: usize, fp: Fingerprint<FP>) -> u64 {
+        let i = self.reduce(i);
+        match (&self.write_order, self.buckets[i].find(fp)) {
+            (Some(order), Some(slot)) => order.get(i, slot),
+            _ => 0,
+        }
+    }
+
+    /// Hands `seq` to the next slot written, see `WriteOrder::carry`.
+    fn carry_write_seq(&mut self, seq: u64) {
+        if let Some(order) = &mut self.write_order {
+            order.carry(seq);
+        }
+    }
+
+    /// Picks the slot of bucket `i` the kick loop evicts next.
+    fn pick_slot(&mut self, i: usize) -> usize {
+        match &self.recency {
+            Some(recency) => recency.pick_slot(i % self.buckets.len(), &mut *self.rng),
+            None => self.rng.gen_range(0, ENTRIES_PER_BUCKET),
+        }
+    }
+
+    /// The smallest candidate bucket of `fai`, which together with the
+    /// fingerprint tags the entry in the hot cache.
+    fn hot_tag(&self, fai: &FaI<FP>) -> usize {
+        if self.ways == 2 && self.index_scheme == IndexScheme::Xor {
+            return cmp::min(self.reduce(fai.i1), self.reduce(fai.i2));
+        }
+        let (buckets, n) = self.relocations(fai.fp, fai.i1);
+        buckets[..n].iter().copied().min().unwrap_or(0)
+    }
+
+    /// Drops the hot cache's copy and the deadline of the entry described
+    /// by `fai` before it gets written.
+    fn forget(&mut self, fai: &FaI<FP>) {
+        let tag = self.hot_tag(fai);
+        if let Some(cache) = &mut self.hot_cache {
+            if let Ok(cache) = cache.get_mut() {
+                cache.remove(tag, fai.fp);
+            }
+        }
+        if let Some(expiry) = &mut self.expiry {
+            expiry.forget(tag, fai.fp);
+        }
+    }
+
+    fn clear_hot_cache(&mut self) {
+        if let Some(Ok(cache)) = self.hot_cache.as_mut().map(Mutex::get_mut) {
+            cache.clear();
+        }
+    }
+
+    /// Searches breadth-first for the shortest chain of entries away from
+    /// home that can move into their primary bucket one after the other,
+    /// each leaving a slot for the one before it, so that `away[k]` can move
+    /// into its primary bucket. The chain, excluding `k`, starts with the
+    /// entry to move out of the primary bucket of `away[k]`.
+    fn chain_home(&self, k: usize, away: &[Homecomer<FP>], residents: &HashMap<usize, Vec<usize>>) -> Option<Vec<usize>> {
+        // entries to move along with the node they were reached from
+        let mut nodes = vec![(k, usize::MAX)];
+        let mut seen = HashSet::new();
+        seen.insert(away[k].primary);
+        let mut next = 0;
+        while next < nodes.len() && nodes.len() < MAX_CHAIN_NODES {
+            let bucket = away[nodes[next].0].primary;
+            if bucket == away[k].at || self.buckets[bucket].find(Fingerprint::empty()).is_some() {
+                let mut chain = Vec::new();
+                let mut node = next;
+                while node != 0 {
+                    chain.push(nodes[node].0);
+                    node = nodes[node].1;
+                }
+                chain.reverse();
+                return Some(chain);
+            }
+            for &m in residents.get(&bucket).into_iter().flatten() {
+                if seen.insert(away[m].primary) {
+                    nodes.push((m, next));
+                }
+            }
+            next += 1;
+        }
+        None
+    }
+
+    /// Looks up the entry described by `fai`, resolving duplicates per policy.
+    fn lookup(&self, fai: &FaI<FP>) -> Option<[u8; V]> {
+        if self.expired(fai) {
+            return None;
+        }
+        self.lookup_hit(fai).map(|(value, _)| value)
+    }
+
+    /// Like `lookup`, also returning whether the first copy found was in
+    /// the primary bucket, but finding expired entries too.
+    fn lookup_hit(&self, fai: &FaI<FP>) -> Option<([u8; V], bool)> {
+        let (buckets, n) = self.candidates(fai);
+        if let (DuplicatePolicy::PreferNewest, Some(order)) = (self.duplicate_policy, &self.write_order) {
+            // the last written copy in the buckets, the first of equally old ones
+            let newest = buckets[..n]
+                .iter()
+                .enumerate()
+                .filter_map(|(k, &i)| self.buckets[i].find(fai.fp).map(|slot| (order.get(i, slot), k, i, slot)))
+                .max_by_key(|&(seq, k, _, _)| (seq, cmp::Reverse(k)));
+            if let Some((_, k, i, slot)) = newest {
+                return Some((self.buckets[i].entry(slot).value, k == 0));
+            }
+        }
+        let mut values = buckets[..n]
+            .iter()
+            .enumerate()
+            .filter_map(|(k, &i)| self.buckets[i].get(fai.fp).map(|value| (value, k == 0)))
+            .chain(self.stashed(fai).map(|k| (self.stash[k].1.value, false)));
+        let (first, primary) = values.next()?;
+
+        match self.duplicate_policy {
+            DuplicatePolicy::Merge(merge) => Some((values.map(|(value, _)| value).fold(first, merge), primary)),
+            _ => Some((first, primary)),
+        }
+    }
+
+    /// Updates the entry described by `fai` or inserts it, kicking out
+    /// other entries at most `max_kicks` times before growing the map
+    /// if auto-growth is enabled.
+    fn insert_fai(&mut self, fai: FaI<FP>, value: [u8; V], max_kicks: u32) -> Result<(), CuckooError<FP, V>> {
+        self.forget(&fai);
+        if self.expiry.is_some() {
+            let (tag, now) = (self.hot_tag(&fai), self.clock.now());
+            if let Some(expiry) = &mut self.expiry {
+                expiry.touch(tag, fai.fp, now);
+            }
+        }
+        self.drain_stash();
+        let (hits, n_hits) = self.hits(&fai);
+        if n_hits > 0 {
+            self.update(hits[0], fai.fp, value);
+            for &i in &hits[1..n_hits] {
+                match self.duplicate_policy {
+                    DuplicatePolicy::PreferPrimary => {}
+                    DuplicatePolicy::PreferNewest => {
+                        self.remove(fai.fp, i);
+                    }
+                    DuplicatePolicy::Merge(_) => {
+                        self.update(i, fai.fp, value);
+                    }
+                }
+            }
+            return Ok(());
+        }
+        if let Some(k) = self.stashed(&fai) {
+            self.stash[k].1.value = value;
+            return Ok(());
+        }
+
+        let mut current_entry = Entry {
+            fingerprint: fai.fp,
+            value
+        };
+        if let Some(order) = &mut self.write_order {
+            order.begin_write();
+        }
+
+        let (buckets, n) = self.candidates(&fai);
+        if buckets[..n].iter().any(|&i| self.put(i, &current_entry)) {
+            return Ok(());
+        }
+
+        let can_grow = self.can_grow();
+        if !can_grow && self.stash_capacity > 0 && self.stash.len() == self.stash_capacity {
+            self.forget(&fai);
+            return Err(self.no_space(fai.fp, value));
+        }
+
+        let load_factor = self.len as f64 / self.capacity() as f64;
+        let max_kicks = self.kick_policy.budget(max_kicks, load_factor);
+        if let PathSearch::BreadthFirst { max_depth } = self.path_search {
+            if self.relocate_along_shortest_path(current_entry, &buckets[..n], cmp::min(max_depth, max_kicks)) {
+                return Ok(());
+            }
+            if !can_grow {
+                if self.stash.len() < self.stash_capacity {
+                    self.stash.push((buckets[0], current_entry));
+                    self.len += 1;
+                    self.note_load();
+                    return Ok(());
+                }
+                self.forget(&fai);
+                return Err(self.no_space(fai.fp, value));
+            }
+        }
+
+        let mut candidates = [EvictionCandidate {
+            bucket: 0,
+            slot: 0,
+            fingerprint: Fingerprint::empty(),
+            value: [0; V],
+            accessed: false,
+        }; MAX_CANDIDATES * ENTRIES_PER_BUCKET];
+        let candidates = &mut candidates[..n * ENTRIES_PER_BUCKET];
+        for (k, candidate) in candidates.iter_mut().enumerate() {
+            let (bucket, slot) = (buckets[k / ENTRIES_PER_BUCKET], k % ENTRIES_PER_BUCKET);
+            let entry = self.buckets[bucket].entry(slot);
+            let accessed = self.access_tracker.as_ref().is_some_and(|t| t.is_marked(bucket, slot));
+            *candidate = EvictionCandidate {
+                bucket,
+                slot,
+                fingerprint: entry.fingerprint,
+                value: entry.value,
+                accessed,
+            };
+        }
+        let chosen = self.eviction.choose(candidates, &mut *self.rng);
+        let (mut i, mut slot) = (candidates[chosen].bucket, candidates[chosen].slot);
+
+        while let Some((homeless, from)) = self.kick(current_entry, i, slot, max_kicks) {
+            if !self.can_grow() {
+                if self.stash.len() < self.stash_capacity {
+                    self.stash.push((from % self.buckets.len(), homeless));
+                    self.len += 1;
+                    self.note_load();
+                    return Ok(());
+                }
+                // without a stash fp is dropped here, which means that the
+                // last item that was rebucketed gets removed from the filter.
+                self.forget(&FaI::stored_at::<H>(homeless.fingerprint, from));
+                return Err(self.no_space(homeless.fingerprint, homeless.value));
+            }
+
+            // place the homeless entry where the growth would have moved it
+            let len = self.buckets.len();
+            self.grow();
+            let alt_hash = get_alt_index::<H, FP>(homeless.fingerprint, 0);
+            let moved = get_migrated_index(alt_hash, from % len, len);
+            let (buckets, n) = self.relocations(homeless.fingerprint, moved);
+            if buckets[..n].iter().any(|&j| self.put(j, &homeless)) {
+                return Ok(());
+            }
+            current_entry = homeless;
+            i = moved;
+            slot = self.rng.gen_range(0, ENTRIES_PER_BUCKET);
+        }
+        Ok(())
+    }
+
+    /// Stores `entry` in the given slot of the bucket indexed by i, moving
+    /// the entries it displaces to their other candidate buckets at most
+    /// `max_kicks` times. Returns the entry left without a slot, if any,
+    /// along with the bucket it was kicked out of.
+    fn kick(
+        &mut self,
+        mut current_entry: Entry<FP, V>,
+        mut i: usize,
+        mut slot: usize,
+        max_kicks: u32,
+    ) -> Option<(Entry<FP, V>, usize)> {
+        let mut from = i;
+        for _ in 0..max_kicks {
+            // swap current_entry with the entry that will get kicked out
+            let bucket = self.reduce(i);
+            let kicked_entry = self.buckets[bucket].replace(slot, current_entry);
+            self.watermarks.evictions += 1;
+            self.verify_write(i, slot, current_entry);
+            self.note_write(i, slot);
+
+            // try the other candidate buckets of kicked_entry, then kick on
+            // from one of them
+            let (buckets, n) = self.relocations(kicked_entry.fingerprint, i);
+            if buckets[1..n].iter().any(|&j| self.put(j, &kicked_entry)) {
+                return None;
+            }
+            from = i;
+            i = match n {
+                1 => buckets[0],
+                2 => buckets[1],
+                _ => buckets[self.rng.gen_range(1, n)],
+            };
+            current_entry = kicked_entry;
+            slot = self.pick_slot(i);
+        }
+        Some((current_entry, from))
+    }
+
+    /// Stores `entry` in one of the full buckets `roots` by moving entries
+    /// along the shortest chain of at most `max_depth` moves that ends in a
+    /// free slot. Returns `false`, with the buckets untouched, if there is no
+    /// such chain.
+    fn relocate_along_shortest_path(&mut self, entry: Entry<FP, V>, roots: &[usize], max_depth: u32) -> bool {
+        let mut nodes = mem::take(&mut self.path_nodes);
+        let path = slots::shortest_path(self, roots, max_depth, &mut nodes);
+        if let Some((leaf, free)) = path {
+            self.apply_path(&nodes, leaf, free, entry);
+        }
+        self.path_nodes = nodes;
+        path.is_some()
+    }
+
+    /// Moves the entries from the root of the search tree down to node
+    /// `leaf` one step along the chain, the last one into bucket `free`,
+    /// and stores `entry` where the chain started.
+    fn apply_path(&mut self, nodes: &[PathNode], leaf: usize, free: usize, entry: Entry<FP, V>) {
+        let path: Vec<_> = slots::chain(nodes, leaf).collect();
+        self.watermarks.evictions += path.len() as u64;
+        let mut carried = entry;
+        for &(bucket, slot) in path.iter().rev() {
+            let written = carried;
+            carried = self.buckets[bucket].replace(slot, written);
+            self.verify_write(bucket, slot, written);
+            self.note_write(bucket, slot);
+        }
+        let placed = self.put(free, &carried);
+        debug_assert!(placed, "the end of the chain has no free slot");
+    }
+
+    /// Whether an insert out of relocations may double the buckets.
+    fn can_grow(&self) -> bool {
+        if !self.auto_grow || self.growths == MAX_GROWTHS {
+            return false;
+        }
+        let grown = 2 * self.buckets.len() * mem::size_of::<Bucket<FP, V>>()
+            + self.access_tracker.as_ref().map_or(0, AccessTracker::memory_usage);
+        self.memory_cap.is_none_or(|cap| self.memory_usage() + grown <= cap)
+    }
+
+    /// The error of an insert that has no slot left for an entry, blaming
+    /// the memory cap if the buckets could have grown otherwise. Counts
+    /// the failure.
+    fn no_space(&mut self, fingerprint: Fingerprint<FP>, value: [u8; V]) -> CuckooError<FP, V> {
+        self.watermarks.failures += 1;
+        if self.auto_grow && self.growths < MAX_GROWTHS {
+            CuckooError::MemoryCapExceeded { fingerprint, value }
+        } else {
+            CuckooError::NotEnoughSpace { fingerprint, value }
+        }
+    }
+
+    /// Doubles the buckets, moving every entry of bucket `j` either to `j`
+    /// or to `j + len` as decided by `get_migrated_index`.
+    fn grow(&mut self) {
+        let len = self.buckets.len();
+        let mut buckets = BucketArray::new(2 * len);
+        let mut order = self.write_order.as_ref().map(|order| order.resized(2 * len));
+        for (j, bucket) in self.buckets.iter().enumerate() {
+            for (slot, entry) in bucket.entries().enumerate().filter(|(_, entry)| !entry.fingerprint.is_empty()) {
+                let alt_hash = get_alt_index::<H, FP>(entry.fingerprint, 0);
+                let moved = get_migrated_index(alt_hash, j, len);
+                let to = buckets[moved].set(entry.fingerprint, entry.value);
+                if let (Some(order), Some(old), Some(to)) = (&mut order, &self.write_order, to) {
+                    order.set(moved, to, old.get(j, slot));
+                }
+            }
+            let migrated = j + 1;
+            if migrated % RESIZE_PROGRESS_STEP == 0 || migrated == len {
+                if let Some(hook) = &mut self.on_resize_progress {
+                    hook(ResizeProgress { migrated, total: len });
+                }
+            }
+        }
+        for (j, entry) in self.stash.iter_mut() {
+            let alt_hash = get_alt_index::<H, FP>(entry.fingerprint, 0);
+            *j = get_migrated_index(alt_hash, *j, len);
+        }
+        self.buckets = buckets;
+        self.write_order = order;
+        self.mask = mask_for(2 * len);
+        self.growths += 1;
+        self.clear_hot_cache();
+        if let Some(tracker) = &mut self.access_tracker {
+            *tracker = AccessTracker::new(2 * len, tracker.sample_every());
+        }
+        if let Some(recency) = &mut self.recency {
+            recency.clear();
+        }
+    }
+
+    /// Deletes the entry described by `fai`, resolving duplicates per policy.
+    fn delete_fai(&mut self, fai: &FaI<FP>) -> bool {
+        // an expired entry is deleted, but wasn't there for the caller
+        let expired = self.expired(fai);
+        self.forget(fai);
+        let (hits, n) = self.hits(fai);
+        if n == 0 {
+            return match self.stashed(fai) {
+                Some(k) => {
+                    self.stash.swap_remove(k);
+                    self.len -= 1;
+                    !expired
+                }
+                None => false,
+            };
+        }
+        let removed = match self.duplicate_policy {
+            DuplicatePolicy::PreferPrimary => self.remove(fai.fp, hits[0]),
+            DuplicatePolicy::PreferNewest | DuplicatePolicy::Merge(_) => {
+                for &i in &hits[..n] {
+                    self.remove(fai.fp, i);
+                }
+                n > 0
+            }
+        };
+        removed && !expired
+    }
+
+    /// Removes the item with the given fingerprint from the bucket indexed by i.
+    fn remove(&mut self, fp: Fingerprint<FP>, i: usize) -> bool {
+        strict_assert!(!fp.is_empty(), "delete of the empty fingerprint");
+        let bucket = self.reduce(i);
+        if let Some(slot) = self.buckets[bucket].reset(fp) {
+            strict_assert!(self.len > 0, "len underflow");
+            self.len -= 1;
+            self.verify_write(i, slot, Entry::new());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Overwrites the value of the entry with the given fingerprint in the bucket indexed by i.
+    fn update(&mut self, i: usize, fp: Fingerprint<FP>, value: [u8; V]) {
+        let bucket = self.reduce(i);
+        if let Some(slot) = self.buckets[bucket].update(fp, value) {
+            self.verify_write(i, slot, Entry { fingerprint: fp, value });
+            if let Some(order) = &mut self.write_order {
+                order.stamp(bucket, slot);
+            }
+        }
+    }
+
+    /// Re-reads a slot of the bucket indexed by i after a write if write
+    /// verification is enabled, counting a failure if it doesn't hold `expected`.
+    fn verify_write(&mut self, i: usize, slot: usize, expected: Entry<FP, V>) {
+        if !self.verify_writes {
+            return;
+        }
+
+        // SAFETY: the pointer comes from a valid reference, the volatile read
+        // only keeps the compiler from reusing the value it just wrote.
+        let bucket = &self.buckets[self.reduce(i)];
+        let (fingerprint, value) = unsafe {
+            (ptr::read_volatile(&bucket.fingerprints[slot]), ptr::read_volatile(&bucket.values[slot]))
+        };
+        let value_matches = expected.fingerprint.is_empty() || value == expected.value;
+        if fingerprint != expected.fingerprint || !value_matches {
+            self.verification_failures += 1;
+        }
+    }
+
+    /// Returns the candidate buckets of the entry described by `fai`,
+    /// reduced and without duplicates, starting with its primary bucket.
+    ///
+    /// After the map grew, this includes the buckets the key's entry was
+    /// migrated to if it was stored in each of the earlier generations.
+    fn candidates(&self, fai: &FaI<FP>) -> Candidates {
+        self.layout().candidates::<H, FP, V>(fai)
+    }
+
+    /// Returns the buckets an entry with fingerprint `fp` stored in the
+    /// bucket indexed by i may be moved to, reduced and starting with i.
+    fn relocations(&self, fp: Fingerprint<FP>, i: usize) -> Candidates {
+        self.layout().relocations::<H, FP, V>(fp, i)
+    }
+
+    fn layout(&self) -> Layout {
+        Layout {
+            len: self.buckets.len(),
+            mask: self.mask,
+            ways: self.ways,
+            index_scheme: self.index_scheme,
+            growths: self.growths,
+        }
+    }
+
+    /// Position in the stash of the entry described by `fai`, if stashed.
+    fn stashed(&self, fai: &FaI<FP>) -> Option<usize> {
+        if self.stash.is_empty() {
+            return None;
+        }
+        let (buckets, n) = self.candidates(fai);
+        self.stash
+            .iter()
+            .position(|(j, entry)| entry.fingerprint == fai.fp && buckets[..n].contains(j))
+    }
+
+    /// Moves stashed entries back into the buckets where one of their
+    /// candidates has a free slot.
+    fn drain_stash(&mut self) {
+        let mut k = 0;
+        while k < self.stash.len() {
+            let (j, entry) = self.stash[k];
+            let (buckets, n) = self.relocations(entry.fingerprint, j);
+            // stashed entries count as written before all others
+            self.carry_write_seq(0);
+            if buckets[..n].iter().any(|&i| self.put(i, &entry)) {
+                self.stash.swap_remove(k);
+                self.len -= 1;
+            } else {
+                k += 1;
+            }
+        }
+    }
+
+    /// All stored entries, in the buckets and in the stash.
+    fn entries(&self) -> impl Iterator<Item = Entry<FP, V>> + '_ {
+        self.placed_entries().map(|(_, entry)| entry)
+    }
+
+    /// All stored entries along with the bucket they are stored in, or for
+    /// stashed entries the bucket they were kicked out of.
+    fn placed_entries(&self) -> impl Iterator<Item = (usize, Entry<FP, V>)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .flat_map(|(i, bucket)| bucket.occupied().map(move |entry| (i, entry)))
+            .chain(self.stash.iter().copied())
+    }
+
+    /// Reduces an index to one of the buckets.
+    #[inline]
+    fn reduce(&self, i: usize) -> usize {
+        if self.mask != 0 {
+            i & self.mask
+        } else {
+            i % self.buckets.len()
+        }
+    }
+
+    /// Returns the candidate buckets holding the fingerprint of `fai`,
+    /// in the order of `candidates`.
+    fn hits(&self, fai: &FaI<FP>) -> Candidates {
+        let (buckets, n) = self.candidates(fai);
+        let mut hits = [0; MAX_CANDIDATES];
+        let mut n_hits = 0;
+        for &i in &buckets[..n] {
+            if self.buckets[i].find(fai.fp).is_some() {
+                hits[n_hits] = i;
+                n_hits += 1;
+            }
+        }
+        (hits, n_hits)
+    }
+
+    /// Stores the entry in the bucket indexed by i if that bucket has an empty slot.
+    fn put(&mut self, i: usize, entry: &Entry<FP, V>) -> bool {
+        strict_assert!(
+            {
+                let (buckets, n) = self.relocations(entry.fingerprint, i);
+                buckets[1..n].iter().all(|&j| self.buckets[j].find(entry.fingerprint).is_none())
+            },
+            "fingerprint inserted into two candidate buckets"
+        );
+
+        let bucket = self.reduce(i);
+        if let Some(slot) = self.buckets[bucket].set(entry.fingerprint, entry.value) {
+            self.len += 1;
+            self.note_load();
+            self.verify_write(i, slot, *entry);
+            self.note_write(i, slot);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Raises the load factor watermark to the current load.
+    fn note_load(&mut self) {
+        let load_factor = self.len as f64 / self.capacity() as f64;
+        if load_factor > self.watermarks.max_load_factor {
+            self.watermarks.max_load_factor = load_factor;
+        }
+    }
+
+    /// calculates the the ratio of filled slots to all slots
+    pub fn density(&self) -> f64 {
+
+        let n_filled_slots = self.buckets.iter()
+            .flat_map(Bucket::occupied)
+            .count();
+
+        n_filled_slots as f64 / self.capacity() as f64
+    }
+
+    /// Checks the internal invariants of the map: the layout of the buckets,
+    /// the number of entries and the stash. Returns the first one violated.
+    ///
+    /// Walks all buckets, so it's meant for tests and soak runs rather than
+    /// for every operation.
+    pub fn debug_validate(&self) -> Result<(), &'static str> {
+        let len = self.buckets.len();
+        if !valid_layout(len, self.index_scheme, self.ways, self.growths) {
+            return Err("the number of buckets, ways, index scheme and growths don't fit together");
+        }
+        if self.mask != mask_for(len) {
+            return Err("the mask doesn't match the number of buckets");
+        }
+        if self.stash.len() > self.stash_capacity || self.stash_capacity > MAX_STASH {
+            return Err("the stash holds more entries than its capacity");
+        }
+        if self.stash.iter().any(|(j, entry)| *j >= len || entry.fingerprint.is_empty()) {
+            return Err("the stash holds an invalid entry");
+        }
+        let placed = self.buckets.iter().map(|bucket| bucket.occupied().count()).sum::<usize>();
+        if self.len != placed + self.stash.len() {
+            return Err("the number of entries doesn't match the buckets and the stash");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{get_fai, get_hash64};
+
+    #[cfg(not(feature = "strict-debug"))]
+    fn map_with_duplicate(policy: DuplicatePolicy) -> (CuckooMap<DefaultHasher>, FaI<1>) {
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        map.set_duplicate_policy(policy);
+
+        let fai = get_fai::<_, DefaultHasher, 1>("key");
+        let len = map.buckets.len();
+        assert_ne!(fai.i1 % len, fai.i2 % len);
+        put_written(&mut map, fai.i1, Entry { fingerprint: fai.fp, value: [1] });
+        put_written(&mut map, fai.i2, Entry { fingerprint: fai.fp, value: [2] });
+        (map, fai)
+    }
+
+    /// Puts `entry` into bucket `i` as a write of its own.
+    #[cfg(not(feature = "strict-debug"))]
+    fn put_written(map: &mut CuckooMap<DefaultHasher>, i: usize, entry: Entry<1, 1>) {
+        if let Some(order) = &mut map.write_order {
+            order.begin_write();
+        }
+        map.put(i, &entry);
+    }
+
+    #[test]
+    #[cfg(not(feature = "strict-debug"))]
+    fn test_duplicate_policy() {
+        let (mut map, _) = map_with_duplicate(DuplicatePolicy::PreferPrimary);
+        assert_eq!(map.get("key"), Some([1]));
+        map.insert_or_update("key", [3]).unwrap();
+        assert_eq!(map.len(), 2);
+        assert!(map.delete("key"));
+        assert_eq!(map.get("key"), Some([2]));
+
+        // the copy in the alternate bucket was written last
+        let (mut map, fai) = map_with_duplicate(DuplicatePolicy::PreferNewest);
+        assert_eq!(map.get("key"), Some([2]));
+        map.insert_or_update("key", [3]).unwrap();
+        assert_eq!(map.len(), 1);
+        let (hits, n) = map.hits(&fai);
+        assert_eq!(hits[..n], [fai.i1 % map.buckets.len()]);
+        assert_eq!(map.get("key"), Some([3]));
+        // writes and moves keep the order, deletes remove every copy
+        put_written(&mut map, fai.i2, Entry { fingerprint: fai.fp, value: [4] });
+        assert_eq!(map.get("key"), Some([4]));
+        map.grow();
+        assert_eq!(map.get("key"), Some([4]));
+        assert!(map.delete("key"));
+        assert_eq!(map.get("key"), None);
+
+        let (mut map, _) = map_with_duplicate(DuplicatePolicy::Merge(|a, b| [a[0] | b[0]]));
+        assert_eq!(map.get("key"), Some([3]));
+        assert!(map.delete("key"));
+        assert!(map.is_empty());
+        assert_eq!(map.get("key"), None);
+    }
+
+    #[test]
+    fn test_hashed_matches_keyed() {
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        let mut hasher = DefaultHasher::default();
+        "key".hash(&mut hasher);
+        let hash = hasher.finish();
+
+        map.insert_or_update("key", [7]).unwrap();
+        assert_eq!(map.get_hashed(hash), Some([7]));
+        map.insert_hashed(hash, [8]).unwrap();
+        assert_eq!(map.get("key"), Some([8]));
+        assert!(map.delete_hashed(hash));
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn test_merge_rule() {
+        let mut a = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        let mut b = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        a.set_merge_rule(MergeRule::Max);
+        b.set_merge_rule(MergeRule::Max);
+
+        a.insert_or_update("shared", [3]).unwrap();
+        a.insert_or_update("only a", [1]).unwrap();
+        b.insert_or_update("shared", [5]).unwrap();
+        b.insert_or_update("only b", [2]).unwrap();
+
+        let mut ab = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        ab.set_merge_rule(MergeRule::Max);
+        ab.merge(&a).unwrap();
+        ab.merge(&b).unwrap();
+        let report = b.merge(&a).unwrap();
+        assert_eq!(report, MergeReport { added: 1, conflicts: 1, dropped: 0 });
+
+        for key in &["shared", "only a", "only b"] {
+            assert_eq!(ab.get(key), b.get(key));
+        }
+        assert_eq!(b.get("shared"), Some([5]));
+        assert_eq!(b.len(), 3);
+
+        let small = CuckooMap::<DefaultHasher>::with_capacity(1 << 4);
+        assert!(matches!(b.merge(&small), Err(CuckooError::CapacityMismatch)));
+
+        // values compare as a whole, not byte by byte
+        let mut wide = CuckooMap::<DefaultHasher, 1, 2>::with_capacity(1 << 10);
+        let mut other = CuckooMap::<DefaultHasher, 1, 2>::with_capacity(1 << 10);
+        wide.set_merge_rule(MergeRule::Max);
+        wide.insert_or_update("epoch", [1, 0xff]).unwrap();
+        other.insert_or_update("epoch", [2, 0x00]).unwrap();
+        wide.merge(&other).unwrap();
+        assert_eq!(wide.get("epoch"), Some([2, 0x00]));
+        assert_eq!(MergeRule::Max.apply([2, 0x00], [1, 0xff]), [2, 0x00]);
+    }
+
+    #[test]
+    fn test_merge_report_counts_drops() {
+        let mut a = CuckooMap::<DefaultHasher>::with_capacity(1 << 4);
+        let mut b = CuckooMap::<DefaultHasher>::with_capacity(1 << 4);
+        for i in 0..64u32 {
+            a.insert_best_effort(&i, [0]);
+            b.insert_best_effort(&(i + 64), [0]);
+        }
+
+        let report = a.merge(&b).unwrap();
+        assert_eq!(report.added + report.conflicts, b.len());
+        assert!(report.dropped > 0);
+    }
+
+    #[test]
+    fn test_adaptive_kick_budget() {
+        let policy = KickPolicy::Adaptive { threshold: 0.5, min_kicks: 16 };
+        assert_eq!(policy.budget(500, 0.25), 500);
+        assert_eq!(policy.budget(500, 0.75), 258);
+        assert_eq!(policy.budget(500, 1.0), 16);
+        assert_eq!(policy.budget(8, 1.0), 8);
+        assert_eq!(KickPolicy::Fixed.budget(500, 1.0), 500);
+    }
+
+    /// Hashes integers to themselves, leaving the upper 32 bits empty.
+    #[derive(Default)]
+    struct IdentityHasher(u64);
+
+    impl Hasher for IdentityHasher {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for b in bytes {
+                self.0 = (self.0 << 8) | u64::from(*b);
+            }
+        }
+
+        fn write_u64(&mut self, i: u64) {
+            self.0 = i;
+        }
+    }
+
+    #[test]
+    fn test_hasher_quality_report() {
+        let map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        let report = map.hasher_quality_report(0..10_000u64);
+        assert_eq!(report.samples, 10_000);
+        assert!(!report.is_poor(), "{:?}", report);
+
+        let map = CuckooMap::<IdentityHasher>::with_capacity(1 << 10);
+        let report = map.hasher_quality_report(0..10_000u64);
+        assert_eq!(report.fingerprint_entropy, 0.0);
+        assert!(report.is_poor());
+    }
+
+    #[test]
+    #[cfg(not(feature = "strict-debug"))]
+    fn test_get_detailed() {
+        let (map, fai) = map_with_duplicate(DuplicatePolicy::PreferPrimary);
+        let lookup = map.get_detailed("key").unwrap();
+        assert_eq!(lookup.value, [1]);
+        assert_eq!(lookup.bucket, fai.i1 % map.buckets.len());
+        assert!(lookup.primary && lookup.ambiguous);
+        assert!(lookup.false_positive_probability > 0.0);
+        assert_eq!(map.get_detailed("other key"), None);
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        map.set_rate_limiter(Some(RateLimiter::new(1, 3)));
+
+        for i in 0..3 {
+            map.insert_or_update(&i, [0]).unwrap();
+        }
+        assert!(matches!(map.insert_or_update(&3, [0]), Err(CuckooError::Throttled)));
+        assert_eq!(map.len(), 3);
+
+        // refills follow the map's clock
+        let clock = MockClock::new();
+        map.set_clock(clock.clone());
+        assert!(matches!(map.insert_or_update(&3, [0]), Err(CuckooError::Throttled)));
+        clock.advance(Duration::from_millis(999));
+        assert!(matches!(map.insert_or_update(&3, [0]), Err(CuckooError::Throttled)));
+        clock.advance(Duration::from_millis(1));
+        map.insert_or_update(&3, [0]).unwrap();
+
+        map.set_rate_limiter(None);
+        map.insert_or_update(&4, [0]).unwrap();
+    }
+
+    #[test]
+    fn test_update_policy() {
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+
+        map.set_update_policy(UpdatePolicy::KeepFirst);
+        map.insert_or_update("token", [1]).unwrap();
+        map.insert_or_update("token", [2]).unwrap();
+        assert_eq!(map.get("token"), Some([1]));
+
+        map.set_update_policy(UpdatePolicy::Merge(|a, b| [a[0] + b[0]]));
+        map.insert_or_update("token", [2]).unwrap();
+        assert_eq!(map.get("token"), Some([3]));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn test_owned_entries() {
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        map.insert_batch((0..100u8).map(|i| (i, [i]))).unwrap();
+
+        let entries = map.to_owned_entries();
+        assert_eq!(entries.len(), 100);
+        let counts = map.count_by_value();
+        assert!(counts[..100].iter().all(|&c| c == 1));
+        assert!(counts[100..].iter().all(|&c| c == 0));
+        let iter = map.into_iter();
+        assert!(iter.clone().eq(entries.iter().cloned()));
+
+        let mut values: Vec<u8> = iter.map(|(_, value)| value[0]).collect();
+        values.sort_unstable();
+        assert_eq!(values, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_verify_writes() {
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        map.set_verify_writes(true);
+        for i in 0..48u8 {
+            map.insert_or_update(&i, [i]).unwrap();
+            map.insert_or_update(&i, [i + 1]).unwrap();
+        }
+        for i in 0..16u8 {
+            assert!(map.delete(&i));
+        }
+        assert_eq!(map.verification_failures(), 0);
+    }
+
+    #[test]
+    fn test_insert_best_effort() {
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 4);
+        map.set_stash_capacity(0);
+        let outcomes: Vec<InsertOutcome> = (0..64u8).map(|i| map.insert_best_effort(&i, [i])).collect();
+
+        assert_eq!(outcomes[0], InsertOutcome::Stored);
+        assert!(outcomes.contains(&InsertOutcome::Evicted));
+        let stats = map.best_effort_stats();
+        assert_eq!(stats.stored + stats.evicted + stats.dropped, 64);
+
+        map.set_rate_limiter(Some(RateLimiter::new(1, 0)));
+        assert_eq!(map.insert_best_effort("late", [0]), InsertOutcome::Dropped);
+        assert_eq!(map.best_effort_stats().dropped, stats.dropped + 1);
+    }
+
+    #[test]
+    fn test_on_store() {
+        use std::sync::{Arc, Mutex};
+
+        let mirrored = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&mirrored);
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        map.set_on_store(move |hash, value| sink.lock().unwrap().push((hash, value)));
+
+        map.insert_or_update("a", [1]).unwrap();
+        map.insert_hashed(42, [2]).unwrap();
+        map.set_update_policy(UpdatePolicy::KeepFirst);
+        map.insert_or_update("a", [3]).unwrap();
+        map.clear_on_store();
+        map.insert_or_update("b", [4]).unwrap();
+
+        let hash = get_hash64::<_, DefaultHasher>("a");
+        assert_eq!(*mirrored.lock().unwrap(), vec![(hash, [1]), (42, [2]), (hash, [1])]);
+
+        // merges bypass the hook
+        let sink = Arc::clone(&mirrored);
+        map.set_on_store(move |hash, value| sink.lock().unwrap().push((hash, value)));
+        let mut other = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        other.insert_or_update("c", [5]).unwrap();
+        assert_eq!(map.merge(&other).unwrap().added, 1);
+        assert_eq!(map.get("c"), Some([5]));
+        assert_eq!(mirrored.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_into_background_drop() {
+        use std::sync::Arc;
+
+        // the hook holds a reference until the map is dropped
+        let tracker = Arc::new(());
+        let held = Arc::clone(&tracker);
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 16);
+        map.set_on_store(move |_, _| assert!(Arc::strong_count(&held) > 1));
+        for i in 0..1000u32 {
+            map.insert_or_update(&i, [1]).unwrap();
+        }
+        assert_eq!(Arc::strong_count(&tracker), 2);
+
+        map.into_background_drop().join().unwrap();
+        assert_eq!(Arc::strong_count(&tracker), 1);
+    }
+
+    #[test]
+    fn test_multi_slot_load_factor() {
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        assert_eq!(map.capacity(), 1 << 10);
+        map.seed_eviction_rng(7);
+
+        let mut key = 0u32;
+        while map.insert_or_update(&key, [0]).is_ok() {
+            key += 1;
+        }
+        assert!(map.density() > 0.9, "{}", map.density());
+    }
+
+    #[test]
+    #[cfg(not(feature = "strict-debug"))]
+    fn test_get_and_promote() {
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        map.set_probe_stats(true);
+        let fai = get_fai::<_, DefaultHasher, 1>("key");
+        let len = map.buckets.len();
+        assert_ne!(fai.i1 % len, fai.i2 % len);
+        map.put(fai.i2, &Entry { fingerprint: fai.fp, value: [1] });
+
+        assert_eq!(map.get("key"), Some([1]));
+        assert_eq!(map.get_and_promote("key"), Some([1]));
+        let (hits, n) = map.hits(&fai);
+        assert_eq!(hits[..n], [fai.i1 % len]);
+        assert_eq!(map.get("key"), Some([1]));
+        assert_eq!(map.get("other key"), None);
+        assert_eq!(map.len(), 1);
+
+        let stats = map.probe_stats();
+        assert_eq!(stats, ProbeStats { primary_hits: 1, alternate_hits: 2, promotions: 1 });
+
+        // counting stops and starts over
+        map.set_probe_stats(false);
+        assert_eq!(map.get("key"), Some([1]));
+        assert_eq!(map.probe_stats(), ProbeStats { primary_hits: 0, alternate_hits: 0, promotions: 1 });
+        assert!(!map.probe_stats_enabled());
+    }
+
+    #[test]
+    fn test_wide_fingerprints() {
+        let mut narrow = CuckooMap::<DefaultHasher>::with_capacity(1 << 12);
+        let mut wide = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 12);
+        for i in 0..3_000u32 {
+            narrow.insert_or_update(&i, [0]).unwrap();
+            wide.insert_or_update(&i, [0]).unwrap();
+        }
+        assert!(wide.memory_usage() > narrow.memory_usage());
+        assert!(wide.false_positive_probability() < narrow.false_positive_probability() / 200.0);
+
+        let false_positives = (3_000..103_000u32).filter(|i| wide.get(i).is_some()).count();
+        assert!(false_positives < 100, "{}", false_positives);
+        assert!((0..3_000u32).all(|i| wide.get(&i).is_some()));
+    }
+
+    #[test]
+    fn test_wide_values() {
+        let mut map = CuckooMap::<DefaultHasher, 4, 8>::with_capacity(1 << 10);
+        for i in 0..500u64 {
+            map.insert_or_update(&i, i.to_le_bytes()).unwrap();
+        }
+        assert!((0..500u64).all(|i| map.get(&i) == Some(i.to_le_bytes())));
+        assert_eq!(mem::size_of::<Bucket<4, 8>>(), ENTRIES_PER_BUCKET * (4 + 8));
+    }
+
+    #[test]
+    fn test_ways() {
+        for &ways in &[3, 4] {
+            let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 10);
+            map.set_ways(ways);
+            map.seed_eviction_rng(7);
+            let n = map.capacity() * 98 / 100;
+            for i in 0..n {
+                map.insert_or_update(&i, [i as u8]).unwrap();
+            }
+            assert_eq!(map.len(), n);
+            assert!((0..n).all(|i| map.get(&i) == Some([i as u8])));
+            assert!((0..n / 2).all(|i| map.delete(&i)));
+            assert!((0..n / 2).all(|i| map.get(&i).is_none()));
+        }
+
+        let mut two_way = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        let mut four_way = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        four_way.set_ways(4);
+        for i in 0..500u32 {
+            two_way.insert_or_update(&i, [0]).unwrap();
+            four_way.insert_or_update(&i, [0]).unwrap();
+        }
+        assert!(four_way.false_positive_probability() > 1.9 * two_way.false_positive_probability());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_set_ways_on_filled_map() {
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        map.insert_or_update("key", [0]).unwrap();
+        map.set_ways(3);
+    }
+
+    #[test]
+    fn test_auto_grow() {
+        let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(64);
+        map.set_auto_grow(true);
+        map.seed_eviction_rng(3);
+        for i in 0..800u32 {
+            map.insert_or_update(&i, [i as u8]).unwrap();
+        }
+        assert_eq!(map.len(), 800);
+        assert_eq!(map.growths(), 4);
+        assert_eq!(map.capacity(), 1024);
+        assert!((0..800u32).all(|i| map.get(&i) == Some([i as u8])));
+
+        assert!((0..400u32).all(|i| map.delete(&i)));
+        assert!((400..800u32).all(|i| map.get(&i) == Some([i as u8])));
+        assert_eq!(map.len(), 400);
+
+        // the last growth is used up, so the map fills up as usual
+        let mut i = 800;
+        while map.insert_or_update(&i, [0]).is_ok() {
+            i += 1;
+        }
+        assert_eq!(map.growths(), MAX_GROWTHS);
+        assert!(map.len() > 950);
+
+        map.clear();
+        assert_eq!(map.growths(), 0);
+        assert_eq!(map.capacity(), 1024);
+    }
+
+    #[test]
+    fn test_kick_cycle_only_moves_chosen_slots() {
+        use rand::rngs::mock::StepRng;
+
+        // with 2 buckets and a generator that always yields 0, every kick
+        // swaps slot 0 of a key's candidates, cycling the same few entries
+        let slots = |map: &CuckooMap<DefaultHasher, 2>| -> Vec<_> {
+            map.buckets.iter().flat_map(|b| b.entries().skip(1).map(|e| (e.fingerprint, e.value))).collect()
+        };
+        let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(8);
+        map.set_stash_capacity(0);
+        map.set_eviction_rng(StepRng::new(0, 0));
+
+        for i in 0u8.. {
+            let (before, len) = (slots(&map), map.len());
+            if let Err(CuckooError::NotEnoughSpace { fingerprint, value }) = map.insert_or_update(&i, [i]) {
+                assert_eq!(slots(&map), before);
+                // exactly one entry of the cycle, possibly the new one, was
+                // lost, and the error returns it
+                assert_eq!(map.len(), len);
+                assert_eq!((0..=i).filter(|k| map.get(k).is_some()).count(), len);
+                let lost = (0..=i).find(|k| map.get(k).is_none()).unwrap();
+                assert_eq!((fingerprint, value), (get_fai::<_, DefaultHasher, 2>(&lost).fp, [lost]));
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn test_breadth_first_path_search() {
+        for &ways in &[2, 4] {
+            let mut map = CuckooMap::<DefaultHasher, 4>::with_capacity(1 << 12);
+            map.set_ways(ways);
+            map.set_path_search(PathSearch::BreadthFirst { max_depth: 5 });
+            map.set_stash_capacity(0);
+            map.set_verify_writes(true);
+            let mut i = 0u32;
+            while map.insert_or_update(&i, [i as u8]).is_ok() {
+                i += 1;
+            }
+            // a failed search leaves the buckets untouched, even without a stash
+            assert_eq!(map.len(), i as usize);
+            assert!((0..i).all(|k| map.get(&k) == Some([k as u8])));
+            assert!(map.len() as f64 / map.capacity() as f64 > 0.9);
+            assert_eq!(map.verification_failures(), 0);
+        }
+    }
+
+    #[test]
+    fn test_stash() {
+        let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 4);
+        map.seed_eviction_rng(5);
+        let mut i = 0u16;
+        let rejected = loop {
+            match map.insert_or_update(&i, [i as u8]) {
+                Ok(()) => i += 1,
+                Err(e) => break e,
+            }
+        };
+        // with a full stash the new entry is rejected and handed back
+        assert!(matches!(
+            rejected,
+            CuckooError::NotEnoughSpace { fingerprint, value: [v] }
+                if fingerprint == get_fai::<_, DefaultHasher, 2>(&i).fp && v == i as u8
+        ));
+        assert_eq!(map.stash_len(), DEFAULT_STASH_CAPACITY);
+        assert_eq!(map.len(), i as usize);
+        assert_eq!(map.get(&i), None);
+        assert!((0..i).all(|k| map.get(&k) == Some([k as u8])));
+        assert_eq!(map.to_owned_entries().len(), i as usize);
+
+        for k in 0..i / 2 {
+            assert!(map.delete(&k));
+        }
+        map.insert_or_update("drain", [0]).unwrap();
+        assert_eq!(map.stash_len(), 0);
+        assert!((i / 2..i).all(|k| map.get(&k) == Some([k as u8])));
+        assert_eq!(map.len(), (i - i / 2) as usize + 1);
+    }
+
+    #[test]
+    fn test_estimated_cardinality_by() {
+        let mut map = CuckooMap::<DefaultHasher, 1>::with_capacity(1 << 12);
+        for i in 0..3_600u32 {
+            map.insert_or_update(&i, [(i % 3) as u8 + 1]).unwrap();
+        }
+        assert!(map.len() < 3_580, "{} entries", map.len());
+
+        let tenants = map.estimated_cardinality_by(|value| value[0]);
+        assert_eq!(tenants.len(), 3);
+        for tenant in 1..=3 {
+            let estimate = tenants[&tenant];
+            assert!((estimate - 1_200.0).abs() < 40.0, "tenant {}: {}", tenant, estimate);
+        }
+
+        let total: f64 = map.estimated_cardinality_by(|_| ()).values().sum();
+        assert!((total - 3_600.0).abs() < 40.0, "{}", total);
+    }
+
+    #[test]
+    fn test_iter_partition() {
+        let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 10);
+        for i in 0..500u32 {
+            map.insert_or_update(&i, [1]).unwrap();
+        }
+
+        let mut seen = 0;
+        for partition in 0..8 {
+            let entries: Vec<_> = map.iter_partition(3, partition).collect();
+            assert!(entries.len() > 30, "{} entries in {}", entries.len(), partition);
+            assert!(entries.iter().all(|&(i, fp, _)| map.buckets[i].find(fp).is_some()));
+            seen += entries.len();
+        }
+        assert_eq!(seen, map.len());
+        assert!((0..500u32).all(|i| {
+            let fp = get_fai::<_, DefaultHasher, 2>(&i).fp;
+            let partition = map.partition_of(&i, 3);
+            map.iter_partition(3, partition).any(|(_, f, _)| f == fp)
+        }));
+        assert_eq!(map.iter_partition(0, 0).count(), map.len());
+    }
+
+    #[test]
+    fn test_get_or_insert_with() {
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        let mut loads = 0;
+        for _ in 0..3 {
+            let value = map.get_or_insert_with("a", || {
+                loads += 1;
+                [7]
+            });
+            assert_eq!(value.unwrap(), [7]);
+        }
+        assert_eq!(loads, 1);
+        assert_eq!(map.len(), 1);
+
+        map.set_value_validator(|_| Err("read-only"));
+        assert!(map.get_or_insert_with("b", || [1]).is_err());
+        assert_eq!(map.get("b"), None);
+    }
+
+    #[test]
+    fn test_max_kicks() {
+        let fill = |max_kicks| {
+            let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 10);
+            map.set_max_kicks(max_kicks);
+            map.set_stash_capacity(0);
+            map.seed_eviction_rng(1);
+            let mut i = 0u32;
+            while map.insert_or_update(&i, [0]).is_ok() {
+                i += 1;
+            }
+            assert_eq!(map.max_kicks(), max_kicks);
+            i
+        };
+        // without relocations inserts fail as soon as both candidates are full
+        assert!(fill(0) < fill(MAX_REBUCKET));
+    }
+
+    #[test]
+    fn test_value_validator() {
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        map.set_value_validator(|value| if *value == [0] { Err("0 is reserved") } else { Ok(()) });
+
+        assert!(matches!(map.insert_or_update("a", [0]), Err(CuckooError::InvalidValue("0 is reserved"))));
+        assert_eq!(map.get("a"), None);
+        map.insert_or_update("a", [1]).unwrap();
+
+        // the value after the update policy is checked
+        map.set_update_policy(UpdatePolicy::Merge(|old, new| [old[0].wrapping_add(new[0])]));
+        assert!(map.insert_or_update("a", [255]).is_err());
+        assert_eq!(map.get("a"), Some([1]));
+
+        map.clear_value_validator();
+        map.insert_or_update("a", [255]).unwrap();
+        assert_eq!(map.get("a"), Some([0]));
+
+        // rejected values don't take tokens from the rate limiter
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        map.set_clock(MockClock::new());
+        map.set_rate_limiter(Some(RateLimiter::new(1, 2)));
+        map.set_value_validator(|value| if *value == [0] { Err("0 is reserved") } else { Ok(()) });
+        for i in 0..10u32 {
+            assert!(matches!(map.insert_or_update(&i, [0]), Err(CuckooError::InvalidValue(_))));
+        }
+        map.insert_or_update(&1u32, [1]).unwrap();
+        map.insert_or_update(&2u32, [1]).unwrap();
+        assert!(matches!(map.insert_or_update(&3u32, [1]), Err(CuckooError::Throttled)));
+    }
+
+    #[test]
+    fn test_resize_progress() {
+        use std::sync::{Arc, Mutex};
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&reports);
+        let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 16);
+        map.set_auto_grow(true);
+        map.set_on_resize_progress(move |progress| sink.lock().unwrap().push(progress));
+
+        let mut i = 0u32;
+        while map.growths() == 0 {
+            map.insert_or_update(&i, [0]).unwrap();
+            i += 1;
+        }
+        let reports = reports.lock().unwrap();
+        let total = 1 << 14;
+        assert_eq!(reports.len(), total / RESIZE_PROGRESS_STEP);
+        assert!(reports.iter().all(|p| p.total == total && p.migrated % RESIZE_PROGRESS_STEP == 0));
+        assert_eq!(reports.last(), Some(&ResizeProgress { migrated: total, total }));
+    }
+
+    #[test]
+    fn test_hot_cache() {
+        let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+        map.set_hot_cache(64);
+        assert_eq!(map.hot_cache_capacity(), 64);
+        map.set_probe_stats(true);
+        for i in 0..500u32 {
+            map.insert_or_update(&i, [1]).unwrap();
+        }
+
+        assert_eq!(map.get(&7u32), Some([1]));
+        let probes = map.probe_stats();
+        assert_eq!(probes.primary_hits + probes.alternate_hits, 1);
+        assert_eq!(map.get(&7u32), Some([1]));
+        assert_eq!(map.probe_stats(), probes);
+
+        // writes are visible through the cache
+        map.insert_or_update(&7u32, [2]).unwrap();
+        assert_eq!(map.get(&7u32), Some([2]));
+        assert!(map.delete(&7u32));
+        assert_eq!(map.get(&7u32), None);
+
+        assert!((0..500u32).filter(|&i| i != 7).all(|i| map.get(&i) == Some([1])));
+        map.clear();
+        assert_eq!(map.get(&8u32), None);
+        map.set_hot_cache(0);
+        assert_eq!(map.hot_cache_capacity(), 0);
+    }
+
+    #[test]
+    fn test_exact_capacity() {
+        let mut map = CuckooMap::<DefaultHasher, 2, 2>::with_exact_capacity(3_000);
+        map.seed_eviction_rng(3);
+        assert_eq!(map.buckets.len(), 750);
+        assert_eq!(map.capacity(), 3_000);
+
+        // both candidates of every key lie within the table and lead back to
+        // each other, from whichever one an entry was kicked out of
+        for i in 0..2_850u32 {
+            map.insert_or_update(&i, [i as u8, 1]).unwrap();
+        }
+        assert_eq!(map.len(), 2_850);
+        assert!((0..2_850u32).all(|i| map.get(&i) == Some([i as u8, 1])));
+        for (i, entry) in map.placed_entries() {
+            let (buckets, n) = map.relocations(entry.fingerprint, i);
+            let alt = buckets[n - 1];
+            assert!(alt < 750);
+            assert_eq!(map.relocations(entry.fingerprint, alt).0[..n].last(), Some(&i));
+        }
+
+        assert!((0..2_850u32).step_by(2).all(|i| map.delete(&i)));
+        assert_eq!(map.len(), 1_425);
+        assert!((1..2_850u32).step_by(2).all(|i| map.get(&i) == Some([i as u8, 1])));
+
+        // same number of buckets, but keys are placed differently
+        let mut exact = CuckooMap::<DefaultHasher, 2, 2>::with_exact_capacity(1 << 12);
+        let rounded = CuckooMap::<DefaultHasher, 2, 2>::with_capacity(1 << 12);
+        assert!(matches!(exact.merge(&rounded), Err(CuckooError::CapacityMismatch)));
+    }
+
+    #[test]
+    fn test_hash_seed() {
+        let keys: Vec<u32> = (0..1_000).collect();
+        let mut plain = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 12);
+        let mut seeded = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 12);
+        seeded.set_hash_seed(42);
+        assert_eq!(plain.hash_seed(), None);
+        assert_eq!(seeded.hash_seed(), Some(42));
+        assert_eq!(plain.key_hash(&7u32), get_hash64::<_, DefaultHasher>(&7u32));
+        assert_ne!(seeded.key_hash(&7u32), plain.key_hash(&7u32));
+
+        for &key in &keys {
+            plain.insert_or_update(&key, [1]).unwrap();
+            seeded.insert_or_update(&key, [1]).unwrap();
+        }
+        assert!(keys.iter().all(|key| seeded.get(key) == Some([1])));
+        assert_eq!(seeded.get_hashed(seeded.key_hash(&7u32)), Some([1]));
+        // the same keys end up in other buckets
+        let placed = |map: &CuckooMap<DefaultHasher, 2>| map.placed_entries().map(|(i, _)| i).collect::<Vec<_>>();
+        assert_ne!(placed(&plain), placed(&seeded));
+
+        let mut other = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 12);
+        let seed = other.randomize_hash_seed();
+        assert_eq!(other.hash_seed(), Some(seed));
+        assert!(matches!(other.merge(&seeded), Err(CuckooError::CapacityMismatch)));
+        other.set_hash_seed(42);
+        other.merge(&seeded).unwrap();
+        assert!(keys.iter().all(|key| other.get(key) == Some([1])));
+    }
+
+    #[test]
+    fn test_access_sampling() {
+        let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 12);
+        assert_eq!(map.access_sampling(), 0);
+        assert_eq!(map.idle_ratio(), None);
+        map.set_access_sampling(4);
+        assert_eq!(map.idle_ratio(), None);
+        for i in 0..2_000u32 {
+            map.insert_or_update(&i, [1]).unwrap();
+        }
+        assert_eq!(map.idle_ratio(), Some(1.0));
+
+        // about every fourth lookup of the first half sets a bit
+        for i in 0..1_000u32 {
+            map.get(&i);
+        }
+        let ratio = map.idle_ratio().unwrap();
+        assert!((ratio - 0.875).abs() < 0.02, "{}", ratio);
+        for _ in 0..30 {
+            for i in 0..1_000u32 {
+                map.get(&i);
+            }
+        }
+        let ratio = map.idle_ratio().unwrap();
+        assert!((ratio - 0.5).abs() < 0.01, "{}", ratio);
+
+        // bits belong to the entry, not to the slot
+        for i in 0..1_000u32 {
+            map.delete(&i);
+        }
+        for i in 2_000..3_000u32 {
+            map.insert_or_update(&i, [1]).unwrap();
+        }
+        assert_eq!(map.idle_ratio(), Some(1.0));
+
+        map.get(&5_000u32);
+        for i in 2_000..3_000u32 {
+            map.get(&i);
+        }
+        assert!(map.idle_ratio().unwrap() < 1.0);
+        map.reset_access_bits();
+        assert_eq!(map.idle_ratio(), Some(1.0));
+
+        map.set_eviction_strategy(LruEviction);
+        map.set_access_sampling(0);
+        assert_eq!(map.idle_ratio(), None);
+    }
+
+    #[test]
+    fn test_ttl_sweep() {
+        let clock = MockClock::new();
+        let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 10);
+        map.set_clock(clock.clone());
+        assert_eq!(map.sweep(256), 0);
+        map.insert_or_update(&0u32, [1]).unwrap();
+        map.set_ttl(Duration::from_millis(50));
+        assert_eq!(map.ttl(), Some(Duration::from_millis(50)));
+        for i in 1..100u32 {
+            map.insert_or_update(&i, [1]).unwrap();
+        }
+        clock.advance(Duration::from_millis(49));
+        assert_eq!(map.get(&1u32), Some([1]));
+        clock.advance(Duration::from_millis(1));
+        for i in 50..100u32 {
+            map.insert_or_update(&i, [2]).unwrap();
+        }
+
+        // expired entries are gone for callers, but still take their slot
+        assert_eq!(map.get(&1u32), None);
+        assert_eq!(map.get(&50u32), Some([2]));
+        assert_eq!(map.get(&0u32), Some([1]));
+        assert_eq!(map.len(), 100);
+        assert!(!map.delete(&2u32));
+        assert_eq!(map.len(), 99);
+        map.insert_or_update(&3u32, [3]).unwrap();
+        assert_eq!(map.get(&3u32), Some([3]));
+
+        // the sweep resumes where it stopped
+        let swept: usize = (0..4).map(|_| map.sweep(64)).sum();
+        assert_eq!(swept, 47);
+        assert_eq!(map.len(), 52);
+        assert_eq!(map.sweep(256), 0);
+        assert!((50..100u32).all(|i| map.get(&i) == Some([2])));
+
+        map.clear_ttl();
+        assert_eq!(map.sweep(256), 0);
+    }
+
+    #[test]
+    fn test_ttl_stale_grace() {
+        let clock = MockClock::new();
+        let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 10);
+        map.set_clock(clock.clone());
+        map.set_ttl(Duration::from_millis(50));
+        assert_eq!(map.stale_grace(), Duration::ZERO);
+        map.set_stale_grace(Duration::from_millis(20));
+        assert_eq!(map.stale_grace(), Duration::from_millis(20));
+        for i in 0..10u32 {
+            map.insert_or_update(&i, [1]).unwrap();
+        }
+        assert!(!map.get_detailed(&0u32).unwrap().expired);
+
+        // stale within the grace period
+        clock.advance(Duration::from_millis(50));
+        map.insert_or_update(&1u32, [2]).unwrap();
+        assert_eq!(map.get(&0u32), None);
+        let stale = map.get_detailed(&0u32).unwrap();
+        assert!(stale.expired);
+        assert_eq!(stale.value, [1]);
+        assert_eq!(map.get_detailed(&1u32).map(|lookup| (lookup.value, lookup.expired)), Some(([2], false)));
+        assert_eq!(map.sweep(256), 0);
+        assert_eq!(map.len(), 10);
+
+        // refreshing a stale entry starts a new lifetime
+        map.insert_or_update(&2u32, [3]).unwrap();
+        clock.advance(Duration::from_millis(19));
+        assert!(map.get_detailed(&0u32).unwrap().expired);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(map.get_detailed(&0u32), None);
+        assert_eq!(map.get(&2u32), Some([3]));
+        assert_eq!(map.sweep(256), 8);
+        assert_eq!(map.len(), 2);
+
+        map.set_stale_grace(Duration::ZERO);
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(map.get_detailed(&1u32), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "stale entries need a TTL")]
+    fn test_stale_grace_without_ttl() {
+        let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 10);
+        map.set_stale_grace(Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_get_many() {
+        let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 10);
+        for i in (0..100u32).step_by(2) {
+            map.insert_or_update(&i, [i as u8]).unwrap();
+        }
+        let values = map.get_many(0..100u32);
+        assert_eq!(values.len(), 100);
+        for (i, value) in values.into_iter().enumerate() {
+            assert_eq!(value, map.get(&(i as u32)));
+        }
+        assert_eq!(map.get_many(["a", "b"]), vec![None, None]);
+        assert!(map.get_many(Vec::<u32>::new()).is_empty());
+        // fewer keys than are prefetched ahead
+        assert_eq!(map.get_many([3u32, 2, 1]), vec![None, Some([2]), None]);
+    }
+
+    #[test]
+    fn test_project_capacity() {
+        let map = CuckooMap::<DefaultHasher, 2>::with_capacity(16);
+        let projection = map.project_capacity(0..10_000u32, 1_000_000, 0.01);
+        assert_eq!(projection.samples, 10_000);
+        assert!(projection.mean_density > 0.85 && projection.mean_density <= 1.0, "{:?}", projection);
+        assert!(projection.target_density <= projection.mean_density);
+        assert!(projection.buckets as f64 >= 1_000_000.0 / 4.0 / projection.mean_density);
+        assert_eq!(projection.capacity(), projection.buckets * 4);
+
+        // candidates beyond two fill tables further
+        let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(16);
+        map.set_ways(4);
+        let wide = map.project_capacity(0..10_000u32, 1_000_000, 0.01);
+        assert!(wide.mean_density > projection.mean_density, "{:?}", wide);
+        assert!(wide.buckets < projection.buckets);
+    }
+
+    #[test]
+    fn test_recency_window() {
+        let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 10);
+        map.set_stash_capacity(0);
+        map.set_recency_window(16);
+        assert_eq!(map.recency_window(), 16);
+
+        // every key inserted before the first failure is still there
+        let mut n = 0u32;
+        while map.insert_or_update(&n, [1]).is_ok() {
+            n += 1;
+        }
+        assert!(map.density() > 0.9);
+        let lost = (0..n).filter(|i| map.get(i).is_none()).count();
+        assert!(lost <= 1, "{} lost", lost);
+
+        map.set_recency_window(0);
+        assert_eq!(map.recency_window(), 0);
+    }
+
+    #[test]
+    fn test_errors_are_small() {
+        fn copy<T: Copy>(error: T) -> (T, T) {
+            (error, error)
+        }
+        let (a, b) = copy(CuckooError::<4, 8>::InvalidValue("negative"));
+        assert_eq!(a, b);
+        copy(SchemaError::Unknown(1));
+        // the largest variant is an entry or a string slice
+        assert!(mem::size_of::<CuckooError<4, 8>>() <= 24);
+    }
+
+    #[test]
+    fn test_local_index_scheme() {
+        let mut map = CuckooMap::<DefaultHasher, 3>::with_capacity(1 << 12);
+        map.set_index_scheme(IndexScheme::Local);
+        for i in 0..1_500u32 {
+            map.insert_or_update(&i, [i as u8]).unwrap();
+        }
+        assert!((0..1_500u32).all(|i| map.get(&i) == Some([i as u8])));
+
+        // both candidates of every entry lie in one group of 4 buckets,
+        // which is one cache line
+        assert_eq!(map.buckets.as_ptr() as usize % 64, 0);
+        for (i, entry) in map.placed_entries() {
+            let (buckets, n) = map.relocations(entry.fingerprint, i);
+            assert_eq!(n, 2);
+            assert_eq!(buckets[0] / 4, buckets[1] / 4);
+        }
+        assert!(map.delete(&7u32));
+        assert_eq!(map.get(&7u32), None);
+    }
+
+    #[test]
+    #[cfg(not(feature = "strict-debug"))]
+    fn test_match_count() {
+        let (mut map, _) = map_with_duplicate(DuplicatePolicy::PreferPrimary);
+        assert_eq!(map.match_count("key"), 2);
+        assert!(map.delete("key"));
+        assert_eq!(map.match_count("key"), 1);
+        assert!(map.delete("key"));
+        assert_eq!(map.match_count("key"), 0);
+    }
+
+    #[test]
+    fn test_memory_cap() {
+        let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 10);
+        map.set_auto_grow(true);
+        let buckets = map.capacity() / ENTRIES_PER_BUCKET * mem::size_of::<Bucket<2, 1>>();
+        // room for the old and the new buckets of a single growth
+        let cap = map.memory_usage() + 3 * buckets;
+        map.set_memory_cap(Some(cap));
+
+        let mut i = 0u32;
+        let error = loop {
+            if let Err(error) = map.insert_or_update(&i, [1]) {
+                break error;
+            }
+            i += 1;
+        };
+        assert!(matches!(error, CuckooError::MemoryCapExceeded { .. }));
+        assert_eq!(map.growths(), 1);
+        assert_eq!(map.stash_len(), map.stash_capacity());
+        assert!(map.memory_usage() <= cap);
+
+        // a map that can't grow anyway runs out of space as before
+        map.set_auto_grow(false);
+        assert!(matches!(map.insert_or_update(&i, [1]), Err(CuckooError::NotEnoughSpace { .. })));
+    }
+
+    #[test]
+    fn test_visit() {
+        let mut map = CuckooMap::<DefaultHasher, 4>::with_capacity(1 << 10);
+        for i in 0..500u32 {
+            map.insert_or_update(&i, [(i % 7) as u8]).unwrap();
+        }
+
+        let mut visited = 0;
+        let all = map.visit(|_, _| {
+            visited += 1;
+            ControlFlow::<()>::Continue(())
+        });
+        assert_eq!(all, ControlFlow::Continue(()));
+        assert_eq!(visited, map.len());
+
+        // stops at the third entry with value 6
+        let (mut calls, mut sixes) = (0, 0);
+        let third = map.visit(|fp, value| {
+            calls += 1;
+            sixes += (value == [6]) as usize;
+            if sixes == 3 {
+                ControlFlow::Break(fp)
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert!(matches!(third, ControlFlow::Break(fp) if !fp.is_empty()));
+        assert!(calls < map.len());
+    }
+
+    #[test]
+    fn test_finalize() {
+        let mut map = CuckooMap::<DefaultHasher, 4>::with_capacity(1 << 12);
+        let keys: Vec<u32> = (0..3_700).collect();
+        map.insert_batch(keys.iter().map(|&i| (i, [i as u8]))).unwrap();
+        let at_home = |map: &CuckooMap<DefaultHasher, 4>| {
+            keys.iter()
+                .filter(|&&key| {
+                    let fai = get_fai::<_, DefaultHasher, 4>(&key);
+                    map.hits(&fai).0[0] == map.candidates(&fai).0[0]
+                })
+                .count()
+        };
+        let before = at_home(&map);
+        assert!(before < keys.len());
+
+        let moved = map.finalize(&keys);
+        assert!(moved > 0);
+        assert_eq!(at_home(&map), before + moved);
+        assert_eq!(map.len(), keys.len());
+        assert!(keys.iter().all(|&i| map.get(&i) == Some([i as u8])));
+        assert_eq!(map.finalize(&keys), 0);
+    }
+
+    #[test]
+    fn test_debug_validate() {
+        let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 8);
+        for i in 0..300u32 {
+            let _ = map.insert_or_update(&i, [1]);
+        }
+        assert!(map.stash_len() > 0);
+        assert_eq!(map.debug_validate(), Ok(()));
+
+        map.len += 1;
+        assert!(map.debug_validate().is_err());
+        map.len -= 1;
+        map.stash[0].0 = map.buckets.len();
+        assert_eq!(map.debug_validate(), Err("the stash holds an invalid entry"));
+    }
+
+    #[test]
+    fn test_ttl_with_hot_cache() {
+        let clock = MockClock::new();
+        let mut map = CuckooMap::<DefaultHasher, 2>::with_capacity(1 << 10);
+        map.set_clock(clock.clone());
+        map.set_hot_cache(64);
+        map.set_ttl(Duration::from_millis(50));
+        map.insert_or_update("key", [1]).unwrap();
 
-        n_filled_buckets as f64 / self.buckets.len() as f64
+        // cached by the first lookup
+        assert_eq!(map.get("key"), Some([1]));
+        assert_eq!(map.get("key"), Some([1]));
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(map.get("key"), None);
+        assert_eq!(map.get_detailed("key"), None);
     }
 }
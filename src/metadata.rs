@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "serde_support")]
+use serde_derive::{Deserialize, Serialize};
+
+/// The longest tag key or value, and the longest source, in bytes.
+pub const MAX_TAG_LEN: usize = 255;
+/// The most tags a map carries.
+pub const MAX_TAGS: usize = 32;
+
+/// Describes where a map came from, carried along when it is exported so a
+/// snapshot can be traced back to the job that produced it.
+///
+/// A new map is stamped with its creation time and nothing else. Exports
+/// add the number of entries at the time of the export.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::CuckooMap;
+/// use std::collections::hash_map::DefaultHasher;
+///
+/// let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+/// map.metadata_mut().set_source("nightly-dedup");
+/// map.metadata_mut().insert_tag("run", "1234");
+///
+/// assert_eq!(map.metadata().source(), Some("nightly-dedup"));
+/// assert_eq!(map.metadata().tag("run"), Some("1234"));
+/// assert!(map.metadata().created().is_some());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct SnapshotMetadata {
+    created: Option<SystemTime>,
+    items: Option<u64>,
+    source: Option<String>,
+    tags: BTreeMap<String, String>,
+}
+
+impl SnapshotMetadata {
+    /// Metadata of a map created just now.
+    pub fn new() -> Self {
+        Self {
+            created: Some(SystemTime::now()),
+            ..Self::default()
+        }
+    }
+
+    /// When the map was created, to the millisecond once exported.
+    pub fn created(&self) -> Option<SystemTime> {
+        self.created
+    }
+
+    /// Overrides the creation time, e.g. with the time the data the map was
+    /// built from was taken.
+    pub fn set_created(&mut self, created: SystemTime) {
+        self.created = Some(created);
+    }
+
+    /// The number of entries when the map was exported, `None` for maps
+    /// that were not loaded from an export.
+    pub fn items(&self) -> Option<u64> {
+        self.items
+    }
+
+    pub(crate) fn set_items(&mut self, items: u64) {
+        self.items = Some(items);
+    }
+
+    /// Identifies the producer of the map, e.g. a job or host name.
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    /// Sets the producer of the map.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `source` is longer than `MAX_TAG_LEN` bytes.
+    pub fn set_source(&mut self, source: &str) {
+        assert!(source.len() <= MAX_TAG_LEN, "metadata strings are at most 255 bytes long");
+        self.source = Some(source.to_owned());
+    }
+
+    /// Returns the value of tag `key`.
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.get(key).map(String::as_str)
+    }
+
+    /// Iterates over the tags ordered by key.
+    pub fn tags(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.tags.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Sets tag `key` to `value`, returning its previous value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` or `value` is longer than `MAX_TAG_LEN` bytes, or if
+    /// `key` is new and there are `MAX_TAGS` tags already.
+    pub fn insert_tag(&mut self, key: &str, value: &str) -> Option<String> {
+        assert!(
+            key.len() <= MAX_TAG_LEN && value.len() <= MAX_TAG_LEN,
+            "metadata strings are at most 255 bytes long"
+        );
+        assert!(
+            self.tags.len() < MAX_TAGS || self.tags.contains_key(key),
+            "maps carry at most 32 tags"
+        );
+        self.tags.insert(key.to_owned(), value.to_owned())
+    }
+
+    /// Removes tag `key`, returning its value.
+    pub fn remove_tag(&mut self, key: &str) -> Option<String> {
+        self.tags.remove(key)
+    }
+
+    /// The creation time in milliseconds since the Unix epoch, as exports
+    /// store it.
+    pub(crate) fn created_millis(&self) -> Option<u64> {
+        let since_epoch = self.created?.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Some(since_epoch.as_millis() as u64)
+    }
+
+    pub(crate) fn set_created_millis(&mut self, millis: u64) {
+        self.created = Some(UNIX_EPOCH + Duration::from_millis(millis));
+    }
+}
@@ -0,0 +1,1127 @@
+use crate::bucket::{Bucket, BucketArray, Entry, Fingerprint, CACHE_LINE, ENTRIES_PER_BUCKET, FINGERPRINT_SIZE, VALUE_SIZE};
+use crate::export::{
+    scheme_code, scheme_from_code, valid_layout, write_metadata, write_option, write_schema, write_watermarks, Reader,
+    HASHER_PROBE,
+};
+#[cfg(feature = "encryption")]
+use crate::encryption::{decrypt, encrypt, EncryptionKey, ENCRYPTED_HEADER_SIZE};
+use crate::util::{get_fai_from_hash, get_hash64};
+use crate::{
+    CuckooError, CuckooMap, CuckooMapView, ImportError, IndexScheme, SnapshotMetadata, Watermarks, MAX_STASH, MAX_TAGS,
+    MAX_TAG_LEN,
+};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::os::unix::io::AsRawFd;
+#[cfg(feature = "encryption")]
+use std::fs;
+use std::path::Path;
+#[cfg(feature = "encryption")]
+use std::path::PathBuf;
+use std::ptr::{self, NonNull};
+use std::slice;
+
+const PERSISTENT_MAGIC: &[u8; 4] = b"CKMF";
+/// Version of the header `PersistentCuckooMap::flush` writes to new files.
+/// Version 2 added the value schema, version 3 the metadata and version 4
+/// the watermarks, which made the header larger. Files of older versions
+/// keep their version, so the buckets stay where they are.
+const PERSISTENT_VERSION: u8 = 4;
+
+/// Bytes the watermarks take in a header, see `write_watermarks`.
+const WATERMARKS_SIZE: usize = 3 * 8;
+
+const OVERFLOW_MAGIC: &[u8; 4] = b"CKMO";
+/// Version of the header of overflow files.
+const OVERFLOW_VERSION: u8 = 1;
+
+/// Bytes before the header of an encrypted map file, its encryption header
+/// rounded up to a cache line.
+#[cfg(feature = "encryption")]
+const ENCRYPTED_FILE_OFFSET: usize = ENCRYPTED_HEADER_SIZE.next_multiple_of(CACHE_LINE);
+
+/// Bytes the metadata takes at most in a header, see `write_metadata`.
+const MAX_METADATA_SIZE: usize = 2 * 9 + 2 + MAX_TAG_LEN + 1 + MAX_TAGS * 2 * (1 + MAX_TAG_LEN);
+
+/// A read-only map looking up keys in an exported map file mapped into
+/// memory, as returned by `CuckooMap::open_mmap`.
+///
+/// The operating system pages in the buckets lookups touch, so opening a
+/// large map doesn't load it onto the heap, and processes mapping the same
+/// file share its pages.
+pub struct MappedCuckooMap<H = DefaultHasher, const FP: usize = FINGERPRINT_SIZE, const V: usize = VALUE_SIZE> {
+    // declared before `mmap` so it's dropped before the bytes it borrows
+    view: CuckooMapView<'static, H, FP, V>,
+    mmap: Mmap,
+}
+
+impl<H, const FP: usize, const V: usize> MappedCuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    /// The view of the mapped bytes.
+    pub fn view(&self) -> &CuckooMapView<'_, H, FP, V> {
+        &self.view
+    }
+
+    /// See `CuckooMap::get`.
+    pub fn get<K: ?Sized + Hash>(&self, key: &K) -> Option<[u8; V]> {
+        self.view.get(key)
+    }
+
+    /// See `CuckooMap::get_hashed`.
+    pub fn get_hashed(&self, hash: u64) -> Option<[u8; V]> {
+        self.view.get_hashed(hash)
+    }
+
+    /// See `CuckooMap::contains`.
+    pub fn contains<K: ?Sized + Hash>(&self, key: &K) -> bool {
+        self.view.contains(key)
+    }
+
+    /// Number of entries of the mapped map.
+    pub fn len(&self) -> usize {
+        self.view.len()
+    }
+
+    /// Whether the mapped map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.view.is_empty()
+    }
+
+    /// The metadata of the mapped map.
+    pub fn metadata(&self) -> &SnapshotMetadata {
+        self.view.metadata()
+    }
+
+    /// The watermarks of the mapped map, see `CuckooMap::watermarks`.
+    pub fn watermarks(&self) -> Watermarks {
+        self.view.watermarks()
+    }
+
+    /// Size of the mapped file in bytes.
+    pub fn mapped_len(&self) -> usize {
+        self.mmap.len
+    }
+}
+
+impl<H, const FP: usize, const V: usize> CuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    /// Maps a file written with `ExportedCuckooMap::to_bytes` into memory
+    /// and opens a read-only map on it, see `MappedCuckooMap`.
+    ///
+    /// Opening reads the file once to check it like `import` does. Files
+    /// that aren't a valid export for this hasher and layout fail with
+    /// `io::ErrorKind::InvalidData`, wrapping the `ImportError`.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated while it's mapped, e.g.
+    /// by writing a new export to a temporary file and renaming it over the
+    /// mapped one instead. Lookups read the changed bytes otherwise, or
+    /// crash the process if the file shrank.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cuckoomap::CuckooMap;
+    /// use std::collections::hash_map::DefaultHasher;
+    ///
+    /// let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+    /// map.insert_or_update("hello", [1]).unwrap();
+    /// let path = std::env::temp_dir().join("cuckoomap-open-mmap-doctest");
+    /// std::fs::write(&path, map.export().to_bytes()).unwrap();
+    ///
+    /// // the file is left alone while mapped
+    /// let mapped = unsafe { CuckooMap::<DefaultHasher>::open_mmap(&path) }.unwrap();
+    /// assert_eq!(mapped.get("hello"), Some([1]));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub unsafe fn open_mmap<P: AsRef<Path>>(path: P) -> io::Result<MappedCuckooMap<H, FP, V>> {
+        let mmap = Mmap::open(&File::open(path)?)?;
+        // SAFETY: the mapping outlives the view, which is dropped first, and
+        // the caller keeps the file unchanged.
+        let bytes = unsafe { slice::from_raw_parts(mmap.ptr as *const u8, mmap.len) };
+        let view = CuckooMapView::new(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(MappedCuckooMap { view, mmap })
+    }
+}
+
+/// A map whose buckets live in a file mapped into memory, as returned by
+/// `CuckooMap::open_persistent` and, with the `encryption` feature,
+/// `CuckooMap::open_persistent_encrypted`.
+///
+/// Inserts and deletes write to the mapped file directly, so the map
+/// survives restarts without exporting and importing it. The rest of the
+/// state, i.e. the stash, the settings deciding where keys are placed, the
+/// value schema, the metadata and the watermarks, is kept in a header at the
+/// start of the file. `flush` writes the header and waits until everything reached the
+/// disk, as does dropping the map.
+///
+/// Only the state at the last `flush` is consistent. The header isn't
+/// written as the stash changes, so after a crash the buckets may hold
+/// writes made since while the stash is as last flushed: entries stashed
+/// since are lost, and entries moved from the stash into the buckets since
+/// may be found twice. Flush after writes that have to survive a crash.
+///
+/// The map derefs to `CuckooMap` for all other operations. Its buckets
+/// can't grow, so `set_auto_grow` panics. To keep entries that find no
+/// room once the stash is full, see `open_overflow`.
+pub struct PersistentCuckooMap<H = DefaultHasher, const FP: usize = FINGERPRINT_SIZE, const V: usize = VALUE_SIZE>
+where
+    H: Hasher + Default,
+{
+    map: CuckooMap<H, FP, V>,
+    /// Version of the header of the file.
+    version: u8,
+    /// Bytes before the header in the mapping.
+    offset: usize,
+    overflow: Option<Overflow<V>>,
+    #[cfg(feature = "encryption")]
+    encryption: Option<Encryption>,
+}
+
+impl<H, const FP: usize, const V: usize> PersistentCuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    /// Writes the header and syncs the mapped file and the overflow file,
+    /// if there is one, to disk. An encrypted file is written anew, see
+    /// `CuckooMap::open_persistent_encrypted`.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let header = self.map.persistent_header(self.version)?;
+        let Some(mapping) = self.map.buckets.mapping_mut() else {
+            return Err(io::Error::other("the buckets were replaced by ones not backed by the file"));
+        };
+        mapping.write(self.offset, &header);
+        #[cfg(feature = "encryption")]
+        if let Some(encryption) = &self.encryption {
+            return encryption.write(mapping.bytes(self.offset, mapping.len() - self.offset));
+        }
+        mapping.flush()?;
+        match &self.overflow {
+            Some(overflow) => overflow.file.sync_data(),
+            None => Ok(()),
+        }
+    }
+
+    /// Opens the append-only overflow file at `path`, creating it if it's
+    /// missing, and keeps entries there that find no room in the buckets.
+    ///
+    /// Once the stash is full, an insert of a new key that finds no slot
+    /// appends the entry to the file instead of failing, and `get`,
+    /// `delete` and `insert_or_update` of this type look it up there by
+    /// the 64bit hash of its key. Unlike in the buckets a deleted entry
+    /// only gets a record saying so, so the file keeps growing until it's
+    /// removed along with the map file. The entries in the file are kept
+    /// in memory too, and not counted in `len`, see `overflow_len`.
+    ///
+    /// Entries only spill over with a stash, which makes an insert that
+    /// runs out of room fail before it moves any other entry. Without one,
+    /// the file is consulted, but nothing is added to it.
+    ///
+    /// Other operations, e.g. `test_and_add` or `insert_batch`, only see
+    /// the buckets. Records are appended as they're written and synced by
+    /// `flush`, a record cut short by a crash is ignored when opening. If
+    /// a record can't be written, inserts fail with `NotEnoughSpace` as
+    /// without the file and deletes return false.
+    /// Fails with `InvalidData` if the file belongs to a map of another
+    /// hasher, hash seed or value size, and with `InvalidInput` for an
+    /// encrypted map, as the overflow file would keep its entries in plain.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cuckoomap::CuckooMap;
+    /// use std::collections::hash_map::DefaultHasher;
+    ///
+    /// let dir = std::env::temp_dir();
+    /// let (path, overflow) = (dir.join("cuckoomap-overflow-doctest"), dir.join("cuckoomap-overflow-doctest.log"));
+    /// # let _ = std::fs::remove_file(&path);
+    /// # let _ = std::fs::remove_file(&overflow);
+    /// // the files are only opened by this process
+    /// let mut map = unsafe { CuckooMap::<DefaultHasher>::open_persistent(&path, 64) }.unwrap();
+    /// map.open_overflow(&overflow).unwrap();
+    /// for i in 0..100u32 {
+    ///     map.insert_or_update(&i, [1]).unwrap();
+    /// }
+    /// assert!(map.overflow_len() > 0);
+    /// assert!((0..100u32).all(|i| map.get(&i) == Some([1])));
+    /// # drop(map);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// # std::fs::remove_file(&overflow).unwrap();
+    /// ```
+    pub fn open_overflow<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        #[cfg(feature = "encryption")]
+        if self.encryption.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "overflow files aren't encrypted"));
+        }
+        let header = self.overflow_header()?;
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        if bytes.is_empty() {
+            file.write_all(&header)?;
+            file.sync_data()?;
+            bytes = header.clone();
+        }
+        if bytes.len() < header.len() || bytes[..header.len()] != header[..] {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, ImportError::Invalid));
+        }
+
+        let mut entries = HashMap::new();
+        // a record cut short by a crash is dropped
+        let records = bytes[header.len()..].chunks_exact(Overflow::<V>::RECORD_SIZE);
+        let complete = header.len() + records.len() * Overflow::<V>::RECORD_SIZE;
+        for record in records {
+            let hash = u64::from_le_bytes(record[..8].try_into().expect("8 bytes"));
+            match record[8] {
+                0 => entries.remove(&hash),
+                _ => entries.insert(hash, record[9..].try_into().expect("V bytes")),
+            };
+        }
+        if complete < bytes.len() {
+            file.set_len(complete as u64)?;
+        }
+        self.overflow = Some(Overflow { file, entries });
+        Ok(())
+    }
+
+    /// Number of entries in the overflow file, 0 without one.
+    pub fn overflow_len(&self) -> usize {
+        self.overflow.as_ref().map_or(0, |overflow| overflow.entries.len())
+    }
+
+    /// See `CuckooMap::get`, also looking in the overflow file.
+    pub fn get<T: ?Sized + Hash>(&self, key: &T) -> Option<[u8; V]> {
+        match &self.overflow {
+            Some(overflow) if !overflow.entries.is_empty() => self
+                .map
+                .get(key)
+                .or_else(|| overflow.entries.get(&self.map.key_hash(key)).copied()),
+            _ => self.map.get(key),
+        }
+    }
+
+    /// See `CuckooMap::insert_or_update`. Once the stash is full, a new key
+    /// that finds no room is appended to the overflow file, if there is
+    /// one, instead of failing with `NotEnoughSpace`.
+    pub fn insert_or_update<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; V]) -> Result<(), CuckooError<FP, V>> {
+        let hash = self.map.key_hash(key);
+        let Some(overflow) = &mut self.overflow else {
+            return self.map.insert_or_update(key, value);
+        };
+        if overflow.entries.contains_key(&hash) {
+            let fingerprint = get_fai_from_hash::<H, FP>(hash).fp;
+            return overflow
+                .append(hash, Some(value))
+                .map_err(|_| CuckooError::NotEnoughSpace { fingerprint, value });
+        }
+        match self.map.insert_or_update(key, value) {
+            // failing as without the file if the record can't be written
+            Err(error @ CuckooError::NotEnoughSpace { .. }) if self.map.stash_capacity() > 0 => {
+                overflow.append(hash, Some(value)).map_err(|_| error)
+            }
+            result => result,
+        }
+    }
+
+    /// See `CuckooMap::delete`, also deleting from the overflow file.
+    pub fn delete<T: ?Sized + Hash>(&mut self, key: &T) -> bool {
+        let hash = self.map.key_hash(key);
+        match &mut self.overflow {
+            Some(overflow) if overflow.entries.contains_key(&hash) => overflow.append(hash, None).is_ok(),
+            _ => self.map.delete(key),
+        }
+    }
+
+    /// The header of an overflow file, telling which map its key hashes
+    /// belong to.
+    fn overflow_header(&self) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        out.extend_from_slice(OVERFLOW_MAGIC);
+        out.write_u8(OVERFLOW_VERSION)?;
+        out.write_u32::<LittleEndian>(V as u32)?;
+        out.write_u64::<LittleEndian>(get_hash64::<_, H>(HASHER_PROBE))?;
+        write_option(&mut out, self.map.hash_seed())?;
+        Ok(out)
+    }
+}
+
+/// The entries of an overflow file, see `PersistentCuckooMap::open_overflow`.
+///
+/// After the header the file holds one record per write: the key hash,
+/// 8 bytes, 1 for a value or 0 for a deletion, and the value, `V` bytes.
+/// The last record of a hash wins.
+struct Overflow<const V: usize> {
+    file: File,
+    entries: HashMap<u64, [u8; V]>,
+}
+
+impl<const V: usize> Overflow<V> {
+    const RECORD_SIZE: usize = 8 + 1 + V;
+
+    /// Appends a record and applies it, `None` deleting the entry.
+    fn append(&mut self, hash: u64, value: Option<[u8; V]>) -> io::Result<()> {
+        let mut record = Vec::with_capacity(Self::RECORD_SIZE);
+        record.extend_from_slice(&hash.to_le_bytes());
+        record.push(value.is_some() as u8);
+        record.extend_from_slice(&value.unwrap_or([0; V]));
+        self.file.write_all(&record)?;
+        match value {
+            Some(value) => self.entries.insert(hash, value),
+            None => self.entries.remove(&hash),
+        };
+        Ok(())
+    }
+}
+
+/// Where and with which key `PersistentCuckooMap::flush` writes an encrypted
+/// map file, see `CuckooMap::open_persistent_encrypted`.
+#[cfg(feature = "encryption")]
+struct Encryption {
+    key: EncryptionKey,
+    path: PathBuf,
+}
+
+#[cfg(feature = "encryption")]
+impl Encryption {
+    /// Replaces the file with `image`, the header and buckets of the map,
+    /// encrypted with a fresh nonce. The file is written next to it and
+    /// renamed, so a crash leaves either the old or the new one.
+    fn write(&self, image: &[u8]) -> io::Result<()> {
+        let mut bytes = vec![0; ENCRYPTED_FILE_OFFSET + image.len()];
+        bytes[ENCRYPTED_FILE_OFFSET..].copy_from_slice(image);
+        let header = encrypt(&self.key, &mut bytes[ENCRYPTED_FILE_OFFSET..]);
+        bytes[..header.len()].copy_from_slice(&header);
+
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(&temporary, &self.path)?;
+        // the rename is only durable once the directory is synced
+        match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+            _ => File::open(".")?.sync_all(),
+        }
+    }
+}
+
+impl<H, const FP: usize, const V: usize> Deref for PersistentCuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    type Target = CuckooMap<H, FP, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+impl<H, const FP: usize, const V: usize> DerefMut for PersistentCuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.map
+    }
+}
+
+/// Flushes the map, ignoring errors. Call `flush` to handle them.
+impl<H, const FP: usize, const V: usize> Drop for PersistentCuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+impl<H, const FP: usize, const V: usize> CuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    /// Opens the map kept in the file at `path`, see `PersistentCuckooMap`.
+    ///
+    /// An empty or missing file is sized for a new map with room for `cap`
+    /// entries, as with `with_capacity`. Otherwise `cap` is ignored and the
+    /// map is opened as last flushed, failing with `InvalidData` wrapping an
+    /// `ImportError` if the file holds no map of this hasher and layout.
+    /// After a crash, only the state at the last flush is consistent, see
+    /// `PersistentCuckooMap`.
+    ///
+    /// # Safety
+    ///
+    /// No other process may open or modify the file while it's mapped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cuckoomap::CuckooMap;
+    /// use std::collections::hash_map::DefaultHasher;
+    ///
+    /// let path = std::env::temp_dir().join("cuckoomap-open-persistent-doctest");
+    /// # let _ = std::fs::remove_file(&path);
+    /// // the file is only opened by this process
+    /// let mut map = unsafe { CuckooMap::<DefaultHasher>::open_persistent(&path, 1 << 10) }.unwrap();
+    /// map.insert_or_update("hello", [1]).unwrap();
+    /// drop(map);
+    ///
+    /// let map = unsafe { CuckooMap::<DefaultHasher>::open_persistent(&path, 1 << 10) }.unwrap();
+    /// assert_eq!(map.get("hello"), Some([1]));
+    /// # drop(map);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub unsafe fn open_persistent<P: AsRef<Path>>(path: P, cap: usize) -> io::Result<PersistentCuckooMap<H, FP, V>> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        if file.metadata()?.len() == 0 {
+            let (size, bucket_count) = Self::size_persistent_file(&file, 0, cap)?;
+            let mut map = Self::new_persistent(MmapMut::open(&file, size)?, 0, bucket_count);
+            map.flush()?;
+            return Ok(map);
+        }
+        let size = file.metadata()?.len() as usize;
+        Self::load_persistent(MmapMut::open(&file, size)?, 0)
+    }
+
+    /// Opens the map kept encrypted under `key` in the file at `path`, like
+    /// `open_persistent`, failing with `InvalidData` wrapping
+    /// `DecryptionFailed` for another key.
+    ///
+    /// The file holds the bytes of a file of `open_persistent`, encrypted
+    /// as described for `ExportedCuckooMap::to_encrypted_bytes`. They're
+    /// decrypted into a private mapping when opening, so the whole map is
+    /// kept in memory, in plain, while writes don't reach the file. `flush`
+    /// encrypts it again with a fresh nonce and replaces the file, writing
+    /// it next to it and renaming it, so after a crash the file holds the
+    /// map as of a flush. Every flush writes the whole file, so prefer
+    /// flushing after batches of writes. An overflow file can't be opened.
+    ///
+    /// # Safety
+    ///
+    /// No other process may open or modify the file while it's mapped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cuckoomap::CuckooMap;
+    /// use std::collections::hash_map::DefaultHasher;
+    ///
+    /// let path = std::env::temp_dir().join("cuckoomap-open-persistent-encrypted-doctest");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let key = [7; 32];
+    /// // the file is only opened by this process
+    /// let mut map = unsafe { CuckooMap::<DefaultHasher>::open_persistent_encrypted(&path, 1 << 10, &key) }.unwrap();
+    /// map.insert_or_update("hello", [1]).unwrap();
+    /// drop(map);
+    ///
+    /// let map = unsafe { CuckooMap::<DefaultHasher>::open_persistent_encrypted(&path, 1 << 10, &key) }.unwrap();
+    /// assert_eq!(map.get("hello"), Some([1]));
+    /// # drop(map);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    #[cfg(feature = "encryption")]
+    pub unsafe fn open_persistent_encrypted<P: AsRef<Path>>(
+        path: P,
+        cap: usize,
+        key: &EncryptionKey,
+    ) -> io::Result<PersistentCuckooMap<H, FP, V>> {
+        let path = path.as_ref();
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let invalid = |e: ImportError| io::Error::new(io::ErrorKind::InvalidData, e);
+        let encryption = Encryption {
+            key: *key,
+            path: path.to_owned(),
+        };
+
+        if file.metadata()?.len() == 0 {
+            let (size, bucket_count) = Self::size_persistent_file(&file, ENCRYPTED_FILE_OFFSET, cap)?;
+            let mapping = MmapMut::open_private(&file, size)?;
+            let mut map = Self::new_persistent(mapping, ENCRYPTED_FILE_OFFSET, bucket_count);
+            map.encryption = Some(encryption);
+            map.flush()?;
+            return Ok(map);
+        }
+
+        let size = file.metadata()?.len() as usize;
+        if size < ENCRYPTED_FILE_OFFSET {
+            return Err(invalid(ImportError::Truncated));
+        }
+        let mut mapping = MmapMut::open_private(&file, size)?;
+        let header = mapping.bytes(0, ENCRYPTED_HEADER_SIZE).to_vec();
+        let encrypted = mapping.bytes_mut(ENCRYPTED_FILE_OFFSET, size - ENCRYPTED_FILE_OFFSET);
+        decrypt(key, &header, encrypted).map_err(invalid)?;
+        if !encrypted.starts_with(PERSISTENT_MAGIC) {
+            return Err(invalid(ImportError::DecryptionFailed));
+        }
+        let mut map = Self::load_persistent(mapping, ENCRYPTED_FILE_OFFSET)?;
+        map.encryption = Some(encryption);
+        Ok(map)
+    }
+
+    /// Sizes the empty `file` for a new map with room for `cap` entries,
+    /// its header starting at `offset`, and returns the size of the file
+    /// and the number of buckets.
+    fn size_persistent_file(file: &File, offset: usize, cap: usize) -> io::Result<(usize, usize)> {
+        let header_size = persistent_header_size::<FP, V>(PERSISTENT_VERSION);
+        let bucket_count = cmp::max(1, (cap / ENTRIES_PER_BUCKET).next_power_of_two());
+        let size = bucket_count
+            .checked_mul(mem::size_of::<Bucket<FP, V>>())
+            .and_then(|size| size.checked_add(offset + header_size))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "capacity overflow"))?;
+        file.set_len(size as u64)?;
+        Ok((size, bucket_count))
+    }
+
+    /// A new map of `bucket_count` buckets in a mapping sized by
+    /// `size_persistent_file`, to be flushed to write its header.
+    fn new_persistent(mapping: MmapMut, offset: usize, bucket_count: usize) -> PersistentCuckooMap<H, FP, V> {
+        let header_size = persistent_header_size::<FP, V>(PERSISTENT_VERSION);
+        let buckets = BucketArray::from_mapping(mapping, offset + header_size, bucket_count);
+        PersistentCuckooMap {
+            map: Self::with_bucket_array(buckets, IndexScheme::Xor),
+            version: PERSISTENT_VERSION,
+            offset,
+            overflow: None,
+            #[cfg(feature = "encryption")]
+            encryption: None,
+        }
+    }
+
+    /// The map in a mapping of a persistent map file, its header starting
+    /// at `offset`.
+    fn load_persistent(mapping: MmapMut, offset: usize) -> io::Result<PersistentCuckooMap<H, FP, V>> {
+        let invalid = |e: ImportError| io::Error::new(io::ErrorKind::InvalidData, e);
+        let size = mapping.len() - offset;
+        // the version after the magic number tells the size of the header
+        let prefix = mapping.bytes(offset, cmp::min(size, PERSISTENT_MAGIC.len() + 1));
+        let version = match prefix.get(PERSISTENT_MAGIC.len()) {
+            Some(&version) => version,
+            None => return Err(invalid(ImportError::Truncated)),
+        };
+        let header_size = persistent_header_size::<FP, V>(version);
+        if size < header_size {
+            return Err(invalid(ImportError::Truncated));
+        }
+        let header = PersistentHeader::<FP, V>::read::<H>(mapping.bytes(offset, header_size)).map_err(invalid)?;
+        if size != header_size + header.bucket_count * mem::size_of::<Bucket<FP, V>>() {
+            return Err(invalid(ImportError::Invalid));
+        }
+
+        let buckets = BucketArray::from_mapping(mapping, offset + header_size, header.bucket_count);
+        let mut map = Self::with_bucket_array(buckets, header.index_scheme);
+        map.ways = header.ways;
+        map.growths = header.growths;
+        map.hash_seed = header.hash_seed;
+        map.value_schema = header.value_schema;
+        if let Some(metadata) = header.metadata {
+            map.metadata = metadata;
+        }
+        if let Some(watermarks) = header.watermarks {
+            map.watermarks = watermarks;
+        }
+        map.set_stash_capacity(header.stash_capacity);
+        map.stash.extend(header.stash);
+        // counted rather than stored, so writes after the last flush count
+        map.len = map.buckets.iter().map(|bucket| bucket.occupied().count()).sum::<usize>() + map.stash.len();
+        map.note_load();
+        Ok(PersistentCuckooMap {
+            map,
+            version,
+            offset,
+            overflow: None,
+            #[cfg(feature = "encryption")]
+            encryption: None,
+        })
+    }
+
+    /// The header of a persistent map file of `version`: the layout, the
+    /// placement settings, the value schema, the stash, the metadata and the
+    /// watermarks, leaving out what older versions lack.
+    fn persistent_header(&self, version: u8) -> io::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(persistent_header_size::<FP, V>(version));
+        out.extend_from_slice(PERSISTENT_MAGIC);
+        out.write_u8(version)?;
+        out.write_u8(FP as u8)?;
+        out.write_u32::<LittleEndian>(V as u32)?;
+        out.write_u32::<LittleEndian>(mem::size_of::<Bucket<FP, V>>() as u32)?;
+        out.write_u64::<LittleEndian>(get_hash64::<_, H>(HASHER_PROBE))?;
+        out.write_u8(self.ways as u8)?;
+        out.write_u8(scheme_code(self.index_scheme))?;
+        out.write_u8(self.growths as u8)?;
+        write_option(&mut out, self.hash_seed)?;
+        out.write_u64::<LittleEndian>(self.buckets.len() as u64)?;
+        if version >= 2 {
+            write_schema(&mut out, self.value_schema)?;
+        }
+        out.write_u8(self.stash_capacity as u8)?;
+        out.write_u8(self.stash.len() as u8)?;
+        for (j, entry) in &self.stash {
+            out.write_u64::<LittleEndian>(*j as u64)?;
+            out.extend_from_slice(&entry.fingerprint.data);
+            out.extend_from_slice(&entry.value);
+        }
+        if version >= 3 {
+            write_metadata(&mut out, &self.metadata)?;
+        }
+        if version >= 4 {
+            write_watermarks(&mut out, &self.watermarks)?;
+        }
+        Ok(out)
+    }
+}
+
+/// Bytes before the buckets of a persistent map file of `version`, room for
+/// the largest header rounded up to a cache line.
+fn persistent_header_size<const FP: usize, const V: usize>(version: u8) -> usize {
+    let metadata = if version >= 3 { MAX_METADATA_SIZE } else { 0 };
+    let watermarks = if version >= 4 { WATERMARKS_SIZE } else { 0 };
+    (64 + MAX_STASH * (8 + FP + V) + metadata + watermarks).next_multiple_of(CACHE_LINE)
+}
+
+/// The decoded header of a persistent map file.
+struct PersistentHeader<const FP: usize, const V: usize> {
+    ways: usize,
+    index_scheme: IndexScheme,
+    growths: u32,
+    hash_seed: Option<u64>,
+    bucket_count: usize,
+    value_schema: Option<u8>,
+    stash_capacity: usize,
+    stash: Vec<(usize, Entry<FP, V>)>,
+    /// Missing before version 3.
+    metadata: Option<SnapshotMetadata>,
+    /// Missing before version 4.
+    watermarks: Option<Watermarks>,
+}
+
+impl<const FP: usize, const V: usize> PersistentHeader<FP, V> {
+    fn read<H: Hasher + Default>(bytes: &[u8]) -> Result<Self, ImportError> {
+        let mut input = Reader(bytes);
+        if input.bytes(PERSISTENT_MAGIC.len())? != PERSISTENT_MAGIC {
+            return Err(ImportError::NotAnExport);
+        }
+        let version = input.u8()?;
+        if version == 0 || version > PERSISTENT_VERSION {
+            return Err(ImportError::UnsupportedVersion(version));
+        }
+        let (fingerprint_size, value_size) = (usize::from(input.u8()?), input.u32()? as usize);
+        if fingerprint_size != FP || value_size != V || input.u32()? as usize != mem::size_of::<Bucket<FP, V>>() {
+            return Err(ImportError::LayoutMismatch {
+                fingerprint_size,
+                value_size,
+            });
+        }
+        if input.u64()? != get_hash64::<_, H>(HASHER_PROBE) {
+            return Err(ImportError::HasherMismatch);
+        }
+        let ways = usize::from(input.u8()?);
+        let index_scheme = scheme_from_code(input.u8()?).ok_or(ImportError::Invalid)?;
+        let growths = u32::from(input.u8()?);
+        let hash_seed = input.option()?;
+        let bucket_count = input.u64()? as usize;
+        let value_schema = if version >= 2 { input.schema()? } else { None };
+        let stash_capacity = usize::from(input.u8()?);
+        let stashed = usize::from(input.u8()?);
+        if !valid_layout(bucket_count, index_scheme, ways, growths) || stash_capacity > MAX_STASH || stashed > stash_capacity {
+            return Err(ImportError::Invalid);
+        }
+        let mut stash = Vec::with_capacity(stashed);
+        for _ in 0..stashed {
+            let j = input.u64()? as usize;
+            let entry = Entry {
+                fingerprint: Fingerprint {
+                    data: input.bytes(FP)?.try_into().expect("FP bytes"),
+                },
+                value: input.bytes(V)?.try_into().expect("V bytes"),
+            };
+            if j >= bucket_count || entry.fingerprint.is_empty() {
+                return Err(ImportError::Invalid);
+            }
+            stash.push((j, entry));
+        }
+        let metadata = if version >= 3 { Some(input.metadata()?) } else { None };
+        let watermarks = if version >= 4 { Some(input.watermarks()?) } else { None };
+        Ok(Self {
+            ways,
+            index_scheme,
+            growths,
+            hash_seed,
+            bucket_count,
+            value_schema,
+            stash_capacity,
+            stash,
+            metadata,
+            watermarks,
+        })
+    }
+}
+
+/// A writable memory mapping of a whole file, shared unless opened with
+/// `open_private`.
+pub(crate) struct MmapMut {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl MmapMut {
+    fn open(file: &File, len: usize) -> io::Result<Self> {
+        Self::map(file, len, libc::MAP_SHARED)
+    }
+
+    /// Maps the file copy-on-write, so writes don't reach it.
+    #[cfg(feature = "encryption")]
+    fn open_private(file: &File, len: usize) -> io::Result<Self> {
+        Self::map(file, len, libc::MAP_PRIVATE)
+    }
+
+    fn map(file: &File, len: usize, flags: libc::c_int) -> io::Result<Self> {
+        // SAFETY: the arguments describe a shared or private mapping of
+        // `len` bytes of a file open for reading and writing, checked for
+        // failure below.
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                flags,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let ptr = NonNull::new(ptr as *mut u8).ok_or_else(io::Error::last_os_error)?;
+        Ok(Self { ptr, len })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    /// The `len` bytes at `offset`, which must not overlap buckets being
+    /// written.
+    fn bytes(&self, offset: usize, len: usize) -> &[u8] {
+        assert!(offset + len <= self.len, "out of the mapping");
+        // SAFETY: the range is within the mapping.
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr().add(offset), len) }
+    }
+
+    /// The `len` bytes at `offset`, before the buckets are taken from the
+    /// mapping.
+    #[cfg(feature = "encryption")]
+    fn bytes_mut(&mut self, offset: usize, len: usize) -> &mut [u8] {
+        assert!(offset + len <= self.len, "out of the mapping");
+        // SAFETY: the range is within the mapping, borrowed mutably.
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr().add(offset), len) }
+    }
+
+    /// Copies `bytes` to `offset`, which must not overlap the buckets.
+    fn write(&mut self, offset: usize, bytes: &[u8]) {
+        assert!(offset + bytes.len() <= self.len, "out of the mapping");
+        // SAFETY: the range is within the mapping, borrowed mutably.
+        unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), self.ptr.as_ptr().add(offset), bytes.len()) };
+    }
+
+    /// Writes changed pages back to the file and waits until they're on disk.
+    fn flush(&self) -> io::Result<()> {
+        // SAFETY: the range is the whole mapping.
+        if unsafe { libc::msync(self.ptr.as_ptr() as *mut libc::c_void, self.len, libc::MS_SYNC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MmapMut {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created in `open` and is unmapped once.
+        unsafe { libc::munmap(self.ptr.as_ptr() as *mut libc::c_void, self.len) };
+    }
+}
+
+// SAFETY: the mapping is owned like an allocation, writes need `&mut self`.
+unsafe impl Send for MmapMut {}
+unsafe impl Sync for MmapMut {}
+
+/// A read-only, private memory mapping of a whole file.
+struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mmap {
+    fn open(file: &File) -> io::Result<Self> {
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            // mapping nothing fails, and an empty file is no export anyway
+            return Err(io::Error::new(io::ErrorKind::InvalidData, ImportError::NotAnExport));
+        }
+        // SAFETY: the arguments describe a read-only mapping of `len` bytes
+        // of an open file, checked for failure below.
+        let ptr = unsafe { libc::mmap(ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr, len })
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created in `open` and is unmapped once.
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+// SAFETY: the mapping is read-only, so it can be shared across threads.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_open_mmap() {
+        let mut map = CuckooMap::<DefaultHasher, 4, 2>::with_capacity(1 << 12);
+        for i in 0..3_000u32 {
+            map.insert_or_update(&i, [i as u8, 3]).unwrap();
+        }
+        let bytes = map.export().to_bytes();
+        let path = std::env::temp_dir().join(format!("cuckoomap-test-open-mmap-{}", std::process::id()));
+        fs::write(&path, &bytes).unwrap();
+
+        let mapped = unsafe { CuckooMap::<DefaultHasher, 4, 2>::open_mmap(&path) }.unwrap();
+        assert_eq!(mapped.len(), 3_000);
+        assert_eq!(mapped.mapped_len(), bytes.len());
+        assert!((0..3_000u32).all(|i| mapped.get(&i) == Some([i as u8, 3])));
+
+        let err = unsafe { CuckooMap::<DefaultHasher, 2, 2>::open_mmap(&path) }.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        drop(mapped);
+        fs::write(&path, b"").unwrap();
+        let err = unsafe { CuckooMap::<DefaultHasher, 4, 2>::open_mmap(&path) }.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_open_persistent() {
+        let path = std::env::temp_dir().join(format!("cuckoomap-test-open-persistent-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut map = unsafe { CuckooMap::<DefaultHasher, 4, 2>::open_persistent(&path, 1 << 10) }.unwrap();
+        map.set_hash_seed(5);
+        map.set_value_schema(3);
+        map.set_stash_capacity(8);
+        // the largest metadata fits the header
+        let long = "x".repeat(MAX_TAG_LEN);
+        map.metadata_mut().set_source(&long);
+        for t in 0..MAX_TAGS {
+            map.metadata_mut().insert_tag(&format!("{:0255}", t), &long);
+        }
+        let inserted = (0..).take_while(|i: &u32| map.insert_or_update(i, [*i as u8, 1]).is_ok()).count() as u32;
+        assert!(map.stash_len() > 0);
+        assert!(map.delete(&0u32));
+        map.flush().unwrap();
+        let len = map.len();
+        let watermarks = map.watermarks();
+        assert_eq!(watermarks.failures, 1);
+        drop(map);
+
+        let map = unsafe { CuckooMap::<DefaultHasher, 4, 2>::open_persistent(&path, 0) }.unwrap();
+        assert_eq!(map.len(), len);
+        assert_eq!(map.watermarks(), watermarks);
+        assert_eq!(map.capacity(), 1 << 10);
+        assert_eq!(map.hash_seed(), Some(5));
+        assert_eq!(map.value_schema(), Some(3));
+        assert_eq!(map.metadata().source(), Some(&long[..]));
+        assert_eq!(map.metadata().tags().count(), MAX_TAGS);
+        assert_eq!(map.stash_capacity(), 8);
+        assert_eq!(map.get(&0u32), None);
+        assert!((1..inserted).all(|i| map.get(&i) == Some([i as u8, 1])));
+        assert_eq!(map.debug_validate(), Ok(()));
+        drop(map);
+
+        let err = unsafe { CuckooMap::<DefaultHasher, 2, 2>::open_persistent(&path, 0) }.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = unsafe { CuckooMap::<fnv::FnvHasher, 4, 2>::open_persistent(&path, 0) }.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_open_persistent_version_2() {
+        let path = std::env::temp_dir().join(format!("cuckoomap-test-persistent-v2-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut map = unsafe { CuckooMap::<DefaultHasher>::open_persistent(&path, 1 << 10) }.unwrap();
+        map.set_value_schema(3);
+        for i in 0..500u32 {
+            map.insert_or_update(&i, [1]).unwrap();
+        }
+        map.flush().unwrap();
+        let len = map.len();
+
+        // the same buckets after the smaller header of version 2
+        let mut old = map.persistent_header(2).unwrap();
+        old.resize(persistent_header_size::<1, 1>(2), 0);
+        drop(map);
+        old.extend_from_slice(&fs::read(&path).unwrap()[persistent_header_size::<1, 1>(PERSISTENT_VERSION)..]);
+        fs::write(&path, &old).unwrap();
+
+        let mut map = unsafe { CuckooMap::<DefaultHasher>::open_persistent(&path, 0) }.unwrap();
+        assert_eq!(map.len(), len);
+        assert_eq!(map.value_schema(), Some(3));
+        // without watermarks only the current load is known
+        assert_eq!(map.watermarks().evictions, 0);
+        assert_eq!(map.watermarks().max_load_factor, len as f64 / map.capacity() as f64);
+        assert!((0..500u32).all(|i| map.get(&i) == Some([1])));
+        // flushing keeps the layout of version 2
+        map.insert_or_update(&500u32, [2]).unwrap();
+        drop(map);
+        assert_eq!(fs::read(&path).unwrap().len(), old.len());
+        let map = unsafe { CuckooMap::<DefaultHasher>::open_persistent(&path, 0) }.unwrap();
+        assert_eq!(map.get(&500u32), Some([2]));
+        drop(map);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_persistent_overflow() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("cuckoomap-test-persistent-overflow-{}", std::process::id()));
+        let log = dir.join(format!("cuckoomap-test-persistent-overflow-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&log);
+
+        let mut map = unsafe { CuckooMap::<DefaultHasher, 2>::open_persistent(&path, 64) }.unwrap();
+        map.set_hash_seed(9);
+        map.open_overflow(&log).unwrap();
+        for i in 0..200u32 {
+            map.insert_or_update(&i, [1]).unwrap();
+        }
+        assert_eq!(map.stash_len(), map.stash_capacity());
+        assert_eq!(map.len() + map.overflow_len(), 200);
+        let spilled: Vec<u32> = (0..200u32).filter(|i| map.deref().get(i).is_none()).collect();
+        assert_eq!(spilled.len(), map.overflow_len());
+        assert!((0..200u32).all(|i| map.get(&i) == Some([1])));
+
+        // updates and deletes of spilled keys stay in the file
+        map.insert_or_update(&spilled[0], [2]).unwrap();
+        assert!(map.delete(&spilled[1]));
+        assert!(!map.delete(&spilled[1]));
+        assert!(map.delete(&0u32));
+        map.flush().unwrap();
+        drop(map);
+
+        // a record cut short is dropped
+        let mut bytes = fs::read(&log).unwrap();
+        let len = bytes.len();
+        bytes.extend_from_slice(&[7; 5]);
+        fs::write(&log, &bytes).unwrap();
+
+        let mut map = unsafe { CuckooMap::<DefaultHasher, 2>::open_persistent(&path, 0) }.unwrap();
+        assert_eq!(map.get(&spilled[0]), None);
+        map.open_overflow(&log).unwrap();
+        assert_eq!(fs::metadata(&log).unwrap().len() as usize, len);
+        assert_eq!(map.len() + map.overflow_len(), 198);
+        assert_eq!(map.get(&spilled[0]), Some([2]));
+        assert_eq!(map.get(&spilled[1]), None);
+        assert_eq!(map.get(&0u32), None);
+        assert!((1..200u32)
+            .filter(|i| !spilled[..2].contains(i))
+            .all(|i| map.get(&i) == Some([1])));
+        drop(map);
+
+        // the file belongs to a map with another seed
+        fs::remove_file(&path).unwrap();
+        let mut other = unsafe { CuckooMap::<DefaultHasher, 2>::open_persistent(&path, 64) }.unwrap();
+        other.set_hash_seed(10);
+        let err = other.open_overflow(&log).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        drop(other);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&log).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_open_persistent_encrypted() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("cuckoomap-test-persistent-encrypted-{}", std::process::id()));
+        let log = dir.join(format!("cuckoomap-test-persistent-encrypted-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let (key, other) = ([1; 32], [2; 32]);
+
+        let mut map = unsafe { CuckooMap::<DefaultHasher, 2>::open_persistent_encrypted(&path, 1024, &key) }.unwrap();
+        for i in 0..500u32 {
+            map.insert_or_update(&i, [i as u8]).unwrap();
+        }
+        map.metadata_mut().insert_tag("owner", "ops");
+        assert_eq!(map.open_overflow(&log).err().unwrap().kind(), io::ErrorKind::InvalidInput);
+        drop(map);
+
+        // neither the header nor the values are in the file in plain
+        let bytes = fs::read(&path).unwrap();
+        assert!(bytes.starts_with(b"CKME"));
+        assert!(!bytes.windows(4).any(|w| w == PERSISTENT_MAGIC));
+        assert!(!bytes.windows(3).any(|w| w == b"ops"));
+
+        let mut map = unsafe { CuckooMap::<DefaultHasher, 2>::open_persistent_encrypted(&path, 0, &key) }.unwrap();
+        assert_eq!(map.len(), 500);
+        assert!((0..500u32).all(|i| map.get(&i) == Some([i as u8])));
+        assert_eq!(map.metadata().tag("owner"), Some("ops"));
+        // writes reach the file only when flushing
+        assert!(map.delete(&0u32));
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+        map.flush().unwrap();
+        assert_ne!(fs::read(&path).unwrap(), bytes);
+        drop(map);
+
+        let map = unsafe { CuckooMap::<DefaultHasher, 2>::open_persistent_encrypted(&path, 0, &key) }.unwrap();
+        assert_eq!((map.len(), map.get(&0u32)), (499, None));
+        drop(map);
+
+        let invalid = |result: io::Result<PersistentCuckooMap<DefaultHasher, 2>>| {
+            let err = result.err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            *err.into_inner().unwrap().downcast::<ImportError>().unwrap()
+        };
+        let opened = unsafe { CuckooMap::<DefaultHasher, 2>::open_persistent_encrypted(&path, 0, &other) };
+        assert_eq!(invalid(opened), ImportError::DecryptionFailed);
+        let opened = unsafe { CuckooMap::<DefaultHasher, 2>::open_persistent(&path, 0) };
+        assert_eq!(invalid(opened), ImportError::NotAnExport);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "backed by a file")]
+    fn test_persistent_no_growth() {
+        let path = std::env::temp_dir().join(format!("cuckoomap-test-persistent-growth-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut map = unsafe { CuckooMap::<DefaultHasher>::open_persistent(&path, 1 << 10) }.unwrap();
+        fs::remove_file(&path).unwrap();
+        map.set_auto_grow(true);
+    }
+}
@@ -0,0 +1,59 @@
+use crate::bucket::VALUE_SIZE;
+use crate::util::get_hash64;
+use crate::{CuckooError, ProbabilisticMap};
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+/// An exact, `HashMap`-backed `ProbabilisticMap` for tests.
+///
+/// Keys are identified by their full 64bit hash instead of a fingerprint,
+/// so there are no false positives and inserts never run out of space.
+pub struct MockCuckooMap<H = DefaultHasher, const V: usize = VALUE_SIZE> {
+    entries: HashMap<u64, [u8; V]>,
+    _hasher: PhantomData<H>,
+}
+
+impl<H, const V: usize> Default for MockCuckooMap<H, V> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            _hasher: PhantomData,
+        }
+    }
+}
+
+impl MockCuckooMap<DefaultHasher> {
+    /// Constructs an empty map with the default hasher.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<H, const V: usize, const FP: usize> ProbabilisticMap<V, FP> for MockCuckooMap<H, V>
+where
+    H: Hasher + Default,
+{
+    fn get<T: ?Sized + Hash>(&self, key: &T) -> Option<[u8; V]> {
+        self.entries.get(&get_hash64::<T, H>(key)).copied()
+    }
+
+    fn insert_or_update<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; V]) -> Result<(), CuckooError<FP, V>> {
+        self.entries.insert(get_hash64::<T, H>(key), value);
+        Ok(())
+    }
+
+    fn delete<T: ?Sized + Hash>(&mut self, key: &T) -> bool {
+        self.entries.remove(&get_hash64::<T, H>(key)).is_some()
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,81 @@
+use crate::bucket::{FINGERPRINT_SIZE, VALUE_SIZE};
+
+/// Layout of an entry with a `FP`-byte fingerprint and a `V`-byte value
+/// packed into a single 32bit word, so a slot can be read and written with
+/// one atomic operation.
+///
+/// The fingerprint occupies the low bytes and the value the bytes above it,
+/// both little-endian. As the empty fingerprint is all zeros, a zeroed word is
+/// an empty slot. Combinations that don't fit into 32 bits fail to compile.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::packed::Fp1Value2;
+///
+/// let word = Fp1Value2::pack([7], [1, 2]);
+/// assert_eq!(Fp1Value2::unpack(word), ([7], [1, 2]));
+/// assert!(!Fp1Value2::is_empty(word));
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PackedLayout<const FP: usize, const V: usize>;
+
+/// One byte fingerprint, one byte value: the layout of `CuckooMap` today.
+pub type Fp1Value1 = PackedLayout<1, 1>;
+/// One byte fingerprint, two byte value.
+pub type Fp1Value2 = PackedLayout<1, 2>;
+/// Two byte fingerprint, one byte value.
+pub type Fp2Value1 = PackedLayout<2, 1>;
+/// Two byte fingerprint, two byte value.
+pub type Fp2Value2 = PackedLayout<2, 2>;
+
+/// The preset matching the map's fingerprint and value sizes.
+pub type MapLayout = PackedLayout<FINGERPRINT_SIZE, VALUE_SIZE>;
+
+impl<const FP: usize, const V: usize> PackedLayout<FP, V> {
+    const FITS: () = assert!(FP >= 1 && V >= 1 && FP + V <= 4, "entry does not fit into 32 bits");
+
+    /// Number of bits of the word that are in use.
+    pub const BITS: u32 = 8 * (FP + V) as u32;
+
+    /// Packs a fingerprint and a value into a word.
+    pub fn pack(fingerprint: [u8; FP], value: [u8; V]) -> u32 {
+        let () = Self::FITS;
+        let mut bytes = [0; 4];
+        bytes[..FP].copy_from_slice(&fingerprint);
+        bytes[FP..FP + V].copy_from_slice(&value);
+        u32::from_le_bytes(bytes)
+    }
+
+    /// Splits a word into its fingerprint and value.
+    pub fn unpack(word: u32) -> ([u8; FP], [u8; V]) {
+        let () = Self::FITS;
+        let bytes = word.to_le_bytes();
+        let mut fingerprint = [0; FP];
+        let mut value = [0; V];
+        fingerprint.copy_from_slice(&bytes[..FP]);
+        value.copy_from_slice(&bytes[FP..FP + V]);
+        (fingerprint, value)
+    }
+
+    /// Checks if the word holds the empty fingerprint.
+    pub fn is_empty(word: u32) -> bool {
+        word & ((1 << (8 * FP)) - 1) == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(Fp2Value2::unpack(Fp2Value2::pack([1, 2], [3, 4])), ([1, 2], [3, 4]));
+        assert_eq!(Fp2Value1::unpack(Fp2Value1::pack([0xff, 0xfe], [9])), ([0xff, 0xfe], [9]));
+        assert_eq!(MapLayout::BITS, 16);
+
+        // the value doesn't matter for emptiness
+        assert!(Fp1Value2::is_empty(Fp1Value2::pack([0], [0xff, 0xff])));
+        assert!(!Fp2Value2::is_empty(Fp2Value2::pack([0, 1], [0, 0])));
+    }
+}
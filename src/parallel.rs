@@ -0,0 +1,167 @@
+use crate::util::{get_fai_from_hash, get_seeded_hash64, FaI};
+use crate::{CuckooError, CuckooMap, IndexScheme};
+
+use rayon::prelude::*;
+
+use std::cmp;
+use std::hash::{Hash, Hasher};
+
+/// Partitions of the bucket array per thread, so threads that finish
+/// early can take over partitions of a slower one.
+const PARTITIONS_PER_THREAD: usize = 4;
+
+impl<H, const FP: usize, const V: usize> CuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    /// Inserts all `(key, value)` pairs like `insert_batch`, but hashes and
+    /// places them on the threads of rayon's global pool.
+    ///
+    /// The bucket array is split into disjoint partitions, each written by
+    /// a single thread. New keys are stored in a free slot of their smaller
+    /// candidate bucket without any kicking. Everything else, i.e. updates
+    /// of keys already in the map and keys whose bucket is full, is then
+    /// inserted one after another in the order given, stopping at the first
+    /// error.
+    ///
+    /// Maps that need to see every write, i.e. with more than 2 ways, exact
+    /// capacity, a grown table, stashed entries, a TTL, a rate limiter, a
+    /// value validator, a store hook or write verification, only hash in
+    /// parallel.
+    pub fn insert_batch_par<K>(&mut self, items: &[(K, [u8; V])]) -> Result<(), CuckooError<FP, V>>
+    where
+        K: Hash + Sync,
+    {
+        let seed = self.hash_seed;
+        let hashed: Vec<(u64, FaI<FP>)> = items
+            .par_iter()
+            .map(|(key, _)| {
+                let hash = get_seeded_hash64::<K, H>(key, seed);
+                (hash, get_fai_from_hash::<H, FP>(hash))
+            })
+            .collect();
+
+        let rest = if self.places_in_parallel() {
+            self.place_par(items, &hashed)
+        } else {
+            (0..items.len()).collect()
+        };
+        for i in rest {
+            let (hash, fai) = hashed[i];
+            self.store(hash, fai, items[i].1, self.max_kicks)?;
+        }
+        Ok(())
+    }
+
+    fn places_in_parallel(&self) -> bool {
+        self.ways == 2
+            && self.index_scheme == IndexScheme::Xor
+            && self.growths == 0
+            && self.stash.is_empty()
+            && self.expiry.is_none()
+            && self.rate_limiter.is_none()
+            && self.value_validator.is_none()
+            && self.on_store.is_none()
+            && !self.verify_writes
+    }
+
+    /// Stores the items not in the map yet into their smaller candidate
+    /// bucket, one thread per partition, and returns the indexes of the
+    /// items left over in ascending order.
+    fn place_par<K: Sync>(&mut self, items: &[(K, [u8; V])], hashed: &[(u64, FaI<FP>)]) -> Vec<usize> {
+        let len = self.buckets.len();
+        let partition = cmp::max(1, len.div_ceil(rayon::current_num_threads() * PARTITIONS_PER_THREAD));
+
+        // Checking for keys before any write is enough: the thread of an
+        // item's smaller bucket is the only one writing its fingerprint to
+        // any of its two buckets, as both indexes follow from either.
+        let buckets = &self.buckets;
+        let present: Vec<bool> = hashed
+            .par_iter()
+            .map(|(_, fai)| buckets[fai.i1 % len].find(fai.fp).is_some() || buckets[fai.i2 % len].find(fai.fp).is_some())
+            .collect();
+        let mut rest = Vec::new();
+        let mut partitions = vec![Vec::new(); len.div_ceil(partition)];
+        for (i, (_, fai)) in hashed.iter().enumerate() {
+            if present[i] {
+                rest.push(i);
+            } else {
+                partitions[self.hot_tag(fai) / partition].push(i);
+            }
+        }
+
+        let results: Vec<_> = self
+            .buckets
+            .par_chunks_mut(partition)
+            .zip(partitions.par_iter())
+            .enumerate()
+            .map(|(p, (chunk, members))| {
+                let (mut placed, mut rest) = (Vec::new(), Vec::new());
+                for &i in members {
+                    let fai = hashed[i].1;
+                    let tag = cmp::min(fai.i1 % len, fai.i2 % len);
+                    let bucket = &mut chunk[tag - p * partition];
+                    // a key given twice is left to the sequential pass,
+                    // which updates it in order
+                    let slot = match bucket.find(fai.fp) {
+                        Some(_) => None,
+                        None => bucket.set(fai.fp, items[i].1),
+                    };
+                    match slot {
+                        Some(slot) => placed.push((i, tag, slot)),
+                        None => rest.push(i),
+                    }
+                }
+                (placed, rest)
+            })
+            .collect();
+
+        for (placed, left) in results {
+            for (i, tag, slot) in placed {
+                self.forget(&hashed[i].1);
+                self.len += 1;
+                self.note_write(tag, slot);
+            }
+            rest.extend(left);
+        }
+        rest.sort_unstable();
+        rest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_insert_batch_par() {
+        let mut map = CuckooMap::<DefaultHasher, 3>::with_capacity(1 << 16);
+        for i in 0..1_000u32 {
+            map.insert_or_update(&i, [0]).unwrap();
+        }
+        // updates, new keys and a key given twice
+        let mut items: Vec<_> = (500..50_000u32).map(|i| (i, [(i % 255) as u8 + 1])).collect();
+        items.push((7, [1]));
+        items.push((7, [2]));
+        map.insert_batch_par(&items).unwrap();
+
+        assert_eq!(map.len(), 50_000);
+        assert!((0..500u32).all(|i| map.get(&i) == Some([0]) || i == 7));
+        assert_eq!(map.get(&7u32), Some([2]));
+        assert!((500..50_000u32).all(|i| map.get(&i) == Some([(i % 255) as u8 + 1])));
+
+        // a store hook sees every write
+        let mut hooked = CuckooMap::<DefaultHasher, 3>::with_capacity(1 << 16);
+        let stored = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&stored);
+        hooked.set_on_store(move |_, _| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        hooked.insert_batch_par(&items).unwrap();
+        assert_eq!(stored.load(Ordering::Relaxed), items.len());
+        assert_eq!(hooked.len(), 49_501);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::bucket::VALUE_SIZE;
+
+use std::cmp;
+
+#[cfg(feature = "serde_support")]
+use serde_derive::{Deserialize, Serialize};
+
+/// How to resolve a key whose fingerprint is stored in more than one of its candidate buckets.
+///
+/// Regular inserts avoid this state, but it can appear after merging or importing
+/// entries. Without a policy, `get` and `delete` would depend on probe order.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum DuplicatePolicy<const V: usize = VALUE_SIZE> {
+    /// Reads and writes use the first copy, starting with the primary bucket,
+    /// `delete` removes one copy at a time.
+    #[default]
+    PreferPrimary,
+    /// Reads use the copy written last, `delete` removes all copies and the next
+    /// write collapses them into a single entry holding the new value.
+    PreferNewest,
+    /// Reads combine all values (primary first) with the given function,
+    /// writes and deletes affect all copies.
+    Merge(fn([u8; V], [u8; V]) -> [u8; V]),
+}
+
+/// What inserting a key that is already present does to its stored value.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum UpdatePolicy<const V: usize = VALUE_SIZE> {
+    /// The new value replaces the stored one.
+    #[default]
+    Overwrite,
+    /// The first stored value wins, e.g. for idempotency tokens
+    /// where a replayed insert must not replace it.
+    KeepFirst,
+    /// The stored value is replaced by `f(stored, new)`.
+    Merge(fn([u8; V], [u8; V]) -> [u8; V]),
+}
+
+impl<const V: usize> UpdatePolicy<V> {
+    /// Returns the value to store when `incoming` is inserted over `existing`.
+    pub fn apply(self, existing: [u8; V], incoming: [u8; V]) -> [u8; V] {
+        match self {
+            UpdatePolicy::Overwrite => incoming,
+            UpdatePolicy::KeepFirst => existing,
+            UpdatePolicy::Merge(f) => f(existing, incoming),
+        }
+    }
+}
+
+/// How `merge` combines the value already stored for a key with the incoming one.
+///
+/// `Max` and `BitOr` are commutative and idempotent, so replicas that merge each
+/// other's state in any order converge to the same values.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum MergeRule {
+    /// Keep the value that is already stored.
+    KeepExisting,
+    /// Replace the stored value with the incoming one.
+    #[default]
+    Overwrite,
+    /// Keep the larger value, comparing the bytes big-endian, i.e.
+    /// last-writer-wins if values are big-endian epochs.
+    Max,
+    /// Combine both values with a bitwise or, e.g. for flag sets.
+    BitOr,
+}
+
+impl MergeRule {
+    /// Combines the `existing` value with the `incoming` one.
+    pub fn apply<const V: usize>(self, existing: [u8; V], incoming: [u8; V]) -> [u8; V] {
+        match self {
+            MergeRule::KeepExisting => existing,
+            MergeRule::Overwrite => incoming,
+            // arrays compare lexicographically, which is big-endian
+            MergeRule::Max => cmp::max(existing, incoming),
+            MergeRule::BitOr => {
+                let mut merged = existing;
+                for (m, i) in merged.iter_mut().zip(incoming.iter()) {
+                    *m |= *i;
+                }
+                merged
+            }
+        }
+    }
+}
+
+/// How the number of relocations allowed per insert depends on the load factor.
+///
+/// Near saturation most inserts fail anyway, so spending the full budget on
+/// each of them mostly burns CPU and inflates tail latency.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum KickPolicy {
+    /// Always allow the full budget.
+    #[default]
+    Fixed,
+    /// Above a load factor of `threshold` the budget shrinks linearly,
+    /// reaching `min_kicks` when the map is full.
+    Adaptive { threshold: f64, min_kicks: u32 },
+}
+
+/// How an insert whose candidate buckets are all full makes room.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum PathSearch {
+    /// Kick out an entry chosen by the eviction strategy and move it on to
+    /// a random other candidate bucket, kicking out another entry there,
+    /// until an entry lands in a free slot or the kick budget is spent.
+    #[default]
+    RandomWalk,
+    /// Search breadth-first for the shortest chain of at most `max_depth`
+    /// moves that ends in a free slot and apply it only once found. An
+    /// insert without such a chain leaves the buckets untouched.
+    BreadthFirst { max_depth: u32 },
+}
+
+impl KickPolicy {
+    /// Returns how many relocations are allowed out of `max_kicks` at the given load factor.
+    pub fn budget(self, max_kicks: u32, load_factor: f64) -> u32 {
+        match self {
+            KickPolicy::Fixed => max_kicks,
+            KickPolicy::Adaptive { threshold, min_kicks } => {
+                if load_factor <= threshold || threshold >= 1.0 {
+                    return max_kicks;
+                }
+                let headroom = ((1.0 - load_factor) / (1.0 - threshold)).clamp(0.0, 1.0);
+                let min_kicks = cmp::min(min_kicks, max_kicks);
+                min_kicks + (f64::from(max_kicks - min_kicks) * headroom) as u32
+            }
+        }
+    }
+}
+
+/// How the alternate bucket of a key is derived from the bucket it is in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum IndexScheme {
+    /// `i ^ h`, with `h` the hash of the fingerprint. Only places keys
+    /// consistently in tables with a power-of-two number of buckets, which
+    /// can then double.
+    #[default]
+    Xor,
+    /// `(h - i) mod len`, the reflection of `i` on a cycle of `len` buckets.
+    /// It is its own inverse for any `len`, so tables can be sized exactly,
+    /// but they can't grow and use 2 ways.
+    Cyclic,
+    /// `i ^ d`, with `d` the hash of the fingerprint reduced below the
+    /// number of buckets per cache line, so both candidates of a key share
+    /// a cache line, see `CuckooMap::set_index_scheme`. Keys only move
+    /// within their line, which lowers the achievable load factor, and
+    /// tables can't grow and use 2 ways.
+    Local,
+}
@@ -0,0 +1,66 @@
+use crate::clock::{Clock, SystemClock};
+
+use std::cmp;
+use std::time::Duration;
+
+/// Fixed-point scale: one token is worth this many units.
+const TOKEN: u64 = 1_000_000;
+
+/// Token bucket limiting how many inserts a `CuckooMap` accepts per second.
+///
+/// Tokens are tracked in fixed-point arithmetic, so the limiter neither drifts
+/// nor depends on floating point rounding.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    per_second: u64,
+    burst: u64,
+    tokens: u64,
+    /// When tokens were last refilled, `None` until the first acquire.
+    last_refill: Option<Duration>,
+}
+
+impl RateLimiter {
+    /// Allows `per_second` operations per second on average and up to `burst`
+    /// operations at once. The bucket starts out full.
+    pub fn new(per_second: u32, burst: u32) -> Self {
+        Self {
+            per_second: u64::from(per_second),
+            burst: u64::from(burst) * TOKEN,
+            tokens: u64::from(burst) * TOKEN,
+            last_refill: None,
+        }
+    }
+
+    /// Forgets the time of the last refill, e.g. when switching clocks.
+    pub(crate) fn restart(&mut self) {
+        self.last_refill = None;
+    }
+
+    /// Takes one token if available.
+    pub fn try_acquire(&mut self) -> bool {
+        self.try_acquire_at(SystemClock.now())
+    }
+
+    /// Takes one token if available at time `now` of some `Clock`, which
+    /// has to be the same for all calls.
+    pub fn try_acquire_at(&mut self, now: Duration) -> bool {
+        let last_refill = *self.last_refill.get_or_insert(now);
+        let elapsed = now.saturating_sub(last_refill).as_nanos();
+        let refill = elapsed * u128::from(self.per_second) * u128::from(TOKEN) / 1_000_000_000;
+
+        // only advance the clock once something was refilled,
+        // so frequent calls at low rates don't lose their fractions
+        if refill > 0 {
+            let refill = cmp::min(refill, u128::from(self.burst)) as u64;
+            self.tokens = cmp::min(self.burst, self.tokens + refill);
+            self.last_refill = Some(now);
+        }
+
+        if self.tokens >= TOKEN {
+            self.tokens -= TOKEN;
+            true
+        } else {
+            false
+        }
+    }
+}
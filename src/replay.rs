@@ -0,0 +1,250 @@
+//! Recording and replaying operation sequences, for reproducing bug reports.
+//!
+//! A recording starts with a header holding the map's capacity and the seed
+//! of its eviction RNG, followed by one record per operation with the key's
+//! hash, the value and the outcome observed while recording.
+//!
+//! Only the operations of the `Recorder` are recorded. Merges aren't, so the
+//! merge rule plays no part in a replay, and replayed maps use the default
+//! settings apart from the capacity and the seed.
+
+use crate::bucket::VALUE_SIZE;
+use crate::{CuckooError, CuckooMap};
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+const MAGIC: &[u8; 4] = b"CMRP";
+const VERSION: u8 = 1;
+
+const OP_INSERT: u8 = 1;
+const OP_DELETE: u8 = 2;
+const OP_GET: u8 = 3;
+const OP_CLEAR: u8 = 4;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ReplayError {
+    Io(io::Error),
+    InvalidFormat,
+    /// The operation with this index produced a different outcome than recorded.
+    Diverged(usize),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "Io({})", e),
+            ReplayError::InvalidFormat => f.write_str("InvalidFormat"),
+            ReplayError::Diverged(op) => write!(f, "Diverged({})", op),
+        }
+    }
+}
+
+impl StdError for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(e: io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+/// Encodes the outcome of an insert as a single byte.
+fn insert_outcome(result: &Result<(), CuckooError>) -> u8 {
+    match result {
+        Ok(()) => 0,
+        Err(CuckooError::NotEnoughSpace { .. }) => 1,
+        Err(CuckooError::CapacityMismatch) => 2,
+        Err(CuckooError::Throttled) => 3,
+        Err(CuckooError::WriteVerificationFailed) => 4,
+        Err(CuckooError::InvalidValue(_)) => 5,
+        Err(CuckooError::MemoryCapExceeded { .. }) => 6,
+    }
+}
+
+/// A `CuckooMap` that logs every operation to `out`.
+pub struct Recorder<H, W> {
+    map: CuckooMap<H>,
+    out: W,
+}
+
+impl<H, W> Recorder<H, W>
+where
+    H: Hasher + Default,
+    W: Write,
+{
+    /// Creates an empty map with the given capacity whose eviction RNG is
+    /// seeded with `seed`, and writes the recording header to `out`.
+    pub fn new(capacity: usize, seed: u64, mut out: W) -> io::Result<Self> {
+        let mut map = CuckooMap::with_capacity(capacity);
+        map.seed_eviction_rng(seed);
+
+        out.write_all(MAGIC)?;
+        out.write_u8(VERSION)?;
+        out.write_u64::<LittleEndian>(capacity as u64)?;
+        out.write_u64::<LittleEndian>(seed)?;
+        Ok(Self { map, out })
+    }
+
+    /// The recorded map.
+    pub fn map(&self) -> &CuckooMap<H> {
+        &self.map
+    }
+
+    /// Records `CuckooMap::get`.
+    pub fn get<T: ?Sized + Hash>(&mut self, key: &T) -> io::Result<Option<[u8; VALUE_SIZE]>> {
+        let hash = self.map.key_hash(key);
+        let result = self.map.get_hashed(hash);
+
+        self.out.write_u8(OP_GET)?;
+        self.out.write_u64::<LittleEndian>(hash)?;
+        match result {
+            Some(value) => {
+                self.out.write_u8(1)?;
+                self.out.write_all(&value)?;
+            }
+            None => self.out.write_u8(0)?,
+        }
+        Ok(result)
+    }
+
+    /// Records `CuckooMap::insert_or_update`.
+    pub fn insert_or_update<T: ?Sized + Hash>(
+        &mut self,
+        key: &T,
+        value: [u8; VALUE_SIZE],
+    ) -> io::Result<Result<(), CuckooError>> {
+        let hash = self.map.key_hash(key);
+        let result = self.map.insert_hashed(hash, value);
+
+        self.out.write_u8(OP_INSERT)?;
+        self.out.write_u64::<LittleEndian>(hash)?;
+        self.out.write_all(&value)?;
+        self.out.write_u8(insert_outcome(&result))?;
+        Ok(result)
+    }
+
+    /// Records `CuckooMap::delete`.
+    pub fn delete<T: ?Sized + Hash>(&mut self, key: &T) -> io::Result<bool> {
+        let hash = self.map.key_hash(key);
+        let result = self.map.delete_hashed(hash);
+
+        self.out.write_u8(OP_DELETE)?;
+        self.out.write_u64::<LittleEndian>(hash)?;
+        self.out.write_u8(result as u8)?;
+        Ok(result)
+    }
+
+    /// Records `CuckooMap::clear`.
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.map.clear();
+        self.out.write_u8(OP_CLEAR)
+    }
+
+    /// Flushes the recording and returns the map and the writer.
+    pub fn finish(mut self) -> io::Result<(CuckooMap<H>, W)> {
+        self.out.flush()?;
+        Ok((self.map, self.out))
+    }
+}
+
+/// Replays a recording, checking every outcome against the recorded one.
+///
+/// Returns the resulting map, or `Diverged` with the index of the first
+/// operation that behaved differently than while recording.
+pub fn replay<H, R>(mut input: R) -> Result<CuckooMap<H>, ReplayError>
+where
+    H: Hasher + Default,
+    R: Read,
+{
+    let mut magic = [0; 4];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC || input.read_u8()? != VERSION {
+        return Err(ReplayError::InvalidFormat);
+    }
+    let capacity = input.read_u64::<LittleEndian>()? as usize;
+    let seed = input.read_u64::<LittleEndian>()?;
+
+    let mut map = CuckooMap::<H>::with_capacity(capacity);
+    map.seed_eviction_rng(seed);
+
+    let mut index = 0;
+    loop {
+        let op = match input.read_u8() {
+            Ok(op) => op,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(map),
+            Err(e) => return Err(e.into()),
+        };
+
+        let matches = match op {
+            OP_INSERT => {
+                let hash = input.read_u64::<LittleEndian>()?;
+                let mut value = [0; VALUE_SIZE];
+                input.read_exact(&mut value)?;
+                let outcome = input.read_u8()?;
+                insert_outcome(&map.insert_hashed(hash, value)) == outcome
+            }
+            OP_DELETE => {
+                let hash = input.read_u64::<LittleEndian>()?;
+                let outcome = input.read_u8()? != 0;
+                map.delete_hashed(hash) == outcome
+            }
+            OP_GET => {
+                let hash = input.read_u64::<LittleEndian>()?;
+                let outcome = if input.read_u8()? != 0 {
+                    let mut value = [0; VALUE_SIZE];
+                    input.read_exact(&mut value)?;
+                    Some(value)
+                } else {
+                    None
+                };
+                map.get_hashed(hash) == outcome
+            }
+            OP_CLEAR => {
+                map.clear();
+                true
+            }
+            _ => return Err(ReplayError::InvalidFormat),
+        };
+
+        if !matches {
+            return Err(ReplayError::Diverged(index));
+        }
+        index += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::hash_map::DefaultHasher;
+
+    #[test]
+    fn test_record_and_replay() {
+        let mut recorder = Recorder::<DefaultHasher, _>::new(64, 42, Vec::new()).unwrap();
+        for i in 0..200u32 {
+            recorder.insert_or_update(&i, [i as u8]).unwrap().ok();
+            recorder.get(&(i / 2)).unwrap();
+            if i % 7 == 0 {
+                recorder.delete(&(i / 3)).unwrap();
+            }
+        }
+        let (recorded, log) = recorder.finish().unwrap();
+
+        let replayed = replay::<DefaultHasher, _>(&log[..]).unwrap();
+        assert_eq!(replayed.len(), recorded.len());
+
+        let mut tampered = log.clone();
+        // flip the outcome of the very first insert
+        tampered[4 + 1 + 8 + 8 + 1 + 8 + VALUE_SIZE] ^= 1;
+        assert!(matches!(
+            replay::<DefaultHasher, _>(&tampered[..]),
+            Err(ReplayError::Diverged(0))
+        ));
+    }
+}
@@ -0,0 +1,210 @@
+use crate::bucket::{FINGERPRINT_SIZE, VALUE_SIZE};
+use crate::util::get_hash64;
+use crate::{CuckooError, CuckooMap};
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Factor by which every generation is larger than the one before.
+const GROWTH_FACTOR: usize = 2;
+
+/// Factor by which the false positive budget of every generation is smaller
+/// than the one of the generation before.
+const TIGHTENING_RATIO: f64 = 0.8;
+
+/// Load factor at which a generation stops taking new keys, well before
+/// inserts would start to fill the stash.
+const MAX_LOAD_FACTOR: f64 = 0.9;
+
+/// A chain of `CuckooMap`s of growing size for an unknown number of keys,
+/// like a scalable Bloom filter.
+///
+/// New keys go into the newest map. Once it reaches its share of the false
+/// positive budget, or fills up, a map twice its size is added. Generation
+/// `i` gets the share `budget * (1 - r) * r^i` with `r = 0.8`, so the shares
+/// of all generations, and thereby the estimated false positive probability
+/// of the whole chain, add up to less than `budget`.
+///
+/// Lookups query every generation, newest first, so they get slower with
+/// every generation added. A larger initial capacity keeps the chain short.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::ScalableCuckooMap;
+/// use std::collections::hash_map::DefaultHasher;
+///
+/// let mut map = ScalableCuckooMap::<DefaultHasher, 2>::with_budget(1 << 10, 0.001);
+/// for i in 0..10_000u32 {
+///     map.insert_or_update(&i, [1]).unwrap();
+/// }
+/// assert_eq!(map.get(&42u32), Some([1]));
+/// assert!(map.generations() > 1);
+/// assert!(map.false_positive_probability() < 0.001);
+/// ```
+pub struct ScalableCuckooMap<H = DefaultHasher, const FP: usize = FINGERPRINT_SIZE, const V: usize = VALUE_SIZE> {
+    generations: Vec<CuckooMap<H, FP, V>>,
+    initial_capacity: usize,
+    false_positive_budget: f64,
+}
+
+impl<H, const FP: usize, const V: usize> ScalableCuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    /// Constructs a map whose first generation holds `initial_capacity`
+    /// entries and whose estimated false positive probability stays below
+    /// `false_positive_budget`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the budget is not between 0 and 1.
+    pub fn with_budget(initial_capacity: usize, false_positive_budget: f64) -> Self {
+        assert!(
+            false_positive_budget > 0.0 && false_positive_budget < 1.0,
+            "the false positive budget must be between 0 and 1"
+        );
+        Self {
+            generations: vec![CuckooMap::with_capacity(initial_capacity)],
+            initial_capacity,
+            false_positive_budget,
+        }
+    }
+
+    /// Returns the value of `key` from the newest generation holding it.
+    pub fn get<T: ?Sized + Hash>(&self, key: &T) -> Option<[u8; V]> {
+        let hash = get_hash64::<T, H>(key);
+        self.generations.iter().rev().find_map(|map| map.get_hashed(hash))
+    }
+
+    /// Updates `key` in the generation already holding it, or adds it to the
+    /// newest generation, adding a new one first if needed.
+    ///
+    /// A key the newest generation has no space for after all goes into a
+    /// new generation.
+    pub fn insert_or_update<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; V]) -> Result<(), CuckooError<FP, V>> {
+        let hash = get_hash64::<T, H>(key);
+        if let Some(map) = self.generations.iter_mut().rev().find(|map| map.get_hashed(hash).is_some()) {
+            return map.insert_hashed(hash, value);
+        }
+
+        while !self.admits_another(self.generations.len() - 1) {
+            self.add_generation();
+        }
+        match self.newest().insert_hashed(hash, value) {
+            Err(CuckooError::NotEnoughSpace { .. }) => {
+                self.add_generation();
+                self.newest().insert_hashed(hash, value)
+            }
+            result => result,
+        }
+    }
+
+    /// Deletes `key` from the newest generation holding it. Returns `true`
+    /// if it was found.
+    pub fn delete<T: ?Sized + Hash>(&mut self, key: &T) -> bool {
+        let hash = get_hash64::<T, H>(key);
+        self.generations.iter_mut().rev().any(|map| map.delete_hashed(hash))
+    }
+
+    /// Estimated probability that `get` returns a value for a key that was
+    /// never inserted, combining the estimates of all generations.
+    pub fn false_positive_probability(&self) -> f64 {
+        1.0 - self
+            .generations
+            .iter()
+            .map(|map| 1.0 - map.false_positive_probability())
+            .product::<f64>()
+    }
+
+    /// The false positive budget the map was constructed with.
+    pub fn false_positive_budget(&self) -> f64 {
+        self.false_positive_budget
+    }
+
+    /// Number of maps in the chain.
+    pub fn generations(&self) -> usize {
+        self.generations.len()
+    }
+
+    /// Number of items in all generations.
+    pub fn len(&self) -> usize {
+        self.generations.iter().map(CuckooMap::len).sum()
+    }
+
+    /// Check if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.generations.iter().all(CuckooMap::is_empty)
+    }
+
+    /// Number of entries all generations can hold.
+    pub fn capacity(&self) -> usize {
+        self.generations.iter().map(CuckooMap::capacity).sum()
+    }
+
+    /// Number of bytes the map occupies in memory.
+    pub fn memory_usage(&self) -> usize {
+        self.generations.iter().map(CuckooMap::memory_usage).sum()
+    }
+
+    /// Drops all generations but the first and empties it.
+    pub fn clear(&mut self) {
+        self.generations.truncate(1);
+        self.newest().clear();
+    }
+
+    fn newest(&mut self) -> &mut CuckooMap<H, FP, V> {
+        self.generations.last_mut().expect("the chain has at least one generation")
+    }
+
+    /// Whether generation `i` can take one more entry while staying within
+    /// its share of the budget and below `MAX_LOAD_FACTOR`.
+    fn admits_another(&self, i: usize) -> bool {
+        let map = &self.generations[i];
+        let share = self.false_positive_budget * (1.0 - TIGHTENING_RATIO) * TIGHTENING_RATIO.powi(i as i32);
+        let len = map.len() + 1;
+        len as f64 <= map.capacity() as f64 * MAX_LOAD_FACTOR && map.false_positive_probability_at(len) <= share
+    }
+
+    fn add_generation(&mut self) {
+        let capacity = self.initial_capacity.saturating_mul(GROWTH_FACTOR.pow(self.generations.len() as u32));
+        self.generations.push(CuckooMap::with_capacity(capacity));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_holds_across_generations() {
+        let budget = 0.01;
+        let mut map = ScalableCuckooMap::<DefaultHasher, 1>::with_budget(1 << 10, budget);
+        for i in 0..20_000u32 {
+            map.insert_or_update(&i, [1]).unwrap();
+        }
+        // a few keys were taken for ones already inserted and only updated them
+        let len = map.len();
+        assert!(len > 19_800, "{} entries", len);
+        assert!(map.generations() > 2);
+        assert!(map.false_positive_probability() < budget);
+        assert!((0..20_000u32).all(|i| map.get(&i) == Some([1])));
+
+        let false_positives = (20_000..120_000u32).filter(|i| map.get(i).is_some()).count();
+        assert!(false_positives < 100_000 * 2 / 100, "{} false positives", false_positives);
+
+        // updates stay in the generation holding the key
+        let generations = map.generations();
+        map.insert_or_update(&7u32, [0]).unwrap();
+        assert_eq!(map.get(&7u32), Some([0]));
+        assert_eq!(map.len(), len);
+        assert_eq!(map.generations(), generations);
+
+        assert!(map.delete(&7u32));
+        assert_eq!(map.len(), len - 1);
+
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.generations(), 1);
+    }
+}
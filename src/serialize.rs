@@ -0,0 +1,214 @@
+use crate::bucket::{Entry, Fingerprint, ENTRIES_PER_BUCKET};
+use crate::export::{valid_layout, HASHER_PROBE};
+use crate::util::get_hash64;
+use crate::{CuckooMap, IndexScheme, KickPolicy, MergeRule, PathSearch, SnapshotMetadata, MAX_STASH, MAX_TAGS, MAX_TAG_LEN};
+
+use serde::de::{self, Deserializer};
+use serde::ser::Serializer;
+use serde_bytes::ByteBuf;
+use serde_derive::{Deserialize, Serialize};
+
+use std::convert::TryInto;
+use std::hash::Hasher;
+
+/// A map as serialized: its configuration along with the buckets and the
+/// stash as raw fingerprint and value bytes.
+#[derive(Serialize, Deserialize)]
+struct SerializedMap {
+    fingerprint_size: usize,
+    value_size: usize,
+    /// The hash of `HASHER_PROBE`.
+    hasher: u64,
+    bucket_count: usize,
+    /// The fingerprint and value of every slot, bucket by bucket.
+    #[serde(with = "serde_bytes")]
+    buckets: Vec<u8>,
+    /// Stashed entries along with the bucket they were kicked out of.
+    stash: Vec<(usize, ByteBuf)>,
+    stash_capacity: usize,
+    len: usize,
+    index_scheme: IndexScheme,
+    ways: usize,
+    max_kicks: u32,
+    kick_policy: KickPolicy,
+    path_search: PathSearch,
+    merge_rule: MergeRule,
+    auto_grow: bool,
+    growths: u32,
+    hash_seed: Option<u64>,
+    value_schema: Option<u8>,
+    memory_cap: Option<usize>,
+    metadata: SnapshotMetadata,
+}
+
+/// Serializes the buckets, the stash, the number of entries and the
+/// configuration of the map, along with its `SnapshotMetadata`, noting the
+/// number of entries.
+///
+/// Closures and runtime state are left out: hooks, the value validator,
+/// the eviction strategy and its RNG, the duplicate and update policies,
+/// the clock, TTLs, the rate limiter, the hot cache and access tracking. A
+/// deserialized map has their defaults, as after `with_capacity`.
+impl<H, const FP: usize, const V: usize> serde::Serialize for CuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut buckets = Vec::with_capacity(self.buckets.len() * ENTRIES_PER_BUCKET * (FP + V));
+        for bucket in self.buckets.iter() {
+            for entry in bucket.entries() {
+                buckets.extend_from_slice(&entry_bytes(&entry));
+            }
+        }
+        let mut metadata = self.metadata.clone();
+        metadata.set_items(self.len as u64);
+
+        SerializedMap {
+            fingerprint_size: FP,
+            value_size: V,
+            hasher: get_hash64::<_, H>(HASHER_PROBE),
+            bucket_count: self.buckets.len(),
+            buckets,
+            stash: self.stash.iter().map(|(j, entry)| (*j, ByteBuf::from(entry_bytes(entry)))).collect(),
+            stash_capacity: self.stash_capacity,
+            len: self.len,
+            index_scheme: self.index_scheme,
+            ways: self.ways,
+            max_kicks: self.max_kicks,
+            kick_policy: self.kick_policy,
+            path_search: self.path_search,
+            merge_rule: self.merge_rule,
+            auto_grow: self.auto_grow,
+            growths: self.growths,
+            hash_seed: self.hash_seed,
+            value_schema: self.value_schema,
+            memory_cap: self.memory_cap,
+            metadata,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Restores a map serialized with the same hasher, fingerprint and value
+/// size, failing if any of them differ or the data is inconsistent.
+impl<'de, H, const FP: usize, const V: usize> serde::Deserialize<'de> for CuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let serialized = SerializedMap::deserialize(deserializer)?;
+        Self::from_serialized(serialized).map_err(de::Error::custom)
+    }
+}
+
+impl<H, const FP: usize, const V: usize> CuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    fn from_serialized(s: SerializedMap) -> Result<Self, &'static str> {
+        if s.fingerprint_size != FP || s.value_size != V {
+            return Err("the map has a different fingerprint or value size");
+        }
+        if s.hasher != get_hash64::<_, H>(HASHER_PROBE) {
+            return Err("the map was serialized with a different hasher");
+        }
+        let bucket_bytes = s.bucket_count.checked_mul(ENTRIES_PER_BUCKET * (FP + V));
+        if s.bucket_count == 0 || bucket_bytes != Some(s.buckets.len()) {
+            return Err("the bucket bytes don't match the number of buckets");
+        }
+        if !valid_layout(s.bucket_count, s.index_scheme, s.ways, s.growths)
+            || (s.auto_grow && (s.ways > 2 || s.index_scheme != IndexScheme::Xor))
+        {
+            return Err("the number of buckets, ways, index scheme and growths don't fit together");
+        }
+        if s.stash_capacity > MAX_STASH
+            || s.stash.len() > s.stash_capacity
+            || s.stash.iter().any(|(j, bytes)| *j >= s.bucket_count || bytes.len() != FP + V)
+        {
+            return Err("the stash is invalid");
+        }
+        let metadata = &s.metadata;
+        if metadata.tags().count() > MAX_TAGS
+            || metadata.tags().any(|(key, value)| key.len() > MAX_TAG_LEN || value.len() > MAX_TAG_LEN)
+            || metadata.source().is_some_and(|source| source.len() > MAX_TAG_LEN)
+        {
+            return Err("the metadata exceeds its limits");
+        }
+
+        let mut map = Self::with_buckets(s.bucket_count, s.index_scheme);
+        let mut placed = 0;
+        for (k, bytes) in s.buckets.chunks_exact(FP + V).enumerate() {
+            let entry = read_entry(bytes);
+            placed += !entry.fingerprint.is_empty() as usize;
+            map.buckets[k / ENTRIES_PER_BUCKET].replace(k % ENTRIES_PER_BUCKET, entry);
+        }
+        if s.len != placed + s.stash.len() {
+            return Err("the number of entries doesn't match the buckets");
+        }
+        map.stash.reserve_exact(s.stash_capacity);
+        map.stash.extend(s.stash.iter().map(|(j, bytes)| (*j, read_entry(bytes))));
+
+        map.len = s.len;
+        map.stash_capacity = s.stash_capacity;
+        map.ways = s.ways;
+        map.max_kicks = s.max_kicks;
+        map.kick_policy = s.kick_policy;
+        map.path_search = s.path_search;
+        map.merge_rule = s.merge_rule;
+        map.auto_grow = s.auto_grow;
+        map.growths = s.growths;
+        map.hash_seed = s.hash_seed;
+        map.value_schema = s.value_schema;
+        map.memory_cap = s.memory_cap;
+        map.metadata = s.metadata;
+        Ok(map)
+    }
+}
+
+fn entry_bytes<const FP: usize, const V: usize>(entry: &Entry<FP, V>) -> Vec<u8> {
+    [&entry.fingerprint.data[..], &entry.value[..]].concat()
+}
+
+/// Reads an entry from the `FP + V` bytes of `entry_bytes`.
+fn read_entry<const FP: usize, const V: usize>(bytes: &[u8]) -> Entry<FP, V> {
+    let (fingerprint, value) = bytes.split_at(FP);
+    Entry {
+        fingerprint: Fingerprint {
+            data: fingerprint.try_into().expect("entries are FP + V bytes"),
+        },
+        value: value.try_into().expect("entries are FP + V bytes"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+
+    #[test]
+    fn test_serde_round_trip() {
+        let mut map = CuckooMap::<DefaultHasher, 2, 3>::with_exact_capacity(3000);
+        map.set_hash_seed(7);
+        map.set_max_kicks(100);
+        map.metadata_mut().set_source("test");
+        for i in 0..2_000u32 {
+            map.insert_or_update(&i, [i as u8, 1, 2]).unwrap();
+        }
+
+        let json = serde_json::to_string(&map).unwrap();
+        let restored: CuckooMap<DefaultHasher, 2, 3> = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.len(), map.len());
+        assert_eq!(restored.capacity(), map.capacity());
+        assert_eq!(restored.index_scheme(), IndexScheme::Cyclic);
+        assert_eq!(restored.max_kicks(), 100);
+        assert!((0..2_000u32).all(|i| restored.get(&i) == Some([i as u8, 1, 2])));
+        assert_eq!(restored.metadata().source(), Some("test"));
+        assert_eq!(restored.metadata().items(), Some(2_000));
+
+        // a different hasher or layout places keys elsewhere
+        assert!(serde_json::from_str::<CuckooMap<fnv::FnvHasher, 2, 3>>(&json).is_err());
+        assert!(serde_json::from_str::<CuckooMap<DefaultHasher, 2, 4>>(&json).is_err());
+        let miscounted = json.replace("\"len\":2000", "\"len\":2001");
+        assert!(serde_json::from_str::<CuckooMap<DefaultHasher, 2, 3>>(&miscounted).is_err());
+    }
+}
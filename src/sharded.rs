@@ -0,0 +1,253 @@
+use crate::bucket::{FINGERPRINT_SIZE, VALUE_SIZE};
+use crate::util::get_shard;
+use crate::{CuckooError, CuckooMap, ProbabilisticMap};
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// A `CuckooMap` split into independently locked shards, to be shared
+/// between threads.
+///
+/// Every key belongs to the shard `CuckooMap::shard_of` picks, so threads
+/// working on keys of different shards never wait for each other, and
+/// lookups within a shard only wait for writers. All methods take `&self`.
+///
+/// A shard whose lock was poisoned by a thread panicking in it, e.g. in a
+/// store hook, keeps being used as it was left.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::ShardedCuckooMap;
+/// use std::collections::hash_map::DefaultHasher;
+/// use std::thread;
+///
+/// let map = ShardedCuckooMap::<DefaultHasher>::with_capacity(8, 1 << 12);
+/// thread::scope(|s| {
+///     for t in 0..4u32 {
+///         let map = &map;
+///         s.spawn(move || {
+///             for i in 0..100 {
+///                 map.insert_or_update(&(t, i), [t as u8]).unwrap();
+///             }
+///         });
+///     }
+/// });
+/// assert_eq!(map.len(), 400);
+/// assert_eq!(map.get(&(3u32, 7)), Some([3]));
+/// ```
+pub struct ShardedCuckooMap<H = DefaultHasher, const FP: usize = FINGERPRINT_SIZE, const V: usize = VALUE_SIZE> {
+    shards: Box<[RwLock<CuckooMap<H, FP, V>>]>,
+}
+
+impl<H, const FP: usize, const V: usize> ShardedCuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    /// Constructs a map of `n_shards` shards holding `cap` entries in total.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n_shards` is zero.
+    pub fn with_capacity(n_shards: usize, cap: usize) -> Self {
+        assert!(n_shards > 0, "n_shards must be greater than zero");
+        Self::from_shards((0..n_shards).map(|_| CuckooMap::with_capacity(cap / n_shards)))
+    }
+
+    /// Constructs a map from preconfigured shards, e.g. built by a
+    /// `CuckooMapBuilder`. Keys are routed by their position.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no shards.
+    pub fn from_shards<I>(shards: I) -> Self
+    where
+        I: IntoIterator<Item = CuckooMap<H, FP, V>>,
+    {
+        let shards: Box<[_]> = shards.into_iter().map(RwLock::new).collect();
+        assert!(!shards.is_empty(), "n_shards must be greater than zero");
+        Self { shards }
+    }
+
+    /// Number of shards.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Checks if `key` is in the map, see `CuckooMap::get`.
+    pub fn get<T: ?Sized + Hash>(&self, key: &T) -> Option<[u8; V]> {
+        self.read(self.shard(key)).get(key)
+    }
+
+    /// Inserts `key` or updates its value, see `CuckooMap::insert_or_update`.
+    pub fn insert_or_update<T: ?Sized + Hash>(&self, key: &T, value: [u8; V]) -> Result<(), CuckooError<FP, V>> {
+        self.write(self.shard(key)).insert_or_update(key, value)
+    }
+
+    /// Inserts `key` unless it is already in the map, see
+    /// `CuckooMap::test_and_add`. The check and the insert happen under
+    /// the same lock.
+    pub fn test_and_add<T: ?Sized + Hash>(&self, key: &T, value: [u8; V]) -> Result<bool, CuckooError<FP, V>> {
+        self.write(self.shard(key)).test_and_add(key, value)
+    }
+
+    /// Returns the value of `key`, or on a miss inserts the value `loader`
+    /// produces, see `CuckooMap::get_or_insert_with`.
+    ///
+    /// The shard stays locked while `loader` runs, so concurrent misses of
+    /// the same key load it only once, at the price of blocking the other
+    /// keys of the shard in the meantime.
+    pub fn get_or_insert_with<T, F>(&self, key: &T, loader: F) -> Result<[u8; V], CuckooError<FP, V>>
+    where
+        T: ?Sized + Hash,
+        F: FnOnce() -> [u8; V],
+    {
+        let shard = self.shard(key);
+        if let Some(value) = self.read(shard).get(key) {
+            return Ok(value);
+        }
+        self.write(shard).get_or_insert_with(key, loader)
+    }
+
+    /// Deletes `key` from the map. Returns true if `key` existed before.
+    pub fn delete<T: ?Sized + Hash>(&self, key: &T) -> bool {
+        self.write(self.shard(key)).delete(key)
+    }
+
+    /// Number of items in all shards. Concurrent writes may or may not be
+    /// counted.
+    pub fn len(&self) -> usize {
+        (0..self.shards.len()).map(|i| self.read(i).len()).sum()
+    }
+
+    /// Check if all shards are empty.
+    pub fn is_empty(&self) -> bool {
+        (0..self.shards.len()).all(|i| self.read(i).is_empty())
+    }
+
+    /// Number of entries all shards have room for.
+    pub fn capacity(&self) -> usize {
+        (0..self.shards.len()).map(|i| self.read(i).capacity()).sum()
+    }
+
+    /// Ratio of filled slots to all slots of all shards.
+    pub fn density(&self) -> f64 {
+        let (filled, slots) = (0..self.shards.len()).fold((0.0, 0.0), |(filled, slots), i| {
+            let shard = self.read(i);
+            let capacity = shard.capacity() as f64;
+            (filled + shard.density() * capacity, slots + capacity)
+        });
+        filled / slots
+    }
+
+    /// Number of bytes the map occupies in memory.
+    pub fn memory_usage(&self) -> usize {
+        (0..self.shards.len()).map(|i| self.read(i).memory_usage()).sum()
+    }
+
+    /// Empties all shards, one after another.
+    pub fn clear(&self) {
+        for i in 0..self.shards.len() {
+            self.write(i).clear();
+        }
+    }
+
+    /// Consumes the map, returning its shards.
+    pub fn into_shards(self) -> Vec<CuckooMap<H, FP, V>> {
+        self.shards
+            .into_vec()
+            .into_iter()
+            .map(|shard| shard.into_inner().unwrap_or_else(PoisonError::into_inner))
+            .collect()
+    }
+
+    fn shard<T: ?Sized + Hash>(&self, key: &T) -> usize {
+        get_shard::<T, H>(key, self.shards.len())
+    }
+
+    fn read(&self, i: usize) -> RwLockReadGuard<'_, CuckooMap<H, FP, V>> {
+        self.shards[i].read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self, i: usize) -> RwLockWriteGuard<'_, CuckooMap<H, FP, V>> {
+        self.shards[i].write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<H, const FP: usize, const V: usize> ProbabilisticMap<V, FP> for ShardedCuckooMap<H, FP, V>
+where
+    H: Hasher + Default,
+{
+    fn get<T: ?Sized + Hash>(&self, key: &T) -> Option<[u8; V]> {
+        ShardedCuckooMap::get(self, key)
+    }
+
+    fn insert_or_update<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; V]) -> Result<(), CuckooError<FP, V>> {
+        ShardedCuckooMap::insert_or_update(self, key, value)
+    }
+
+    fn test_and_add<T: ?Sized + Hash>(&mut self, key: &T, value: [u8; V]) -> Result<bool, CuckooError<FP, V>> {
+        ShardedCuckooMap::test_and_add(self, key, value)
+    }
+
+    fn delete<T: ?Sized + Hash>(&mut self, key: &T) -> bool {
+        ShardedCuckooMap::delete(self, key)
+    }
+
+    fn len(&self) -> usize {
+        ShardedCuckooMap::len(self)
+    }
+
+    fn clear(&mut self) {
+        ShardedCuckooMap::clear(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_shards() {
+        let map = ShardedCuckooMap::<DefaultHasher, 2>::with_capacity(4, 1 << 14);
+        assert_eq!(map.shards(), 4);
+        assert_eq!(map.capacity(), 1 << 14);
+
+        let loads = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for t in 0..8u32 {
+                let (map, loads) = (&map, &loads);
+                s.spawn(move || {
+                    for i in 0..1_000u32 {
+                        map.insert_or_update(&(t, i), [t as u8]).unwrap();
+                        assert_eq!(map.get(&(t, i)), Some([t as u8]));
+                        // every thread loads the shared keys, but only one of them wins
+                        let value = map.get_or_insert_with(&i, || {
+                            loads.fetch_add(1, Ordering::Relaxed);
+                            [9]
+                        });
+                        assert_eq!(value.unwrap(), [9]);
+                    }
+                });
+            }
+        });
+        assert_eq!(loads.load(Ordering::Relaxed), 1_000);
+        assert_eq!(map.len(), 9_000);
+        assert!((map.density() - 9_000.0 / (1 << 14) as f64).abs() < 1e-9);
+
+        let shards = map.into_shards();
+        for (n, shard) in shards.iter().enumerate() {
+            assert!(shard.len() > 1_800 && shard.len() < 2_700, "{} in shard {}", shard.len(), n);
+        }
+        let map = ShardedCuckooMap::from_shards(shards);
+        assert!(map.delete(&(0u32, 0u32)));
+        assert!(!map.test_and_add(&(0u32, 1u32), [0]).unwrap());
+        assert_eq!(map.len(), 8_999);
+        map.clear();
+        assert!(map.is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+//! Probing and path search over any table of cuckoo buckets.
+//!
+//! `CuckooMap`, whether its buckets live on the heap or in a mapped file,
+//! `AtomicCuckooMap` and `CppCuckooFilter` store their slots differently,
+//! but read them through `SlotAccess`, so the searches below are written
+//! and tested once. Writing stays with each table, as they differ in how a
+//! chain of moves is applied: in place, by compare-and-swap, or by the
+//! random walk the C++ implementation prescribes.
+
+use crate::bucket::{Fingerprint, ENTRIES_PER_BUCKET};
+use crate::MAX_CANDIDATES;
+
+/// How many entries a breadth-first path search inspects at most.
+pub const MAX_PATH_NODES: usize = 1 << 12;
+
+/// The most buckets an entry may be moved to, including its own.
+pub const MAX_RELOCATIONS: usize = MAX_CANDIDATES;
+
+/// Buckets an entry may be moved to, the first `.1` of `.0` are valid.
+pub type Relocations = ([usize; MAX_RELOCATIONS], usize);
+
+/// An entry a breadth-first path search may move: its bucket and slot, the
+/// node it was reached from and the number of moves up to and including it.
+pub type PathNode = (usize, usize, usize, u32);
+
+/// Read access to a table of buckets of 4 slots, each holding a fingerprint.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::slots::{self, Relocations, SlotAccess, MAX_RELOCATIONS};
+/// use cuckoomap::Fingerprint;
+///
+/// // two buckets, an entry may move between them
+/// struct Table([[u8; 4]; 2]);
+///
+/// impl SlotAccess<1> for Table {
+///     fn fingerprint(&self, bucket: usize, slot: usize) -> Fingerprint<1> {
+///         Fingerprint { data: [self.0[bucket][slot]] }
+///     }
+///
+///     fn relocations(&self, _fp: Fingerprint<1>, bucket: usize) -> Relocations {
+///         let mut buckets = [0; MAX_RELOCATIONS];
+///         buckets[..2].copy_from_slice(&[bucket, 1 - bucket]);
+///         (buckets, 2)
+///     }
+/// }
+///
+/// let table = Table([[1, 2, 3, 4], [5, 0, 0, 0]]);
+/// assert_eq!(table.find(0, Fingerprint { data: [3] }), Some(2));
+/// let mut nodes = Vec::new();
+/// let (leaf, free) = slots::shortest_path(&table, &[0], 1, &mut nodes).unwrap();
+/// assert_eq!((nodes[leaf].0, nodes[leaf].1, free), (0, 0, 1));
+/// ```
+pub trait SlotAccess<const FP: usize> {
+    /// The fingerprint in `slot` of `bucket`, empty if the slot is free.
+    fn fingerprint(&self, bucket: usize, slot: usize) -> Fingerprint<FP>;
+
+    /// The buckets the entry with fingerprint `fp` stored in `bucket` may
+    /// be moved to, starting with `bucket`.
+    fn relocations(&self, fp: Fingerprint<FP>, bucket: usize) -> Relocations;
+
+    /// The first slot of `bucket` holding `fp`, a free one for the empty
+    /// fingerprint.
+    fn find(&self, bucket: usize, fp: Fingerprint<FP>) -> Option<usize> {
+        (0..ENTRIES_PER_BUCKET).find(|&slot| self.fingerprint(bucket, slot) == fp)
+    }
+}
+
+/// The first free slot of `bucket`.
+pub fn free_slot<S: SlotAccess<FP> + ?Sized, const FP: usize>(table: &S, bucket: usize) -> Option<usize> {
+    table.find(bucket, Fingerprint::empty())
+}
+
+/// Searches breadth-first for the shortest chain of at most `max_depth`
+/// moves that frees a slot in one of the buckets `roots`: every entry of
+/// the chain moves into the slot of the next one, the last into a free slot.
+///
+/// Returns the node of the last entry and the bucket with the free slot it
+/// moves to, or `None` if there is no such chain. `nodes` is cleared and
+/// holds the search tree afterwards, see `chain`. Free slots in `roots` are
+/// not looked for.
+pub fn shortest_path<S: SlotAccess<FP> + ?Sized, const FP: usize>(
+    table: &S,
+    roots: &[usize],
+    max_depth: u32,
+    nodes: &mut Vec<PathNode>,
+) -> Option<(usize, usize)> {
+    nodes.clear();
+    if max_depth > 0 {
+        for &bucket in roots {
+            nodes.extend((0..ENTRIES_PER_BUCKET).map(|slot| (bucket, slot, usize::MAX, 1)));
+        }
+    }
+
+    let mut next = 0;
+    while next < nodes.len() {
+        let (bucket, slot, _, depth) = nodes[next];
+        let fp = table.fingerprint(bucket, slot);
+        if fp.is_empty() {
+            // freed since it was queued
+            next += 1;
+            continue;
+        }
+        let (targets, n) = table.relocations(fp, bucket);
+        for &target in &targets[1..n] {
+            if free_slot(table, target).is_some() {
+                return Some((next, target));
+            }
+            let on_path = || chain(nodes, next).any(|(b, _)| b == target);
+            if depth < max_depth && nodes.len() < MAX_PATH_NODES && !on_path() {
+                nodes.extend((0..ENTRIES_PER_BUCKET).map(|slot| (target, slot, next, depth + 1)));
+            }
+        }
+        next += 1;
+    }
+    None
+}
+
+/// The bucket and slot of node `leaf` of a search tree built by
+/// `shortest_path`, then of every node up to the root.
+pub fn chain(nodes: &[PathNode], leaf: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let mut k = leaf;
+    std::iter::from_fn(move || {
+        if k == usize::MAX {
+            return None;
+        }
+        let (bucket, slot, parent, _) = nodes[k];
+        k = parent;
+        Some((bucket, slot))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Buckets in a ring, an entry may move one bucket to the right.
+    struct Ring(Vec<[u8; ENTRIES_PER_BUCKET]>);
+
+    impl SlotAccess<1> for Ring {
+        fn fingerprint(&self, bucket: usize, slot: usize) -> Fingerprint<1> {
+            Fingerprint { data: [self.0[bucket][slot]] }
+        }
+
+        fn relocations(&self, _fp: Fingerprint<1>, bucket: usize) -> Relocations {
+            let mut buckets = [0; MAX_RELOCATIONS];
+            buckets[..2].copy_from_slice(&[bucket, (bucket + 1) % self.0.len()]);
+            (buckets, 2)
+        }
+    }
+
+    #[test]
+    fn test_shortest_path() {
+        let mut ring = Ring(vec![[1; ENTRIES_PER_BUCKET]; 4]);
+        ring.0[3][2] = 0;
+        assert_eq!(ring.find(3, Fingerprint { data: [1] }), Some(0));
+        assert_eq!(free_slot(&ring, 3), Some(2));
+        assert_eq!(free_slot(&ring, 0), None);
+
+        // from bucket 0 the free slot is three moves away
+        let mut nodes = Vec::new();
+        assert_eq!(shortest_path(&ring, &[0], 2, &mut nodes), None);
+        let (leaf, free) = shortest_path(&ring, &[0], 3, &mut nodes).unwrap();
+        assert_eq!(free, 3);
+        let path: Vec<_> = chain(&nodes, leaf).map(|(bucket, _)| bucket).collect();
+        assert_eq!(path, [2, 1, 0]);
+
+        // the shorter of two chains wins
+        let (leaf, free) = shortest_path(&ring, &[0, 2], 3, &mut nodes).unwrap();
+        assert_eq!((chain(&nodes, leaf).count(), free), (1, 3));
+
+        // no chain leads back into a bucket on it
+        ring.0[3][2] = 1;
+        assert_eq!(shortest_path(&ring, &[0], 10, &mut nodes), None);
+        assert!(nodes.iter().all(|&(_, _, _, depth)| depth <= 4));
+    }
+}
@@ -0,0 +1,146 @@
+use crate::bucket::{FINGERPRINT_SIZE, VALUE_SIZE};
+use crate::{CuckooError, CuckooMap};
+
+use std::borrow::Borrow;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+/// A `CuckooMap` locked to keys of type `K`.
+///
+/// `CuckooMap` accepts any hashable key per call, so a key inserted as
+/// `&str` and queried as `u64` silently misses. Here every key must be a
+/// `K`, or a type `K` borrows as, which hashes identically by the contract
+/// of `Borrow`.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::TypedCuckooMap;
+///
+/// let mut map = TypedCuckooMap::<String>::new();
+///
+/// map.insert_or_update("hello world", [1]).unwrap();
+/// assert_eq!(map.get("hello world"), Some([1]));
+/// assert_eq!(map.get(&"hello world".to_owned()), Some([1]));
+/// ```
+///
+/// Mixing key types doesn't compile:
+///
+/// ```compile_fail
+/// use cuckoomap::TypedCuckooMap;
+///
+/// let mut map = TypedCuckooMap::<String>::new();
+/// map.insert_or_update(&42u64, [1]).unwrap();
+/// ```
+pub struct TypedCuckooMap<K: ?Sized, H = DefaultHasher, const FP: usize = FINGERPRINT_SIZE, const V: usize = VALUE_SIZE> {
+    map: CuckooMap<H, FP, V>,
+    _key: PhantomData<fn(&K)>,
+}
+
+impl<K: ?Sized> Default for TypedCuckooMap<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: ?Sized> TypedCuckooMap<K> {
+    /// Construct a TypedCuckooMap with default capacity and hasher.
+    pub fn new() -> Self {
+        CuckooMap::new().into()
+    }
+}
+
+impl<K, H, const FP: usize, const V: usize> TypedCuckooMap<K, H, FP, V>
+where
+    K: ?Sized + Hash,
+    H: Hasher + Default,
+{
+    /// Constructs a TypedCuckooMap with a given max capacity.
+    pub fn with_capacity(cap: usize) -> Self {
+        CuckooMap::with_capacity(cap).into()
+    }
+
+    /// See `CuckooMap::get`.
+    pub fn get<Q>(&self, key: &Q) -> Option<[u8; V]>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash,
+    {
+        self.map.get(key)
+    }
+
+    /// See `CuckooMap::insert_or_update`.
+    pub fn insert_or_update<Q>(&mut self, key: &Q, value: [u8; V]) -> Result<(), CuckooError<FP, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash,
+    {
+        self.map.insert_or_update(key, value)
+    }
+
+    /// See `CuckooMap::get_or_insert_with`.
+    pub fn get_or_insert_with<Q, F>(&mut self, key: &Q, loader: F) -> Result<[u8; V], CuckooError<FP, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash,
+        F: FnOnce() -> [u8; V],
+    {
+        self.map.get_or_insert_with(key, loader)
+    }
+
+    /// See `CuckooMap::test_and_add`.
+    pub fn test_and_add<Q>(&mut self, key: &Q, value: [u8; V]) -> Result<bool, CuckooError<FP, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash,
+    {
+        self.map.test_and_add(key, value)
+    }
+
+    /// See `CuckooMap::delete`.
+    pub fn delete<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash,
+    {
+        self.map.delete(key)
+    }
+
+    /// Number of items in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Check if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Number of bytes the map occupies in memory.
+    pub fn memory_usage(&self) -> usize {
+        self.map.memory_usage()
+    }
+
+    /// Empty all the buckets and reset the number of items.
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    /// The underlying map, for statistics and settings getters.
+    pub fn as_map(&self) -> &CuckooMap<H, FP, V> {
+        &self.map
+    }
+
+    /// Consumes the typed map, returning the underlying map.
+    pub fn into_map(self) -> CuckooMap<H, FP, V> {
+        self.map
+    }
+}
+
+/// Locks an already configured map to one key type.
+impl<K: ?Sized, H, const FP: usize, const V: usize> From<CuckooMap<H, FP, V>> for TypedCuckooMap<K, H, FP, V> {
+    fn from(map: CuckooMap<H, FP, V>) -> Self {
+        Self { map, _key: PhantomData }
+    }
+}
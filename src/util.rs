@@ -1,47 +1,174 @@
-use crate::bucket::{Fingerprint, FINGERPRINT_SIZE};
+use crate::bucket::Fingerprint;
+use crate::MAX_WAYS;
 
 use std::hash::{Hash, Hasher};
 
-use byteorder::{BigEndian, WriteBytesExt};
-
 // A struct combining *F*ingerprint *a*nd *I*ndexes,
 // to have a return type with named fields
 // instead of a tuple with unnamed fields.
-pub struct FaI {
-    pub fp: Fingerprint,
+#[derive(Clone, Copy)]
+pub struct FaI<const FP: usize> {
+    pub fp: Fingerprint<FP>,
     pub i1: usize,
     pub i2: usize,
 }
 
-fn get_hash<T: ?Sized + Hash, H: Hasher + Default>(data: &T) -> (u32, u32) {
+pub fn get_hash64<T: ?Sized + Hash, H: Hasher + Default>(data: &T) -> u64 {
+    get_seeded_hash64::<T, H>(data, None)
+}
+
+/// Hashes `data` after feeding `seed`, if any, into the hasher, so keys
+/// collide in one seeded map only by chance.
+pub fn get_seeded_hash64<T: ?Sized + Hash, H: Hasher + Default>(data: &T, seed: Option<u64>) -> u64 {
     let mut hasher = <H as Default>::default();
+    if let Some(seed) = seed {
+        hasher.write_u64(seed);
+    }
     data.hash(&mut hasher);
-    let result = hasher.finish();
+    hasher.finish()
+}
 
+fn get_hash<T: ?Sized + Hash, H: Hasher + Default>(data: &T) -> (u32, u32) {
+    split_hash(get_hash64::<_, H>(data))
+}
+
+fn split_hash(result: u64) -> (u32, u32) {
     // split 64bit hash value in the upper and the lower 32bit parts,
     // one used for the fingerprint, the other used for the indexes.
     ((result >> 32) as u32, result as u32)
 }
 
-pub fn get_alt_index<H: Hasher + Default>(fp: Fingerprint, i: usize) -> usize {
+/// Returns the other candidate index of a fingerprint stored at index `i`.
+///
+/// The alternate index is `i ^ h`, where `h` is the lower 32bit half of the
+/// hash of the fingerprint's bytes. XOR can neither overflow nor wrap around,
+/// for any `i` up to `usize::MAX`, and it is its own inverse.
+/// Indexes are reduced to a bucket with `i % len` only when a bucket is
+/// accessed. Since `len` is a power of two, the reduction commutes with XOR:
+/// `alt(i) % len == alt(i % len) % len`, so placement does not depend on
+/// whether an index was reduced before. Alternate implementations must follow
+/// the same rules to place keys identically.
+pub fn get_alt_index<H: Hasher + Default, const FP: usize>(fp: Fingerprint<FP>, i: usize) -> usize {
     let (_, index_hash) = get_hash::<_, H>(&fp.data);
     let alt_i = index_hash as usize;
-    (i ^ alt_i) as usize
+    i ^ alt_i
+}
+
+/// Returns the other candidate bucket of a fingerprint stored in bucket `i`
+/// of a table with any number `len` of buckets.
+///
+/// The alternate bucket is `(h - i) mod len`, with `h` the lower 32bit half
+/// of the hash of the fingerprint's bytes reduced to `len`. Unlike XOR,
+/// this reflection is its own inverse within `0..len` whether `len` is a
+/// power of two or not. `i` must already be reduced.
+pub fn get_cyclic_alt_index<H: Hasher + Default, const FP: usize>(fp: Fingerprint<FP>, i: usize, len: usize) -> usize {
+    debug_assert!(i < len);
+    let (_, index_hash) = get_hash::<_, H>(&fp.data);
+    let h = index_hash as usize % len;
+    (h + len - i) % len
 }
 
-impl FaI {
-    fn from_key<T: ?Sized + Hash, H: Hasher + Default>(key: &T) -> Self {
-        let (fp_hash, index_hash) = get_hash::<_, H>(key);
+/// Returns the other candidate bucket of a fingerprint stored in bucket `i`,
+/// within the aligned group of `group` buckets `i` belongs to.
+///
+/// The alternate bucket is `i ^ d`, with `d` in `1..group` taken from the
+/// lower 32bit half of the hash of the fingerprint's bytes. As `group` is a
+/// power of two, XOR with `d` only flips bits below it, so both buckets lie
+/// in the same group and the alternate of the alternate is `i` again.
+/// A group of a single bucket leaves `i` without an alternate.
+pub fn get_local_alt_index<H: Hasher + Default, const FP: usize>(fp: Fingerprint<FP>, i: usize, group: usize) -> usize {
+    debug_assert!(group.is_power_of_two());
+    if group < 2 {
+        return i;
+    }
+    let (_, index_hash) = get_hash::<_, H>(&fp.data);
+    i ^ (1 + index_hash as usize % (group - 1))
+}
+
+/// Returns the candidate buckets of a fingerprint stored in bucket `i` of a
+/// table with `len` buckets, for 3 or 4 candidate buckets per key.
+///
+/// With `a` and `b` the lower and upper 32bit halves of the hash of the
+/// fingerprint's bytes, the buckets `i`, `i ^ a`, `i ^ b` and `i ^ a ^ b`
+/// are closed under XOR with `a` and `b`: starting from any of them yields
+/// the same four buckets, just like the alternate index of two-way hashing.
+/// With three ways the bucket `j` with the largest `j ^ a ^ b` is left out,
+/// which again doesn't depend on the bucket we started from, but unlike
+/// leaving out the largest bucket doesn't starve the end of the table.
+/// The returned buckets are reduced, sorted and without duplicates,
+/// followed by zeros.
+pub fn get_dary_indexes<H: Hasher + Default, const FP: usize>(
+    fp: Fingerprint<FP>,
+    i: usize,
+    len: usize,
+    ways: usize,
+) -> ([usize; MAX_WAYS], usize) {
+    let (b, a) = get_hash::<_, H>(&fp.data);
+    let (a, b) = (a as usize, b as usize);
+    let i = i % len;
+    let mut indexes = [i, (i ^ a) % len, (i ^ b) % len, (i ^ a ^ b) % len];
+    indexes.sort_unstable_by_key(|&j| j ^ a ^ b);
+
+    let mut n = 0;
+    for k in 0..MAX_WAYS {
+        if !indexes[..n].contains(&indexes[k]) {
+            indexes[n] = indexes[k];
+            n += 1;
+        }
+    }
+    let n = n.min(ways);
+    indexes[..n].sort_unstable();
+    for index in indexes.iter_mut().skip(n) {
+        *index = 0;
+    }
+    (indexes, n)
+}
 
-        let mut fp_hash_arr = [0; FINGERPRINT_SIZE];
-        let _ = (&mut fp_hash_arr[..]).write_u32::<BigEndian>(fp_hash);
-        let mut valid_fp_hash: [u8; FINGERPRINT_SIZE] = [0; FINGERPRINT_SIZE];
+/// Returns the bucket an entry in bucket `j` of a table with `len` buckets
+/// moves to when the table doubles: either `j` or `j + len`.
+///
+/// The new bit of the index would come from the key's hash, which isn't
+/// stored. Instead it is taken from bit `p` of `j`, with `p` chosen from
+/// `alt_hash`, the XOR distance between the entry's candidate buckets: the
+/// lowest bit below `len` that differs between both candidates if they
+/// differ in bit `len`, the lowest one that doesn't otherwise. Both
+/// candidates of an entry thereby move consistently, wherever it was
+/// stored, and the alternate index of the moved entry is still
+/// `i ^ alt_hash`.
+pub fn get_migrated_index(alt_hash: usize, j: usize, len: usize) -> usize {
+    let low = if alt_hash & len != 0 { alt_hash } else { !alt_hash } & (len - 1);
+    let p = low & low.wrapping_neg();
+    if j & p != 0 {
+        j + len
+    } else {
+        j
+    }
+}
+
+impl<const FP: usize> FaI<FP> {
+    /// Describes an entry already stored in bucket `i`, taking that bucket
+    /// as its primary one.
+    pub fn stored_at<H: Hasher + Default>(fp: Fingerprint<FP>, i: usize) -> Self {
+        Self {
+            fp,
+            i1: i,
+            i2: get_alt_index::<H, FP>(fp, i),
+        }
+    }
+
+    fn from_hash<H: Hasher + Default>(hash: u64) -> Self {
+        let (fp_hash, index_hash) = split_hash(hash);
+
+        // the fingerprint takes the most significant bytes of the upper half
+        let mut fp_hash_arr = [0; FP];
+        fp_hash_arr.copy_from_slice(&fp_hash.to_be_bytes()[..FP]);
+        let mut valid_fp_hash: [u8; FP] = [0; FP];
         let mut n = 0;
         let fp;
 
         // increment every byte of the hash until we find one that is a valid fingerprint
         loop {
-            for i in 0..FINGERPRINT_SIZE {
+            for i in 0..FP {
                 valid_fp_hash[i] = fp_hash_arr[i] + n;
             }
 
@@ -53,22 +180,81 @@ impl FaI {
         }
 
         let i1 = index_hash as usize;
-        let i2 = get_alt_index::<H>(fp, i1);
+        let i2 = get_alt_index::<H, FP>(fp, i1);
         Self { fp, i1, i2 }
     }
+}
+
+/// Hints the CPU to load the cache line holding `value`.
+/// Does nothing on platforms without a stable prefetch intrinsic.
+#[inline(always)]
+pub fn prefetch<T>(value: &T) {
+    #[cfg(target_arch = "x86_64")]
+    // SAFETY: prefetching is only a hint and never faults,
+    // and SSE is part of the x86_64 baseline.
+    unsafe {
+        use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+        _mm_prefetch::<_MM_HINT_T0>(value as *const T as *const i8);
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = value;
+}
+
+#[cfg(test)]
+pub fn get_fai<T: ?Sized + Hash, H: Hasher + Default, const FP: usize>(data: &T) -> FaI<FP> {
+    FaI::from_hash::<H>(get_hash64::<_, H>(data))
+}
 
-    /// randomly returns `i1` or `i2`
-    pub fn random_index<R: ::rand::Rng>(&self, r: &mut R) -> usize {
-        if r.gen() {
-            self.i1
-        } else {
-            self.i2
+pub fn get_fai_from_hash<H: Hasher + Default, const FP: usize>(hash: u64) -> FaI<FP> {
+    FaI::from_hash::<H>(hash)
+}
+
+/// Derives another well-mixed 64bit hash from `hash`, a different one for
+/// every `salt`.
+pub fn remix_hash(hash: u64, salt: u64) -> u64 {
+    // splitmix64 finalizer
+    let mut h = hash.wrapping_add(salt.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+/// The CRC-32 lookup table of the IEEE polynomial, reflected.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            bit += 1;
         }
+        table[i] = crc;
+        i += 1;
     }
+    table
+};
+
+/// The CRC-32 checksum of `bytes`, as used by zlib and Ethernet.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| {
+        CRC32_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
 }
 
-pub fn get_fai<T: ?Sized + Hash, H: Hasher + Default>(data: &T) -> FaI {
-    FaI::from_key::<_, H>(data)
+/// Maps `data` onto one of `n_shards` shards.
+///
+/// The 64bit hash is remixed before reducing it, so the shard is independent
+/// of the bits used for the fingerprint and the bucket indexes and every shard
+/// still uses its whole bucket array.
+pub fn get_shard<T: ?Sized + Hash, H: Hasher + Default>(data: &T, n_shards: usize) -> usize {
+    assert!(n_shards > 0, "n_shards must be greater than zero");
+
+    let h = remix_hash(get_hash64::<_, H>(data), 0);
+
+    // multiply-shift reduction into 0..n_shards
+    ((u128::from(h) * n_shards as u128) >> 64) as usize
 }
 
 #[cfg(test)]
@@ -79,12 +265,136 @@ mod tests {
     fn test_fp_and_index() {
         use std::collections::hash_map::DefaultHasher;
         let data = "seif";
-        let fai = get_fai::<_, DefaultHasher>(data);
+        let fai = get_fai::<_, DefaultHasher, 1>(data);
         let FaI { fp, i1, i2 } = fai;
-        let i11 = get_alt_index::<DefaultHasher>(fp, i2);
+        let i11 = get_alt_index::<DefaultHasher, 1>(fp, i2);
         assert_eq!(i11, i1);
 
-        let i22 = get_alt_index::<DefaultHasher>(fp, i11);
+        let i22 = get_alt_index::<DefaultHasher, 1>(fp, i11);
         assert_eq!(i22, i2);
+
+        let wide = get_fai::<_, DefaultHasher, 4>(data);
+        assert_eq!(wide.fp.data[0], fp.data[0]);
+        assert_eq!(wide.i1, i1);
+        assert_eq!(get_alt_index::<DefaultHasher, 4>(wide.fp, wide.i2), i1);
+    }
+
+    #[test]
+    fn test_golden_placement() {
+        let golden = include_str!("../tests/golden/placement_fnv.txt");
+
+        for line in golden.lines().filter(|l| !l.starts_with('#')) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let fai = match fields[0] {
+                "str" => get_fai::<_, fnv::FnvHasher, 1>(fields[1]),
+                "u64" => get_fai::<_, fnv::FnvHasher, 1>(&fields[1].parse::<u64>().unwrap()),
+                other => panic!("unknown key type {}", other),
+            };
+
+            let expected: Vec<usize> = fields[2..].iter().map(|f| f.parse().unwrap()).collect();
+            assert_eq!(
+                vec![fai.fp.data[0] as usize, fai.i1, fai.i2],
+                expected,
+                "placement of {} changed",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_shard_distribution() {
+        let mut counts = [0; 4];
+        for i in 0..10_000u64 {
+            assert_eq!(get_shard::<_, fnv::FnvHasher>(&i, 1), 0);
+            counts[get_shard::<_, fnv::FnvHasher>(&i, 4)] += 1;
+        }
+        assert!(counts.iter().all(|&c| c > 2_250 && c < 2_750), "{:?}", counts);
+    }
+
+    #[test]
+    fn test_dary_indexes_from_any_bucket() {
+        use std::collections::hash_map::DefaultHasher;
+        let len = 1 << 10;
+
+        for key in 0..100u32 {
+            let fai = get_fai::<_, DefaultHasher, 1>(&key);
+            for &ways in &[3, 4] {
+                let (indexes, n) = get_dary_indexes::<DefaultHasher, 1>(fai.fp, fai.i1, len, ways);
+                assert!(n <= ways);
+                for &i in &indexes[..n] {
+                    assert_eq!(get_dary_indexes::<DefaultHasher, 1>(fai.fp, i, len, ways), (indexes, n));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_migrated_bit_keeps_candidates_together() {
+        use std::collections::hash_map::DefaultHasher;
+
+        for key in 0..1000u32 {
+            let fai = get_fai::<_, DefaultHasher, 1>(&key);
+            let alt_hash = fai.i1 ^ fai.i2;
+            for &len in &[1usize, 2, 64, 1 << 10] {
+                let (j1, j2) = (fai.i1 % len, fai.i2 % len);
+                let moved = |j: usize| get_migrated_index(alt_hash, j, len);
+                // whichever candidate the entry was in, the other one is
+                // still its alternate after the move
+                assert!(moved(j1) == (moved(j2) ^ alt_hash) % (2 * len) || j1 == j2);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cyclic_alt_index_is_an_involution() {
+        use std::collections::hash_map::DefaultHasher;
+
+        for key in 0..1000u32 {
+            let fai = get_fai::<_, DefaultHasher, 1>(&key);
+            for &len in &[1usize, 3, 1000, 1 << 10, 12_345] {
+                let i = fai.i1 % len;
+                let alt = get_cyclic_alt_index::<DefaultHasher, 1>(fai.fp, i, len);
+                assert!(alt < len);
+                assert_eq!(get_cyclic_alt_index::<DefaultHasher, 1>(fai.fp, alt, len), i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_local_alt_index_stays_in_group() {
+        use std::collections::hash_map::DefaultHasher;
+
+        for key in 0..1000u32 {
+            let fai = get_fai::<_, DefaultHasher, 1>(&key);
+            for &group in &[2usize, 4, 8] {
+                let i = fai.i1 % (1 << 10);
+                let alt = get_local_alt_index::<DefaultHasher, 1>(fai.fp, i, group);
+                assert_ne!(alt, i);
+                assert_eq!(alt / group, i / group);
+                assert_eq!(get_local_alt_index::<DefaultHasher, 1>(fai.fp, alt, group), i);
+            }
+            assert_eq!(get_local_alt_index::<DefaultHasher, 1>(fai.fp, 0, 1), 0);
+        }
+    }
+
+    #[test]
+    fn test_alt_index_wrap_around() {
+        use std::collections::hash_map::DefaultHasher;
+        let fp = get_fai::<_, DefaultHasher, 1>("seif").fp;
+
+        for &i in &[0, 1, u32::MAX as usize, usize::MAX - 1, usize::MAX] {
+            let alt = get_alt_index::<DefaultHasher, 1>(fp, i);
+            assert_eq!(get_alt_index::<DefaultHasher, 1>(fp, alt), i);
+
+            for &len in &[1usize, 2, 1 << 10, 1 << 20, 1 << 31] {
+                assert_eq!(alt % len, get_alt_index::<DefaultHasher, 1>(fp, i % len) % len);
+            }
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
     }
 }
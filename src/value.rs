@@ -0,0 +1,319 @@
+use std::collections::BTreeMap;
+use std::error::Error as StdError;
+use std::fmt;
+
+/// Bit budget of a `V`-byte value, splitting it into user payload and
+/// `INTERNAL` bits of metadata reserved for crate features such as expiry or
+/// pinning.
+///
+/// The value is read as a little-endian integer; the internal bits are its
+/// top `INTERNAL` bits and the user payload is everything below them. The
+/// accessors only ever touch their own part, so a payload that doesn't fit
+/// is rejected instead of overwriting flags. Budgets that exceed the value
+/// or 64 bits fail to compile.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::ValueLayout;
+///
+/// type Layout = ValueLayout<2, 4>;
+///
+/// let value = Layout::with_user([0; 2], 0x0abc).unwrap();
+/// let value = Layout::with_flag(value, 0, true);
+/// assert_eq!(Layout::user(value), 0x0abc);
+/// assert!(Layout::flag(value, 0));
+/// assert_eq!(Layout::with_user(value, 0x1000), None);
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ValueLayout<const V: usize, const INTERNAL: u32>;
+
+impl<const V: usize, const INTERNAL: u32> ValueLayout<V, INTERNAL> {
+    const FITS: () = assert!(
+        V >= 1 && V <= 8 && INTERNAL <= 8 * V as u32,
+        "internal bits must fit into a value of at most 8 bytes"
+    );
+
+    /// Number of bits available to the user payload.
+    pub const USER_BITS: u32 = 8 * V as u32 - INTERNAL;
+    /// Number of bits reserved for internal metadata.
+    pub const INTERNAL_BITS: u32 = INTERNAL;
+
+    const USER_MASK: u64 = if Self::USER_BITS == 64 { u64::MAX } else { (1 << Self::USER_BITS) - 1 };
+
+    /// Returns the user payload of the value.
+    pub fn user(value: [u8; V]) -> u64 {
+        Self::to_bits(value) & Self::USER_MASK
+    }
+
+    /// Replaces the user payload, keeping the internal bits. Returns `None`
+    /// if the payload needs more than `USER_BITS` bits.
+    pub fn with_user(value: [u8; V], payload: u64) -> Option<[u8; V]> {
+        if payload & !Self::USER_MASK != 0 {
+            return None;
+        }
+        Some(Self::from_bits(Self::to_bits(value) & !Self::USER_MASK | payload))
+    }
+
+    /// Reads internal flag `bit`, counted from the lowest internal bit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bit` is not below `INTERNAL_BITS`.
+    pub fn flag(value: [u8; V], bit: u32) -> bool {
+        Self::to_bits(value) & Self::flag_mask(bit) != 0
+    }
+
+    /// Sets or clears internal flag `bit`, keeping everything else.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bit` is not below `INTERNAL_BITS`.
+    pub fn with_flag(value: [u8; V], bit: u32, on: bool) -> [u8; V] {
+        let bits = Self::to_bits(value);
+        let mask = Self::flag_mask(bit);
+        Self::from_bits(if on { bits | mask } else { bits & !mask })
+    }
+
+    fn flag_mask(bit: u32) -> u64 {
+        assert!(bit < INTERNAL, "flag {} is outside the {} internal bits", bit, INTERNAL);
+        1 << (Self::USER_BITS + bit)
+    }
+
+    fn to_bits(value: [u8; V]) -> u64 {
+        let () = Self::FITS;
+        let mut bytes = [0; 8];
+        bytes[..V].copy_from_slice(&value);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn from_bits(bits: u64) -> [u8; V] {
+        let mut value = [0; V];
+        value.copy_from_slice(&bits.to_le_bytes()[..V]);
+        value
+    }
+}
+
+/// Types that can be stored as the `V`-byte value of a map, used by
+/// `CuckooMap::insert_typed` and `CuckooMap::get_typed`.
+///
+/// Implemented for the integer types, `bool` and byte arrays; small enums
+/// can implement it by hand.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::{CuckooMap, ValueCodec};
+/// use std::collections::hash_map::DefaultHasher;
+///
+/// #[derive(Clone, Copy, Debug, PartialEq)]
+/// enum State { Active, Blocked }
+///
+/// impl ValueCodec<1> for State {
+///     fn encode(self) -> [u8; 1] {
+///         [self as u8]
+///     }
+///
+///     fn decode(bytes: [u8; 1]) -> Self {
+///         if bytes[0] == 0 { State::Active } else { State::Blocked }
+///     }
+/// }
+///
+/// let mut map = CuckooMap::<DefaultHasher>::new();
+/// map.insert_typed("user", State::Blocked).unwrap();
+/// assert_eq!(map.get_typed("user"), Some(State::Blocked));
+///
+/// let mut wide = CuckooMap::<DefaultHasher, 1, 4>::with_capacity(1 << 10);
+/// wide.insert_typed("requests", 70_000u32).unwrap();
+/// assert_eq!(wide.get_typed::<_, u32>("requests"), Some(70_000));
+/// ```
+pub trait ValueCodec<const V: usize>: Copy {
+    /// Encodes the value into the bytes stored in the map.
+    fn encode(self) -> [u8; V];
+
+    /// Decodes a value from the bytes stored in the map. Since the map is
+    /// probabilistic, this may see bytes of another key on a false positive.
+    fn decode(bytes: [u8; V]) -> Self;
+}
+
+impl<const V: usize> ValueCodec<V> for [u8; V] {
+    fn encode(self) -> [u8; V] {
+        self
+    }
+
+    fn decode(bytes: [u8; V]) -> Self {
+        bytes
+    }
+}
+
+impl ValueCodec<1> for bool {
+    fn encode(self) -> [u8; 1] {
+        [self as u8]
+    }
+
+    fn decode(bytes: [u8; 1]) -> Self {
+        bytes[0] != 0
+    }
+}
+
+macro_rules! impl_int_codec {
+    ($($ty:ty),*) => {
+        $(
+            impl ValueCodec<{ std::mem::size_of::<$ty>() }> for $ty {
+                fn encode(self) -> [u8; std::mem::size_of::<$ty>()] {
+                    self.to_le_bytes()
+                }
+
+                fn decode(bytes: [u8; std::mem::size_of::<$ty>()]) -> Self {
+                    <$ty>::from_le_bytes(bytes)
+                }
+            }
+        )*
+    };
+}
+
+impl_int_codec!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+/// Decoders of the value formats a map's values may have been written in,
+/// keyed by a schema id the map is tagged with.
+///
+/// Tag a map with `CuckooMap::set_value_schema` whenever the meaning of its
+/// value bytes changes and register a decoder for every id still found in
+/// stored maps. `CuckooMap::get_decoded` then decodes the values of a map
+/// written by an older release with the decoder of its time, and refuses
+/// maps of an unknown schema rather than misinterpreting their bytes.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::{CuckooMap, SchemaError, ValueSchema};
+/// use std::collections::hash_map::DefaultHasher;
+///
+/// #[derive(Debug, PartialEq)]
+/// enum Access { Denied, Read, Write }
+///
+/// // version 1 stored a flag, version 2 a level
+/// let schema = ValueSchema::new()
+///     .register(1, |v: [u8; 1]| if v[0] == 0 { Access::Denied } else { Access::Write })
+///     .register(2, |v| match v[0] { 0 => Access::Denied, 1 => Access::Read, _ => Access::Write });
+///
+/// let mut old = CuckooMap::<DefaultHasher>::new();
+/// old.set_value_schema(1);
+/// old.insert_or_update("alice", [1]).unwrap();
+/// assert_eq!(old.get_decoded("alice", &schema), Ok(Some(Access::Write)));
+///
+/// let mut new = CuckooMap::<DefaultHasher>::new();
+/// new.set_value_schema(2);
+/// new.insert_or_update("alice", [1]).unwrap();
+/// assert_eq!(new.get_decoded("alice", &schema), Ok(Some(Access::Read)));
+///
+/// new.set_value_schema(3);
+/// assert_eq!(new.get_decoded("alice", &schema), Err(SchemaError::Unknown(3)));
+/// ```
+pub struct ValueSchema<T, const V: usize> {
+    decoders: BTreeMap<u8, fn([u8; V]) -> T>,
+}
+
+impl<T, const V: usize> ValueSchema<T, V> {
+    /// Creates a registry without decoders.
+    pub fn new() -> Self {
+        Self { decoders: BTreeMap::new() }
+    }
+
+    /// Registers the decoder of the values of schema `id`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` already has a decoder.
+    pub fn register(mut self, id: u8, decoder: fn([u8; V]) -> T) -> Self {
+        let previous = self.decoders.insert(id, decoder);
+        assert!(previous.is_none(), "value schema {} is registered twice", id);
+        self
+    }
+
+    /// Decodes `bytes` written in schema `id`.
+    pub fn decode(&self, id: u8, bytes: [u8; V]) -> Result<T, SchemaError> {
+        let decoder = self.decoders.get(&id).ok_or(SchemaError::Unknown(id))?;
+        Ok(decoder(bytes))
+    }
+
+    /// Whether schema `id` has a decoder.
+    pub fn knows(&self, id: u8) -> bool {
+        self.decoders.contains_key(&id)
+    }
+}
+
+impl<T, const V: usize> Default for ValueSchema<T, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Why the values of a map can't be decoded with a `ValueSchema`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SchemaError {
+    /// The map is not tagged with a schema id.
+    Untagged,
+    /// The map's schema id has no decoder.
+    Unknown(u8),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaError::Untagged => f.write_str("the map has no value schema"),
+            SchemaError::Unknown(id) => write!(f, "unknown value schema {}", id),
+        }
+    }
+}
+
+impl StdError for SchemaError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_and_flags_are_disjoint() {
+        type Layout = ValueLayout<1, 2>;
+        assert_eq!(Layout::USER_BITS, 6);
+
+        let value = Layout::with_flag([0], 1, true);
+        assert_eq!(value, [0b1000_0000]);
+        let value = Layout::with_user(value, 0b11_1111).unwrap();
+        assert_eq!(value, [0b1011_1111]);
+        assert!(Layout::flag(value, 1));
+        assert!(!Layout::flag(value, 0));
+        assert_eq!(Layout::with_user(value, 0b100_0000), None);
+
+        let value = Layout::with_flag(value, 1, false);
+        assert_eq!(Layout::user(value), 0b11_1111);
+        assert_eq!(value, [0b0011_1111]);
+
+        // the whole value as payload
+        type Full = ValueLayout<8, 0>;
+        assert_eq!(Full::user(Full::with_user([0; 8], u64::MAX).unwrap()), u64::MAX);
+    }
+
+    #[test]
+    fn test_codec_round_trip() {
+        assert_eq!(u16::decode(0xbeefu16.encode()), 0xbeef);
+        assert_eq!(i32::decode((-5i32).encode()), -5);
+        assert_eq!(300u16.encode(), [44, 1]);
+        assert!(bool::decode(true.encode()));
+        assert_eq!(<[u8; 3]>::decode([1, 2, 3].encode()), [1, 2, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "registered twice")]
+    fn test_schema_ids_are_unique() {
+        ValueSchema::new().register(1, u8::decode).register(1, |v: [u8; 1]| v[0] + 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_flag_out_of_range() {
+        ValueLayout::<2, 3>::flag([0; 2], 3);
+    }
+}
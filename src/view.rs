@@ -0,0 +1,217 @@
+use crate::bucket::{ENTRIES_PER_BUCKET, FINGERPRINT_SIZE, VALUE_SIZE};
+use crate::export::{RawExport, HASHER_PROBE};
+use crate::util::{get_fai_from_hash, get_hash64, get_seeded_hash64};
+use crate::{ImportError, Layout, SchemaError, SnapshotMetadata, ValueSchema, Watermarks};
+
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryInto;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+/// A read-only map looking up keys directly in the bytes of
+/// `ExportedCuckooMap::to_bytes`.
+///
+/// Importing an export copies all its buckets, which takes a while and
+/// doubles the memory for large maps. A view borrows the bytes instead, e.g.
+/// from a cache or a memory mapped file, and lookups don't allocate.
+/// Opening a view reads the bytes once to check them like `CuckooMap::import`
+/// does, but copies nothing.
+///
+/// Lookups return the value of the first entry found, as with the default
+/// `DuplicatePolicy`.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::{CuckooMap, CuckooMapView};
+/// use std::collections::hash_map::DefaultHasher;
+///
+/// let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+/// map.insert_or_update("hello", [1]).unwrap();
+/// let bytes = map.export().to_bytes();
+///
+/// let view = CuckooMapView::<DefaultHasher>::new(&bytes).unwrap();
+/// assert_eq!(view.get("hello"), Some([1]));
+/// assert_eq!(view.get("world"), None);
+/// ```
+pub struct CuckooMapView<'a, H = DefaultHasher, const FP: usize = FINGERPRINT_SIZE, const V: usize = VALUE_SIZE> {
+    length: usize,
+    layout: Layout,
+    hash_seed: Option<u64>,
+    value_schema: Option<u8>,
+    values: &'a [u8],
+    /// See `RawExport::stash`.
+    stash: &'a [u8],
+    metadata: SnapshotMetadata,
+    watermarks: Watermarks,
+    _hasher: PhantomData<fn() -> H>,
+}
+
+/// A reader of exported snapshots, another name for `CuckooMapView`.
+///
+/// It checks and queries the bytes of `ExportedCuckooMap::to_bytes` where
+/// they are, e.g. in a file mapped with `CuckooMap::open_mmap`, without
+/// building a `CuckooMap` or allocating per lookup, which suits short-lived
+/// tools probing large snapshots.
+///
+/// # Examples
+///
+/// ```
+/// use cuckoomap::{CuckooMap, SnapshotReader};
+/// use std::collections::hash_map::DefaultHasher;
+///
+/// let mut map = CuckooMap::<DefaultHasher>::with_capacity(1 << 10);
+/// map.insert_or_update("hello", [1]).unwrap();
+/// let bytes = map.export().to_bytes();
+///
+/// let reader = SnapshotReader::<DefaultHasher>::new(&bytes).unwrap();
+/// assert_eq!((reader.len(), reader.get("hello")), (1, Some([1])));
+/// ```
+pub type SnapshotReader<'a, H = DefaultHasher, const FP: usize = FINGERPRINT_SIZE, const V: usize = VALUE_SIZE> =
+    CuckooMapView<'a, H, FP, V>;
+
+impl<'a, H, const FP: usize, const V: usize> CuckooMapView<'a, H, FP, V>
+where
+    H: Hasher + Default,
+{
+    /// Opens a view of the bytes of `ExportedCuckooMap::to_bytes`, failing
+    /// like `CuckooMap::import` does.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, ImportError> {
+        let raw = RawExport::parse::<FP, V>(bytes)?;
+        if raw.hasher != get_hash64::<_, H>(HASHER_PROBE) {
+            return Err(ImportError::HasherMismatch);
+        }
+        Ok(Self {
+            length: raw.length,
+            layout: raw.layout,
+            hash_seed: raw.hash_seed,
+            value_schema: raw.value_schema,
+            values: raw.values,
+            stash: raw.stash,
+            metadata: raw.metadata,
+            watermarks: raw.watermarks,
+            _hasher: PhantomData,
+        })
+    }
+
+    /// See `CuckooMap::get`.
+    pub fn get<K: ?Sized + Hash>(&self, key: &K) -> Option<[u8; V]> {
+        self.get_hashed(self.key_hash(key))
+    }
+
+    /// See `CuckooMap::get_hashed`.
+    pub fn get_hashed(&self, hash: u64) -> Option<[u8; V]> {
+        let fai = get_fai_from_hash::<H, FP>(hash);
+        let (buckets, n) = self.layout.candidates::<H, FP, V>(&fai);
+        let fp = &fai.fp.data[..];
+
+        let bucket_size = ENTRIES_PER_BUCKET * (FP + V);
+        let found = buckets[..n].iter().find_map(|&i| {
+            self.values[i * bucket_size..(i + 1) * bucket_size]
+                .chunks_exact(FP + V)
+                .find(|entry| &entry[..FP] == fp)
+        });
+        let found = found.or_else(|| {
+            self.stash.chunks_exact(8 + FP + V).find_map(|stashed| {
+                let (j, entry) = stashed.split_at(8);
+                let j = u64::from_le_bytes(j.try_into().expect("8 bytes")) as usize;
+                Some(entry).filter(|entry| &entry[..FP] == fp && buckets[..n].contains(&j))
+            })
+        })?;
+        Some(found[FP..].try_into().expect("entries are FP + V bytes"))
+    }
+
+    /// See `CuckooMap::get_decoded`.
+    pub fn get_decoded<K: ?Sized + Hash, C>(&self, key: &K, schema: &ValueSchema<C, V>) -> Result<Option<C>, SchemaError> {
+        let id = self.value_schema.ok_or(SchemaError::Untagged)?;
+        if !schema.knows(id) {
+            return Err(SchemaError::Unknown(id));
+        }
+        self.get(key).map(|value| schema.decode(id, value)).transpose()
+    }
+
+    /// See `CuckooMap::contains`.
+    pub fn contains<K: ?Sized + Hash>(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// See `CuckooMap::key_hash`.
+    pub fn key_hash<K: ?Sized + Hash>(&self, key: &K) -> u64 {
+        get_seeded_hash64::<K, H>(key, self.hash_seed)
+    }
+
+    /// Number of entries of the exported map.
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Whether the exported map was empty.
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Number of buckets of the exported map.
+    pub fn buckets(&self) -> usize {
+        self.layout.len
+    }
+
+    /// The value schema the exported map was tagged with, see
+    /// `CuckooMap::set_value_schema`.
+    pub fn value_schema(&self) -> Option<u8> {
+        self.value_schema
+    }
+
+    /// The metadata of the exported map, with the number of entries noted.
+    pub fn metadata(&self) -> &SnapshotMetadata {
+        &self.metadata
+    }
+
+    /// The watermarks of the exported map, see `CuckooMap::watermarks`.
+    pub fn watermarks(&self) -> Watermarks {
+        self.watermarks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CuckooMap;
+
+    #[test]
+    fn test_view() {
+        let mut map = CuckooMap::<DefaultHasher, 4, 2>::with_capacity(1 << 10);
+        map.set_auto_grow(true);
+        map.set_hash_seed(3);
+        map.set_stash_capacity(8);
+        for i in 0..3_000u32 {
+            map.insert_or_update(&i, [i as u8, 7]).unwrap();
+        }
+        map.metadata_mut().set_source("test");
+        let bytes = map.export().to_bytes();
+
+        let view = CuckooMapView::<DefaultHasher, 4, 2>::new(&bytes).unwrap();
+        assert_eq!(view.len(), 3_000);
+        assert_eq!(view.buckets(), map.capacity() / ENTRIES_PER_BUCKET);
+        assert_eq!(view.metadata().source(), Some("test"));
+        assert!((0..3_000u32).all(|i| view.get(&i) == Some([i as u8, 7])));
+        assert!((3_000..4_000u32).all(|i| view.get(&i) == map.get(&i)));
+
+        // a full map spills into its stash
+        let mut full = CuckooMap::<DefaultHasher, 4, 2>::with_capacity(1 << 8);
+        full.set_stash_capacity(8);
+        let inserted = (0..).take_while(|i: &u32| full.insert_or_update(i, [*i as u8, 1]).is_ok()).count() as u32;
+        assert!(full.stash_len() > 0);
+        let bytes = full.export().to_bytes();
+        let view = CuckooMapView::<DefaultHasher, 4, 2>::new(&bytes).unwrap();
+        assert!((0..inserted).all(|i| view.get(&i) == full.get(&i)));
+
+        assert_eq!(
+            CuckooMapView::<fnv::FnvHasher, 4, 2>::new(&bytes).err(),
+            Some(ImportError::HasherMismatch)
+        );
+        assert_eq!(
+            CuckooMapView::<DefaultHasher, 4, 2>::new(&bytes[..bytes.len() - 1]).err(),
+            Some(ImportError::ChecksumMismatch)
+        );
+    }
+}
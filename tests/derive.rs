@@ -0,0 +1,54 @@
+use cuckoomap::CuckooMap;
+use cuckoomap_derive::CuckooKey;
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+#[derive(CuckooKey)]
+#[cuckoo_key(version = 2)]
+struct Before {
+    #[cuckoo_key(order = 0)]
+    tenant: u32,
+    #[cuckoo_key(order = 1)]
+    user: String,
+}
+
+// same key with its fields reordered and a field added that isn't hashed
+#[derive(CuckooKey)]
+#[cuckoo_key(version = 2)]
+struct After {
+    #[cuckoo_key(skip)]
+    _cached_len: usize,
+    #[cuckoo_key(order = 1)]
+    user: String,
+    #[cuckoo_key(order = 0)]
+    tenant: u32,
+}
+
+#[derive(CuckooKey)]
+struct Pair<T>(#[cuckoo_key(order = 1)] T, #[cuckoo_key(order = 0)] T);
+
+fn hash<T: Hash>(key: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[test]
+fn reordering_fields_keeps_hash() {
+    let before = Before { tenant: 3, user: "ada".into() };
+    let after = After { _cached_len: 3, user: "ada".into(), tenant: 3 };
+    assert_eq!(hash(&before), hash(&after));
+    assert_eq!(hash(&before), hash(&(2u32, 3u32, "ada")));
+    assert_eq!(<Before as cuckoomap::CuckooKey>::KEY_VERSION, 2);
+
+    let mut map = CuckooMap::<DefaultHasher>::new();
+    map.insert_or_update(&before, [1]).unwrap();
+    assert_eq!(map.get(&after), Some([1]));
+}
+
+#[test]
+fn tuple_fields_and_generics() {
+    assert_eq!(hash(&Pair(1u8, 2u8)), hash(&(0u32, 2u8, 1u8)));
+    assert_eq!(<Pair<u8> as cuckoomap::CuckooKey>::KEY_VERSION, 0);
+}
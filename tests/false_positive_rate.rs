@@ -1,4 +1,4 @@
-use cuckoomap::{CuckooMap, Value};
+use cuckoomap::CuckooMap;
 
 use std::collections::hash_map::DefaultHasher;
 
@@ -44,6 +44,14 @@ fn false_positive_rate() {
         (filter.memory_usage() as f64) / 1024.0
     );
     println!("false positive rate: {}%", 100.0 * false_positive_rate);
-    // ratio should be around 0.024, round up to 0.03 to accomodate for random fluctuation
-    assert!(false_positive_rate < 0.03);
+    // A query matches each of the 8 slots of its 2 buckets of 4 entries with
+    // probability load / 255 for 1 byte fingerprints, so at the load factor
+    // of about 0.94 reached here the rate is about 1 - (1 - 0.94 / 255)^8,
+    // or 0.029, see `false_positive_probability`. Over a million queries its
+    // standard deviation is about 0.00017, so allowing 10% either way leaves
+    // a margin of more than 15 of them.
+    let expected = filter.false_positive_probability();
+    println!("load factor: {}", filter.len() as f64 / filter.capacity() as f64);
+    println!("expected false positive rate: {}%", 100.0 * expected);
+    assert!((false_positive_rate - expected).abs() < 0.1 * expected);
 }
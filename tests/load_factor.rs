@@ -0,0 +1,40 @@
+use cuckoomap::CuckooMap;
+
+use std::collections::hash_map::DefaultHasher;
+
+// Measures the load factor at which the first insertion fails for every kick
+// budget, so configurations can be chosen from data. Run it with
+// `cargo test --release --test load_factor -- --ignored --nocapture`.
+
+const CAPACITY: usize = 1 << 16;
+const RUNS: u64 = 5;
+
+fn load_factor_at_first_failure(max_kicks: u32, seed: u64) -> f64 {
+    let mut map = CuckooMap::<DefaultHasher>::with_capacity(CAPACITY);
+    map.seed_eviction_rng(seed);
+
+    let mut key = seed << 32;
+    while map.insert_with_budget(&key, [0], max_kicks).is_ok() {
+        key += 1;
+    }
+    map.len() as f64 / CAPACITY as f64
+}
+
+#[test]
+#[ignore]
+fn achievable_load_factor() {
+    println!("{} entries, 4 per bucket, 1 byte fingerprints", CAPACITY);
+    println!("{:>10} {:>10} {:>10} {:>10}", "max_kicks", "min", "mean", "max");
+
+    for &max_kicks in &[0, 4, 16, 64, 128, 250, 500, 1000, 4000] {
+        let loads: Vec<f64> = (0..RUNS)
+            .map(|seed| load_factor_at_first_failure(max_kicks, seed))
+            .collect();
+        let min = loads.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max = loads.iter().cloned().fold(0.0, f64::max);
+        let mean = loads.iter().sum::<f64>() / loads.len() as f64;
+
+        println!("{:>10} {:>10.4} {:>10.4} {:>10.4}", max_kicks, min, mean, max);
+        assert!(min > 0.0 && max <= 1.0);
+    }
+}
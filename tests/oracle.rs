@@ -0,0 +1,141 @@
+use cuckoomap::{CuckooMap, IndexScheme, PathSearch};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+
+// Runs random operations against a map and a std HashMap holding the exact
+// contents, in several configurations. 4 byte fingerprints make two live
+// keys sharing an entry so unlikely that every disagreement is a bug.
+// The long run is ignored by default, run it with
+// `cargo test --release --test oracle -- --ignored --nocapture`.
+
+type Map = CuckooMap<DefaultHasher, 4, 4>;
+type Configuration = (&'static str, fn() -> Map);
+
+/// Keys are drawn from `0..KEYS`, so they get inserted and deleted over and
+/// over, and looked up from `KEYS..` to count false positives.
+const KEYS: u64 = 1 << 13;
+
+/// Operations between two checks of every key.
+const CHECK_EVERY: usize = 10_000;
+
+fn configurations() -> Vec<Configuration> {
+    vec![
+        // grows at most 4 times, to 16 times the keys
+        ("auto-grow", || {
+            let mut map = Map::with_capacity(1 << 10);
+            map.set_auto_grow(true);
+            map
+        }),
+        ("4 ways, bfs", || {
+            let mut map = Map::with_capacity(1 << 14);
+            map.set_ways(4);
+            map.set_path_search(PathSearch::BreadthFirst { max_depth: 5 });
+            map
+        }),
+        ("exact capacity", || Map::with_exact_capacity(3 * KEYS as usize / 2)),
+        ("cache-local", || {
+            let mut map = Map::with_capacity(1 << 15);
+            map.set_index_scheme(IndexScheme::Local);
+            map
+        }),
+        ("hot cache, recency", || {
+            let mut map = Map::with_capacity(1 << 11);
+            map.set_auto_grow(true);
+            map.set_hot_cache(64);
+            map.set_access_sampling(4);
+            map.set_recency_window(8);
+            map
+        }),
+    ]
+}
+
+fn run(name: &str, mut map: Map, seed: u64, ops: usize) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut oracle: HashMap<u64, [u8; 4]> = HashMap::new();
+    let (mut queries, mut false_positives, mut expected) = (0u64, 0u64, 0.0);
+
+    for op in 1..=ops {
+        let key = rng.gen_range(0, KEYS);
+        let value = rng.gen::<u32>().to_le_bytes();
+        match rng.gen_range(0, 100) {
+            0..=34 => {
+                let result = map.insert_or_update(&key, value);
+                assert_eq!(result, Ok(()), "{}: insert_or_update({}) after {} ops", name, key, op);
+                oracle.insert(key, value);
+            }
+            35..=44 => {
+                let added = map.test_and_add(&key, value).unwrap();
+                assert_eq!(added, !oracle.contains_key(&key), "{}: test_and_add({}) after {} ops", name, key, op);
+                oracle.entry(key).or_insert(value);
+            }
+            45..=54 => {
+                let loaded = map.get_or_insert_with(&key, || value).unwrap();
+                assert_eq!(loaded, *oracle.entry(key).or_insert(value), "{}: get_or_insert_with({})", name, key);
+            }
+            // only keys known to be in the map are deleted, as deleting
+            // any other key may remove an entry sharing its fingerprint
+            55..=74 if oracle.contains_key(&key) => {
+                assert!(map.delete(&key), "{}: delete({}) after {} ops", name, key, op);
+                oracle.remove(&key);
+            }
+            55..=74 => {}
+            75..=89 => assert_eq!(map.get(&key), oracle.get(&key).copied(), "{}: get({}) after {} ops", name, key, op),
+            _ => {
+                let absent = rng.gen_range(KEYS, u64::MAX);
+                expected += map.false_positive_probability();
+                queries += 1;
+                false_positives += map.get(&absent).is_some() as u64;
+            }
+        }
+        if rng.gen_range(0, 50 * CHECK_EVERY) == 0 {
+            map.clear();
+            oracle.clear();
+        }
+
+        if op % CHECK_EVERY == 0 {
+            assert_eq!(map.len(), oracle.len(), "{}: len after {} ops", name, op);
+            for (key, value) in &oracle {
+                assert_eq!(map.get(key), Some(*value), "{}: false negative for {} after {} ops", name, key, op);
+            }
+        }
+    }
+
+    // false positives are roughly Poisson distributed around the estimate
+    let bound = 2.0 * expected + 5.0 * expected.sqrt() + 5.0;
+    assert!(
+        false_positives as f64 <= bound,
+        "{}: {} false positives in {} queries, expected {:.2}",
+        name,
+        false_positives,
+        queries,
+        expected
+    );
+    println!(
+        "{:>20}: {} entries, {} growths, {} false positives in {} queries",
+        name,
+        map.len(),
+        map.growths(),
+        false_positives,
+        queries
+    );
+}
+
+#[test]
+fn matches_oracle() {
+    for (seed, (name, build)) in configurations().into_iter().enumerate() {
+        run(name, build(), seed as u64, 5 * CHECK_EVERY);
+    }
+}
+
+#[test]
+#[ignore]
+fn matches_oracle_long() {
+    for seed in 0..4 {
+        for (name, build) in configurations() {
+            run(name, build(), seed, 2_000_000);
+        }
+    }
+}